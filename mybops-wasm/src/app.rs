@@ -2,22 +2,26 @@ use crate::{
     Content, ListsRoute, Route,
     base::Input,
     bootstrap::Modal,
+    compare::Compare,
     dataframe::DataFrame,
     docs,
     edit::Edit,
     home::Home,
-    integrations::spotify::SpotifyIntegration,
+    integrations::{
+        imports::ImportsIntegration, scrobbles::ScrobblesIntegration, spotify::SpotifyIntegration,
+    },
     list,
     list::item::{ItemMode, ListItems},
     plot::DataView,
-    random::{RandomMatches, RandomRounds},
+    random::{RandomMatches, RandomRounds, TierMatches},
     search::Search,
     settings::Settings,
+    tier::TierList,
     tournament::{RandomTournamentLoader, TournamentLoader},
 };
-use mybops::{List, ListMode, User};
+use mybops::{Credentials, List, ListMode, LoginProvider, User};
 use std::{collections::HashMap, rc::Rc};
-use web_sys::{HtmlSelectElement, MouseEvent};
+use web_sys::{HtmlInputElement, HtmlSelectElement, MouseEvent};
 use yew::{Callback, Component, Context, Html, NodeRef, Properties, html};
 use yew_router::{
     BrowserRouter, Switch,
@@ -34,6 +38,9 @@ pub enum ListPage {
     RandomRounds,
     Tournament,
     RandomTournament,
+    TierTournament,
+    TierMatches,
+    Tiers,
 }
 
 fn switch(
@@ -56,6 +63,7 @@ fn switch(
             html! { <Switch<ListsRoute> {render}/> }
         }
         Route::Search => html! { <Search {logged_in}/> },
+        Route::Compare => html! { <Compare/> },
         Route::Settings => html! {
             if let Some(user) = (*user).clone() {
                 <Settings {user}/>
@@ -64,6 +72,8 @@ fn switch(
             }
         },
         Route::Spotify => html! { <SpotifyIntegration {logged_in}/> },
+        Route::Scrobbles => html! { <ScrobblesIntegration {logged_in}/> },
+        Route::Imports => html! { <ImportsIntegration {logged_in}/> },
     }
 }
 
@@ -74,6 +84,10 @@ pub enum Msg {
     HideSidebar,
     Login,
     HideLogin,
+    PasswordLogin,
+    Signup,
+    LoginFailed(String),
+    Providers(Vec<LoginProvider>),
     Dropdown,
     ResetDropdown,
     ListDropdown,
@@ -87,6 +101,10 @@ pub struct App {
     user: Rc<Option<User>>,
     sidebar: bool,
     login: bool,
+    login_error: Option<String>,
+    providers: Vec<LoginProvider>,
+    username_ref: NodeRef,
+    password_ref: NodeRef,
     dropdown: bool,
     list_dropdown: bool,
     integrations_dropdown: bool,
@@ -108,23 +126,70 @@ impl Component for App {
             user: Rc::new(None),
             sidebar: false,
             login: false,
+            login_error: None,
+            providers: Vec::new(),
+            username_ref: NodeRef::default(),
+            password_ref: NodeRef::default(),
             dropdown: false,
             list_dropdown: false,
             integrations_dropdown: false,
         }
     }
 
-    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::Demo => self.user_loaded = true,
             Msg::Success(user) => {
                 self.user_loaded = true;
+                self.login = false;
                 self.user = Rc::new(Some(user))
             }
             Msg::Sidebar => self.sidebar = true,
             Msg::HideSidebar => self.sidebar = false,
-            Msg::Login => self.login = true,
-            Msg::HideLogin => self.login = false,
+            Msg::Login => {
+                self.login = true;
+                if self.providers.is_empty() {
+                    ctx.link().send_future(async move {
+                        Msg::Providers(crate::get_login_providers().await.unwrap_or_default())
+                    });
+                }
+            }
+            Msg::Providers(providers) => self.providers = providers,
+            Msg::HideLogin => {
+                self.login = false;
+                self.login_error = None;
+            }
+            Msg::PasswordLogin | Msg::Signup => {
+                let credentials = Credentials {
+                    username: self
+                        .username_ref
+                        .cast::<HtmlInputElement>()
+                        .unwrap()
+                        .value(),
+                    password: self
+                        .password_ref
+                        .cast::<HtmlInputElement>()
+                        .unwrap()
+                        .value(),
+                };
+                let signup = matches!(msg, Msg::Signup);
+                ctx.link().send_future(async move {
+                    let result = if signup {
+                        crate::signup(&credentials).await
+                    } else {
+                        crate::password_login(&credentials).await
+                    };
+                    if let Err(e) = result {
+                        return Msg::LoginFailed(e.as_string().unwrap_or_default());
+                    }
+                    match crate::get_user().await {
+                        Ok(user) => Msg::Success(user),
+                        Err(_) => Msg::LoginFailed(String::from("Failed to load user")),
+                    }
+                });
+                return false;
+            }
+            Msg::LoginFailed(e) => self.login_error = Some(e),
             Msg::Dropdown => self.dropdown = !self.dropdown,
             // We need to check which dropdown is clicked instead of relying on stop_propagation
             // TODO: fix multiple open dropdowns
@@ -152,8 +217,6 @@ impl Component for App {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let window = crate::window();
-        let location = window.location();
         //let onclick = ctx.link().callback(|_| Msg::Logout);
         // TODO: make anchors active if active
         let search = /*if location.pathname().unwrap() == "/search" {
@@ -181,6 +244,8 @@ impl Component for App {
         let hide_sidebar = ctx.link().callback(|_| Msg::HideSidebar);
         let login = ctx.link().callback(|_| Msg::Login);
         let hide = ctx.link().callback(|_| Msg::HideLogin);
+        let password_login = ctx.link().callback(|_| Msg::PasswordLogin);
+        let signup = ctx.link().callback(|_| Msg::Signup);
         let reset_dropdown = ctx.link().callback(|_| Msg::ResetDropdown);
         let render = {
             let user = Rc::clone(&self.user);
@@ -226,10 +291,15 @@ impl Component for App {
                       <li class="nav-item">
                         <Link<Route> classes={search} to={Route::Search}>{"Query"}</Link<Route>>
                       </li>
+                      <li class="nav-item">
+                        <Link<Route> classes={search} to={Route::Compare}>{"Compare"}</Link<Route>>
+                      </li>
                       <li class="nav-item dropdown">
                         <a class={int_toggle_class} href="#" onclick={int_dropdown}>{"Integrations"}</a>
                         <ul class={int_menu_class}>
                           <li><Link<Route> classes="dropdown-item" to={Route::Spotify}>{"Spotify"}</Link<Route>></li>
+                          <li><Link<Route> classes="dropdown-item" to={Route::Scrobbles}>{"Last.fm and ListenBrainz"}</Link<Route>></li>
+                          <li><Link<Route> classes="dropdown-item" to={Route::Imports}>{"File Imports"}</Link<Route>></li>
                         </ul>
                       </li>
                       <li class="nav-item">
@@ -267,8 +337,19 @@ impl Component for App {
               if self.login {
                 <Modal header={"Log in"} {hide}>
                   <div class="modal-body d-grid gap-2">
-                    <a class="btn btn-success" href={format!("https://accounts.spotify.com/authorize?client_id=ee3d1b4f8d80477ea48743a511ef3018&redirect_uri={}/api/login&response_type=code&scope=playlist-modify-public playlist-modify-private user-read-recently-played playlist-read-private", location.origin().unwrap().as_str())}>{"Log in with Spotify"}</a>
-                    <a class="btn btn-success" href={format!("https://accounts.google.com/o/oauth2/v2/auth?client_id=1038220726403-n55jha2cvprd8kdb4akdfvo0uiok4p5u.apps.googleusercontent.com&redirect_uri={}/api/login/google&response_type=code&scope=email", location.origin().unwrap().as_str())}>{"Log in with Google"}</a>
+                    if let Some(error) = &self.login_error {
+                      <div class="alert alert-danger mb-0">{error}</div>
+                    }
+                    <input ref={&self.username_ref} type="text" class="form-control" placeholder="Username" autocomplete="username"/>
+                    <input ref={&self.password_ref} type="password" class="form-control" placeholder="Password" autocomplete="current-password"/>
+                    <div class="d-flex gap-2">
+                      <button type="button" class="btn btn-primary flex-grow-1" onclick={password_login}>{"Log in"}</button>
+                      <button type="button" class="btn btn-outline-primary flex-grow-1" onclick={signup}>{"Sign up"}</button>
+                    </div>
+                    <hr/>
+                    {for self.providers.iter().map(|provider| html! {
+                      <a class="btn btn-success" href={provider.url.clone()}>{format!("Log in with {}", provider.name)}</a>
+                    })}
                   </div>
                 </Modal>
               }
@@ -446,7 +527,8 @@ impl Component for ListComponent {
             | ListsRoute::View { id }
             | ListsRoute::Edit { id }
             | ListsRoute::Match { id }
-            | ListsRoute::Tournament { id } => id.clone(),
+            | ListsRoute::Tournament { id }
+            | ListsRoute::Tiers { id } => id.clone(),
         };
         ctx.link().send_future(async move {
             if let Some(list) = crate::fetch_list(&id).await.unwrap() {
@@ -501,7 +583,8 @@ impl Component for ListComponent {
                 | ListsRoute::View { id }
                 | ListsRoute::Edit { id }
                 | ListsRoute::Match { id }
-                | ListsRoute::Tournament { id } => id.clone(),
+                | ListsRoute::Tournament { id }
+                | ListsRoute::Tiers { id } => id.clone(),
             };
             ctx.link().send_future(async move {
                 ListMsg::Load(Box::new(crate::fetch_list(&id).await.unwrap().unwrap()))
@@ -527,20 +610,17 @@ impl Component for ListComponent {
             ListsRoute::View { .. } => ListPage::View,
             ListsRoute::List { .. } => ListPage::List,
             ListsRoute::Edit { .. } => ListPage::Edit,
-            ListsRoute::Tournament { .. } => {
-                if query.get("mode").map(String::as_str) == Some("random") {
-                    ListPage::RandomTournament
-                } else {
-                    ListPage::Tournament
-                }
-            }
-            ListsRoute::Match { .. } => {
-                if query.get("mode").map(String::as_str) == Some("rounds") {
-                    ListPage::RandomRounds
-                } else {
-                    ListPage::RandomMatches
-                }
-            }
+            ListsRoute::Tournament { .. } => match query.get("mode").map(String::as_str) {
+                Some("random") => ListPage::RandomTournament,
+                Some("tier") => ListPage::TierTournament,
+                _ => ListPage::Tournament,
+            },
+            ListsRoute::Match { .. } => match query.get("mode").map(String::as_str) {
+                Some("rounds") => ListPage::RandomRounds,
+                Some("tier") => ListPage::TierMatches,
+                _ => ListPage::RandomMatches,
+            },
+            ListsRoute::Tiers { .. } => ListPage::Tiers,
         };
         let mut tabs = ["nav-link"; 3];
         let active = "nav-link active";
@@ -565,6 +645,13 @@ impl Component for ListComponent {
                     html! { <RandomTournamentLoader list={*list.clone()}/> }
                 }
                 ListPage::Tournament => html! { <TournamentLoader list={*list.clone()}/> },
+                ListPage::TierTournament => {
+                    html! { <TournamentLoader list={*list.clone()} seed_by_tier=true/> }
+                }
+                ListPage::TierMatches => {
+                    html! { <TierMatches id={list.id.clone()} tiers={list.tiers.clone()}/> }
+                }
+                ListPage::Tiers => html! { <TierList list={*list.clone()}/> },
             }
         } else {
             match view {
@@ -581,6 +668,9 @@ impl Component for ListComponent {
             ListPage::RandomRounds => "Random Rounds",
             ListPage::Tournament => "Tournament",
             ListPage::RandomTournament => "Random Tournament",
+            ListPage::TierTournament => "Tier Tournament",
+            ListPage::TierMatches => "Tier Matches",
+            ListPage::Tiers => "Tier List",
             _ => "Rank",
         };
         let toggle_class = match (toggle, ctx.props().dropdown) {
@@ -606,6 +696,10 @@ impl Component for ListComponent {
                         <li><Link<ListsRoute, RouteQuery> classes="dropdown-item" to={ListsRoute::Tournament{ id: list.id.clone() }} query={Some(&[("mode", "random")][..])}>{"Random Tournament"}</Link<ListsRoute, RouteQuery>></li>
                         <li><Link<ListsRoute> classes="dropdown-item" to={ListsRoute::Match{ id: list.id.clone() }}>{"Random Matches"}</Link<ListsRoute>></li>
                         <li><Link<ListsRoute, RouteQuery> classes="dropdown-item" to={ListsRoute::Match{ id: list.id.clone() }} query={Some(&[("mode", "rounds")][..])}>{"Random Rounds"}</Link<ListsRoute, RouteQuery>></li>
+                        <li><hr class="dropdown-divider"/></li>
+                        <li><Link<ListsRoute> classes="dropdown-item" to={ListsRoute::Tiers{ id: list.id.clone() }}>{"Tier List"}</Link<ListsRoute>></li>
+                        <li><Link<ListsRoute, RouteQuery> classes="dropdown-item" to={ListsRoute::Tournament{ id: list.id.clone() }} query={Some(&[("mode", "tier")][..])}>{"Tier Tournament"}</Link<ListsRoute, RouteQuery>></li>
+                        <li><Link<ListsRoute, RouteQuery> classes="dropdown-item" to={ListsRoute::Match{ id: list.id.clone() }} query={Some(&[("mode", "tier")][..])}>{"Tier Matches"}</Link<ListsRoute, RouteQuery>></li>
                     </ul>
                 </li>
             }
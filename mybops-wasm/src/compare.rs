@@ -0,0 +1,174 @@
+use crate::{bootstrap::Alert, dataframe::DataFrame, plot::DataView};
+use arrow::{
+    array::{ArrayRef, Int32Array},
+    datatypes::{DataType, Field, Schema},
+};
+use mybops::TasteComparison;
+use std::sync::Arc;
+use web_sys::{HtmlInputElement, KeyboardEvent};
+use yew::{Component, Context, Html, NodeRef, html};
+
+pub enum Msg {
+    Compare,
+    Fetched(Result<TasteComparison, String>),
+    HideAlert,
+}
+
+/// Compare the user's rankings with another user's public lists
+pub struct Compare {
+    user_ref: NodeRef,
+    comparison: Option<TasteComparison>,
+    /// Scores of the user and the other user for the scatter plot
+    scores: Option<DataFrame>,
+    alert: Option<Result<String, String>>,
+}
+
+impl Component for Compare {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_: &Context<Self>) -> Self {
+        Compare {
+            user_ref: NodeRef::default(),
+            comparison: None,
+            scores: None,
+            alert: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Compare => {
+                let user = self.user_ref.cast::<HtmlInputElement>().unwrap().value();
+                ctx.link().send_future(async move {
+                    Msg::Fetched(
+                        crate::get_comparison(&user)
+                            .await
+                            .map_err(|e| e.as_string().unwrap_or_default()),
+                    )
+                });
+                false
+            }
+            Msg::Fetched(Ok(comparison)) => {
+                self.scores = (!comparison.items.is_empty()).then(|| scores(&comparison));
+                self.comparison = Some(comparison);
+                self.alert = None;
+                true
+            }
+            Msg::Fetched(Err(e)) => {
+                self.alert = Some(Err(e));
+                true
+            }
+            Msg::HideAlert => {
+                self.alert = None;
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let onclick = ctx.link().callback(|_| Msg::Compare);
+        let onkeydown = ctx.link().batch_callback(|event: KeyboardEvent| {
+            if event.key_code() == 13 {
+                event.prevent_default();
+                Some(Msg::Compare)
+            } else {
+                None
+            }
+        });
+        html! {
+          <div style="max-width: 1000px">
+            <h1>{"Compare"}</h1>
+            <p>{"Compare your rankings with the items another user has shared in public lists. Only items that both of you have played matches for are compared."}</p>
+            if let Some(result) = &self.alert {
+              <Alert result={result.clone()} hide={ctx.link().callback(|_| Msg::HideAlert)}/>
+            }
+            <div class="input-group mb-3" style="max-width: 400px">
+              <input ref={self.user_ref.clone()} type="text" class="form-control" placeholder="User" {onkeydown}/>
+              <button type="button" class="btn btn-primary" {onclick}>{"Compare"}</button>
+            </div>
+            if let Some(comparison) = &self.comparison {
+              {self.view_comparison(comparison)}
+            }
+          </div>
+        }
+    }
+
+    fn rendered(&mut self, _: &Context<Self>, _: bool) {
+        if let Some(df) = &self.scores
+            && let Err(e) = DataView::ScatterPlot.draw(df)
+        {
+            self.alert = Some(Err(e.to_string()));
+        }
+    }
+}
+
+impl Compare {
+    fn view_comparison(&self, comparison: &TasteComparison) -> Html {
+        let Some(scores) = &self.scores else {
+            return html! {
+              <p>{format!("You don't have any ranked items in common with {}", comparison.user_id)}</p>
+            };
+        };
+        let correlation = |value: Option<f64>| {
+            value.map_or_else(|| String::from("N/A"), |value| format!("{value:.3}"))
+        };
+        html! {
+          <>
+            <dl class="row">
+              <dt class="col-sm-3">{"Common items"}</dt>
+              <dd class="col-sm-9">{comparison.items.len()}</dd>
+              <dt class="col-sm-3">{"Kendall tau"}</dt>
+              <dd class="col-sm-9">{correlation(comparison.kendall_tau)}</dd>
+              <dt class="col-sm-3">{"Spearman correlation"}</dt>
+              <dd class="col-sm-9">{correlation(comparison.spearman)}</dd>
+            </dl>
+            {DataView::ScatterPlot.render(scores)}
+            <h4 class="mt-3">{"Biggest disagreements"}</h4>
+            <div class="table-responsive">
+              <table class="table table-striped">
+                <thead>
+                  <tr>
+                    <th>{"Item"}</th>
+                    <th>{"Your rank"}</th>
+                    <th>{format!("{}'s rank", comparison.user_id)}</th>
+                    <th>{"Your score"}</th>
+                    <th>{format!("{}'s score", comparison.user_id)}</th>
+                  </tr>
+                </thead>
+                <tbody>
+                  {for comparison.disagreements.iter().map(|item| html! {
+                    <tr>
+                      <td>{&item.name}</td>
+                      <td>{item.rank}</td>
+                      <td>{item.other_rank}</td>
+                      <td>{item.score}</td>
+                      <td>{item.other_score}</td>
+                    </tr>
+                  })}
+                </tbody>
+              </table>
+            </div>
+          </>
+        }
+    }
+}
+
+fn scores(comparison: &TasteComparison) -> DataFrame {
+    let score: Int32Array = comparison.items.iter().map(|item| item.score).collect();
+    let other_score: Int32Array = comparison
+        .items
+        .iter()
+        .map(|item| item.other_score)
+        .collect();
+    DataFrame {
+        schema: Arc::new(Schema::new(vec![
+            Field::new("score", DataType::Int32, false),
+            Field::new(&comparison.user_id, DataType::Int32, false),
+        ])),
+        arrays: vec![
+            Arc::new(score) as ArrayRef,
+            Arc::new(other_score) as ArrayRef,
+        ],
+    }
+}
@@ -13,11 +13,15 @@ pub fn docs() -> Html {
             <h5>{"Rank items"}</h5>
             <p>{"Rank items like your songs to figure out what your favorite songs are."}</p>
             <h5>{"Rate items"}</h5>
-            <p>{"Item ratings provide a method for ranking/grouping your items using a scale of 1 to 10."}</p>
+            <p>{"Item ratings provide a method for ranking/grouping your items using a scale of 0 to 10, 1 to 5 stars with halves, 0 to 100 or like and dislike.
+                Films and books are rated with stars and other items out of 10 unless the list settings choose a scale for the list.
+                Ratings are stored on the scale they were entered with and normalized_rating compares ratings on different scales out of 100."}</p>
             <h5>{"Query items"}</h5>
             <p>{"Query using SQL to gain insights about your data by calculating statistics and filtering items.
                 You can also view your data using different types of charts.
-                The first column returned by the SQL query is used as the x-axis and the second column is used as the y."}</p>
+                The first column returned by the SQL query is used as the x-axis and the second column is used as the y.
+                Line graphs draw a separate line for each value of a third column and use a time axis when the first column is a timestamp,
+                so querying item_history for ts, score and name charts how your scores changed after each match."}</p>
             <h5>{"Manage items"}</h5>
             <p>{"You can mark items as hidden for queries to filter on.
                 You can also delete items to remove it from all lists and queries."}</p>
@@ -25,13 +29,49 @@ pub fn docs() -> Html {
             <h5>{"Create lists of items using data sources"}</h5>
             <p>{"Add items to a list by adding a data source that resolves to items.
                 Deleting a data source will remove the items from the list but the data for items will still be preserved."}</p>
+            <p>{"List Items data sources include the items of another list by its ID, including any lists that list is built from.
+                When the items of a list change, lists that use it as a data source are updated too.
+                Lists can't include themselves, either directly or through other lists."}</p>
+            <p>{"Scrobbles data sources pull your most played tracks, albums or artists from Last.fm or ListenBrainz and match them to Spotify.
+                They are written as service:user:kind:period, for example lastfm:rj:tracks:month or listenbrainz:rob:albums:year.
+                Kind can be tracks, albums or artists and period can be week, month, quarter, half_year, year or all_time."}</p>
+            <p>{"Setlist data sources can be a setlist.fm setlist link or a Setlists source that combines every song played on a tour or in an artist's recent shows.
+                Setlists sources are written as tour:artist:tour name or recent:artist:count, for example tour:Radiohead:OK Computer or recent:Radiohead:10.
+                Songs are ordered by how many shows they were played at and songs that couldn't be found in Spotify are listed under the data source.
+                Only the first 100 shows of a tour are counted and the data source name says so when a tour has more shows."}</p>
+            <p>{"Import data sources contain films, books, board games or video games uploaded from the file imports integration.
+                They are written as letterboxd, goodreads, bgg or igdb and include every item you've imported.
+                Board games and video games can be queried by fields such as designer, year, weight, min_players, max_players and platforms."}</p>
+            <p>{"Local Library data sources scan a directory of FLAC, MP3, MP4, Ogg or WAV files on the server for tracks.
+                They are written as a path relative to the server's library root and use the same artists, album, year, track_number and duration_ms fields as Spotify tracks.
+                Only files that were added or changed are read again when the list is updated."}</p>
+            <p>{"Filter data sources make a smart list from every item that matches a filter, for example rating >= 8 AND year > 2015 or hidden = false ORDER BY user_score DESC.
+                Unlike views, the matching items are stored in the list so they can be ranked with matches and tournaments, and items that still match keep their scores when the list is re-evaluated.
+                Smart lists are re-evaluated when the list is saved and when Refresh is pressed on the list page.
+                Lists that only combine filters with lists, imports or custom items are also re-evaluated when items are edited, merged, deleted or imported."}</p>
+            <p>{"Composite data sources combine other sources with | for items in any source, & for items in every source and - for items in the first source but not the others.
+                Sources are written as list:ID, Spotify links, import:format or scrobbles:service:user:kind:period, for example list:gym - list:running or (list:mine & list:friend) | list:favorites.
+                & is applied before | and -, and the editor shows how the expression is grouped as a tree."}</p>
             <h5>{"Query items in a list"}</h5>
             <p>{"Queries under a list page are similar to queries in the top-level page except they will also be filtered against items in the list."}</p>
             <h5>{"Integrate with external systems"}</h5>
             <p>{"If the data sources of your list support external integrations, you can define an ID that will be used for the integration.
                 For example, lists that only use Spotify data sources can push the items into a Spotify playlist with the given ID."}</p>
+            <p>{"Any list can also be exported as an M3U8 or XSPF playlist, a CSV file or JSON, ordered by the list's sort mode, rank, score or the list's query.
+                Local library tracks are exported as file paths and Spotify tracks as Spotify URIs."}</p>
             <h5>{"Define a default query for the list"}</h5>
             <p>{"The query will be used as the default query for the query view and any push actions."}</p>
+            <h5>{"Sort the items in a list"}</h5>
+            <p>{"Lists are sorted by their query by default and can instead be sorted by Elo score, tournament rank, rating or manually in the list settings.
+                Manually sorted lists are reordered by dragging items on the list page and new items from data sources are added to the end.
+                The sort mode is also used when the list is pushed to Spotify or exported."}</p>
+            <h5>{"Rank items in tiers"}</h5>
+            <p>{"The Tier List page under Rank lets you drag the items of a list into tiers, which are S, A, B, C and D unless other tiers are set in the list settings.
+                Tiers are kept for each list and can be used in the list's queries as the tier field, for example SELECT name, tier FROM item WHERE tier = 'S'.
+                Tier Tournaments seed items by tier before score and Tier Matches pair items with items in the same or adjacent tiers."}</p>
+            <h5>{"Compare rankings"}</h5>
+            <p>{"The Compare page compares your Elo scores with another user's scores for the items you have both played matches for, using the items in their public lists.
+                It shows the Kendall tau and Spearman rank correlations, a scatter plot of both users' scores and the items you disagree on most."}</p>
             <h5>{"Favorite lists"}</h5>
             <p>{"Favorite lists will show up on the home page along with results from the default query."}</p>
             <h3>{"Combined features"}</h3>
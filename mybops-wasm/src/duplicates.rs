@@ -0,0 +1,182 @@
+use crate::bootstrap::Alert;
+use mybops::{DuplicateGroup, IdentifySummary, MergeItems};
+use yew::{Component, Context, Html, html};
+
+pub enum Msg {
+    Fetched(Result<Vec<DuplicateGroup>, String>),
+    Select(usize, String),
+    Merge(usize),
+    Merged(Result<(), String>),
+    Identify,
+    Identified(Result<IdentifySummary, String>),
+    HideAlert,
+}
+
+/// Review items that look like the same recording and merge them
+pub struct Duplicates {
+    groups: Option<Vec<DuplicateGroup>>,
+    /// Selected merge target for each group
+    targets: Vec<String>,
+    alert: Option<Result<String, String>>,
+    loading: bool,
+}
+
+impl Component for Duplicates {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        fetch(ctx);
+        Duplicates {
+            groups: None,
+            targets: Vec::new(),
+            alert: None,
+            loading: true,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Fetched(Ok(groups)) => {
+                self.targets = groups
+                    .iter()
+                    .map(|group| group.items[0].id.clone())
+                    .collect();
+                self.groups = Some(groups);
+                self.loading = false;
+            }
+            Msg::Fetched(Err(e)) => {
+                self.alert = Some(Err(e));
+                self.loading = false;
+            }
+            Msg::Select(i, id) => self.targets[i] = id,
+            Msg::Merge(i) => {
+                let Some(group) = self.groups.as_ref().and_then(|groups| groups.get(i)) else {
+                    return false;
+                };
+                let target = self.targets[i].clone();
+                let merge = MergeItems {
+                    ids: group
+                        .items
+                        .iter()
+                        .filter(|item| item.id != target)
+                        .map(|item| item.id.clone())
+                        .collect(),
+                    target,
+                };
+                self.loading = true;
+                ctx.link().send_future(async move {
+                    Msg::Merged(
+                        crate::merge_items(&merge)
+                            .await
+                            .map_err(|e| e.as_string().unwrap_or_default()),
+                    )
+                });
+            }
+            Msg::Merged(result) => {
+                self.alert = Some(result.map(|_| String::from("Merged items")));
+                fetch(ctx);
+            }
+            Msg::Identify => {
+                self.loading = true;
+                self.alert = Some(Ok(String::from("Looking up MusicBrainz recordings")));
+                ctx.link().send_future(async move {
+                    Msg::Identified(
+                        crate::identify_items()
+                            .await
+                            .map_err(|e| e.as_string().unwrap_or_default()),
+                    )
+                });
+            }
+            Msg::Identified(result) => {
+                self.alert = Some(result.map(|summary| {
+                    format!(
+                        "Matched {} of {} items to MusicBrainz recordings. {} items haven't been looked up yet.",
+                        summary.matched, summary.items, summary.remaining
+                    )
+                }));
+                fetch(ctx);
+            }
+            Msg::HideAlert => self.alert = None,
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        html! {
+          <div class="mb-3" style="max-width: 1000px">
+            <p>{"Items are grouped if they have the same ISRC, MusicBrainz recording or title and main artist.
+                Merging adds the match records and scores of the other items to the selected item and replaces them in your lists."}</p>
+            if let Some(result) = &self.alert {
+              <Alert result={result.clone()} hide={ctx.link().callback(|_| Msg::HideAlert)}/>
+            }
+            <button type="button" class="btn btn-primary mb-3" disabled={self.loading} onclick={ctx.link().callback(|_| Msg::Identify)}>{"Look up MusicBrainz recordings"}</button>
+            if let Some(groups) = &self.groups {
+              if groups.is_empty() {
+                <p>{"No duplicates found"}</p>
+              }
+              {for groups.iter().enumerate().map(|(i, group)| self.view_group(ctx, i, group))}
+            }
+          </div>
+        }
+    }
+}
+
+impl Duplicates {
+    fn view_group(&self, ctx: &Context<Self>, i: usize, group: &DuplicateGroup) -> Html {
+        let reasons: String = group
+            .reasons
+            .iter()
+            .map(String::as_str)
+            .intersperse(", ")
+            .collect();
+        html! {
+          <div class="card mb-3">
+            <div class="card-body">
+              <h6 class="card-subtitle mb-2 text-body-secondary">{format!("Matched by {reasons}")}</h6>
+              <table class="table table-sm">
+                <thead>
+                  <tr>
+                    <th>{"Keep"}</th>
+                    <th>{"Name"}</th>
+                    <th>{"Artists"}</th>
+                    <th>{"Album"}</th>
+                    <th>{"Rating"}</th>
+                    <th>{"Score"}</th>
+                    <th>{"Record"}</th>
+                  </tr>
+                </thead>
+                <tbody>
+                  {for group.items.iter().map(|item| {
+                    let id = item.id.clone();
+                    let onchange = ctx.link().callback(move |_| Msg::Select(i, id.clone()));
+                    html! {
+                      <tr>
+                        <td><input class="form-check-input" type="radio" name={format!("duplicate-{i}")} checked={self.targets[i] == item.id} {onchange}/></td>
+                        <td>{&item.name}</td>
+                        <td>{item.artists.join(", ")}</td>
+                        <td>{item.album.clone().unwrap_or_default()}</td>
+                        <td>{item.rating.map(|r| r.to_string()).unwrap_or_default()}</td>
+                        <td>{item.score}</td>
+                        <td>{format!("{}-{}", item.wins, item.losses)}</td>
+                      </tr>
+                    }
+                  })}
+                </tbody>
+              </table>
+              <button type="button" class="btn btn-warning" disabled={self.loading} onclick={ctx.link().callback(move |_| Msg::Merge(i))}>{"Merge"}</button>
+            </div>
+          </div>
+        }
+    }
+}
+
+fn fetch(ctx: &Context<Duplicates>) {
+    ctx.link().send_future(async move {
+        Msg::Fetched(
+            crate::get_duplicates()
+                .await
+                .map_err(|e| e.as_string().unwrap_or_default()),
+        )
+    });
+}
@@ -1,4 +1,7 @@
-use mybops::{Id, List, ListMode, Source, SourceType, Spotify};
+use mybops::{
+    Id, List, ListMode, RatingScale, SortMode, Source, SourceType, Spotify,
+    composite::{self, Composite},
+};
 use web_sys::{HtmlInputElement, HtmlSelectElement};
 use yew::{Component, Context, Html, NodeRef, Properties, html};
 use yew_router::scope_ext::RouterScopeExt;
@@ -9,7 +12,9 @@ pub enum Msg {
     None,
     AddSource,
     DeleteSource(usize),
+    SourceInput(usize),
     Save,
+    SaveError(String),
     Delete,
     DeleteAll,
 }
@@ -25,11 +30,17 @@ pub struct Edit {
     counter: i32,
     list: List,
     sources: Vec<(i32, NodeRef, NodeRef, Option<SourceType>)>,
+    /// Parsed expressions of combined sources by row, shown as a tree while editing
+    composites: Vec<Option<Result<Composite, String>>>,
     name_ref: NodeRef,
     external_ref: NodeRef,
     query_ref: NodeRef,
+    sort_ref: NodeRef,
+    tiers_ref: NodeRef,
+    rating_scale_ref: NodeRef,
     favorite_ref: NodeRef,
     public_ref: NodeRef,
+    error: Option<String>,
 }
 
 impl Component for Edit {
@@ -51,15 +62,27 @@ impl Component for Edit {
                 )
             })
             .collect();
+        let composites = sources
+            .iter()
+            .map(|(_, _, _, source)| match source {
+                Some(SourceType::Composite(composite)) => Some(Ok(composite.clone())),
+                _ => None,
+            })
+            .collect();
         Edit {
             counter: sources.len() as i32,
             list,
             sources,
+            composites,
             name_ref: NodeRef::default(),
             external_ref: NodeRef::default(),
             query_ref: NodeRef::default(),
+            sort_ref: NodeRef::default(),
+            tiers_ref: NodeRef::default(),
+            rating_scale_ref: NodeRef::default(),
             favorite_ref: NodeRef::default(),
             public_ref: NodeRef::default(),
+            error: None,
         }
     }
 
@@ -69,11 +92,24 @@ impl Component for Edit {
             Msg::AddSource => {
                 self.sources
                     .push((self.counter, NodeRef::default(), NodeRef::default(), None));
+                self.composites.push(None);
                 self.counter += 1;
                 true
             }
             Msg::DeleteSource(i) => {
                 self.sources.remove(i);
+                self.composites.remove(i);
+                true
+            }
+            Msg::SourceInput(i) => {
+                let (_, source, id, _) = &self.sources[i];
+                let source = source.cast::<HtmlSelectElement>().unwrap().value();
+                self.composites[i] = if source == "Composite" {
+                    let id = id.cast::<HtmlInputElement>().unwrap().value();
+                    Some(id.parse())
+                } else {
+                    None
+                };
                 true
             }
             Msg::Save => {
@@ -95,6 +131,36 @@ impl Component for Edit {
                     }
                 }
                 self.list.query = self.query_ref.cast::<HtmlInputElement>().unwrap().value();
+                if let Ok(sort) = self
+                    .sort_ref
+                    .cast::<HtmlSelectElement>()
+                    .unwrap()
+                    .value()
+                    .parse()
+                {
+                    self.list.sort = sort;
+                }
+                // Tiers are written from best to worst separated by commas
+                let tiers: Vec<_> = self
+                    .tiers_ref
+                    .cast::<HtmlInputElement>()
+                    .unwrap()
+                    .value()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tier| !tier.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect();
+                if !tiers.is_empty() {
+                    self.list.tiers = tiers;
+                }
+                self.list.rating_scale = self
+                    .rating_scale_ref
+                    .cast::<HtmlSelectElement>()
+                    .unwrap()
+                    .value()
+                    .parse()
+                    .ok();
                 self.list.favorite = self
                     .favorite_ref
                     .cast::<HtmlInputElement>()
@@ -115,6 +181,7 @@ impl Component for Edit {
                                 self.list.sources.push(Source {
                                     source_type: SourceType::Spotify(source),
                                     name: String::new(),
+                                    unmatched: Vec::new(),
                                 });
                             } else {
                                 return false;
@@ -125,6 +192,7 @@ impl Component for Edit {
                                 self.list.sources.push(Source {
                                     source_type: SourceType::Custom(json),
                                     name: String::new(),
+                                    unmatched: Vec::new(),
                                 });
                             } else {
                                 return false;
@@ -135,17 +203,80 @@ impl Component for Edit {
                                 self.list.sources.push(Source {
                                     source_type: SourceType::Setlist(id),
                                     name: String::new(),
+                                    unmatched: Vec::new(),
                                 });
                             } else {
                                 return false;
                             }
                         }
+                        "Setlists" => {
+                            if let Ok(setlists) = id.parse() {
+                                self.list.sources.push(Source {
+                                    source_type: SourceType::Setlists(setlists),
+                                    name: String::new(),
+                                    unmatched: Vec::new(),
+                                });
+                            } else {
+                                return false;
+                            }
+                        }
+                        "Import" => {
+                            if let Ok(format) = id.parse() {
+                                self.list.sources.push(Source {
+                                    source_type: SourceType::Import(format),
+                                    name: String::new(),
+                                    unmatched: Vec::new(),
+                                });
+                            } else {
+                                return false;
+                            }
+                        }
+                        "Local Library" => {
+                            self.list.sources.push(Source {
+                                source_type: SourceType::LocalLibrary(id),
+                                name: String::new(),
+                                unmatched: Vec::new(),
+                            });
+                        }
                         "List Items" => {
                             self.list.sources.push(Source {
                                 source_type: SourceType::ListItems(id),
                                 name: String::new(),
+                                unmatched: Vec::new(),
                             });
                         }
+                        "Scrobbles" => {
+                            if let Ok(scrobbles) = id.parse() {
+                                self.list.sources.push(Source {
+                                    source_type: SourceType::Scrobbles(scrobbles),
+                                    name: String::new(),
+                                    unmatched: Vec::new(),
+                                });
+                            } else {
+                                return false;
+                            }
+                        }
+                        "Filter" => {
+                            if id.trim().is_empty() {
+                                return false;
+                            }
+                            self.list.sources.push(Source {
+                                source_type: SourceType::Query(id),
+                                name: String::new(),
+                                unmatched: Vec::new(),
+                            });
+                        }
+                        "Composite" => {
+                            if let Ok(composite) = id.parse() {
+                                self.list.sources.push(Source {
+                                    source_type: SourceType::Composite(composite),
+                                    name: String::new(),
+                                    unmatched: Vec::new(),
+                                });
+                            } else {
+                                return false;
+                            }
+                        }
                         _ => {
                             return false;
                         }
@@ -153,10 +284,18 @@ impl Component for Edit {
                 }
                 let list = self.list.clone();
                 ctx.link().send_future(async move {
-                    crate::update_list(&list).await.unwrap();
-                    Msg::None
+                    // Data sources can be rejected, e.g. if lists include each other
+                    match crate::update_list(&list).await {
+                        Ok(()) => Msg::None,
+                        Err(e) => Msg::SaveError(e.as_string().unwrap_or_default()),
+                    }
                 });
-                false
+                self.error = None;
+                true
+            }
+            Msg::SaveError(e) => {
+                self.error = Some(e);
+                true
             }
             Msg::Delete => {
                 let id = self.list.id.clone();
@@ -199,29 +338,54 @@ impl Component for Edit {
             .iter()
             .enumerate()
             .map(|(i, (key, source_ref, id, source))| {
-                let mut selected = [false; 4];
+                let mut selected = [false; 10];
                 match source {
                     None => selected[1] = true,
                     Some(SourceType::Custom(_)) => selected[0] = true,
                     Some(SourceType::Spotify(_)) => selected[1] = true,
                     Some(SourceType::Setlist(_)) => selected[2] = true,
                     Some(SourceType::ListItems(_)) => selected[3] = true,
+                    Some(SourceType::Scrobbles(_)) => selected[4] = true,
+                    Some(SourceType::Setlists(_)) => selected[5] = true,
+                    Some(SourceType::Import(_)) => selected[6] = true,
+                    Some(SourceType::LocalLibrary(_)) => selected[7] = true,
+                    Some(SourceType::Composite(_)) => selected[8] = true,
+                    Some(SourceType::Query(_)) => selected[9] = true,
                 };
                 let onclick = ctx.link().callback(move |_| Msg::DeleteSource(i));
+                let onchange = ctx.link().callback(move |_| Msg::SourceInput(i));
+                let oninput = ctx.link().callback(move |_| Msg::SourceInput(i));
                 html! {
                     <div class="row mb-1" key={*key}>
                         <div class="col-4 col-sm-3 col-md-2">
-                            <select ref={source_ref} class="form-select">
+                            <select ref={source_ref} class="form-select" {onchange}>
                                 <option selected={selected[0]}>{"Custom"}</option>
                                 <option selected={selected[1]}>{"Spotify"}</option>
                                 <option selected={selected[2]}>{"Setlist"}</option>
                                 <option selected={selected[3]}>{"List Items"}</option>
+                                <option selected={selected[4]}>{"Scrobbles"}</option>
+                                <option selected={selected[5]}>{"Setlists"}</option>
+                                <option selected={selected[6]}>{"Import"}</option>
+                                <option selected={selected[7]}>{"Local Library"}</option>
+                                <option selected={selected[8]}>{"Composite"}</option>
+                                <option selected={selected[9]}>{"Filter"}</option>
                             </select>
                         </div>
-                        <input class="col-9 col-sm-7 col-md-8" ref={id}/>
+                        <input class="col-9 col-sm-7 col-md-8" ref={id} {oninput}/>
                         <div class="col-auto">
                             <button type="button" class="btn btn-danger" {onclick}>{"Delete"}</button>
                         </div>
+                        {match &self.composites[i] {
+                            Some(Ok(composite)) => html! {
+                                <ul class="offset-4 offset-sm-3 offset-md-2 col mt-1 mb-0 small">
+                                    {expression_tree(&SourceType::Composite(composite.clone()))}
+                                </ul>
+                            },
+                            Some(Err(e)) => html! {
+                                <div class="offset-4 offset-sm-3 offset-md-2 col form-text text-danger">{e}</div>
+                            },
+                            None => html! {},
+                        }}
                     </div>
                 }
             });
@@ -260,6 +424,27 @@ impl Component for Edit {
                         <input class="form-control" id="query" ref={&self.query_ref} placeholder="External ID"/>
                         <label for="query">{"Query"}</label>
                     </div>
+                    <div class="form-floating mb-3">
+                        <select class="form-select" id="sort" ref={&self.sort_ref}>
+                            {for [SortMode::Query, SortMode::Score, SortMode::Rank, SortMode::Rating, SortMode::Manual].into_iter().map(|sort| html! {
+                                <option value={sort.id()} selected={sort == self.list.sort}>{sort.name()}</option>
+                            })}
+                        </select>
+                        <label for="sort">{"Sort by"}</label>
+                    </div>
+                    <div class="form-floating mb-3">
+                        <input class="form-control" id="tiers" ref={&self.tiers_ref} placeholder="Tiers"/>
+                        <label for="tiers">{"Tiers"}</label>
+                    </div>
+                    <div class="form-floating mb-3">
+                        <select class="form-select" id="ratingScale" ref={&self.rating_scale_ref}>
+                            <option value="" selected={self.list.rating_scale.is_none()}>{"Item type default"}</option>
+                            {for [RatingScale::Ten, RatingScale::HalfStars, RatingScale::Hundred, RatingScale::LikeDislike].into_iter().map(|scale| html! {
+                                <option value={scale.id()} selected={Some(scale) == self.list.rating_scale}>{scale.name()}</option>
+                            })}
+                        </select>
+                        <label for="ratingScale">{"Rating scale"}</label>
+                    </div>
                     <div class="form-check">
                         <label class="form-check-label" for="favorite">{"Favorite"}</label>
                         <input ref={&self.favorite_ref} class="form-check-input" type="checkbox" id="favorite"/>
@@ -277,6 +462,9 @@ impl Component for Edit {
                     <button type="button" class="btn btn-primary" onclick={add_source}>{"Add source"}</button>
                 </div>
                 <hr/>
+                if let Some(e) = &self.error {
+                    <div class="alert alert-danger" role="alert">{e}</div>
+                }
                 <button type="button" class="btn btn-success mb-3" onclick={save} {disabled}>{"Save all settings"}</button>
                 <div class="d-flex gap-3">
                     <button type="button" class="btn btn-danger" onclick={delete} {disabled}>{"Delete"}</button>
@@ -306,6 +494,10 @@ impl Component for Edit {
                 .cast::<HtmlInputElement>()
                 .unwrap()
                 .set_value(&self.list.query);
+            self.tiers_ref
+                .cast::<HtmlInputElement>()
+                .unwrap()
+                .set_value(&self.list.tiers.join(", "));
             if self.list.favorite {
                 self.favorite_ref
                     .cast::<HtmlInputElement>()
@@ -329,9 +521,30 @@ impl Component for Edit {
                     ) => raw_id.clone(),
                     Some(SourceType::Setlist(Id { raw_id, .. })) => raw_id.clone(),
                     Some(SourceType::ListItems(id)) => id.clone(),
+                    Some(SourceType::Scrobbles(scrobbles)) => scrobbles.to_string(),
+                    Some(SourceType::Setlists(setlists)) => setlists.to_string(),
+                    Some(SourceType::Import(format)) => format.id().to_owned(),
+                    Some(SourceType::LocalLibrary(path)) => path.clone(),
+                    Some(SourceType::Composite(composite)) => composite.to_string(),
+                    Some(SourceType::Query(filter)) => filter.clone(),
                 };
                 id.cast::<HtmlInputElement>().unwrap().set_value(&value);
             }
         }
     }
 }
+
+/// Show an operation with its operands nested under it
+fn expression_tree(source: &SourceType) -> Html {
+    match source {
+        SourceType::Composite(composite) => html! {
+            <li>
+                {composite.operation.name()}
+                <ul>{for composite.operands.iter().map(expression_tree)}</ul>
+            </li>
+        },
+        source => html! {
+            <li><code>{composite::format_operand(source)}</code></li>
+        },
+    }
+}
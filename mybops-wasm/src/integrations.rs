@@ -1 +1,3 @@
+pub mod imports;
+pub mod scrobbles;
 pub mod spotify;
@@ -0,0 +1,125 @@
+use crate::{ListsRoute, UserProps, bootstrap::Alert};
+use mybops::List;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{File, HtmlInputElement, HtmlSelectElement, Request, RequestInit, Response};
+use yew::{Component, Context, Html, NodeRef, html};
+use yew_router::prelude::Link;
+
+pub enum Msg {
+    Upload,
+    Uploaded(Result<Box<List>, String>),
+    HideAlert,
+}
+
+pub struct ImportsIntegration {
+    format_ref: NodeRef,
+    file_ref: NodeRef,
+    list: Option<List>,
+    alert: Option<Result<String, String>>,
+}
+
+impl Component for ImportsIntegration {
+    type Message = Msg;
+    type Properties = UserProps;
+
+    fn create(_: &Context<Self>) -> Self {
+        ImportsIntegration {
+            format_ref: NodeRef::default(),
+            file_ref: NodeRef::default(),
+            list: None,
+            alert: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Upload => {
+                let Some(file) = self
+                    .file_ref
+                    .cast::<HtmlInputElement>()
+                    .unwrap()
+                    .files()
+                    .and_then(|files| files.get(0))
+                else {
+                    return false;
+                };
+                let format = self.format_ref.cast::<HtmlSelectElement>().unwrap().value();
+                self.list = None;
+                self.alert = Some(Ok(format!("Importing {}", file.name())));
+                ctx.link().send_future(async move {
+                    Msg::Uploaded(
+                        upload_export(&format, &file)
+                            .await
+                            .map(Box::new)
+                            .map_err(|e| e.as_string().unwrap_or_default()),
+                    )
+                });
+            }
+            Msg::Uploaded(Ok(list)) => {
+                self.alert = Some(Ok(format!(
+                    "Updated {} with {} items",
+                    list.name,
+                    list.items.len()
+                )));
+                self.list = Some(*list);
+            }
+            Msg::Uploaded(Err(e)) => self.alert = Some(Err(e)),
+            Msg::HideAlert => self.alert = None,
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        crate::nav_content(
+            html! {
+              <ul class="navbar-nav me-auto">
+                <li class="navbar-brand">{"File Imports"}</li>
+              </ul>
+            },
+            html! {
+              <div style="max-width: 800px">
+                <p>{"Import films from a Letterboxd diary, ratings or watched CSV, books from a Goodreads library export,
+                    board games from a BoardGameGeek collection or thing XML or video games from a JSON array of IGDB games.
+                    Films and books are rated with half stars and IGDB games can include a user_rating out of 100.
+                    Upload a newer export at any time to add new items and update ratings without losing your scores."}</p>
+                if ctx.props().logged_in {
+                  if let Some(result) = &self.alert {
+                    <Alert result={result.clone()} hide={ctx.link().callback(|_| Msg::HideAlert)}/>
+                  }
+                  if let Some(list) = &self.list {
+                    <p><Link<ListsRoute> to={ListsRoute::View { id: list.id.clone() }}>{&list.name}</Link<ListsRoute>></p>
+                  }
+                  <form class="d-flex flex-wrap gap-2">
+                    <select ref={&self.format_ref} class="form-select w-auto">
+                      <option value="letterboxd" selected=true>{"Letterboxd"}</option>
+                      <option value="goodreads">{"Goodreads"}</option>
+                      <option value="bgg">{"BoardGameGeek"}</option>
+                      <option value="igdb">{"IGDB"}</option>
+                    </select>
+                    <input ref={&self.file_ref} type="file" class="form-control w-auto" accept=".csv,.xml,.json"/>
+                    <button type="button" class="btn btn-primary" onclick={ctx.link().callback(|_| Msg::Upload)}>{"Upload"}</button>
+                  </form>
+                } else {
+                  <p>{"Create an account to import your films, books and games"}</p>
+                }
+              </div>
+            },
+        )
+    }
+}
+
+async fn upload_export(format: &str, file: &File) -> Result<List, JsValue> {
+    let window = crate::window();
+    let opts = RequestInit::new();
+    opts.set_method("POST");
+    opts.set_body(file);
+    let request = Request::new_with_str_and_init(&format!("/api/imports?format={format}"), &opts)?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if ![200, 201].contains(&resp.status()) {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
@@ -0,0 +1,137 @@
+use crate::{ListsRoute, UserProps, bootstrap::Alert};
+use mybops::List;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{File, HtmlInputElement, HtmlSelectElement, Request, RequestInit, Response};
+use yew::{Component, Context, Html, NodeRef, html};
+use yew_router::prelude::Link;
+
+pub enum Msg {
+    Upload,
+    Uploaded(Result<Box<List>, String>),
+    HideAlert,
+}
+
+pub struct ScrobblesIntegration {
+    service_ref: NodeRef,
+    type_ref: NodeRef,
+    file_ref: NodeRef,
+    list: Option<List>,
+    alert: Option<Result<String, String>>,
+}
+
+impl Component for ScrobblesIntegration {
+    type Message = Msg;
+    type Properties = UserProps;
+
+    fn create(_: &Context<Self>) -> Self {
+        ScrobblesIntegration {
+            service_ref: NodeRef::default(),
+            type_ref: NodeRef::default(),
+            file_ref: NodeRef::default(),
+            list: None,
+            alert: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Upload => {
+                let Some(file) = self
+                    .file_ref
+                    .cast::<HtmlInputElement>()
+                    .unwrap()
+                    .files()
+                    .and_then(|files| files.get(0))
+                else {
+                    return false;
+                };
+                let service = self
+                    .service_ref
+                    .cast::<HtmlSelectElement>()
+                    .unwrap()
+                    .value();
+                let kind = self.type_ref.cast::<HtmlSelectElement>().unwrap().value();
+                self.list = None;
+                self.alert = Some(Ok(format!("Matching {} to Spotify", file.name())));
+                ctx.link().send_future(async move {
+                    Msg::Uploaded(
+                        upload_export(&service, &kind, &file)
+                            .await
+                            .map(Box::new)
+                            .map_err(|e| e.as_string().unwrap_or_default()),
+                    )
+                });
+            }
+            Msg::Uploaded(Ok(list)) => {
+                self.alert = Some(Ok(format!(
+                    "Created {} with {} items",
+                    list.name,
+                    list.items.len()
+                )));
+                self.list = Some(*list);
+            }
+            Msg::Uploaded(Err(e)) => self.alert = Some(Err(e)),
+            Msg::HideAlert => self.alert = None,
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        crate::nav_content(
+            html! {
+              <ul class="navbar-nav me-auto">
+                <li class="navbar-brand">{"Last.fm and ListenBrainz"}</li>
+              </ul>
+            },
+            html! {
+              <div style="max-width: 800px">
+                <p>{"Add a Scrobbles data source to a list to keep it in sync with your top tracks, albums or artists.
+                    You can also create a list from an export file.
+                    Last.fm exports are CSV files with artist, album and track columns and ListenBrainz exports are JSON files of listens."}</p>
+                if ctx.props().logged_in {
+                  if let Some(result) = &self.alert {
+                    <Alert result={result.clone()} hide={ctx.link().callback(|_| Msg::HideAlert)}/>
+                  }
+                  if let Some(list) = &self.list {
+                    <p><Link<ListsRoute> to={ListsRoute::View { id: list.id.clone() }}>{&list.name}</Link<ListsRoute>></p>
+                  }
+                  <form class="d-flex flex-wrap gap-2">
+                    <select ref={&self.service_ref} class="form-select w-auto">
+                      <option value="lastfm" selected=true>{"Last.fm"}</option>
+                      <option value="listenbrainz">{"ListenBrainz"}</option>
+                    </select>
+                    <select ref={&self.type_ref} class="form-select w-auto">
+                      <option value="tracks" selected=true>{"Tracks"}</option>
+                      <option value="albums">{"Albums"}</option>
+                      <option value="artists">{"Artists"}</option>
+                    </select>
+                    <input ref={&self.file_ref} type="file" class="form-control w-auto" accept=".csv,.json,.jsonl"/>
+                    <button type="button" class="btn btn-primary" onclick={ctx.link().callback(|_| Msg::Upload)}>{"Upload"}</button>
+                  </form>
+                } else {
+                  <p>{"Create an account to import your listening history"}</p>
+                }
+              </div>
+            },
+        )
+    }
+}
+
+async fn upload_export(service: &str, kind: &str, file: &File) -> Result<List, JsValue> {
+    let window = crate::window();
+    let opts = RequestInit::new();
+    opts.set_method("POST");
+    opts.set_body(file);
+    let request = Request::new_with_str_and_init(
+        &format!("/api/scrobbles?service={service}&type={kind}"),
+        &opts,
+    )?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if resp.status() != 201 {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
@@ -1,25 +1,49 @@
-use crate::{UserProps, bootstrap::Accordion};
+use crate::{
+    UserProps,
+    bootstrap::{Accordion, Alert},
+};
 use mybops::{
-    Spotify,
-    spotify::{Playlists, RecentTracks},
+    List, ListMode, Source, SourceType, Spotify,
+    spotify::{HistorySummary, Playlists, RecentTracks, SearchPage, SearchResults},
 };
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{HtmlSelectElement, Response};
+use web_sys::{File, HtmlInputElement, HtmlSelectElement, Request, RequestInit, Response};
 use yew::{Component, Context, Html, NodeRef, html};
 
+const SEARCH_LIMIT: usize = 20;
+
 pub enum Msg {
     None,
     LoadRecentTracks(RecentTracks),
     LoadPlaylists(Playlists),
     Import,
     ImportTrack(String),
+    LoadLists(Vec<List>),
+    Search,
+    SearchPage(usize),
+    LoadSearchResults(Result<SearchResults, String>),
+    Add(String, String),
+    Added(Result<String, String>),
+    HideAlert,
+    UploadHistory,
+    HistoryUploaded(Result<String, String>),
+    HideHistoryAlert,
 }
 
 pub struct SpotifyIntegration {
     import_ref: NodeRef,
     recent_tracks: Option<RecentTracks>,
     playlists: Option<Playlists>,
+    search_ref: NodeRef,
+    search_type_ref: NodeRef,
+    list_ref: NodeRef,
+    lists: Vec<List>,
+    search: Option<(String, String, usize)>,
+    search_results: Option<SearchResults>,
+    alert: Option<Result<String, String>>,
+    history_ref: NodeRef,
+    history_alert: Option<Result<String, String>>,
 }
 
 impl Component for SpotifyIntegration {
@@ -32,11 +56,23 @@ impl Component for SpotifyIntegration {
                 .send_future(async { Msg::LoadRecentTracks(get_recent_tracks().await.unwrap()) });
             ctx.link()
                 .send_future(async { Msg::LoadPlaylists(get_playlists().await.unwrap()) });
+            ctx.link().send_future(async {
+                Msg::LoadLists(crate::fetch_lists(false).await.unwrap_or_default())
+            });
         }
         SpotifyIntegration {
             import_ref: NodeRef::default(),
             recent_tracks: None,
             playlists: None,
+            search_ref: NodeRef::default(),
+            search_type_ref: NodeRef::default(),
+            list_ref: NodeRef::default(),
+            lists: Vec::new(),
+            search: None,
+            search_results: None,
+            alert: None,
+            history_ref: NodeRef::default(),
+            history_alert: None,
         }
     }
 
@@ -75,6 +111,101 @@ impl Component for SpotifyIntegration {
                     Msg::None
                 });
             }
+            Msg::LoadLists(lists) => {
+                // Only user lists can have sources added to them
+                self.lists = lists
+                    .into_iter()
+                    .filter(|list| matches!(list.mode, ListMode::User(_)))
+                    .collect();
+            }
+            Msg::Search => {
+                let query = self.search_ref.cast::<HtmlInputElement>().unwrap().value();
+                let search_type = self
+                    .search_type_ref
+                    .cast::<HtmlSelectElement>()
+                    .unwrap()
+                    .value();
+                self.search = Some((query, search_type, 0));
+                self.search_results = None;
+                ctx.link().send_message(Msg::SearchPage(0));
+                return false;
+            }
+            Msg::SearchPage(offset) => {
+                let Some((query, search_type, current_offset)) = &mut self.search else {
+                    return false;
+                };
+                *current_offset = offset;
+                let query = query.clone();
+                let search_type = search_type.clone();
+                ctx.link().send_future(async move {
+                    Msg::LoadSearchResults(
+                        search(&query, &search_type, offset)
+                            .await
+                            .map_err(|e| e.as_string().unwrap_or_default()),
+                    )
+                });
+                return false;
+            }
+            Msg::LoadSearchResults(Ok(results)) => self.search_results = Some(results),
+            Msg::LoadSearchResults(Err(e)) => self.alert = Some(Err(e)),
+            Msg::Add(name, url) => {
+                let Some(spotify) = crate::parse_spotify_source(url) else {
+                    return false;
+                };
+                let list_id = self.list_ref.cast::<HtmlSelectElement>().unwrap().value();
+                ctx.link().send_future(async move {
+                    Msg::Added(
+                        add_source(&list_id, name, spotify)
+                            .await
+                            .map_err(|e| e.as_string().unwrap_or_default()),
+                    )
+                });
+                return false;
+            }
+            Msg::Added(result) => {
+                // Refresh the current page so that added results are marked
+                if result.is_ok()
+                    && let Some((_, _, offset)) = self.search
+                {
+                    ctx.link().send_message(Msg::SearchPage(offset));
+                }
+                self.alert = Some(result);
+            }
+            Msg::HideAlert => self.alert = None,
+            Msg::UploadHistory => {
+                let Some(files) = self.history_ref.cast::<HtmlInputElement>().unwrap().files()
+                else {
+                    return false;
+                };
+                let files: Vec<_> = (0..files.length()).filter_map(|i| files.get(i)).collect();
+                if files.is_empty() {
+                    return false;
+                }
+                self.history_alert = Some(Ok(format!("Uploading {} files", files.len())));
+                ctx.link().send_future(async move {
+                    let mut plays = 0;
+                    let mut items = 0;
+                    // Upload files one at a time as each file can be large
+                    for file in files {
+                        match upload_history(&file).await {
+                            Ok(summary) => {
+                                plays += summary.plays;
+                                items += summary.items;
+                            }
+                            Err(e) => {
+                                return Msg::HistoryUploaded(Err(format!(
+                                    "{}: {}",
+                                    file.name(),
+                                    e.as_string().unwrap_or_default()
+                                )));
+                            }
+                        }
+                    }
+                    Msg::HistoryUploaded(Ok(format!("Imported {plays} plays for {items} tracks")))
+                });
+            }
+            Msg::HistoryUploaded(result) => self.history_alert = Some(result),
+            Msg::HideHistoryAlert => self.history_alert = None,
         }
         true
     }
@@ -107,6 +238,10 @@ impl Component for SpotifyIntegration {
             Vec::new()
         };
         let import = ctx.link().callback(|_| Msg::Import);
+        let search = ctx.link().callback(|e: web_sys::SubmitEvent| {
+            e.prevent_default();
+            Msg::Search
+        });
         crate::nav_content(
             html! {
               <ul class="navbar-nav me-auto">
@@ -127,6 +262,36 @@ impl Component for SpotifyIntegration {
                     <p>{"Create an account to view and import tracks that were recently played in Spotify"}</p>
                   }
                 </Accordion>
+                <Accordion header={"Search"} collapsed={false}>
+                  if ctx.props().logged_in {
+                    if let Some(result) = &self.alert {
+                      <Alert result={result.clone()} hide={ctx.link().callback(|_| Msg::HideAlert)}/>
+                    }
+                    <form class="d-flex gap-2 mb-3" onsubmit={search}>
+                      <input ref={&self.search_ref} type="search" class="form-control" placeholder="Search Spotify"/>
+                      <select ref={&self.search_type_ref} class="form-select w-auto">
+                        <option value="track" selected=true>{"Tracks"}</option>
+                        <option value="album">{"Albums"}</option>
+                        <option value="artist">{"Artists"}</option>
+                        <option value="playlist">{"Playlists"}</option>
+                      </select>
+                      <button type="submit" class="btn btn-primary">{"Search"}</button>
+                    </form>
+                    <div class="d-flex gap-2 align-items-center mb-3">
+                      <label for="search-list" class="text-nowrap">{"Add to"}</label>
+                      <select ref={&self.list_ref} id="search-list" class="form-select w-auto">
+                        {for self.lists.iter().map(|list| html! {
+                          <option value={list.id.clone()}>{&list.name}</option>
+                        })}
+                      </select>
+                    </div>
+                    if let Some(page) = self.search_results.as_ref().and_then(search_page) {
+                      {self.view_search_page(ctx, page)}
+                    }
+                  } else {
+                    <p>{"Create an account to search Spotify and add results to your lists"}</p>
+                  }
+                </Accordion>
                 <Accordion header={"Saved Playlists"} collapsed={false}>
                   if ctx.props().logged_in {
                     if let Some(playlists) = &self.playlists {
@@ -136,6 +301,20 @@ impl Component for SpotifyIntegration {
                     <p>{"Create an account to import playlists from Spotify"}</p>
                   }
                 </Accordion>
+                <Accordion header={"Streaming History"} collapsed={true}>
+                  if ctx.props().logged_in {
+                    <p>{"Upload the Streaming_History_Audio JSON files from a Spotify extended streaming history export to add play counts and listening time to tracks. Uploading the same file again won't double count plays."}</p>
+                    if let Some(result) = &self.history_alert {
+                      <Alert result={result.clone()} hide={ctx.link().callback(|_| Msg::HideHistoryAlert)}/>
+                    }
+                    <div class="d-flex gap-2 mb-3">
+                      <input ref={&self.history_ref} type="file" class="form-control" accept=".json,application/json" multiple=true/>
+                      <button type="button" class="btn btn-primary" onclick={ctx.link().callback(|_| Msg::UploadHistory)}>{"Upload"}</button>
+                    </div>
+                  } else {
+                    <p>{"Create an account to import your Spotify listening history"}</p>
+                  }
+                </Accordion>
                 <h2>{"Import from Spotify link"}</h2>
                 <form>
                   <div class="row">
@@ -153,6 +332,109 @@ impl Component for SpotifyIntegration {
     }
 }
 
+impl SpotifyIntegration {
+    fn view_search_page(&self, ctx: &Context<Self>, page: &SearchPage) -> Html {
+        let offset = page.offset;
+        let has_next = page.offset + page.limit < page.total.min(1000);
+        html! {
+          <>
+            <div class="row">
+              <div class="col"></div>
+              <div class="col-1"><strong>{"Rating"}</strong></div>
+              <div class="col-1"><strong>{"User Score"}</strong></div>
+            </div>
+            {for page.items.iter().map(|result| {
+                // Artists can't be used as a list source
+                let addable = !result.uri.starts_with("spotify:artist:") && !self.lists.is_empty();
+                let name = result.name.clone();
+                let url = result.url.clone();
+                let add = ctx.link().callback(move |_| Msg::Add(name.clone(), url.clone()));
+                html! {
+                  <div class="row mb-1">
+                    <div class="col">
+                      <a href={result.url.clone()}>{&result.name}</a>
+                      if !result.description.is_empty() {
+                        <span class="text-muted ms-2">{&result.description}</span>
+                      }
+                      if result.added {
+                        <span class="badge text-bg-secondary ms-2">{"Added"}</span>
+                      }
+                      if addable {
+                        <button type="button" class="btn btn-sm btn-success ms-2" onclick={add}>{"Add"}</button>
+                      }
+                    </div>
+                    <div class="col-1">{result.rating}</div>
+                    <div class="col-1">{result.user_score}</div>
+                  </div>
+                }
+            })}
+            <div class="d-flex gap-2 align-items-center">
+              <button type="button" class="btn btn-outline-secondary" disabled={offset == 0} onclick={ctx.link().callback(move |_| Msg::SearchPage(offset.saturating_sub(SEARCH_LIMIT)))}>{"Previous"}</button>
+              <span>{format!("{}-{} of {}", (offset + 1).min(page.total), offset + page.items.len(), page.total)}</span>
+              <button type="button" class="btn btn-outline-secondary" disabled={!has_next} onclick={ctx.link().callback(move |_| Msg::SearchPage(offset + SEARCH_LIMIT))}>{"Next"}</button>
+            </div>
+          </>
+        }
+    }
+}
+
+fn search_page(results: &SearchResults) -> Option<&SearchPage> {
+    results
+        .tracks
+        .as_ref()
+        .or(results.albums.as_ref())
+        .or(results.artists.as_ref())
+        .or(results.playlists.as_ref())
+}
+
+async fn add_source(list_id: &str, name: String, spotify: Spotify) -> Result<String, JsValue> {
+    let Some(mut list) = crate::fetch_list(list_id).await? else {
+        return Err(JsValue::from_str("List no longer exists"));
+    };
+    let message = format!("Added {name} to {}", list.name);
+    list.sources.push(Source {
+        source_type: SourceType::Spotify(spotify),
+        name,
+        unmatched: Vec::new(),
+    });
+    crate::update_list(&list).await?;
+    Ok(message)
+}
+
+async fn search(query: &str, search_type: &str, offset: usize) -> Result<SearchResults, JsValue> {
+    let window = crate::window();
+    let request = crate::query(
+        &format!(
+            "/api/spotify/search?q={}&type={search_type}&offset={offset}&limit={SEARCH_LIMIT}",
+            js_sys::encode_uri_component(query)
+        ),
+        "GET",
+    )?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if resp.status() != 200 {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
+
+async fn upload_history(file: &File) -> Result<HistorySummary, JsValue> {
+    let window = crate::window();
+    let opts = RequestInit::new();
+    opts.set_method("POST");
+    opts.set_body(file);
+    let request = Request::new_with_str_and_init("/api/spotify/history", &opts)?;
+    request.headers().set("Content-Type", "application/json")?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if resp.status() != 200 {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
+
 async fn get_recent_tracks() -> Result<RecentTracks, JsValue> {
     let window = crate::window();
     let request = crate::query("/api/spotify/recentTracks", "GET").unwrap();
@@ -2,9 +2,15 @@
 use crate::{app::App, dataframe::DataFrame};
 use arrow::array::AsArray;
 use js_sys::Uint8Array;
-use mybops::{Id, Items, List, ListMode, Lists, Spotify, User};
+use mybops::{
+    Credentials, DuplicateGroup, Id, IdentifySummary, Items, List, ListMode, Lists, LoginProvider,
+    MergeItems, PasswordChange, SessionInfo, Spotify, TasteComparison, User,
+};
 use regex::Regex;
-use std::{collections::HashSet, io::Cursor};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+};
 use wasm_bindgen::{JsCast, prelude::*};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{Request, RequestInit, RequestMode, Response, Window};
@@ -14,8 +20,10 @@ use yew_router::Routable;
 mod app;
 mod base;
 mod bootstrap;
+mod compare;
 mod dataframe;
 mod docs;
+mod duplicates;
 mod edit;
 mod home;
 mod integrations;
@@ -24,6 +32,7 @@ mod plot;
 mod random;
 mod search;
 mod settings;
+mod tier;
 pub mod tournament;
 
 #[derive(Clone, Routable, PartialEq)]
@@ -38,10 +47,16 @@ enum Route {
     Lists,
     #[at("/search")]
     Search,
+    #[at("/compare")]
+    Compare,
     #[at("/settings")]
     Settings,
     #[at("/integrations/spotify")]
     Spotify,
+    #[at("/integrations/scrobbles")]
+    Scrobbles,
+    #[at("/integrations/imports")]
+    Imports,
 }
 
 #[derive(Clone, Routable, PartialEq)]
@@ -56,6 +71,8 @@ pub enum ListsRoute {
     Match { id: String },
     #[at("/lists/:id/tournament")]
     Tournament { id: String },
+    #[at("/lists/:id/tiers")]
+    Tiers { id: String },
 }
 
 #[derive(Eq, PartialEq, Properties)]
@@ -226,15 +243,12 @@ async fn create_list(query: Option<String>) -> Result<List, JsValue> {
 }
 
 async fn update_list(list: &List) -> Result<(), JsValue> {
-    let window = window();
-    let opts = RequestInit::new();
-    opts.set_method("PUT");
-    opts.set_mode(RequestMode::Cors);
-    opts.set_body(&JsValue::from_str(&serde_json::to_string(&list).unwrap()));
-    let request = Request::new_with_str_and_init(&format!("/api/lists/{}", list.id), &opts)?;
-    request.headers().set("Content-Type", "application/json")?;
-    JsFuture::from(window.fetch_with_request(&request)).await?;
-    Ok(())
+    send_json(
+        &format!("/api/lists/{}", list.id),
+        "PUT",
+        serde_json::to_string(&list).unwrap(),
+    )
+    .await
 }
 
 async fn delete_list(id: &str) -> Result<(), JsValue> {
@@ -305,6 +319,56 @@ async fn push_list(id: &str) -> Result<(), JsValue> {
     Ok(())
 }
 
+async fn sync_list(id: &str) -> Result<(), JsValue> {
+    let window = window();
+    let request = query(&format!("/api/?action=sync&list={}", id), "POST")?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if [400, 404, 500].contains(&resp.status()) {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    Ok(())
+}
+
+async fn reorder_list(id: &str, ids: &[String]) -> Result<(), JsValue> {
+    send_json(
+        &format!("/api/?action=reorder&list={}", id),
+        "POST",
+        serde_json::to_string(ids).unwrap(),
+    )
+    .await
+}
+
+async fn set_tiers(id: &str, tiers: &HashMap<String, String>) -> Result<(), JsValue> {
+    send_json(
+        &format!("/api/?action=tiers&list={}", id),
+        "POST",
+        serde_json::to_string(tiers).unwrap(),
+    )
+    .await
+}
+
+async fn refresh_list(id: &str) -> Result<(), JsValue> {
+    let window = window();
+    let request = query(&format!("/api/?action=refresh&list={}", id), "POST")?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if [400, 404, 500].contains(&resp.status()) {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    Ok(())
+}
+
+async fn resolve_conflict(list: &str, item: &str) -> Result<(), JsValue> {
+    let window = window();
+    let request = query(
+        &format!("/api/?action=resolve&list={list}&item={item}"),
+        "POST",
+    )?;
+    JsFuture::from(window.fetch_with_request(&request)).await?;
+    Ok(())
+}
+
 async fn import_list(source: &str, id: &str) -> Result<(), JsValue> {
     let window = window();
     let request = query(
@@ -342,6 +406,54 @@ async fn delete_items(ids: &[String]) -> Result<(), JsValue> {
     Ok(())
 }
 
+async fn get_duplicates() -> Result<Vec<DuplicateGroup>, JsValue> {
+    let window = window();
+    let request = query("/api/items/duplicates", "GET")?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if [401, 500].contains(&resp.status()) {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
+
+async fn get_comparison(user: &str) -> Result<TasteComparison, JsValue> {
+    let window = window();
+    let request = query(
+        &format!("/api/compare?user={}", js_sys::encode_uri_component(user)),
+        "GET",
+    )?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if [400, 401, 500].contains(&resp.status()) {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
+
+async fn merge_items(merge: &MergeItems) -> Result<(), JsValue> {
+    send_json(
+        "/api/items/merge",
+        "POST",
+        serde_json::to_string(merge).unwrap(),
+    )
+    .await
+}
+
+async fn identify_items() -> Result<IdentifySummary, JsValue> {
+    let window = window();
+    let request = query("/api/items/identify", "POST")?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if [401, 500].contains(&resp.status()) {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
+
 fn query(url: &str, method: &str) -> Result<Request, JsValue> {
     let opts = RequestInit::new();
     opts.set_method(method);
@@ -358,6 +470,93 @@ async fn get_user() -> Result<User, JsValue> {
     Ok(serde_wasm_bindgen::from_value(json).unwrap())
 }
 
+async fn get_login_providers() -> Result<Vec<LoginProvider>, JsValue> {
+    let window = window();
+    let request = query("/api/login/providers", "GET")?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
+
+async fn get_sessions() -> Result<Vec<SessionInfo>, JsValue> {
+    let window = window();
+    let request = query("/api/sessions", "GET")?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json).unwrap())
+}
+
+async fn revoke_session(id: &str) -> Result<(), JsValue> {
+    send(query(&format!("/api/sessions/{id}"), "DELETE")?).await
+}
+
+async fn revoke_other_sessions() -> Result<(), JsValue> {
+    send(query("/api/sessions", "DELETE")?).await
+}
+
+async fn signup(credentials: &Credentials) -> Result<(), JsValue> {
+    send_json(
+        "/api/signup",
+        "POST",
+        serde_json::to_string(credentials).unwrap(),
+    )
+    .await
+}
+
+async fn password_login(credentials: &Credentials) -> Result<(), JsValue> {
+    send_json(
+        "/api/login/password",
+        "POST",
+        serde_json::to_string(credentials).unwrap(),
+    )
+    .await
+}
+
+async fn change_password(change: &PasswordChange) -> Result<(), JsValue> {
+    send_json(
+        "/api/user/password",
+        "PUT",
+        serde_json::to_string(change).unwrap(),
+    )
+    .await
+}
+
+async fn unlink_login(provider: &str) -> Result<(), JsValue> {
+    send(query(&format!("/api/user/logins/{provider}"), "DELETE")?).await
+}
+
+async fn delete_user(confirm: &str) -> Result<(), JsValue> {
+    send(query(
+        &format!(
+            "/api/user?confirm={}",
+            js_sys::encode_uri_component(confirm)
+        ),
+        "DELETE",
+    )?)
+    .await
+}
+
+async fn send(request: Request) -> Result<(), JsValue> {
+    let resp_value = JsFuture::from(window().fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if [400, 401, 500].contains(&resp.status()) {
+        return Err(JsFuture::from(resp.text()?).await?);
+    }
+    Ok(())
+}
+
+async fn send_json(url: &str, method: &str, body: String) -> Result<(), JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method(method);
+    opts.set_mode(RequestMode::Cors);
+    opts.set_body(&JsValue::from_str(&body));
+    let request = Request::new_with_str_and_init(url, &opts)?;
+    request.headers().set("Content-Type", "application/json")?;
+    send(request).await
+}
+
 fn user_list(list: &List, user: &Option<User>) -> bool {
     Some(&list.user_id) == user.as_ref().as_ref().map(|u| &u.user_id)
         || (user.is_none() && list.user_id == "demo")
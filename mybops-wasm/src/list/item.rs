@@ -3,9 +3,15 @@ use crate::{
     bootstrap::{Alert, Modal},
     dataframe::DataFrame,
 };
-use arrow::{array::AsArray, datatypes::UInt64Type};
+use arrow::{
+    array::{Array, AsArray},
+    datatypes::UInt64Type,
+};
 use js_sys::Error;
-use mybops::{Id, ItemMetadata, List, ListMode, SourceType, Spotify, User};
+use mybops::{
+    Id, ItemMetadata, List, ListMode, RatingScale, SortMode, SourceType, Spotify, SyncConflict,
+    User,
+};
 use serde_json::Value;
 use std::{collections::HashMap, rc::Rc};
 use wasm_bindgen::{JsCast, JsValue};
@@ -13,7 +19,7 @@ use wasm_bindgen_futures::JsFuture;
 use web_sys::{
     HtmlInputElement, HtmlSelectElement, Request, RequestInit, RequestMode, Response, Url,
 };
-use yew::{Callback, Component, Context, Html, NodeRef, Properties, html};
+use yew::{Callback, Component, Context, DragEvent, Html, NodeRef, Properties, html};
 use yew_router::prelude::Link;
 
 pub enum Msg {
@@ -25,12 +31,21 @@ pub enum Msg {
     HideAlert,
     SaveSuccess(Vec<(usize, HashMap<String, Value>)>),
     Push,
+    Export,
+    Sync,
+    SyncError(String),
+    Refresh,
+    Resolve(String),
+    ResolveSuccess(String),
     Open(usize),
     ModalBack,
     ModalForward,
     HideModal,
     Delete((String, usize)),
     DeleteSuccess(usize),
+    DragStart(usize),
+    Drop(usize),
+    ReorderError(String),
 }
 
 #[derive(PartialEq, Properties)]
@@ -46,6 +61,12 @@ pub struct ListItems {
     state: Option<Vec<State>>,
     alert: Option<Result<String, String>>,
     modal: Option<usize>,
+    conflicts: Vec<SyncConflict>,
+    sync_error: Option<String>,
+    export_format_ref: NodeRef,
+    export_order_ref: NodeRef,
+    /// Index of the item being dragged in manually sorted lists
+    dragging: Option<usize>,
 }
 
 struct ListItem {
@@ -55,8 +76,10 @@ struct ListItem {
 
 #[derive(Clone, Default)]
 struct State {
+    /// Rating on the scale the item is shown with
     rating: Option<u64>,
     hidden: bool,
+    scale: RatingScale,
 }
 
 #[derive(Clone, PartialEq)]
@@ -77,7 +100,7 @@ impl Component for ListItems {
                 Msg::Load(
                     crate::query_list(
                         &list,
-                        Some("SELECT id, rating, hidden FROM item".to_owned()),
+                        Some("SELECT id, rating, rating_scale, type, hidden FROM item".to_owned()),
                     )
                     .await
                     .unwrap(),
@@ -99,6 +122,17 @@ impl Component for ListItems {
             state: None,
             alert: None,
             modal: None,
+            conflicts: ctx
+                .props()
+                .list
+                .sync
+                .as_ref()
+                .map(|sync| sync.conflicts.clone())
+                .unwrap_or_default(),
+            sync_error: None,
+            export_format_ref: NodeRef::default(),
+            export_order_ref: NodeRef::default(),
+            dragging: None,
         }
     }
 
@@ -126,12 +160,32 @@ impl Component for ListItems {
                 } else {
                     vec![None; query.column("rating").unwrap().len()]
                 };
+                let rating_scales = query
+                    .column("rating_scale")
+                    .unwrap()
+                    .as_string_opt::<i64>()
+                    .map(|scales| scales.iter().collect())
+                    .unwrap_or_else(|| vec![None; ids.len()]);
+                let types = query.column("type").unwrap().as_string::<i64>();
                 let hidden = query.column("hidden").unwrap().as_boolean();
                 let mut state = vec![State::default(); self.items.len()];
-                for ((id, &rating), hidden) in ids.iter().zip(ratings.iter()).zip(hidden.iter()) {
+                for ((((id, &rating), rating_scale), r#type), hidden) in ids
+                    .iter()
+                    .zip(ratings.iter())
+                    .zip(rating_scales)
+                    .zip(types.iter())
+                    .zip(hidden.iter())
+                {
+                    let item_scale = rating_scale
+                        .and_then(|scale| scale.parse().ok())
+                        .unwrap_or_else(|| RatingScale::for_type(r#type.unwrap_or_default()));
+                    // Lists with their own scale show ratings from other scales converted
+                    let scale = ctx.props().list.rating_scale.unwrap_or(item_scale);
                     state[index[id.unwrap()]] = State {
-                        rating,
+                        rating: rating
+                            .map(|rating| item_scale.convert(rating as i32, scale) as u64),
                         hidden: hidden.unwrap(),
+                        scale,
                     };
                 }
                 self.prev_state = Some(state.clone());
@@ -151,10 +205,16 @@ impl Component for ListItems {
                     .zip(self.state.as_ref().unwrap().iter())
                     .enumerate()
                 {
-                    let State { rating, hidden } = rating_hidden;
+                    let State {
+                        rating,
+                        hidden,
+                        scale,
+                    } = rating_hidden;
                     let mut updates = HashMap::new();
+                    // Ratings are saved on the scale they were entered with
                     if self.prev_state.as_ref().unwrap()[i].rating != *rating {
                         updates.insert(String::from("rating"), (*rating).into());
+                        updates.insert(String::from("rating_scale"), scale.id().into());
                     }
                     let value =
                         Value::Bool(hidden_ref.cast::<HtmlInputElement>().unwrap().checked());
@@ -217,12 +277,13 @@ impl Component for ListItems {
             Msg::SaveSuccess(updates) => {
                 for (i, update) in updates {
                     for (k, v) in update {
-                        let State { rating, hidden } =
+                        let State { rating, hidden, .. } =
                             self.state.as_mut().unwrap().get_mut(i).unwrap();
                         match k.as_str() {
                             "rating" => {
                                 *rating = v.as_u64();
                             }
+                            "rating_scale" => {}
                             "hidden" => {
                                 *hidden = v.as_bool().unwrap();
                             }
@@ -242,6 +303,69 @@ impl Component for ListItems {
                 });
                 false
             }
+            Msg::Export => {
+                let format = self
+                    .export_format_ref
+                    .cast::<HtmlSelectElement>()
+                    .unwrap()
+                    .value();
+                let order = self
+                    .export_order_ref
+                    .cast::<HtmlSelectElement>()
+                    .map_or_else(|| String::from("query"), |select| select.value());
+                // The response is an attachment so the browser downloads it without leaving the page
+                crate::window()
+                    .location()
+                    .set_href(&format!(
+                        "/api/lists/{}/export?format={format}&order={order}",
+                        ctx.props().list.id
+                    ))
+                    .unwrap();
+                false
+            }
+            Msg::Sync => {
+                let id = ctx.props().list.id.clone();
+                ctx.link().send_future(async move {
+                    match crate::sync_list(&id).await {
+                        Ok(()) => {
+                            // Sources and items can change so reload the whole list
+                            crate::window().location().reload().unwrap();
+                            Msg::None
+                        }
+                        Err(e) => Msg::SyncError(e.as_string().unwrap_or_default()),
+                    }
+                });
+                false
+            }
+            Msg::Refresh => {
+                let id = ctx.props().list.id.clone();
+                ctx.link().send_future(async move {
+                    match crate::refresh_list(&id).await {
+                        Ok(()) => {
+                            crate::window().location().reload().unwrap();
+                            Msg::None
+                        }
+                        Err(e) => Msg::SyncError(e.as_string().unwrap_or_default()),
+                    }
+                });
+                false
+            }
+            Msg::SyncError(e) => {
+                self.sync_error = Some(e);
+                true
+            }
+            Msg::Resolve(item) => {
+                let id = ctx.props().list.id.clone();
+                ctx.link().send_future(async move {
+                    crate::resolve_conflict(&id, &item).await.unwrap();
+                    Msg::ResolveSuccess(item)
+                });
+                false
+            }
+            Msg::ResolveSuccess(item) => {
+                self.conflicts.retain(|c| c.id != item);
+                true
+            }
             Msg::Open(item) => {
                 self.modal = Some(item);
                 true
@@ -276,6 +400,37 @@ impl Component for ListItems {
                 }
                 true
             }
+            Msg::DragStart(i) => {
+                self.dragging = Some(i);
+                false
+            }
+            Msg::Drop(i) => {
+                let Some(from) = self.dragging.take() else {
+                    return false;
+                };
+                if from == i {
+                    return false;
+                }
+                let item = self.items.remove(from);
+                self.items.insert(i, item);
+                if let Some(state) = &mut self.state {
+                    let item = state.remove(from);
+                    state.insert(i, item);
+                }
+                let id = ctx.props().list.id.clone();
+                let ids: Vec<_> = self.items.iter().map(|i| i.item.id.clone()).collect();
+                ctx.link().send_future(async move {
+                    match crate::reorder_list(&id, &ids).await {
+                        Ok(()) => Msg::None,
+                        Err(e) => Msg::ReorderError(e.as_string().unwrap_or_default()),
+                    }
+                });
+                true
+            }
+            Msg::ReorderError(e) => {
+                self.alert = Some(Err(e));
+                true
+            }
         }
     }
 
@@ -305,7 +460,7 @@ impl Component for ListItems {
                 </div>
                 if let Some(state) = self.state.as_ref() {
                   <div class="col-2">
-                    <Rating rating={state[i].rating} {onchange} disabled={disabled}/>
+                    <Rating rating={state[i].rating} scale={state[i].scale} {onchange} disabled={disabled}/>
                   </div>
                 }
               </Modal>
@@ -325,6 +480,7 @@ impl Component for ListItems {
                 _ => None,
             };
             html! {
+              <>
                 if let SourceType::ListItems(id) = &source.source_type {
                     <div class="mb-2"><Link<ListsRoute> to={ListsRoute::View { id: id.clone() }}>{&source.name}</Link<ListsRoute>></div>
                 } else if let Some(href) = raw_id {
@@ -332,6 +488,19 @@ impl Component for ListItems {
                 } else {
                     <p class="mb-2">{&source.name}</p>
                 }
+                if !source.unmatched.is_empty() {
+                  <details class="mb-2 ms-3">
+                    <summary>{format!("{} songs couldn't be found in Spotify", source.unmatched.len())}</summary>
+                    <p class="mb-1">{"Add them to the list with a Spotify track data source."}</p>
+                    <ul>
+                      {for source.unmatched.iter().map(|song| {
+                        let href = format!("https://open.spotify.com/search/{}", js_sys::encode_uri_component(song));
+                        html! {<li><a {href} target="_blank">{song}</a></li>}
+                      })}
+                    </ul>
+                  </details>
+                }
+              </>
             }
         });
         let (style, grid) = match ctx.props().mode {
@@ -346,6 +515,26 @@ impl Component for ListItems {
             ),
         };
         let html: Html = match ctx.props().mode {
+            // Manually sorted lists are reordered by dragging items
+            ItemMode::View if list.sort == SortMode::Manual && !disabled => self
+                .items
+                .iter()
+                .enumerate()
+                .map(|(i, ListItem { item, .. })| {
+                    let open = ctx.link().callback(move |_| Msg::Open(i));
+                    let ondragstart = ctx.link().callback(move |_: DragEvent| Msg::DragStart(i));
+                    let ondragover = Callback::from(|e: DragEvent| e.prevent_default());
+                    let ondrop = ctx.link().callback(move |e: DragEvent| {
+                        e.prevent_default();
+                        Msg::Drop(i)
+                    });
+                    html! {
+                        <label class="col-form-label" draggable="true" style="cursor: grab" {ondragstart} {ondragover} {ondrop}>
+                            <a href="#" onclick={open}>{&item.name}</a>
+                        </label>
+                    }
+                })
+                .collect(),
             ItemMode::View => self
                 .items
                 .iter()
@@ -370,9 +559,9 @@ impl Component for ListItems {
                         html! {
                             <>
                                 <label class="col-form-label"><a href="#" onclick={open}>{&item.name}</a></label>
-                                if let Some(State { rating, hidden }) = self.state.as_ref().and_then(|s| s.get(i)) {
+                                if let Some(State { rating, hidden, scale }) = self.state.as_ref().and_then(|s| s.get(i)) {
                                     <div>
-                                        <Rating {rating} onchange={ctx.link().callback(move |rating| Msg::UpdateRating(i, rating))} {disabled}/>
+                                        <Rating {rating} scale={*scale} onchange={ctx.link().callback(move |rating| Msg::UpdateRating(i, rating))} {disabled}/>
                                     </div>
                                     <div class="d-flex justify-content-center">
                                         <input ref={hidden_ref} class="form-check-input mt-2" type="checkbox" checked={*hidden}/>
@@ -416,6 +605,31 @@ impl Component for ListItems {
         } else {
             false
         };
+        let export = ctx.link().callback(|_| Msg::Export);
+        let sync = ctx.link().callback(|_| Msg::Sync);
+        let sync_available = push_available && matches!(list.mode, ListMode::User(Some(_)));
+        let refresh = ctx.link().callback(|_| Msg::Refresh);
+        // Smart lists can be re-evaluated after changing items outside of the list
+        let smart = list.sources.iter().any(|s| match &s.source_type {
+            SourceType::Query(_) => true,
+            SourceType::Composite(composite) => composite
+                .leaves()
+                .iter()
+                .any(|s| matches!(s, SourceType::Query(_))),
+            _ => false,
+        });
+        let conflict_html = self.conflicts.iter().map(|conflict| {
+            let resolve = {
+                let id = conflict.id.clone();
+                ctx.link().callback(move |_| Msg::Resolve(id.clone()))
+            };
+            html! {
+                <li class="list-group-item d-flex justify-content-between align-items-center">
+                    {&conflict.name}
+                    <button type="button" class="btn btn-sm btn-primary" onclick={resolve} {disabled}>{"Keep in Spotify"}</button>
+                </li>
+            }
+        });
         let hide = ctx.link().callback(|_| Msg::HideAlert);
         html! {
             <div>
@@ -449,6 +663,41 @@ impl Component for ListItems {
                 if !matches!(list.mode, ListMode::External) {
                     <button type="button" class="btn btn-success" onclick={push} disabled={!push_available}>{"Push"}</button>
                 }
+                if let ListMode::User(Some(_)) = list.mode {
+                    <button type="button" class="btn btn-primary ms-2" onclick={sync} disabled={!sync_available}>{"Sync"}</button>
+                }
+                if smart {
+                    <button type="button" class="btn btn-primary ms-2" onclick={refresh} {disabled}>{"Refresh"}</button>
+                }
+                if !matches!(list.mode, ListMode::External) {
+                    <form class="d-flex flex-wrap gap-2 mt-3">
+                        <select ref={&self.export_format_ref} class="form-select w-auto">
+                            <option value="m3u8" selected=true>{"M3U8"}</option>
+                            <option value="xspf">{"XSPF"}</option>
+                            <option value="csv">{"CSV"}</option>
+                            <option value="json">{"JSON"}</option>
+                        </select>
+                        if !matches!(list.mode, ListMode::View(_)) {
+                            <select ref={&self.export_order_ref} class="form-select w-auto">
+                                <option value="list" selected=true>{"By list order"}</option>
+                                <option value="rank">{"By rank"}</option>
+                                <option value="score">{"By score"}</option>
+                                <option value="query">{"By query"}</option>
+                            </select>
+                        }
+                        <button type="button" class="btn btn-secondary" onclick={export}>{"Export"}</button>
+                    </form>
+                }
+                if let Some(e) = &self.sync_error {
+                    <div class="alert alert-danger mt-3" role="alert">{e}</div>
+                }
+                if !self.conflicts.is_empty() {
+                    <h5 class="mt-3">{"Removed in Spotify"}</h5>
+                    <p class="text-body-secondary">{"These tracks were removed from the Spotify playlist but are still provided by a data source."}</p>
+                    <ul class="list-group">
+                        {for conflict_html}
+                    </ul>
+                }
             </div>
         }
     }
@@ -457,6 +706,7 @@ impl Component for ListItems {
 #[derive(PartialEq, Properties)]
 struct RatingProps {
     rating: Option<u64>,
+    scale: RatingScale,
     onchange: Callback<Option<u64>>,
     disabled: bool,
 }
@@ -476,9 +726,32 @@ impl Component for Rating {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let rating = &ctx.props().rating;
+        let RatingProps {
+            rating,
+            scale,
+            disabled,
+            ..
+        } = *ctx.props();
         let onchange = ctx.props().onchange.clone();
         let select_ref = self.select_ref.clone();
+        // Ratings out of 100 have too many values for a dropdown
+        if scale == RatingScale::Hundred {
+            let values = scale.values();
+            let onchange = ctx.link().callback(move |_| {
+                let input = select_ref.cast::<HtmlInputElement>().unwrap();
+                onchange.emit(
+                    input
+                        .value()
+                        .parse()
+                        .ok()
+                        .filter(|rating| values.contains(rating))
+                        .map(|rating: i32| rating as u64),
+                )
+            });
+            return html! {
+                <input ref={&self.select_ref} {onchange} class="form-control" type="number" min={scale.values().start().to_string()} max={scale.values().end().to_string()} value={rating.map(|rating| rating.to_string()).unwrap_or_default()} {disabled}/>
+            };
+        }
         let onchange = ctx.link().callback(move |_| {
             onchange.emit(
                 select_ref
@@ -490,19 +763,11 @@ impl Component for Rating {
             )
         });
         html! {
-            <select ref={&self.select_ref} {onchange} class="form-select" disabled={ctx.props().disabled}>
+            <select ref={&self.select_ref} {onchange} class="form-select" {disabled}>
                 <option selected={rating.is_none()}></option>
-                <option selected={*rating == Some(0)}>{"0"}</option>
-                <option selected={*rating == Some(1)}>{"1"}</option>
-                <option selected={*rating == Some(2)}>{"2"}</option>
-                <option selected={*rating == Some(3)}>{"3"}</option>
-                <option selected={*rating == Some(4)}>{"4"}</option>
-                <option selected={*rating == Some(5)}>{"5"}</option>
-                <option selected={*rating == Some(6)}>{"6"}</option>
-                <option selected={*rating == Some(7)}>{"7"}</option>
-                <option selected={*rating == Some(8)}>{"8"}</option>
-                <option selected={*rating == Some(9)}>{"9"}</option>
-                <option selected={*rating == Some(10)}>{"10"}</option>
+                {for scale.values().map(|value| html! {
+                    <option value={value.to_string()} selected={rating == Some(value as u64)}>{scale.label(value)}</option>
+                })}
             </select>
         }
     }
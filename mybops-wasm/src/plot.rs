@@ -1,5 +1,5 @@
 use arrow::{
-    array::{AsArray, RecordBatch},
+    array::{ArrayRef, AsArray, RecordBatch},
     compute,
     csv::Writer,
     datatypes::{DataType, Float64Type, UInt32Type},
@@ -7,10 +7,11 @@ use arrow::{
 };
 use plotters::prelude::{
     BLACK, ChartBuilder, Circle, Color, Histogram, IntoDrawingArea, IntoSegmentedCoord, LineSeries,
-    RED, WHITE,
+    Palette, Palette99, PathElement, RED, WHITE,
 };
 use plotters_canvas::CanvasBackend;
 use std::{collections::HashMap, sync::Arc};
+use wasm_bindgen::JsValue;
 use yew::{Html, html};
 
 use crate::dataframe::DataFrame;
@@ -168,23 +169,54 @@ fn draw_line_graph(df: &DataFrame) -> Result<(), Box<dyn std::error::Error>> {
         .x_label_area_size(35)
         .y_label_area_size(40)
         .margin(5);
-    let data = df_coords(df)?;
+    let time = is_time(&df.arrays[0]);
+    let series = df_series(df, time)?;
+    let Some((x_min, x_max, y_min, y_max)) = series
+        .iter()
+        .flat_map(|(_, data)| data)
+        .map(|&(x, y)| (x, x, y, y))
+        .reduce(|(x_min, x_max, y_min, y_max), (x, _, y, _)| {
+            (x_min.min(x), x_max.max(x), y_min.min(y), y_max.max(y))
+        })
+    else {
+        return Ok(());
+    };
+    let multiple = series.len() > 1;
+    // Timestamps and multiple lines are zoomed in so that changes over time are visible
     let mut chart = builder.build_cartesian_2d(
-        0f64..compute::max(
-            compute::cast(&df.arrays[0], &DataType::Float64)
-                .unwrap()
-                .as_primitive::<Float64Type>(),
-        )
-        .unwrap(),
-        0f64..compute::max(
-            compute::cast(&df.arrays[1], &DataType::Float64)
-                .unwrap()
-                .as_primitive::<Float64Type>(),
-        )
-        .unwrap(),
+        if time { x_min } else { 0f64 }..x_max,
+        if multiple { y_min } else { 0f64 }..y_max,
     )?;
-    chart.configure_mesh().draw()?;
-    chart.draw_series(LineSeries::new(data, BLACK))?;
+    let format_date = |x: &f64| {
+        String::from(js_sys::Date::new(&JsValue::from_f64(*x)).to_iso_string())
+            .chars()
+            .take(10)
+            .collect()
+    };
+    let mut mesh = chart.configure_mesh();
+    if time {
+        mesh.x_label_formatter(&format_date);
+    }
+    mesh.draw()?;
+    for (i, (name, data)) in series.into_iter().enumerate() {
+        let color = if multiple {
+            Palette99::pick(i).to_rgba()
+        } else {
+            BLACK.to_rgba()
+        };
+        let line = chart.draw_series(LineSeries::new(data, color))?;
+        if multiple {
+            line.label(name)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+    }
+    if multiple {
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+    }
     Ok(())
 }
 
@@ -250,6 +282,62 @@ fn draw_cum_line_graph(df: &DataFrame) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
+/// Name and coordinates of a line
+type Series = (String, Vec<(f64, f64)>);
+
+fn is_time(array: &ArrayRef) -> bool {
+    matches!(array.data_type(), DataType::Utf8 | DataType::LargeUtf8)
+}
+
+/// Lines to draw, which are split by the values of the third column if there is one
+fn df_series(df: &DataFrame, time: bool) -> Result<Vec<Series>, Box<dyn std::error::Error>> {
+    let coords = if time {
+        df_time_coords(df)?
+    } else {
+        df_coords(df)?
+    };
+    let Some(keys) = df.arrays.get(2) else {
+        return Ok(vec![(df.schema.fields[1].name().clone(), coords)]);
+    };
+    let mut series: Vec<Series> = Vec::new();
+    for (i, coord) in coords.into_iter().enumerate() {
+        let key = display::array_value_to_string(keys, i)?;
+        match series.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, data)) => data.push(coord),
+            None => series.push((key, vec![coord])),
+        }
+    }
+    // Rows of different series can be interleaved in any order
+    for (_, data) in &mut series {
+        data.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    Ok(series)
+}
+
+/// Coordinates with ISO 8601 timestamps converted to milliseconds since the epoch
+fn df_time_coords(df: &DataFrame) -> Result<Vec<(f64, f64)>, Box<dyn std::error::Error>> {
+    let range = compute::cast(&df.arrays[1], &DataType::Float64).unwrap();
+    let range = range.as_primitive::<Float64Type>();
+    range
+        .iter()
+        .enumerate()
+        .map(|(i, y)| {
+            let ts = display::array_value_to_string(&df.arrays[0], i)?;
+            let x = js_sys::Date::parse(&ts);
+            if x.is_nan() {
+                return Err(format!("invalid timestamp for line graph: {ts}").into());
+            }
+            Ok((
+                x,
+                y.ok_or(format!(
+                    "unsupported data type for line graph: {:?}",
+                    df.arrays[1].data_type()
+                ))?,
+            ))
+        })
+        .collect()
+}
+
 fn df_coords(df: &DataFrame) -> Result<Vec<(f64, f64)>, Box<dyn std::error::Error>> {
     let domain = compute::cast(&df.arrays[0], &DataType::Float64).unwrap();
     let domain = domain.as_primitive::<Float64Type>();
@@ -43,10 +43,35 @@ impl Component for RandomRounds {
     }
 }
 
+#[derive(Clone, PartialEq, Properties)]
+pub struct TierMatchProps {
+    pub id: String,
+    pub tiers: Vec<String>,
+}
+
+pub struct TierMatches;
+
+impl Component for TierMatches {
+    type Message = ();
+    type Properties = TierMatchProps;
+
+    fn create(_: &Context<Self>) -> Self {
+        TierMatches
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        html! {
+            <Match id={ctx.props().id.clone()} mode={Mode::Tier} tiers={ctx.props().tiers.clone()}/>
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum Mode {
     Match,
     Round,
+    /// Random matches between items in the same or adjacent tiers
+    Tier,
 }
 
 #[derive(Clone)]
@@ -65,6 +90,8 @@ pub enum Msg {
 pub struct MatchComponentProps {
     pub id: String,
     pub mode: Mode,
+    #[prop_or_default]
+    pub tiers: Vec<String>,
 }
 
 pub struct Match {
@@ -123,6 +150,27 @@ impl Component for Match {
                             queued_scores.pop().unwrap().clone().unwrap(),
                         )
                     }
+                    Mode::Tier => {
+                        let tiers = &ctx.props().tiers;
+                        let tier_index = |item: &ItemMetadata| {
+                            item.tier
+                                .as_ref()
+                                .and_then(|tier| tiers.iter().position(|t| t == tier))
+                                .unwrap_or(tiers.len())
+                        };
+                        let mut queued_scores: Vec<_> = query.items.iter().flatten().collect();
+                        queued_scores.shuffle(&mut rand::thread_rng());
+                        let left = queued_scores.pop().unwrap();
+                        // Fall back to any item if no other item is in a nearby tier
+                        let right = match queued_scores
+                            .iter()
+                            .position(|item| tier_index(item).abs_diff(tier_index(left)) <= 1)
+                        {
+                            Some(i) => queued_scores.remove(i),
+                            None => queued_scores.pop().unwrap(),
+                        };
+                        (left.clone(), right.clone())
+                    }
                 };
                 self.data = Some(MatchData { left, right, query });
                 true
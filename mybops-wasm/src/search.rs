@@ -1,10 +1,11 @@
-use crate::{bootstrap::Collapse, dataframe::DataFrame, plot::DataView};
+use crate::{bootstrap::Collapse, dataframe::DataFrame, duplicates::Duplicates, plot::DataView};
 use web_sys::{HtmlSelectElement, KeyboardEvent};
 use yew::{Component, Context, Html, NodeRef, Properties, html};
 
 pub enum SearchMsg {
     ToggleHelp,
     Toggle,
+    ToggleDuplicates,
 }
 
 #[derive(PartialEq, Properties)]
@@ -15,6 +16,7 @@ pub struct SearchProps {
 pub struct Search {
     help_collapsed: bool,
     split_view: bool,
+    show_duplicates: bool,
 }
 
 impl Component for Search {
@@ -25,6 +27,7 @@ impl Component for Search {
         Search {
             help_collapsed: ctx.props().logged_in,
             split_view: false,
+            show_duplicates: false,
         }
     }
 
@@ -32,6 +35,7 @@ impl Component for Search {
         match msg {
             SearchMsg::ToggleHelp => self.help_collapsed = !self.help_collapsed,
             SearchMsg::Toggle => self.split_view = !self.split_view,
+            SearchMsg::ToggleDuplicates => self.show_duplicates = !self.show_duplicates,
         }
         true
     }
@@ -50,6 +54,9 @@ impl Component for Search {
                   <li class="navbar-brand">{"Query"}</li>
                 </ul>
                 <div class="d-flex gap-3">
+                  if ctx.props().logged_in {
+                    <button type="button" class="btn btn-info" onclick={ctx.link().callback(|_| SearchMsg::ToggleDuplicates)}>{"Duplicates"}</button>
+                  }
                   <button type="button" class="btn btn-info" style="width: 112px" {onclick}>{button_text}</button>
                   <button class="btn btn-info" onclick={ctx.link().callback(|_| SearchMsg::ToggleHelp)}>{"Help"}</button>
                 </div>
@@ -72,18 +79,31 @@ impl Component for Search {
                     <code>{"SELECT name, artists FROM item, json_each(metadata->'artists') WHERE json_each.value='Troy'"}</code>
                     <p>{"Get your average score for each group of artists:"}</p>
                     <code>{"SELECT artists, AVG(user_score) FROM item WHERE type='track' GROUP BY artists"}</code>
+                    <p>{"Chart the score history of your top 5 items as a line graph:"}</p>
+                    <code>{"SELECT ts, score, name FROM item_history WHERE item_id IN (SELECT id FROM item ORDER BY user_score DESC LIMIT 5)"}</code>
                     <p><strong>{"Fields"}</strong></p>
                     <p>{"The fields you can query on are listed below.
                         Here is the list of fields that are available for all items:"}</p>
                     <ul>
                       <li>{"type: string - The type of item"}</li>
                       <li>{"name: string - The name of the item"}</li>
-                      <li>{"rating: number - The rating that you gave the item"}</li>
+                      <li>{"rating: number - The rating that you gave the item on its rating scale"}</li>
+                      <li>{"rating_scale: string - ten, stars, hundred or like, or empty for the default scale of the item type"}</li>
+                      <li>{"normalized_rating: number - The rating out of 100 for comparing ratings on different scales"}</li>
                       <li>{"user_score: number - Score computed from tournaments and matches"}</li>
                       <li>{"user_wins: number - Tournament and match wins"}</li>
                       <li>{"user_losses: number - Tournament and match losses"}</li>
                       <li>{"hidden: boolean - The item was hidden"}</li>
                     </ul>
+                    <p>{"A snapshot of the score and rank of both items is added to the item_history table after each match.
+                        Snapshots have these fields along with the name, type and item type specific fields of their item:"}</p>
+                    <ul>
+                      <li>{"item_id: string - The ID of the item"}</li>
+                      <li>{"list_id: string - The list that the match was played in"}</li>
+                      <li>{"ts: string - When the match was played (ISO 8601)"}</li>
+                      <li>{"score: number - The item's score after the match"}</li>
+                      <li>{"rank: number - The item's rank by score among the list's items that aren't hidden"}</li>
+                    </ul>
                     <p>{"There are also fields that are specific to a single item type."}</p>
                     <p><em>{"Spotify Item Fields"}</em></p>
                     <p>{"Type is set to 'track' for Spotify items"}</p>
@@ -93,9 +113,30 @@ impl Component for Search {
                       <li>{"duration_ms: number - The track length in milliseconds"}</li>
                       <li>{"popularity - Spotify popularity of the track"}</li>
                       <li>{"track_number - The number of the track"}</li>
+                      <li>{"release_date: string - The album release date"}</li>
+                      <li>{"year: number - The album release year"}</li>
+                      <li>{"explicit: boolean - The track has explicit lyrics"}</li>
+                      <li>{"isrc: string - The International Standard Recording Code of the track"}</li>
+                    </ul>
+                    <p>{"These fields are added when Spotify enrichment is enabled:"}</p>
+                    <ul>
+                      <li>{"genres: array of string - The genres of the track's artists"}</li>
+                      <li>{"acousticness, danceability, energy, instrumentalness, liveness, speechiness, valence: number - Audio features from 0 to 1"}</li>
+                      <li>{"tempo: number - The tempo in beats per minute"}</li>
+                      <li>{"loudness: number - The loudness in decibels"}</li>
+                      <li>{"key, mode, time_signature: number - The key, modality and time signature of the track"}</li>
+                    </ul>
+                    <p>{"These fields are added by uploading a Spotify extended streaming history export:"}</p>
+                    <ul>
+                      <li>{"play_count: number - The number of times the track was played"}</li>
+                      <li>{"ms_played: number - Total listening time in milliseconds"}</li>
+                      <li>{"first_played, last_played: string - When the track was first and last played (ISO 8601)"}</li>
                     </ul>
                   </Collapse>
                 </div>
+                if self.show_duplicates {
+                  <Duplicates/>
+                }
                 if self.split_view {
                   <div class="d-flex gap-3">
                     <SearchPane/>
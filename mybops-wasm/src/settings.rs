@@ -1,25 +1,178 @@
-use mybops::User;
-use yew::{Component, Context, Html, Properties, html};
+use crate::bootstrap::{Alert, Modal};
+use mybops::{LoginProvider, PasswordChange, SessionInfo, User};
+use web_sys::HtmlInputElement;
+use yew::{Component, Context, Html, NodeRef, Properties, html};
 
 #[derive(Eq, PartialEq, Properties)]
 pub struct SettingsProps {
     pub user: User,
 }
 
-pub struct Settings;
+pub enum SettingsMsg {
+    ChangePassword,
+    PasswordResult(Result<String, String>),
+    HideAlert,
+    Providers(Vec<LoginProvider>),
+    Sessions(Vec<SessionInfo>),
+    Revoke(Option<String>),
+    LoadSessions,
+    Unlink(String),
+    Failed(String),
+    ShowDelete,
+    HideDelete,
+    Delete,
+    Reload,
+}
+
+pub struct Settings {
+    has_password: bool,
+    current_password_ref: NodeRef,
+    new_password_ref: NodeRef,
+    alert: Option<Result<String, String>>,
+    providers: Vec<LoginProvider>,
+    sessions: Vec<SessionInfo>,
+    delete: bool,
+    delete_error: Option<String>,
+    confirm_ref: NodeRef,
+}
 
 impl Component for Settings {
-    type Message = ();
+    type Message = SettingsMsg;
     type Properties = SettingsProps;
 
-    fn create(_: &Context<Self>) -> Self {
-        Settings
+    fn create(ctx: &Context<Self>) -> Self {
+        ctx.link().send_future(async move {
+            SettingsMsg::Providers(crate::get_login_providers().await.unwrap_or_default())
+        });
+        ctx.link().send_message(SettingsMsg::LoadSessions);
+        Settings {
+            has_password: ctx.props().user.has_password,
+            current_password_ref: NodeRef::default(),
+            new_password_ref: NodeRef::default(),
+            alert: None,
+            providers: Vec::new(),
+            sessions: Vec::new(),
+            delete: false,
+            delete_error: None,
+            confirm_ref: NodeRef::default(),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            SettingsMsg::ChangePassword => {
+                let change = PasswordChange {
+                    current_password: self
+                        .current_password_ref
+                        .cast::<HtmlInputElement>()
+                        .map(|input| input.value()),
+                    new_password: self
+                        .new_password_ref
+                        .cast::<HtmlInputElement>()
+                        .unwrap()
+                        .value(),
+                };
+                ctx.link().send_future(async move {
+                    SettingsMsg::PasswordResult(match crate::change_password(&change).await {
+                        Ok(()) => Ok(String::from("Password was saved")),
+                        Err(e) => Err(e.as_string().unwrap_or_default()),
+                    })
+                });
+                false
+            }
+            SettingsMsg::PasswordResult(result) => {
+                if result.is_ok() {
+                    self.has_password = true;
+                    if let Some(input) = self.current_password_ref.cast::<HtmlInputElement>() {
+                        input.set_value("");
+                    }
+                    self.new_password_ref
+                        .cast::<HtmlInputElement>()
+                        .unwrap()
+                        .set_value("");
+                }
+                self.alert = Some(result);
+                true
+            }
+            SettingsMsg::HideAlert => {
+                self.alert = None;
+                true
+            }
+            SettingsMsg::Providers(providers) => {
+                self.providers = providers;
+                true
+            }
+            SettingsMsg::Sessions(sessions) => {
+                self.sessions = sessions;
+                true
+            }
+            SettingsMsg::Revoke(id) => {
+                ctx.link().send_future(async move {
+                    let result = match id {
+                        Some(id) => crate::revoke_session(&id).await,
+                        None => crate::revoke_other_sessions().await,
+                    };
+                    match result {
+                        Ok(()) => SettingsMsg::LoadSessions,
+                        Err(e) => SettingsMsg::Failed(e.as_string().unwrap_or_default()),
+                    }
+                });
+                false
+            }
+            SettingsMsg::LoadSessions => {
+                ctx.link().send_future(async move {
+                    SettingsMsg::Sessions(crate::get_sessions().await.unwrap_or_default())
+                });
+                false
+            }
+            SettingsMsg::Unlink(provider) => {
+                ctx.link().send_future(async move {
+                    match crate::unlink_login(&provider).await {
+                        Ok(()) => SettingsMsg::Reload,
+                        Err(e) => SettingsMsg::Failed(e.as_string().unwrap_or_default()),
+                    }
+                });
+                false
+            }
+            SettingsMsg::Failed(e) => {
+                if self.delete {
+                    self.delete_error = Some(e);
+                } else {
+                    self.alert = Some(Err(e));
+                }
+                true
+            }
+            SettingsMsg::ShowDelete => {
+                self.delete = true;
+                true
+            }
+            SettingsMsg::HideDelete => {
+                self.delete = false;
+                self.delete_error = None;
+                true
+            }
+            SettingsMsg::Delete => {
+                let confirm = self.confirm_ref.cast::<HtmlInputElement>().unwrap().value();
+                ctx.link().send_future(async move {
+                    match crate::delete_user(&confirm).await {
+                        Ok(()) => {
+                            crate::window().location().set_href("/").unwrap();
+                            SettingsMsg::HideDelete
+                        }
+                        Err(e) => SettingsMsg::Failed(e.as_string().unwrap_or_default()),
+                    }
+                });
+                false
+            }
+            SettingsMsg::Reload => {
+                crate::window().location().reload().unwrap();
+                false
+            }
+        }
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let window = crate::window();
-        let location = window.location();
-        // TODO: let you remove integrations
+        let user = &ctx.props().user;
         // Should we link to Google profile?
         crate::nav_content(
             html! {
@@ -29,21 +182,110 @@ impl Component for Settings {
             },
             html! {
               <div>
-                <h1>{"Integrations"}</h1>
-                <h2>{"Spotify"}</h2>
-                if let (Some(url), Some(user)) = (&ctx.props().user.spotify_url, &ctx.props().user.spotify_user) {
-                  <a href={url.clone()}>{&user}</a>
-                } else {
-                  <a class="btn btn-success" href={format!("https://accounts.spotify.com/authorize?client_id=ee3d1b4f8d80477ea48743a511ef3018&redirect_uri={}/api/login&response_type=code&scope=playlist-modify-public playlist-modify-private user-read-recently-played playlist-read-private", location.origin().unwrap().as_str())}>{"Log in with Spotify"}</a>
+                <h1>{"Account"}</h1>
+                <h2>{"Password"}</h2>
+                if let Some(result) = &self.alert {
+                  <Alert result={result.clone()} hide={ctx.link().callback(|_| SettingsMsg::HideAlert)}/>
                 }
-                <h2>{"Google"}</h2>
-                if let Some(google_email) = &ctx.props().user.google_email {
-                  <p>{google_email}</p>
-                } else {
-                  <a class="btn btn-success" href={format!("https://accounts.google.com/o/oauth2/v2/auth?client_id=1038220726403-n55jha2cvprd8kdb4akdfvo0uiok4p5u.apps.googleusercontent.com&redirect_uri={}/api/login/google&response_type=code&scope=email", location.origin().unwrap().as_str())}>{"Log in with Google"}</a>
+                <p>{format!("Username: {}", user.user_id)}</p>
+                <div class="d-flex flex-column gap-2 mb-3" style="max-width: 400px">
+                  if self.has_password {
+                    <input ref={&self.current_password_ref} type="password" class="form-control" placeholder="Current password" autocomplete="current-password"/>
+                  }
+                  <input ref={&self.new_password_ref} type="password" class="form-control" placeholder="New password" autocomplete="new-password"/>
+                  <div>
+                    <button type="button" class="btn btn-success" onclick={ctx.link().callback(|_| SettingsMsg::ChangePassword)}>
+                      if self.has_password {
+                        {"Change password"}
+                      } else {
+                        {"Set password"}
+                      }
+                    </button>
+                  </div>
+                </div>
+                <h2>{"Sessions"}</h2>
+                <table class="table">
+                  <thead>
+                    <tr>
+                      <th>{"Device"}</th>
+                      <th>{"Signed in"}</th>
+                      <th>{"Expires"}</th>
+                      <th></th>
+                    </tr>
+                  </thead>
+                  <tbody>
+                    {for self.sessions.iter().map(|session| html! {
+                      <tr>
+                        <td>
+                          {session.user_agent.as_deref().unwrap_or("Unknown device")}
+                          if session.current {
+                            <span class="badge text-bg-success ms-2">{"Current"}</span>
+                          }
+                        </td>
+                        <td>{session.created.map(format_timestamp).unwrap_or_default()}</td>
+                        <td>{format_timestamp(session.expires)}</td>
+                        <td>
+                          if !session.current {
+                            <button type="button" class="btn btn-sm btn-outline-danger" onclick={let id = session.id.clone(); ctx.link().callback(move |_| SettingsMsg::Revoke(Some(id.clone())))}>{"Revoke"}</button>
+                          }
+                        </td>
+                      </tr>
+                    })}
+                  </tbody>
+                </table>
+                <button type="button" class="btn btn-outline-danger mb-3" onclick={ctx.link().callback(|_| SettingsMsg::Revoke(None))}>{"Log out other sessions"}</button>
+                <h1>{"Integrations"}</h1>
+                {for self.providers.iter().map(|provider| {
+                    let linked = match provider.id.as_str() {
+                        "spotify" => user.spotify_url.as_ref().zip(user.spotify_user.as_ref()).map(|(url, spotify_user)| html! {
+                          <a href={url.clone()}>{spotify_user}</a>
+                        }),
+                        "google" => user.google_email.as_ref().map(|google_email| html! {
+                          <p>{google_email}</p>
+                        }),
+                        "oidc" => user.oidc_linked.then(|| html! {
+                          <p>{"Linked"}</p>
+                        }),
+                        _ => None,
+                    };
+                    html! {
+                      <>
+                        <h2>{&provider.name}</h2>
+                        if let Some(linked) = linked {
+                          {linked}
+                          <button type="button" class="btn btn-outline-danger mb-3" onclick={let id = provider.id.clone(); ctx.link().callback(move |_| SettingsMsg::Unlink(id.clone()))}>{"Remove"}</button>
+                        } else {
+                          <a class="btn btn-success" href={provider.url.clone()}>{format!("Log in with {}", provider.name)}</a>
+                        }
+                      </>
+                    }
+                })}
+                <h1 class="mt-3">{"Delete account"}</h1>
+                <p>{"Deleting your account removes all of your lists, items and match results. This can't be undone."}</p>
+                <button type="button" class="btn btn-danger" onclick={ctx.link().callback(|_| SettingsMsg::ShowDelete)}>{"Delete account"}</button>
+                if self.delete {
+                  <Modal header={"Delete account"} hide={ctx.link().callback(|_| SettingsMsg::HideDelete)}>
+                    <div class="modal-body d-grid gap-2">
+                      if let Some(error) = &self.delete_error {
+                        <div class="alert alert-danger mb-0">{error}</div>
+                      }
+                      <label for="confirm">{format!("Enter {} to confirm", user.user_id)}</label>
+                      <input ref={&self.confirm_ref} id="confirm" type="text" class="form-control" autocomplete="off"/>
+                    </div>
+                    <div class="modal-footer">
+                      <button type="button" class="btn btn-secondary" onclick={ctx.link().callback(|_| SettingsMsg::HideDelete)}>{"Cancel"}</button>
+                      <button type="button" class="btn btn-danger" onclick={ctx.link().callback(|_| SettingsMsg::Delete)}>{"Delete account"}</button>
+                    </div>
+                  </Modal>
                 }
               </div>
             },
         )
     }
 }
+
+fn format_timestamp(timestamp: i64) -> String {
+    js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(timestamp as f64 * 1000.))
+        .to_locale_string("default", &wasm_bindgen::JsValue::UNDEFINED)
+        .into()
+}
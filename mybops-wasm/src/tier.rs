@@ -0,0 +1,132 @@
+use crate::bootstrap::Alert;
+use mybops::{ItemMetadata, List};
+use std::collections::HashMap;
+use yew::{Callback, Component, Context, DragEvent, Html, Properties, html};
+
+pub enum Msg {
+    None,
+    DragStart(usize),
+    Drop(Option<String>),
+    SaveError(String),
+    HideAlert,
+}
+
+#[derive(PartialEq, Properties)]
+pub struct TierListProps {
+    pub list: List,
+}
+
+/// Rank items by dragging them into the list's tiers
+pub struct TierList {
+    items: Vec<ItemMetadata>,
+    /// Index of the item being dragged
+    dragging: Option<usize>,
+    alert: Option<Result<String, String>>,
+}
+
+impl Component for TierList {
+    type Message = Msg;
+    type Properties = TierListProps;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        TierList {
+            items: ctx.props().list.items.clone(),
+            dragging: None,
+            alert: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::None => false,
+            Msg::DragStart(i) => {
+                self.dragging = Some(i);
+                false
+            }
+            Msg::Drop(tier) => {
+                let Some(i) = self.dragging.take() else {
+                    return false;
+                };
+                if self.items[i].tier == tier {
+                    return false;
+                }
+                self.items[i].tier = tier;
+                let id = ctx.props().list.id.clone();
+                let tiers: HashMap<_, _> = self
+                    .items
+                    .iter()
+                    .filter_map(|item| Some((item.id.clone(), item.tier.clone()?)))
+                    .collect();
+                ctx.link().send_future(async move {
+                    match crate::set_tiers(&id, &tiers).await {
+                        Ok(()) => Msg::None,
+                        Err(e) => Msg::SaveError(e.as_string().unwrap_or_default()),
+                    }
+                });
+                true
+            }
+            Msg::SaveError(e) => {
+                self.alert = Some(Err(e));
+                true
+            }
+            Msg::HideAlert => {
+                self.alert = None;
+                true
+            }
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>, _: &Self::Properties) -> bool {
+        self.items = ctx.props().list.items.clone();
+        self.dragging = None;
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let list = &ctx.props().list;
+        let mut rows: Vec<_> = list
+            .tiers
+            .iter()
+            .map(|tier| (tier.as_str(), Vec::new()))
+            .collect();
+        // Untiered items are listed after the last tier
+        rows.push(("Unranked", Vec::new()));
+        for (i, item) in self.items.iter().enumerate() {
+            rows[list.tier_index(item)].1.push(i);
+        }
+        let html: Html = rows
+            .into_iter()
+            .zip(list.tiers.iter().map(Some).chain([None]))
+            .map(|((name, items), tier)| {
+                let tier = tier.cloned();
+                let ondragover = Callback::from(|e: DragEvent| e.prevent_default());
+                let ondrop = ctx.link().callback(move |e: DragEvent| {
+                    e.prevent_default();
+                    Msg::Drop(tier.clone())
+                });
+                html! {
+                    <div class="d-flex border-bottom" style="min-height: 3rem">
+                        <div class="fw-bold p-2 text-center" style="width: 6rem">{name}</div>
+                        <div class="d-flex flex-wrap flex-grow-1 align-items-center gap-2 p-2" {ondragover} {ondrop}>
+                            {for items.into_iter().map(|i| {
+                                let ondragstart = ctx.link().callback(move |_: DragEvent| Msg::DragStart(i));
+                                html! {
+                                    <span class="badge text-bg-secondary" draggable="true" style="cursor: grab" {ondragstart}>{&self.items[i].name}</span>
+                                }
+                            })}
+                        </div>
+                    </div>
+                }
+            })
+            .collect();
+        let hide = ctx.link().callback(|_| Msg::HideAlert);
+        html! {
+            <div>
+                if let Some(result) = self.alert.clone() {
+                    <Alert {result} {hide}/>
+                }
+                {html}
+            </div>
+        }
+    }
+}
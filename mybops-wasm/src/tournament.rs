@@ -8,6 +8,9 @@ use yew::{Callback, Component, Context, Html, NodeRef, Properties, html};
 #[derive(Eq, PartialEq, Properties)]
 pub struct TournamentLoaderProps {
     pub list: List,
+    /// Seed items by tier before score
+    #[prop_or_default]
+    pub seed_by_tier: bool,
 }
 
 pub struct TournamentLoader;
@@ -21,20 +24,19 @@ impl Component for TournamentLoader {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let mut items: Vec<_> = (0..ctx.props().list.items.len()).collect();
-        items.sort_by_key(|&i| -ctx.props().list.items[i].score);
-        let previous_ranks = ctx
-            .props()
-            .list
-            .items
-            .iter()
-            .map(|i| (i.id.clone(), i.rank))
-            .collect();
+        let list = &ctx.props().list;
+        let mut items: Vec<_> = (0..list.items.len()).collect();
+        if ctx.props().seed_by_tier {
+            items.sort_by_key(|&i| (list.tier_index(&list.items[i]), -list.items[i].score));
+        } else {
+            items.sort_by_key(|&i| -list.items[i].score);
+        }
+        let previous_ranks = list.items.iter().map(|i| (i.id.clone(), i.rank)).collect();
         let bracket = TournamentBracket::new(items, usize::MAX);
         let state = TournamentFields {
             state: TournamentState::Tournament,
             view_state: ViewState::Tournament,
-            list: ctx.props().list.clone(),
+            list: list.clone(),
             previous_ranks,
             bracket,
         };
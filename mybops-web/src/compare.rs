@@ -0,0 +1,237 @@
+use crate::{Item, RawItem, query::IntoQuery};
+use mybops::{
+    ComparedItem, Error, TasteComparison, UserId,
+    storage::{CosmosQuery, QueryDocumentsBuilder, SessionClient, View},
+};
+use std::collections::HashMap;
+
+/// Number of disagreements that are returned
+const DISAGREEMENTS: usize = 10;
+
+/// Compare the user's rankings with the items another user has shared in public lists
+pub async fn compare(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    other: &UserId,
+) -> Result<TasteComparison, Error> {
+    if other.0.is_empty() {
+        return Err(Error::client_error("Missing user to compare with"));
+    }
+    if user_id == other {
+        return Err(Error::client_error("Can't compare rankings with yourself"));
+    }
+    let items = get_items(client, View::User(user_id.clone())).await?;
+    let other_items = get_items(client, View::Shared(other.clone())).await?;
+    Ok(compare_items(other, &items, &other_items))
+}
+
+async fn get_items(client: &impl SessionClient, view: View) -> Result<Vec<Item>, Error> {
+    client
+        .query_documents::<RawItem>(QueryDocumentsBuilder::new(
+            "item",
+            view,
+            CosmosQuery::new(
+                "SELECT * FROM item WHERE hidden = false AND user_wins + user_losses > 0"
+                    .into_query()?,
+            ),
+        ))
+        .await?
+        .into_iter()
+        .map(Item::try_from)
+        .collect()
+}
+
+/// Rank items that both users have played matches for
+pub fn compare_items(other: &UserId, items: &[Item], other_items: &[Item]) -> TasteComparison {
+    let other_scores: HashMap<_, _> = other_items
+        .iter()
+        .filter(|item| !item.hidden && item.user_wins + item.user_losses > 0)
+        .map(|item| (item.id.as_str(), item.user_score))
+        .collect();
+    let mut common: Vec<_> = items
+        .iter()
+        .filter(|item| !item.hidden && item.user_wins + item.user_losses > 0)
+        .filter_map(|item| Some((item, *other_scores.get(item.id.as_str())?)))
+        .collect();
+    common.sort_by(|(a, _), (b, _)| b.user_score.cmp(&a.user_score).then(a.id.cmp(&b.id)));
+    let scores: Vec<_> = common.iter().map(|(item, _)| item.user_score).collect();
+    let other_scores: Vec<_> = common.iter().map(|(_, score)| *score).collect();
+    let items: Vec<_> = common
+        .iter()
+        .map(|(item, other_score)| ComparedItem {
+            id: item.id.clone(),
+            name: item.name.clone(),
+            score: item.user_score,
+            other_score: *other_score,
+            rank: rank(&scores, item.user_score),
+            other_rank: rank(&other_scores, *other_score),
+        })
+        .collect();
+    let mut disagreements = items.clone();
+    disagreements.sort_by_key(|item| std::cmp::Reverse(item.rank.abs_diff(item.other_rank)));
+    disagreements.truncate(DISAGREEMENTS);
+    TasteComparison {
+        user_id: other.0.clone(),
+        kendall_tau: kendall_tau(&scores, &other_scores),
+        spearman: spearman(&scores, &other_scores),
+        items,
+        disagreements,
+    }
+}
+
+/// Standard competition rank where higher scores rank first and ties share a rank
+fn rank(scores: &[i32], score: i32) -> usize {
+    scores.iter().filter(|s| **s > score).count() + 1
+}
+
+/// Kendall tau-b, which accounts for ties in either ranking
+pub fn kendall_tau(x: &[i32], y: &[i32]) -> Option<f64> {
+    let mut concordant = 0i64;
+    let mut discordant = 0i64;
+    let mut x_ties = 0i64;
+    let mut y_ties = 0i64;
+    let mut pairs = 0i64;
+    for i in 0..x.len() {
+        for j in i + 1..x.len() {
+            pairs += 1;
+            let dx = x[i].cmp(&x[j]);
+            let dy = y[i].cmp(&y[j]);
+            if dx.is_eq() {
+                x_ties += 1;
+            }
+            if dy.is_eq() {
+                y_ties += 1;
+            }
+            if dx.is_ne() && dy.is_ne() {
+                if dx == dy {
+                    concordant += 1;
+                } else {
+                    discordant += 1;
+                }
+            }
+        }
+    }
+    let denominator = (((pairs - x_ties) * (pairs - y_ties)) as f64).sqrt();
+    (denominator > 0.).then(|| (concordant - discordant) as f64 / denominator)
+}
+
+/// Spearman correlation, calculated as the Pearson correlation of fractional ranks
+pub fn spearman(x: &[i32], y: &[i32]) -> Option<f64> {
+    pearson(&fractional_ranks(x), &fractional_ranks(y))
+}
+
+/// Ranks starting from 1 where tied values get the average of their ranks
+fn fractional_ranks(values: &[i32]) -> Vec<f64> {
+    values
+        .iter()
+        .map(|value| {
+            let less = values.iter().filter(|v| *v < value).count();
+            let equal = values.iter().filter(|v| *v == value).count();
+            less as f64 + (equal as f64 + 1.) / 2.
+        })
+        .collect()
+}
+
+fn pearson(x: &[f64], y: &[f64]) -> Option<f64> {
+    let n = x.len() as f64;
+    let x_mean = x.iter().sum::<f64>() / n;
+    let y_mean = y.iter().sum::<f64>() / n;
+    let covariance: f64 = x
+        .iter()
+        .zip(y)
+        .map(|(x, y)| (x - x_mean) * (y - y_mean))
+        .sum();
+    let x_variance: f64 = x.iter().map(|x| (x - x_mean).powi(2)).sum();
+    let y_variance: f64 = y.iter().map(|y| (y - y_mean).powi(2)).sum();
+    let denominator = (x_variance * y_variance).sqrt();
+    (denominator > 0.).then(|| covariance / denominator)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Item;
+    use mybops::UserId;
+    use serde_json::Map;
+
+    fn item(id: &str, score: i32) -> Item {
+        Item {
+            id: id.to_owned(),
+            user_id: String::new(),
+            r#type: String::from("track"),
+            name: id.to_uppercase(),
+            iframe: None,
+            rating: None,
+            rating_scale: None,
+            user_score: score,
+            user_wins: 1,
+            user_losses: 0,
+            metadata: Map::new(),
+            hidden: false,
+        }
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_kendall_tau() {
+        assert_close(super::kendall_tau(&[1, 2, 3], &[4, 5, 6]), 1.);
+        assert_close(super::kendall_tau(&[1, 2, 3], &[6, 5, 4]), -1.);
+        assert_close(super::kendall_tau(&[1, 2, 3, 4], &[1, 3, 2, 4]), 2. / 3.);
+        // Tau-b with a tie in one ranking
+        assert_close(
+            super::kendall_tau(&[1, 2, 2, 3], &[1, 2, 3, 4]),
+            5. / 30f64.sqrt(),
+        );
+        assert_eq!(super::kendall_tau(&[1, 1, 1], &[1, 2, 3]), None);
+        assert_eq!(super::kendall_tau(&[1], &[1]), None);
+    }
+
+    #[test]
+    fn test_spearman() {
+        assert_close(super::spearman(&[10, 20, 30], &[1, 2, 3]), 1.);
+        assert_close(super::spearman(&[1, 2, 3, 4], &[1, 3, 2, 4]), 0.8);
+        // Tied values share the average rank
+        assert_close(
+            super::spearman(&[1, 2, 2, 3], &[1, 2, 3, 4]),
+            4.5 / 22.5f64.sqrt(),
+        );
+        assert_eq!(super::spearman(&[], &[]), None);
+    }
+
+    #[test]
+    fn test_compare_items() {
+        let mut unplayed = item("e", 1500);
+        unplayed.user_wins = 0;
+        let items = [
+            item("a", 1600),
+            item("b", 1550),
+            item("c", 1500),
+            item("d", 1450),
+            unplayed,
+        ];
+        let other_items = [
+            item("d", 1700),
+            item("b", 1550),
+            item("a", 1500),
+            item("e", 1450),
+            item("f", 1400),
+        ];
+        let comparison = super::compare_items(&UserId(String::from("other")), &items, &other_items);
+        assert_eq!(comparison.user_id, "other");
+        assert_eq!(
+            comparison
+                .items
+                .iter()
+                .map(|item| (item.id.as_str(), item.rank, item.other_rank))
+                .collect::<Vec<_>>(),
+            [("a", 1, 3), ("b", 2, 2), ("d", 3, 1)]
+        );
+        assert_close(comparison.kendall_tau, -1.);
+        assert_close(comparison.spearman, -1.);
+        assert_eq!(comparison.disagreements[0].id, "a");
+        assert_eq!(comparison.disagreements.len(), 3);
+    }
+}
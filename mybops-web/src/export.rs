@@ -0,0 +1,429 @@
+use crate::{
+    Item, RawItem,
+    query::{self, IntoQuery},
+};
+use mybops::{
+    Error, List, ListMode, SortMode, UserId,
+    storage::{CosmosQuery, QueryDocumentsBuilder, SessionClient, View},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{collections::HashMap, fmt::Write, path::Path, str::FromStr};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    M3u8,
+    Xspf,
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::M3u8 => "m3u8",
+            ExportFormat::Xspf => "xspf",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::M3u8 => "audio/x-mpegurl",
+            ExportFormat::Xspf => "application/xspf+xml",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m3u8" | "m3u" => Ok(ExportFormat::M3u8),
+            "xspf" => Ok(ExportFormat::Xspf),
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("{s} is not a supported export format")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExportOrder {
+    /// The list's sort mode
+    #[default]
+    List,
+    /// Ranked items first and then the list's order
+    Rank,
+    /// Highest score first
+    Score,
+    /// Items filtered and ordered by the list's query
+    Query,
+}
+
+impl FromStr for ExportOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(ExportOrder::List),
+            "rank" => Ok(ExportOrder::Rank),
+            "score" => Ok(ExportOrder::Score),
+            "query" => Ok(ExportOrder::Query),
+            _ => Err(format!("{s} is not a supported export order")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ExportItem {
+    position: usize,
+    id: String,
+    name: String,
+    /// File path for local library items and URI for Spotify items
+    location: Option<String>,
+    rating: Option<i32>,
+    score: i32,
+    wins: i32,
+    losses: i32,
+    metadata: Map<String, Value>,
+}
+
+impl ExportItem {
+    fn artists(&self) -> Option<String> {
+        let artists: Vec<_> = self
+            .metadata
+            .get("artists")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+        (!artists.is_empty()).then(|| artists.join(", "))
+    }
+
+    fn string(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).and_then(Value::as_str)
+    }
+
+    fn number(&self, key: &str) -> Option<i64> {
+        self.metadata.get(key).and_then(Value::as_i64)
+    }
+}
+
+/// Write the items of a list in the given order
+pub async fn export_list(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    list: List,
+    format: ExportFormat,
+    order: ExportOrder,
+) -> Result<Vec<u8>, Error> {
+    let ids: Vec<_> = match (&list.mode, order) {
+        // Views don't store their items so they are always in query order
+        (ListMode::View(_), _) => query::get_view_items(client, user_id, &list)
+            .await?
+            .map(|i| i.id)
+            .collect(),
+        (_, ExportOrder::List) => query::get_list_items(client, user_id, list.clone())
+            .await?
+            .items
+            .into_iter()
+            .flatten()
+            .map(|i| i.id)
+            .collect(),
+        (_, ExportOrder::Query) => {
+            let list = List {
+                sort: SortMode::Query,
+                tiers: mybops::default_tiers(),
+                rating_scale: None,
+                ..list.clone()
+            };
+            query::get_list_items(client, user_id, list)
+                .await?
+                .items
+                .into_iter()
+                .flatten()
+                .map(|i| i.id)
+                .collect()
+        }
+        _ => crate::source::sync::order_by_rank(
+            &list,
+            list.items.iter().map(|i| i.id.clone()).collect(),
+        ),
+    };
+    let mut items = get_items(client, user_id, &ids).await?;
+    if order == ExportOrder::Score {
+        items.sort_by_key(|item| std::cmp::Reverse(item.user_score));
+    }
+    let root = std::env::var("LIBRARY_ROOT").ok();
+    let items: Vec<_> = items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| ExportItem {
+            position: i + 1,
+            location: get_location(&item, root.as_deref()),
+            id: item.id,
+            name: item.name,
+            rating: item.rating,
+            score: item.user_score,
+            wins: item.user_wins,
+            losses: item.user_losses,
+            metadata: item.metadata,
+        })
+        .collect();
+    match format {
+        ExportFormat::M3u8 => Ok(write_m3u8(&items).into_bytes()),
+        ExportFormat::Xspf => Ok(write_xspf(&list.name, &items).into_bytes()),
+        ExportFormat::Csv => write_csv(&items),
+        ExportFormat::Json => Ok(serde_json::to_vec(&items)?),
+    }
+}
+
+/// Items are returned in the order of the IDs and merged or deleted items are skipped
+async fn get_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    ids: &[String],
+) -> Result<Vec<Item>, Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut items: HashMap<_, _> = client
+        .query_documents::<RawItem>(QueryDocumentsBuilder::new(
+            "item",
+            View::List(user_id.clone(), ids.to_vec()),
+            CosmosQuery::new("SELECT * FROM item".into_query()?),
+        ))
+        .await?
+        .into_iter()
+        .map(|item| Ok((item.id.clone(), Item::try_from(item)?)))
+        .collect::<Result<_, Error>>()?;
+    Ok(ids.iter().filter_map(|id| items.remove(id)).collect())
+}
+
+/// Local library paths are relative to `LIBRARY_ROOT`, which is added if the server has one
+fn get_location(item: &Item, root: Option<&str>) -> Option<String> {
+    if item.id.starts_with("spotify:") {
+        return Some(item.id.clone());
+    }
+    if !item.id.starts_with("local:") {
+        return None;
+    }
+    let path = item.metadata.get("path")?.as_str()?;
+    Some(match root {
+        Some(root) => Path::new(root).join(path).to_string_lossy().into_owned(),
+        None => path.to_owned(),
+    })
+}
+
+/// Items without a location can't be played so they are left out
+fn write_m3u8(items: &[ExportItem]) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for item in items {
+        let Some(location) = &item.location else {
+            continue;
+        };
+        let duration = item.number("duration_ms").map_or(-1, |ms| ms / 1000);
+        let title = match item.artists() {
+            Some(artists) => format!("{artists} - {}", item.name),
+            None => item.name.clone(),
+        };
+        writeln!(playlist, "#EXTINF:{duration},{}", title.replace('\n', " ")).unwrap();
+        writeln!(playlist, "{location}").unwrap();
+    }
+    playlist
+}
+
+fn write_xspf(name: &str, items: &[ExportItem]) -> String {
+    let mut playlist = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    writeln!(playlist, "  <title>{}</title>", escape_xml(name)).unwrap();
+    playlist.push_str("  <trackList>\n");
+    for item in items {
+        playlist.push_str("    <track>\n");
+        let mut element = |tag: &str, value: &str| {
+            writeln!(playlist, "      <{tag}>{}</{tag}>", escape_xml(value)).unwrap()
+        };
+        if let Some(location) = &item.location {
+            element("location", &file_uri(location));
+        }
+        element("identifier", &item.id);
+        element("title", &item.name);
+        if let Some(artists) = item.artists() {
+            element("creator", &artists);
+        }
+        if let Some(album) = item.string("album") {
+            element("album", album);
+        }
+        if let Some(number) = item.number("track_number") {
+            element("trackNum", &number.to_string());
+        }
+        if let Some(duration) = item.number("duration_ms") {
+            element("duration", &duration.to_string());
+        }
+        playlist.push_str("    </track>\n");
+    }
+    playlist.push_str("  </trackList>\n</playlist>\n");
+    playlist
+}
+
+fn write_csv(items: &[ExportItem]) -> Result<Vec<u8>, Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let write_error = |e: csv::Error| Error::internal_error(format!("Failed to write CSV: {e}"));
+    writer
+        .write_record([
+            "position",
+            "id",
+            "name",
+            "artists",
+            "album",
+            "duration_ms",
+            "rating",
+            "score",
+            "wins",
+            "losses",
+            "location",
+        ])
+        .map_err(write_error)?;
+    for item in items {
+        writer
+            .write_record([
+                item.position.to_string(),
+                item.id.clone(),
+                item.name.clone(),
+                item.artists().unwrap_or_default(),
+                item.string("album").unwrap_or_default().to_owned(),
+                item.number("duration_ms")
+                    .map(|ms| ms.to_string())
+                    .unwrap_or_default(),
+                item.rating.map(|r| r.to_string()).unwrap_or_default(),
+                item.score.to_string(),
+                item.wins.to_string(),
+                item.losses.to_string(),
+                item.location.clone().unwrap_or_default(),
+            ])
+            .map_err(write_error)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| Error::internal_error(format!("Failed to write CSV: {e}")))
+}
+
+/// XSPF locations are URIs so file paths are percent-encoded
+fn file_uri(location: &str) -> String {
+    if location.starts_with("spotify:") {
+        return location.to_owned();
+    }
+    let path = location
+        .split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/");
+    if location.starts_with('/') {
+        format!("file://{path}")
+    } else {
+        path
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExportItem;
+    use serde_json::json;
+
+    fn items() -> Vec<ExportItem> {
+        vec![
+            ExportItem {
+                position: 1,
+                id: "local:track:abc".to_owned(),
+                name: "Airbag".to_owned(),
+                location: Some("/music/OK Computer/01 Airbag.flac".to_owned()),
+                rating: Some(9),
+                score: 1600,
+                wins: 2,
+                losses: 0,
+                metadata: json!({"artists": ["Radiohead"], "album": "OK Computer", "duration_ms": 284000, "track_number": 1})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            },
+            ExportItem {
+                position: 2,
+                id: "spotify:track:1".to_owned(),
+                name: "Tom & Jerry".to_owned(),
+                location: Some("spotify:track:1".to_owned()),
+                rating: None,
+                score: 1500,
+                wins: 0,
+                losses: 0,
+                metadata: serde_json::Map::new(),
+            },
+            ExportItem {
+                position: 3,
+                id: "goodreads:book:1".to_owned(),
+                name: "Book".to_owned(),
+                location: None,
+                rating: None,
+                score: 1400,
+                wins: 0,
+                losses: 2,
+                metadata: serde_json::Map::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_write_m3u8() {
+        assert_eq!(
+            super::write_m3u8(&items()),
+            "#EXTM3U
+#EXTINF:284,Radiohead - Airbag
+/music/OK Computer/01 Airbag.flac
+#EXTINF:-1,Tom & Jerry
+spotify:track:1
+"
+        );
+    }
+
+    #[test]
+    fn test_write_xspf() {
+        let playlist = super::write_xspf("Best <3", &items());
+        assert!(playlist.contains("<title>Best &lt;3</title>"));
+        assert!(playlist.contains(
+            "<location>file:///music/OK%20Computer/01%20Airbag.flac</location>
+      <identifier>local:track:abc</identifier>
+      <title>Airbag</title>
+      <creator>Radiohead</creator>
+      <album>OK Computer</album>
+      <trackNum>1</trackNum>
+      <duration>284000</duration>"
+        ));
+        assert!(playlist.contains("<title>Tom &amp; Jerry</title>"));
+        assert_eq!(playlist.matches("<track>").count(), 3);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        assert_eq!(
+            String::from_utf8(super::write_csv(&items()).unwrap()).unwrap(),
+            "position,id,name,artists,album,duration_ms,rating,score,wins,losses,location
+1,local:track:abc,Airbag,Radiohead,OK Computer,284000,9,1600,2,0,/music/OK Computer/01 Airbag.flac
+2,spotify:track:1,Tom & Jerry,,,,,1500,0,0,spotify:track:1
+3,goodreads:book:1,Book,,,,,1400,0,2,
+"
+        );
+    }
+}
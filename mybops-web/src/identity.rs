@@ -0,0 +1,570 @@
+use crate::{Item, RawItem, query::IntoQuery};
+use futures::{StreamExt, TryStreamExt};
+use mybops::{
+    DuplicateGroup, DuplicateItem, Error, IdentifySummary, ItemMetadata, List, RawList, UserId,
+    storage::{
+        CosmosQuery, DocumentWriter, QueryDocumentsBuilder, ReplaceDocumentBuilder, SessionClient,
+        View,
+    },
+};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+/// Metadata key set on items that were merged into another item
+pub const MERGED_INTO: &str = "merged_into";
+/// Metadata key for the MusicBrainz recording ID, which is empty if the ISRC wasn't found
+const RECORDING_MBID: &str = "recording_mbid";
+/// MusicBrainz allows one request per second so lookups are done in small batches
+const MUSICBRAINZ_BATCH: usize = 25;
+
+#[derive(Debug, Deserialize)]
+struct Alias {
+    id: String,
+    merged_into: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IsrcResponse {
+    recordings: Vec<Recording>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recording {
+    id: String,
+}
+
+/// Group items that share an ISRC, MusicBrainz recording or normalized title and artist
+pub async fn get_duplicates(
+    client: &impl SessionClient,
+    user_id: &UserId,
+) -> Result<Vec<DuplicateGroup>, Error> {
+    Ok(group_duplicates(&get_items(client, user_id).await?))
+}
+
+/// Merge duplicate items into the target item.
+///
+/// Match records are added to the target and scores are combined by adding each duplicate's
+/// score difference from the starting score. Duplicates are kept as hidden aliases so that
+/// sources that still provide them are mapped to the target.
+pub async fn merge_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    target: &str,
+    ids: &[String],
+) -> Result<(), Error> {
+    if ids.is_empty() || ids.iter().any(|id| id == target) {
+        return Err(Error::client_error(
+            "Merge requires a target and at least one other item",
+        ));
+    }
+    let mut items: HashMap<_, _> = get_items(client, user_id)
+        .await?
+        .into_iter()
+        .map(|item| (item.id.clone(), item))
+        .collect();
+    let mut target = items.remove(target).ok_or(Error::NotFound)?;
+    let duplicates = ids
+        .iter()
+        .map(|id| items.remove(id).ok_or(Error::NotFound))
+        .collect::<Result<Vec<_>, _>>()?;
+    if std::iter::once(&target)
+        .chain(&duplicates)
+        .any(|item| item.metadata.contains_key(MERGED_INTO))
+    {
+        return Err(Error::client_error("Items have already been merged"));
+    }
+
+    merge_stats(&mut target, &duplicates);
+    let aliases: HashMap<_, _> = ids
+        .iter()
+        .map(|id| (id.clone(), target.id.clone()))
+        .collect();
+    let lists: Vec<RawList> = client
+        .query_documents(QueryDocumentsBuilder::new(
+            "list",
+            View::User(user_id.clone()),
+            CosmosQuery::new("SELECT * FROM list".into_query()?),
+        ))
+        .await?;
+    for list in lists {
+        let mut list: List = list.try_into()?;
+        if !list.items.iter().any(|i| aliases.contains_key(&i.id)) {
+            continue;
+        }
+        list.items = apply_aliases(list.items, &aliases);
+        if let Some(item) = list.items.iter_mut().find(|i| i.id == target.id) {
+            item.name.clone_from(&target.name);
+            item.iframe.clone_from(&target.iframe);
+        }
+        crate::source::update_list(client, user_id, list).await?;
+    }
+
+    let target_id = target.id.clone();
+    let writes = duplicates
+        .into_iter()
+        .map(|mut item| {
+            item.user_score = 1500;
+            item.user_wins = 0;
+            item.user_losses = 0;
+            item.hidden = true;
+            item.metadata
+                .insert(MERGED_INTO.to_owned(), Value::String(target_id.clone()));
+            item
+        })
+        .chain(std::iter::once(target))
+        .map(RawItem::from)
+        .collect::<Vec<_>>();
+    // Stats and the merged identity are separate updates so item updates don't touch metadata
+    for collection_name in ["item", "item_identity"] {
+        futures::stream::iter(writes.iter().cloned().map(|item| {
+            client.write_document(DocumentWriter::Replace(ReplaceDocumentBuilder {
+                collection_name,
+                document_name: item.id.clone(),
+                partition_key: user_id.clone(),
+                document: item,
+            }))
+        }))
+        .buffered(5)
+        .try_collect::<()>()
+        .await?;
+    }
+    Ok(())
+}
+
+/// Replace merged items with the item they were merged into
+pub async fn resolve_aliases(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    items: Vec<ItemMetadata>,
+) -> Result<Vec<ItemMetadata>, Error> {
+    let aliases: Vec<Alias> = client
+        .query_documents(QueryDocumentsBuilder::new(
+            "item",
+            View::User(user_id.clone()),
+            CosmosQuery::new(
+                "SELECT id, metadata->>'merged_into' AS merged_into FROM item WHERE metadata->>'merged_into' IS NOT NULL"
+                    .into_query()?,
+            ),
+        ))
+        .await?;
+    if aliases.is_empty() {
+        return Ok(items);
+    }
+    let aliases = aliases
+        .into_iter()
+        .map(|alias| (alias.id, alias.merged_into))
+        .collect();
+    Ok(apply_aliases(items, &aliases))
+}
+
+/// Look up MusicBrainz recordings for items with an ISRC.
+///
+/// Remasters usually have a different ISRC but belong to the same MusicBrainz recording.
+pub async fn identify_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+) -> Result<IdentifySummary, Error> {
+    let mut pending: Vec<_> = get_items(client, user_id)
+        .await?
+        .into_iter()
+        .filter(|item| {
+            !item.metadata.contains_key(MERGED_INTO)
+                && !item.metadata.contains_key(RECORDING_MBID)
+                && item.metadata.get("isrc").is_some_and(Value::is_string)
+        })
+        .collect();
+    let remaining = pending.len().saturating_sub(MUSICBRAINZ_BATCH);
+    pending.truncate(MUSICBRAINZ_BATCH);
+
+    let http = Client::new();
+    let mut items = Vec::with_capacity(pending.len());
+    let mut matched = 0;
+    for (i, mut item) in pending.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let isrc = item.metadata["isrc"].as_str().unwrap_or_default();
+        let mbid = get_recording_mbid(&http, isrc).await?;
+        if mbid.is_some() {
+            matched += 1;
+        }
+        // Only the new key is sent so that it is merged into the existing metadata
+        item.metadata = [(
+            RECORDING_MBID.to_owned(),
+            Value::String(mbid.unwrap_or_default()),
+        )]
+        .into_iter()
+        .collect();
+        items.push(item);
+    }
+    let summary = IdentifySummary {
+        items: items.len(),
+        matched,
+        remaining,
+    };
+    crate::source::merge_item_metadata(client, items).await?;
+    Ok(summary)
+}
+
+async fn get_recording_mbid(http: &Client, isrc: &str) -> Result<Option<String>, Error> {
+    let resp = http
+        .get(format!("https://musicbrainz.org/ws/2/isrc/{isrc}"))
+        .query(&[("fmt", "json")])
+        // MusicBrainz blocks requests without a meaningful user agent
+        .header(
+            "User-Agent",
+            "mybops/0.1 ( https://github.com/bngo92/zeroflops )",
+        )
+        .send()
+        .await?;
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let resp: IsrcResponse = resp.error_for_status()?.json().await?;
+    Ok(resp.recordings.into_iter().next().map(|r| r.id))
+}
+
+async fn get_items(client: &impl SessionClient, user_id: &UserId) -> Result<Vec<Item>, Error> {
+    client
+        .query_documents::<RawItem>(QueryDocumentsBuilder::new(
+            "item",
+            View::User(user_id.clone()),
+            CosmosQuery::new("SELECT * FROM item".into_query()?),
+        ))
+        .await?
+        .into_iter()
+        .map(Item::try_from)
+        .collect()
+}
+
+/// Lowercase alphanumeric title without version suffixes like "- 2011 Remaster" or "(Live)"
+pub fn normalize_title(name: &str) -> String {
+    let name = name.to_lowercase();
+    let name = name.split(" - ").next().unwrap_or_default();
+    let mut depth = 0usize;
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            c if depth == 0 && c.is_alphanumeric() => normalized.push(c),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+fn get_keys(item: &Item) -> Vec<(&'static str, String)> {
+    let mut keys = Vec::new();
+    if let Some(isrc) = item.metadata.get("isrc").and_then(Value::as_str) {
+        keys.push(("ISRC", format!("isrc:{}", isrc.to_uppercase())));
+    }
+    if let Some(mbid) = item.metadata.get(RECORDING_MBID).and_then(Value::as_str)
+        && !mbid.is_empty()
+    {
+        keys.push(("MusicBrainz recording", format!("mbid:{mbid}")));
+    }
+    let title = normalize_title(&item.name);
+    let artist = item
+        .metadata
+        .get("artists")
+        .and_then(|artists| artists.get(0))
+        .and_then(Value::as_str)
+        .map(normalize_title);
+    if let Some(artist) = artist
+        && !title.is_empty()
+    {
+        keys.push((
+            "Title and artist",
+            format!("name:{}:{title}:{artist}", item.r#type),
+        ));
+    }
+    keys
+}
+
+pub fn group_duplicates(items: &[Item]) -> Vec<DuplicateGroup> {
+    let items: Vec<_> = items
+        .iter()
+        .filter(|item| !item.metadata.contains_key(MERGED_INTO))
+        .collect();
+    let mut parents: Vec<_> = (0..items.len()).collect();
+    fn find(parents: &mut [usize], i: usize) -> usize {
+        if parents[i] != i {
+            parents[i] = find(parents, parents[i]);
+        }
+        parents[i]
+    }
+
+    let mut seen = HashMap::new();
+    let mut edges = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        for (reason, key) in get_keys(item) {
+            if let Some(&j) = seen.get(&key) {
+                let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+                parents[a] = b;
+                edges.push((j, reason));
+            } else {
+                seen.insert(key, i);
+            }
+        }
+    }
+
+    let mut groups: Vec<(usize, Vec<usize>)> = Vec::new();
+    let mut positions = HashMap::new();
+    for i in 0..items.len() {
+        let root = find(&mut parents, i);
+        let position = *positions.entry(root).or_insert_with(|| {
+            groups.push((root, Vec::new()));
+            groups.len() - 1
+        });
+        groups[position].1.push(i);
+    }
+    groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(root, mut members)| {
+            let mut reasons = Vec::new();
+            for &(j, reason) in &edges {
+                if find(&mut parents, j) == root && !reasons.contains(&reason) {
+                    reasons.push(reason);
+                }
+            }
+            // The item with the most matches is the suggested target
+            members.sort_by_key(|&i| {
+                let item = items[i];
+                (
+                    std::cmp::Reverse(item.user_wins + item.user_losses),
+                    item.rating.is_none(),
+                )
+            });
+            DuplicateGroup {
+                reasons: reasons.into_iter().map(str::to_owned).collect(),
+                items: members
+                    .into_iter()
+                    .map(|i| duplicate_item(items[i]))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn duplicate_item(item: &Item) -> DuplicateItem {
+    DuplicateItem {
+        id: item.id.clone(),
+        name: item.name.clone(),
+        album: item
+            .metadata
+            .get("album")
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+        artists: item
+            .metadata
+            .get("artists")
+            .and_then(Value::as_array)
+            .map(|artists| {
+                artists
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        rating: item.rating,
+        score: item.user_score,
+        wins: item.user_wins,
+        losses: item.user_losses,
+    }
+}
+
+fn merge_stats(target: &mut Item, duplicates: &[Item]) {
+    for item in duplicates {
+        target.user_score += item.user_score - 1500;
+        target.user_wins += item.user_wins;
+        target.user_losses += item.user_losses;
+        if target.rating.is_none() && item.rating.is_some() {
+            target.rating = item.rating;
+            target.rating_scale = Some(item.rating_scale());
+        }
+        target.hidden &= item.hidden;
+    }
+}
+
+/// Rename aliased items and combine them with any other entries for the same item
+fn apply_aliases(items: Vec<ItemMetadata>, aliases: &HashMap<String, String>) -> Vec<ItemMetadata> {
+    let targets: HashSet<_> = aliases.values().collect();
+    let mut positions = HashMap::new();
+    let mut merged: Vec<ItemMetadata> = Vec::with_capacity(items.len());
+    for mut item in items {
+        if let Some(target) = aliases.get(&item.id) {
+            item.id.clone_from(target);
+        }
+        // Lists can contain the same item more than once so only merged items are combined
+        if !targets.contains(&item.id) {
+            merged.push(item);
+            continue;
+        }
+        if let Some(&i) = positions.get(&item.id) {
+            let existing: &mut ItemMetadata = &mut merged[i];
+            existing.score += item.score - 1500;
+            existing.wins += item.wins;
+            existing.losses += item.losses;
+            existing.rank = match (existing.rank, item.rank) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+        } else {
+            positions.insert(item.id.clone(), merged.len());
+            merged.push(item);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Item;
+    use mybops::{ItemMetadata, RatingScale};
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+
+    fn item(id: &str, name: &str, metadata: Value) -> Item {
+        Item {
+            id: id.to_owned(),
+            user_id: "user".to_owned(),
+            r#type: "track".to_owned(),
+            name: name.to_owned(),
+            iframe: None,
+            rating: None,
+            rating_scale: None,
+            user_score: 1500,
+            user_wins: 0,
+            user_losses: 0,
+            metadata: serde_json::from_value(metadata).unwrap(),
+            hidden: false,
+        }
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(super::normalize_title("Karma Police"), "karmapolice");
+        assert_eq!(
+            super::normalize_title("Karma Police - 2017 Remaster"),
+            "karmapolice"
+        );
+        assert_eq!(
+            super::normalize_title("Karma Police (Live) [Bonus]"),
+            "karmapolice"
+        );
+        assert_eq!(super::normalize_title("Café"), "café");
+    }
+
+    #[test]
+    fn test_group_duplicates() {
+        let mut live = item(
+            "spotify:track:3",
+            "Karma Police - Live",
+            json!({"artists": ["Radiohead"], "isrc": "GBAYE0000003"}),
+        );
+        live.user_wins = 2;
+        let items = [
+            item(
+                "spotify:track:1",
+                "Karma Police",
+                json!({"artists": ["Radiohead"], "isrc": "GBAYE9700001"}),
+            ),
+            item(
+                "spotify:track:2",
+                "Karma Police - Remastered",
+                json!({"artists": ["Radiohead"], "isrc": "gbaye9700001"}),
+            ),
+            live,
+            item(
+                "spotify:track:4",
+                "Karma Police",
+                json!({"artists": ["Radiohead"], "merged_into": "spotify:track:1"}),
+            ),
+            item(
+                "spotify:track:5",
+                "Karma Police",
+                json!({"artists": ["Another Artist"]}),
+            ),
+            item("custom", "Karma Police", json!({})),
+        ];
+        let groups = super::group_duplicates(&items);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].reasons, ["ISRC", "Title and artist"]);
+        assert_eq!(
+            groups[0]
+                .items
+                .iter()
+                .map(|i| i.id.as_str())
+                .collect::<Vec<_>>(),
+            ["spotify:track:3", "spotify:track:1", "spotify:track:2"]
+        );
+        assert_eq!(groups[0].items[0].artists, ["Radiohead"]);
+    }
+
+    #[test]
+    fn test_merge_stats() {
+        let mut target = item("1", "", json!({}));
+        target.user_score = 1600;
+        target.user_wins = 3;
+        target.user_losses = 1;
+        let mut duplicate = item("2", "", json!({}));
+        duplicate.user_score = 1450;
+        duplicate.user_wins = 1;
+        duplicate.user_losses = 2;
+        duplicate.rating = Some(80);
+        duplicate.rating_scale = Some(RatingScale::Hundred);
+        super::merge_stats(&mut target, &[duplicate]);
+        assert_eq!(
+            (
+                target.user_score,
+                target.user_wins,
+                target.user_losses,
+                target.rating,
+                target.rating_scale
+            ),
+            (1550, 4, 3, Some(80), Some(RatingScale::Hundred))
+        );
+    }
+
+    #[test]
+    fn test_apply_aliases() {
+        let list_item = |id: &str, score, wins, rank| ItemMetadata {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            iframe: None,
+            score,
+            wins,
+            losses: 0,
+            rank,
+            tier: None,
+        };
+        let aliases: HashMap<_, _> = [("2".to_owned(), "1".to_owned())].into_iter().collect();
+        assert_eq!(
+            super::apply_aliases(
+                vec![
+                    list_item("3", 1500, 0, None),
+                    list_item("3", 1500, 0, None),
+                    list_item("2", 1520, 1, Some(2)),
+                    list_item("1", 1510, 2, Some(5)),
+                ],
+                &aliases
+            ),
+            [
+                list_item("3", 1500, 0, None),
+                list_item("3", 1500, 0, None),
+                ItemMetadata {
+                    id: "1".to_owned(),
+                    ..list_item("2", 1530, 3, Some(2))
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use mybops::{
+    Error, UserId,
+    storage::{CreateDocumentBuilder, DocumentWriter, SessionClient},
+};
+use serde::Serialize;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+/// Snapshots are written in one statement that reads each item's current score and rank
+#[derive(Debug, Serialize)]
+pub struct ItemSnapshots {
+    pub user_id: String,
+    pub list_id: String,
+    pub ts: String,
+    pub item_ids: String,
+}
+
+/// Record the score and rank of items after a match
+pub async fn record_snapshots(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    list_id: &str,
+    item_ids: &[&str],
+) -> Result<(), Error> {
+    let ts = OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .unwrap()
+        .format(&Rfc3339)
+        .map_err(|e| Error::internal_error(e.to_string()))?;
+    client
+        .write_document(DocumentWriter::Create(CreateDocumentBuilder {
+            collection_name: "item_history",
+            document: ItemSnapshots {
+                user_id: user_id.0.clone(),
+                list_id: list_id.to_owned(),
+                ts,
+                item_ids: serde_json::to_string(item_ids)?,
+            },
+            is_upsert: false,
+        }))
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use crate::query::test::{Mock, TestSessionClient};
+    use mybops::{UserId, storage::DocumentWriter};
+
+    #[tokio::test]
+    async fn test_record_snapshots() {
+        let client = TestSessionClient {
+            get_mock: Mock::empty(),
+            query_mock: Mock::empty(),
+            write_mock: Mock::new(vec![()]),
+        };
+        super::record_snapshots(
+            &client,
+            &UserId("user".to_owned()),
+            "list",
+            &["win", "lose"],
+        )
+        .await
+        .unwrap();
+        let writes = client.write_mock.call_args.lock().unwrap();
+        let DocumentWriter::Create(builder) = &writes[0] else {
+            unreachable!()
+        };
+        assert_eq!(builder.collection_name, "item_history");
+        assert!(builder.document.contains(r#""list_id":"list""#));
+        assert!(
+            builder
+                .document
+                .contains(r#""item_ids":"[\"win\",\"lose\"]""#)
+        );
+    }
+}
@@ -1,25 +1,35 @@
 #[cfg(feature = "azure")]
 use azure_data_cosmos::prelude::CosmosEntity;
-use mybops::{Error, ItemMetadata};
+use mybops::{Error, ItemMetadata, RatingScale};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
+pub mod compare;
+pub mod export;
+pub mod identity;
+pub mod item_history;
 pub mod query;
 pub mod source;
 pub mod user;
 
-pub const ITEM_FIELDS: [&str; 9] = [
+pub const ITEM_FIELDS: [&str; 11] = [
     "id",
     "type",
     "name",
     "iframe",
     "rating",
+    "rating_scale",
+    "normalized_rating",
     "user_score",
     "user_wins",
     "user_losses",
     "hidden",
 ];
 
+/// Columns of the item_history view, which also has the metadata of each snapshot's item
+pub const ITEM_HISTORY_FIELDS: [&str; 7] =
+    ["item_id", "list_id", "ts", "score", "rank", "name", "type"];
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Token {
     pub access_token: String,
@@ -34,6 +44,9 @@ pub struct Item {
     pub name: String,
     pub iframe: Option<String>,
     pub rating: Option<i32>,
+    /// Scale the rating was entered with, which defaults to the scale of the item's type
+    #[serde(default)]
+    pub rating_scale: Option<RatingScale>,
     pub user_score: i32,
     pub user_wins: i32,
     pub user_losses: i32,
@@ -41,7 +54,7 @@ pub struct Item {
     pub hidden: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RawItem {
     pub id: String,
     pub user_id: String,
@@ -49,6 +62,11 @@ pub struct RawItem {
     pub name: String,
     pub iframe: Option<String>,
     pub rating: Option<i32>,
+    #[serde(default)]
+    pub rating_scale: Option<String>,
+    /// Rating out of 100 for comparing ratings across scales
+    #[serde(default)]
+    pub normalized_rating: Option<f64>,
     pub user_score: i32,
     pub user_wins: i32,
     pub user_losses: i32,
@@ -56,8 +74,16 @@ pub struct RawItem {
     pub hidden: bool,
 }
 
+impl Item {
+    pub fn rating_scale(&self) -> RatingScale {
+        self.rating_scale
+            .unwrap_or_else(|| RatingScale::for_type(&self.r#type))
+    }
+}
+
 impl From<Item> for RawItem {
     fn from(i: Item) -> RawItem {
+        let normalized_rating = i.rating.map(|rating| i.rating_scale().normalize(rating));
         RawItem {
             id: i.id,
             user_id: i.user_id,
@@ -65,6 +91,8 @@ impl From<Item> for RawItem {
             name: i.name,
             iframe: i.iframe,
             rating: i.rating,
+            rating_scale: i.rating_scale.map(|scale| scale.id().to_owned()),
+            normalized_rating,
             user_score: i.user_score,
             user_wins: i.user_wins,
             user_losses: i.user_losses,
@@ -84,6 +112,10 @@ impl TryFrom<RawItem> for Item {
             name: i.name,
             iframe: i.iframe,
             rating: i.rating,
+            rating_scale: i
+                .rating_scale
+                .map(|scale| scale.parse().map_err(Error::internal_error))
+                .transpose()?,
             user_score: i.user_score,
             user_wins: i.user_wins,
             user_losses: i.user_losses,
@@ -108,3 +140,43 @@ pub fn convert_items(items: &[Item]) -> Vec<ItemMetadata> {
         .map(|i| ItemMetadata::new(i.id.clone(), i.name.clone(), i.iframe.clone()))
         .collect()
 }
+
+#[cfg(test)]
+mod test {
+    use mybops::RatingScale;
+    use serde_json::Map;
+
+    #[test]
+    fn test_normalized_rating() {
+        let item = |r#type: &str, rating, rating_scale| super::Item {
+            id: String::new(),
+            user_id: String::new(),
+            r#type: r#type.to_owned(),
+            name: String::new(),
+            iframe: None,
+            rating,
+            rating_scale,
+            user_score: 1500,
+            user_wins: 0,
+            user_losses: 0,
+            metadata: Map::new(),
+            hidden: false,
+        };
+        let normalized = |item| super::RawItem::from(item).normalized_rating;
+        assert_eq!(normalized(item("track", Some(7), None)), Some(70.));
+        // Films default to half stars so 7 is 3.5 stars
+        assert_eq!(normalized(item("film", Some(7), None)), Some(70.));
+        assert_eq!(
+            normalized(item("track", Some(85), Some(RatingScale::Hundred))),
+            Some(85.)
+        );
+        assert_eq!(
+            normalized(item("track", Some(0), Some(RatingScale::LikeDislike))),
+            Some(0.)
+        );
+        assert_eq!(normalized(item("track", None, None)), None);
+        assert_eq!(RatingScale::HalfStars.label(7), "3.5 ★");
+        assert_eq!(RatingScale::Hundred.convert(73, RatingScale::HalfStars), 7);
+        assert_eq!(RatingScale::Ten.convert(2, RatingScale::LikeDislike), 0);
+    }
+}
@@ -6,19 +6,24 @@ use async_trait::async_trait;
 use axum::{
     Router,
     body::Bytes,
-    extract::{Host, OriginalUri, Path, Query, State},
-    http::StatusCode,
+    extract::{DefaultBodyLimit, Host, OriginalUri, Path, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
     response::{IntoResponse, Json, Redirect, Response},
-    routing::{get, post},
+    routing::{delete, get, post, put},
 };
 use axum_login::{
     AuthManagerLayerBuilder,
-    tower_sessions::{Expiry, SessionManagerLayer},
+    tower_sessions::{ExpiredDeletion, Expiry, Session, SessionManagerLayer},
 };
 use futures::{TryStreamExt, stream::FuturesUnordered};
 use mybops::{
-    Error, Id, InternalError, Items, List, ListMode, Lists, RawList, UserId,
-    spotify::{Playlists, RecentTracks},
+    Credentials, DuplicateGroup, Error, Id, IdentifySummary, InternalError, Items, List, ListMode,
+    Lists, LoginProvider, MergeItems, PasswordChange, RawList, SessionInfo, SortMode,
+    TasteComparison, UserId,
+    import::ImportFormat,
+    scrobble::{ScrobbleKind, ScrobbleService},
+    spotify::{HistorySummary, Playlists, RecentTracks, SearchResults},
     storage::{
         CosmosQuery, CreateDocumentBuilder, DeleteDocumentBuilder, DocumentWriter,
         GetDocumentBuilder, QueryDocumentsBuilder, ReplaceDocumentBuilder, SessionClient,
@@ -26,16 +31,21 @@ use mybops::{
     },
 };
 use mybops_web::{
-    Item, RawItem,
+    Item, RawItem, compare,
+    export::{self, ExportFormat, ExportOrder},
+    identity, item_history,
     query::{self, IntoQuery},
-    source::{self, spotify},
-    user::{self, Auth, GoogleClient, SqlStore, User},
+    source::{self, history, import, spotify},
+    user::{
+        self, Auth, DEMO_USER, Device, GoogleClient, SqlStore, User,
+        oidc::{self, AuthorizationRequest, OidcClient, OidcConfig},
+    },
 };
 use rusqlite::Connection;
 use serde_arrow::schema::{SchemaLike, TracingOptions};
 use serde_json::{Map, Value};
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use time::Duration;
+use time::{Duration, OffsetDateTime};
 #[cfg(feature = "dev")]
 use tower_http::services::ServeFile;
 use tower_http::trace::TraceLayer;
@@ -52,6 +62,7 @@ fn get_user_or_demo_user(auth: AuthContext) -> UserId {
     }
 }
 
+#[allow(clippy::result_large_err)]
 fn require_user(auth: AuthContext) -> Result<User, Response> {
     if let Some(user) = auth.user {
         Ok(user)
@@ -60,7 +71,15 @@ fn require_user(auth: AuthContext) -> Result<User, Response> {
     }
 }
 
-const DEMO_USER: &str = "demo";
+const OIDC_SESSION_KEY: &str = "oidc";
+
+fn get_origin(host: &str, path: &str) -> String {
+    if cfg!(feature = "dev") {
+        format!("http://{host}{path}")
+    } else {
+        format!("https://{host}{path}")
+    }
+}
 
 async fn login_handler(
     OriginalUri(original_uri): OriginalUri,
@@ -69,15 +88,7 @@ async fn login_handler(
     auth: AuthContext,
     Host(host): Host,
 ) -> Result<impl IntoResponse, Response> {
-    let origin;
-    #[cfg(feature = "dev")]
-    {
-        origin = format!("http://{}{}", host, original_uri.path());
-    }
-    #[cfg(not(feature = "dev"))]
-    {
-        origin = format!("https://{}{}", host, original_uri.path());
-    }
+    let origin = get_origin(&host, original_uri.path());
     user::spotify_login(
         Connection::open(state.sql_store.path).map_err(Error::from)?,
         SpotifyClient,
@@ -90,24 +101,94 @@ async fn login_handler(
 }
 
 // TODO: fix rerender on logout
-async fn logout_handler(
-    State(state): State<Arc<AppState>>,
-    mut auth: AuthContext,
-) -> impl IntoResponse {
-    if let Some(user) = &mut auth.user {
-        // Log out of all sessions with axum-login by changing the user secret
-        user.secret = user::generate_secret();
-        let conn = Connection::open(state.sql_store.path).expect("Couldn't reset password");
-        conn.execute(
-            "UPDATE user SET secret = ?1 WHERE id = ?2",
-            [&user.secret, &user.id],
-        )
-        .expect("Couldn't reset password");
+async fn logout_handler(mut auth: AuthContext) -> impl IntoResponse {
+    if auth.user.is_some() {
         auth.logout().await.unwrap();
     }
     Redirect::to("/")
 }
 
+/// Remembers which device a logged in session belongs to
+async fn record_device(
+    auth: AuthContext,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    if auth.user.is_some()
+        && let Ok(None) = session.get::<Device>(user::DEVICE_KEY).await
+    {
+        let device = Device {
+            user_agent: request
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|user_agent| user_agent.to_str().ok())
+                .map(String::from),
+            created: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+        if let Err(e) = session.insert(user::DEVICE_KEY, device).await {
+            tracing::warn!("Failed to record device: {e}");
+        }
+    }
+    next.run(request).await
+}
+
+async fn get_sessions(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+    session: Session,
+) -> Result<Json<Vec<SessionInfo>>, Response> {
+    let user = require_user(auth)?;
+    let current = session.id();
+    let sessions = state
+        .sql_store
+        .get_sessions(&user)?
+        .into_iter()
+        .map(|record| {
+            let device: Option<Device> = record
+                .data
+                .get(user::DEVICE_KEY)
+                .and_then(|device| serde_json::from_value(device.clone()).ok());
+            SessionInfo {
+                id: user::get_public_session_id(&record.id),
+                user_agent: device.as_ref().and_then(|device| device.user_agent.clone()),
+                created: device.map(|device| device.created),
+                expires: record.expiry_date.unix_timestamp(),
+                current: Some(record.id) == current,
+            }
+        })
+        .collect();
+    Ok(Json(sessions))
+}
+
+async fn revoke_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    auth: AuthContext,
+) -> Result<StatusCode, Response> {
+    let user = require_user(auth)?;
+    if state.sql_store.delete_sessions(&user, |record| {
+        user::get_public_session_id(&record.id) == id
+    })? == 0
+    {
+        return Err(Error::NotFound.into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn revoke_other_sessions(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+    session: Session,
+) -> Result<StatusCode, Response> {
+    let user = require_user(auth)?;
+    let current = session.id();
+    state
+        .sql_store
+        .delete_sessions(&user, |record| Some(record.id) != current)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 async fn google_login_handler(
     OriginalUri(original_uri): OriginalUri,
     State(state): State<Arc<AppState>>,
@@ -115,15 +196,7 @@ async fn google_login_handler(
     auth: AuthContext,
     Host(host): Host,
 ) -> Result<impl IntoResponse, Response> {
-    let origin;
-    #[cfg(feature = "dev")]
-    {
-        origin = format!("http://{}{}", host, original_uri.path());
-    }
-    #[cfg(not(feature = "dev"))]
-    {
-        origin = format!("https://{}{}", host, original_uri.path());
-    }
+    let origin = get_origin(&host, original_uri.path());
     user::google_login(
         Connection::open(state.sql_store.path).map_err(Error::from)?,
         GoogleClient,
@@ -135,6 +208,170 @@ async fn google_login_handler(
     Ok(Redirect::to("/"))
 }
 
+async fn oidc_start_handler(
+    State(state): State<Arc<AppState>>,
+    session: Session,
+    Host(host): Host,
+) -> Result<impl IntoResponse, Response> {
+    let Some(config) = &state.oidc else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    let metadata = oidc::discover(&reqwest::Client::new(), &config.issuer).await?;
+    let request = AuthorizationRequest::new();
+    session
+        .insert(OIDC_SESSION_KEY, &request)
+        .await
+        .map_err(|e| Error::internal_error(e.to_string()))?;
+    Ok(Redirect::to(&request.url(
+        config,
+        &metadata,
+        &get_origin(&host, "/api/login/oidc"),
+    )))
+}
+
+async fn oidc_login_handler(
+    OriginalUri(original_uri): OriginalUri,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    session: Session,
+    auth: AuthContext,
+    Host(host): Host,
+) -> Result<impl IntoResponse, Response> {
+    let Some(config) = &state.oidc else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    let request: Option<AuthorizationRequest> = session
+        .remove(OIDC_SESSION_KEY)
+        .await
+        .map_err(|e| Error::internal_error(e.to_string()))?;
+    let (Some(request), Some(code)) = (request, params.get("code")) else {
+        return Err(Error::client_error("Login request is missing or expired").into());
+    };
+    if params.get("state") != Some(&request.state) {
+        return Err(Error::client_error("Login state doesn't match").into());
+    }
+    let metadata = oidc::discover(&reqwest::Client::new(), &config.issuer).await?;
+    user::oidc_login(
+        Connection::open(state.sql_store.path).map_err(Error::from)?,
+        OidcClient {
+            config: config.clone(),
+            metadata,
+            request,
+        },
+        &mut AuthWrapper(auth),
+        code,
+        &get_origin(&host, original_uri.path()),
+    )
+    .await?;
+    Ok(Redirect::to("/"))
+}
+
+async fn login_providers(
+    State(state): State<Arc<AppState>>,
+    Host(host): Host,
+) -> Json<Vec<LoginProvider>> {
+    let mut providers = vec![
+        LoginProvider {
+            id: String::from("spotify"),
+            name: String::from("Spotify"),
+            url: SpotifyClient::authorize_url(&get_origin(&host, "/api/login")),
+        },
+        LoginProvider {
+            id: String::from("google"),
+            name: String::from("Google"),
+            url: GoogleClient::authorize_url(&get_origin(&host, "/api/login/google")),
+        },
+    ];
+    if let Some(config) = &state.oidc {
+        providers.push(LoginProvider {
+            id: String::from("oidc"),
+            name: config.name.clone(),
+            url: String::from("/api/login/oidc/start"),
+        });
+    }
+    Json(providers)
+}
+
+async fn signup_handler(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+    Json(credentials): Json<Credentials>,
+) -> Result<StatusCode, Response> {
+    user::signup(
+        Connection::open(state.sql_store.path).map_err(Error::from)?,
+        &mut AuthWrapper(auth),
+        credentials,
+    )
+    .await?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn password_login_handler(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+    Json(credentials): Json<Credentials>,
+) -> Result<StatusCode, Response> {
+    user::password_login(
+        Connection::open(state.sql_store.path).map_err(Error::from)?,
+        &mut AuthWrapper(auth),
+        credentials,
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn change_password_handler(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+    Json(change): Json<PasswordChange>,
+) -> Result<StatusCode, Response> {
+    if auth.user.is_none() {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    }
+    user::change_password(
+        Connection::open(state.sql_store.path).map_err(Error::from)?,
+        &mut AuthWrapper(auth),
+        change,
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unlink_login_handler(
+    State(state): State<Arc<AppState>>,
+    Path(provider): Path<String>,
+    auth: AuthContext,
+) -> Result<StatusCode, Response> {
+    let user = require_user(auth)?;
+    user::unlink_login(
+        Connection::open(state.sql_store.path).map_err(Error::from)?,
+        &user,
+        &provider,
+    )?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_user_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    auth: AuthContext,
+) -> Result<StatusCode, Response> {
+    if auth.user.is_none() {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    }
+    user::delete_user(
+        Connection::open(state.sql_store.path).map_err(Error::from)?,
+        Connection::open(state.sql_client.path).map_err(Error::from)?,
+        &mut AuthWrapper(auth),
+        params
+            .get("confirm")
+            .map(String::as_str)
+            .unwrap_or_default(),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[async_trait]
 impl Auth for AuthWrapper {
     fn current_user(&self) -> &Option<User> {
@@ -189,6 +426,11 @@ async fn get_list(
             .await?
             .collect();
         list.items = items;
+    } else if list.sort != SortMode::Query {
+        let ids = list.items.iter().map(|i| i.id.clone()).collect();
+        let ids = query::sort_items(&state.sql_client, &user_id, &list, ids).await?;
+        let mut items: HashMap<_, _> = list.items.drain(..).map(|i| (i.id.clone(), i)).collect();
+        list.items = ids.iter().filter_map(|id| items.remove(id)).collect();
     }
     Ok(Json(list))
 }
@@ -217,6 +459,45 @@ async fn query_list(
     Ok(serialize_arrow(records)?)
 }
 
+async fn export_list(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    auth: AuthContext,
+) -> Result<impl IntoResponse, Response> {
+    let user_id = get_user_or_demo_user(auth);
+    let format: ExportFormat = params
+        .get("format")
+        .ok_or(Error::client_error("format is required"))?
+        .parse()
+        .map_err(Error::client_error)?;
+    let order = match params.get("order") {
+        Some(order) => order.parse().map_err(Error::client_error)?,
+        None => ExportOrder::default(),
+    };
+    let list = source::get_list(&state.sql_client, &user_id, &id).await?;
+    let filename: String = list
+        .name
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .collect();
+    let body = export::export_list(&state.sql_client, &user_id, list, format, order).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}.{}\"",
+                    filename.trim(),
+                    format.extension()
+                ),
+            ),
+        ],
+        body,
+    ))
+}
+
 fn serialize_arrow(mut records: Vec<Map<String, Value>>) -> Result<Vec<u8>, Error> {
     records = records
         .into_iter()
@@ -365,6 +646,48 @@ async fn handle_action(
                 return Ok(push_list(state, &mut user, id).await?);
             }
         }
+        Some("sync") => {
+            if let Some(id) = params.get("list") {
+                let mut user = require_user(auth)?;
+                return Ok(sync_list(state, &mut user, id).await?);
+            }
+        }
+        Some("refresh") => {
+            if let Some(id) = params.get("list") {
+                let user = require_user(auth)?;
+                let user_id = UserId(user.user_id.clone());
+                let list = source::get_list(&state.sql_client, &user_id, id).await?;
+                source::update_list_items(&state.sql_client, &user_id, list).await?;
+                return Ok(StatusCode::OK);
+            }
+        }
+        Some("reorder") => {
+            if let Some(id) = params.get("list") {
+                let user = require_user(auth)?;
+                let user_id = UserId(user.user_id.clone());
+                let order: Vec<String> = serde_json::from_slice(&body).map_err(Error::from)?;
+                source::reorder_items(&state.sql_client, &user_id, id, &order).await?;
+                return Ok(StatusCode::NO_CONTENT);
+            }
+        }
+        Some("tiers") => {
+            if let Some(id) = params.get("list") {
+                let user = require_user(auth)?;
+                let user_id = UserId(user.user_id.clone());
+                let tiers: HashMap<String, String> =
+                    serde_json::from_slice(&body).map_err(Error::from)?;
+                source::set_tiers(&state.sql_client, &user_id, id, &tiers).await?;
+                return Ok(StatusCode::NO_CONTENT);
+            }
+        }
+        Some("resolve") => {
+            if let (Some(id), Some(item)) = (params.get("list"), params.get("item")) {
+                let user = require_user(auth)?;
+                let user_id = UserId(user.user_id.clone());
+                source::sync::resolve_conflict(&state.sql_client, &user_id, id, item).await?;
+                return Ok(StatusCode::OK);
+            }
+        }
         Some("import") => {
             if let (Some(source), Some(id)) = (params.remove("source"), params.remove("id")) {
                 let user = require_user(auth)?;
@@ -445,6 +768,7 @@ async fn handle_stats_update(
         })),
     )
     .await?;
+    item_history::record_snapshots(client, &user_id, id, &[win, lose]).await?;
     Ok(StatusCode::OK)
 }
 
@@ -472,7 +796,8 @@ async fn push_list(state: Arc<AppState>, user: &mut User, id: &str) -> Result<St
         id.id
     };
     let ids: Vec<_> = match list.mode {
-        ListMode::User(_) => query::get_list_items(&state.sql_client, &user_id, list)
+        // Items are pushed in the list's sort order
+        ListMode::User(_) => query::get_list_items(&state.sql_client, &user_id, list.clone())
             .await?
             .items
             .into_iter()
@@ -488,6 +813,14 @@ async fn push_list(state: Arc<AppState>, user: &mut User, id: &str) -> Result<St
     Ok(StatusCode::OK)
 }
 
+async fn sync_list(state: Arc<AppState>, user: &mut User, id: &str) -> Result<StatusCode, Error> {
+    let user_id = UserId(user.user_id.clone());
+    let list = source::get_list(&state.sql_client, &user_id, id).await?;
+    let access_token = spotify::get_access_token(&state.sql_client, user).await?;
+    source::sync::sync_list(&state.sql_client, &user_id, access_token, list).await?;
+    Ok(StatusCode::OK)
+}
+
 async fn import_list(
     state: Arc<AppState>,
     user_id: UserId,
@@ -502,6 +835,7 @@ async fn import_list(
         }
         _ => todo!(),
     };
+    source::smart::refresh_lists(&state.sql_client, &user_id).await?;
     Ok(StatusCode::CREATED)
 }
 
@@ -608,12 +942,24 @@ async fn update_items(
                     "rating" => {
                         item.rating = serde_json::from_value(v)?;
                     }
+                    "rating_scale" => {
+                        let scale: String = serde_json::from_value(v)?;
+                        item.rating_scale = Some(scale.parse().map_err(Error::client_error)?);
+                    }
                     "hidden" => {
                         item.hidden = serde_json::from_value(v)?;
                     }
                     _ => {}
                 }
             }
+            if let Some(rating) = item.rating
+                && !item.rating_scale().values().contains(&rating)
+            {
+                return Err(Error::client_error(format!(
+                    "{rating} is not a rating on the {} scale",
+                    item.rating_scale().name()
+                )));
+            }
             state
                 .sql_client
                 .write_document(DocumentWriter::Replace(ReplaceDocumentBuilder {
@@ -627,6 +973,7 @@ async fn update_items(
         .collect::<FuturesUnordered<_>>()
         .try_collect::<()>()
         .await?;
+    source::smart::refresh_lists(&state.sql_client, user_id).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -654,9 +1001,61 @@ async fn delete_items(
         .collect::<FuturesUnordered<_>>()
         .try_collect::<()>()
         .await?;
+    source::smart::refresh_lists(&state.sql_client, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_duplicates(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+) -> Result<Json<Vec<DuplicateGroup>>, Response> {
+    let user = require_user(auth)?;
+    let user_id = UserId(user.user_id);
+    Ok(Json(
+        identity::get_duplicates(&state.sql_client, &user_id).await?,
+    ))
+}
+
+async fn compare_users(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    auth: AuthContext,
+) -> Result<Json<TasteComparison>, Response> {
+    let user_id = get_user_or_demo_user(auth);
+    let other = UserId(
+        params
+            .get("user")
+            .ok_or(Error::client_error("user is required"))?
+            .to_owned(),
+    );
+    Ok(Json(
+        compare::compare(&state.sql_client, &user_id, &other).await?,
+    ))
+}
+
+async fn merge_items(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+    Json(merge): Json<MergeItems>,
+) -> Result<StatusCode, Response> {
+    let user = require_user(auth)?;
+    let user_id = UserId(user.user_id);
+    identity::merge_items(&state.sql_client, &user_id, &merge.target, &merge.ids).await?;
+    source::smart::refresh_lists(&state.sql_client, &user_id).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn identify_items(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+) -> Result<Json<IdentifySummary>, Response> {
+    let user = require_user(auth)?;
+    let user_id = UserId(user.user_id);
+    Ok(Json(
+        identity::identify_items(&state.sql_client, &user_id).await?,
+    ))
+}
+
 async fn user_handler(auth: AuthContext) -> Result<Json<mybops::User>, Response> {
     let user = require_user(auth)?;
     Ok(Json(mybops::User {
@@ -664,6 +1063,8 @@ async fn user_handler(auth: AuthContext) -> Result<Json<mybops::User>, Response>
         spotify_user: user.spotify_credentials.as_ref().map(|c| c.user_id.clone()),
         spotify_url: user.spotify_credentials.map(|c| c.url),
         google_email: user.google_email,
+        has_password: user.password.is_some(),
+        oidc_linked: user.oidc_subject.is_some(),
     }))
 }
 
@@ -694,9 +1095,133 @@ async fn get_spotify_playlists(
     Ok(Json(spotify::get_playlists(access_token).await?))
 }
 
+async fn search_spotify(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    auth: AuthContext,
+) -> Result<Json<SearchResults>, Response> {
+    let user = require_user(auth)?;
+    let user_id = UserId(user.user_id);
+    let Some(query) = params.get("q") else {
+        return Err(Error::client_error("q is required").into());
+    };
+    let types = params
+        .get("type")
+        .map(String::as_str)
+        .unwrap_or("track,album,artist,playlist");
+    let offset = params
+        .get("offset")
+        .map(|offset| offset.parse())
+        .transpose()
+        .map_err(|_| Error::client_error("invalid offset"))?
+        .unwrap_or(0);
+    let limit = params
+        .get("limit")
+        .map(|limit| limit.parse())
+        .transpose()
+        .map_err(|_| Error::client_error("invalid limit"))?
+        .unwrap_or(20);
+    let token = spotify::get_token().await?;
+    Ok(Json(
+        spotify::search(
+            &state.sql_client,
+            &user_id,
+            &token,
+            query,
+            types,
+            offset,
+            limit,
+        )
+        .await?,
+    ))
+}
+
+async fn upload_spotify_history(
+    State(state): State<Arc<AppState>>,
+    auth: AuthContext,
+    body: Bytes,
+) -> Result<Json<HistorySummary>, Response> {
+    let user = require_user(auth)?;
+    let user_id = UserId(user.user_id);
+    let entries = history::parse_history(&body)?;
+    Ok(Json(
+        history::import_history(&state.sql_client, &user_id, entries).await?,
+    ))
+}
+
+/// Create a list from the most played entries in a Last.fm or ListenBrainz export
+async fn import_scrobbles(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    auth: AuthContext,
+    body: Bytes,
+) -> Result<impl IntoResponse, Response> {
+    let user = require_user(auth)?;
+    let user_id = UserId(user.user_id);
+    let service: ScrobbleService = params
+        .get("service")
+        .ok_or(Error::client_error("service is required"))?
+        .parse()
+        .map_err(Error::client_error)?;
+    let kind: ScrobbleKind = params
+        .get("type")
+        .map(String::as_str)
+        .unwrap_or("tracks")
+        .parse()
+        .map_err(Error::client_error)?;
+    let items = source::scrobble::import_export(&user_id, service, kind, &body).await?;
+    let list = List::new(
+        Uuid::new_v4().to_hyphenated().to_string(),
+        &user_id,
+        ListMode::User(None),
+        format!("{} top {}", service.name(), kind.id()),
+        Vec::new(),
+        None,
+        mybops_web::convert_items(&items),
+    );
+    source::create_items(&state.sql_client, items, false).await?;
+    create_list_doc(&state.sql_client, list.clone(), false).await?;
+    Ok((StatusCode::CREATED, Json(list)))
+}
+
+/// Import a file export and update the lists that use it, creating a list on the first import
+async fn import_file(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    auth: AuthContext,
+    body: Bytes,
+) -> Result<impl IntoResponse, Response> {
+    let user = require_user(auth)?;
+    let user_id = UserId(user.user_id);
+    let format: ImportFormat = params
+        .get("format")
+        .ok_or(Error::client_error("format is required"))?
+        .parse()
+        .map_err(Error::client_error)?;
+    let items = import::parse_export(format, &user_id, &body)?;
+    import::save_items(&state.sql_client, &user_id, format, items).await?;
+    source::smart::refresh_lists(&state.sql_client, &user_id).await?;
+    if let Some(list) = import::refresh_lists(&state.sql_client, &user_id, format).await? {
+        return Ok((StatusCode::OK, Json(list)));
+    }
+    let (source, items) = import::get_import(&state.sql_client, &user_id, format).await?;
+    let list = List::new(
+        Uuid::new_v4().to_hyphenated().to_string(),
+        &user_id,
+        ListMode::User(None),
+        format.list_name().to_owned(),
+        vec![source],
+        None,
+        items,
+    );
+    create_list_doc(&state.sql_client, list.clone(), false).await?;
+    Ok((StatusCode::CREATED, Json(list)))
+}
+
 struct AppState {
     sql_store: SqlStore,
     sql_client: SqlSessionClient,
+    oidc: Option<OidcConfig>,
 }
 
 #[tokio::main]
@@ -712,6 +1237,7 @@ async fn main() {
     let shared_state = Arc::new(AppState {
         sql_store: session_store.clone(),
         sql_client: SqlSessionClient { path: "data" },
+        oidc: OidcConfig::from_env().expect("OpenID Connect configuration is invalid"),
     });
 
     // Reset demo user data during startup in production
@@ -741,6 +1267,10 @@ async fn main() {
                 favorite: true,
                 query: String::from("SELECT artists, AVG(user_score) FROM item GROUP BY artists"),
                 public: true,
+                sync: None,
+                sort: SortMode::default(),
+                tiers: mybops::default_tiers(),
+                rating_scale: None,
             },
             true,
         )
@@ -759,6 +1289,10 @@ async fn main() {
                 favorite: true,
                 query: String::from("SELECT name, user_score FROM item WHERE user_score >= 1500"),
                 public: true,
+                sync: None,
+                sort: SortMode::default(),
+                tiers: mybops::default_tiers(),
+                rating_scale: None,
             },
             true,
         )
@@ -767,6 +1301,17 @@ async fn main() {
         println!("Demo lists were created");
     }
 
+    let deletion_store = session_store.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = deletion_store.delete_expired().await {
+                tracing::error!("Failed to delete expired sessions: {e}");
+            }
+        }
+    });
+
     let session_layer = SessionManagerLayer::new(session_store.clone())
         .with_secure(false)
         .with_expiry(Expiry::OnInactivity(Duration::seconds(31536000)));
@@ -781,14 +1326,43 @@ async fn main() {
         )
         .route("/lists/:id/items", get(get_list_items))
         .route("/lists/:id/query", get(query_list))
+        .route("/lists/:id/export", get(export_list))
         .route("/items", get(find_items).delete(delete_items))
+        .route("/items/duplicates", get(get_duplicates))
+        .route("/items/merge", post(merge_items))
+        .route("/items/identify", post(identify_items))
+        .route("/compare", get(compare_users))
         .route("/", post(handle_action))
         .route("/login", get(login_handler))
         .route("/login/google", get(google_login_handler))
+        .route("/login/oidc", get(oidc_login_handler))
+        .route("/login/oidc/start", get(oidc_start_handler))
+        .route("/login/password", post(password_login_handler))
+        .route("/login/providers", get(login_providers))
+        .route("/signup", post(signup_handler))
         .route("/logout", get(logout_handler))
-        .route("/user", get(user_handler))
+        .route("/user", get(user_handler).delete(delete_user_handler))
+        .route("/user/logins/:provider", delete(unlink_login_handler))
+        .route("/sessions", get(get_sessions).delete(revoke_other_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+        .route("/user/password", put(change_password_handler))
         .route("/spotify/recentTracks", get(get_spotify_recent_tracks))
         .route("/spotify/playlists", get(get_spotify_playlists))
+        .route("/spotify/search", get(search_spotify))
+        .route(
+            "/scrobbles",
+            post(import_scrobbles).layer(DefaultBodyLimit::max(64 * 1024 * 1024)),
+        )
+        .route(
+            "/imports",
+            post(import_file).layer(DefaultBodyLimit::max(64 * 1024 * 1024)),
+        )
+        // Extended streaming history files are split into roughly 10 MB files
+        .route(
+            "/spotify/history",
+            post(upload_spotify_history).layer(DefaultBodyLimit::max(64 * 1024 * 1024)),
+        )
+        .layer(middleware::from_fn(record_device))
         .with_state(shared_state);
 
     let app = Router::new()
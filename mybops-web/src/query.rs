@@ -1,42 +1,34 @@
-use crate::ITEM_FIELDS;
+use crate::{ITEM_FIELDS, ITEM_HISTORY_FIELDS};
 use mybops::{
-    Error, InternalError, ItemMetadata, Items, List, ListMode, UserId,
+    Error, InternalError, ItemMetadata, Items, List, ListMode, SortMode, UserId,
     storage::{CosmosQuery, QueryDocumentsBuilder, SessionClient, SqlSessionClient, View},
 };
 use serde_json::{Map, Value};
 use sqlparser::{
     ast::{
         Expr, FunctionArg, FunctionArgExpr, Ident, JsonOperator, Query, SelectItem, SetExpr,
-        Statement,
+        Statement, TableFactor,
     },
     dialect::MySqlDialect,
     parser::Parser,
 };
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+};
 
 pub async fn get_view_items(
     client: &impl SessionClient,
     user_id: &UserId,
     list: &List,
 ) -> Result<impl Iterator<Item = ItemMetadata>, Error> {
-    let mut query = list.query.into_query()?;
-    let SetExpr::Select(select) = &mut *query.body else {
+    let query = list.query.into_query()?;
+    let SetExpr::Select(select) = &*query.body else {
         return Err(Error::client_error("Only SELECT queries are supported"));
     };
     // GROUP BY queries create schemas that don't produce items
     let items = if select.group_by.is_empty() {
-        select.projection = ["id", "name", "iframe"]
-            .into_iter()
-            .map(|s| SelectItem::UnnamedExpr(Expr::Identifier(Ident::new(s))))
-            .collect();
-        let (query, _) = rewrite_query(query)?;
-        client
-            .query_documents::<Map<String, Value>>(QueryDocumentsBuilder::new(
-                "item",
-                View::User(user_id.clone()),
-                CosmosQuery::new(query),
-            ))
-            .await?
+        query_items(client, user_id, query).await?
     } else {
         Vec::new()
     };
@@ -48,9 +40,33 @@ pub async fn get_view_items(
         wins: 0,
         losses: 0,
         rank: None,
+        tier: None,
     }))
 }
 
+/// Select the id, name and iframe of the user's items that match a query
+pub async fn query_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    mut query: Query,
+) -> Result<Vec<Map<String, Value>>, Error> {
+    let SetExpr::Select(select) = &mut *query.body else {
+        return Err(Error::client_error("Only SELECT queries are supported"));
+    };
+    select.projection = ["id", "name", "iframe"]
+        .into_iter()
+        .map(|s| SelectItem::UnnamedExpr(Expr::Identifier(Ident::new(s))))
+        .collect();
+    let (query, _) = rewrite_query(query)?;
+    client
+        .query_documents(QueryDocumentsBuilder::new(
+            "item",
+            View::User(user_id.clone()),
+            CosmosQuery::new(query),
+        ))
+        .await
+}
+
 /// Filter list items with the query set for the list
 pub async fn get_list_items(
     client: &impl SessionClient,
@@ -70,10 +86,7 @@ pub async fn get_list_items(
         let mut items: Vec<_> = client
             .query_documents::<Map<String, Value>>(QueryDocumentsBuilder::new(
                 "item",
-                View::List(
-                    user_id.clone(),
-                    list.items.iter().map(|i| i.id.clone()).collect(),
-                ),
+                View::ListTiers(user_id.clone(), list.id.clone()),
                 CosmosQuery::new(query.clone()),
             ))
             .await
@@ -81,8 +94,8 @@ pub async fn get_list_items(
             .into_iter()
             .map(|r| r["id"].as_str().unwrap().to_owned())
             .collect();
-        // Use list item order if an ordering wasn't provided
-        if query.order_by.is_empty() {
+        // Use list item order if an ordering wasn't provided or the list has its own sort mode
+        if query.order_by.is_empty() || list.sort != SortMode::Query {
             let item_metadata: HashSet<_> = items.into_iter().collect();
             items = list
                 .items
@@ -96,6 +109,7 @@ pub async fn get_list_items(
                 })
                 .collect();
         };
+        let items = sort_items(client, user_id, &list, items).await?;
         let mut map = HashMap::new();
         for i in &list.items {
             map.insert(i.id.clone(), i);
@@ -115,6 +129,49 @@ pub async fn get_list_items(
     }
 }
 
+/// Order item IDs that are in the list's order by the list's sort mode
+pub async fn sort_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    list: &List,
+    mut ids: Vec<String>,
+) -> Result<Vec<String>, Error> {
+    match list.sort {
+        SortMode::Query | SortMode::Manual => {}
+        SortMode::Score => {
+            let scores: HashMap<_, _> = list.items.iter().map(|i| (&i.id, i.score)).collect();
+            ids.sort_by_key(|id| Reverse(scores.get(id).copied().unwrap_or(i32::MIN)));
+        }
+        SortMode::Rank => ids = crate::source::sync::order_by_rank(list, ids),
+        SortMode::Rating => {
+            if ids.is_empty() {
+                return Ok(ids);
+            }
+            let ratings: HashMap<_, _> = client
+                .query_documents::<Map<String, Value>>(QueryDocumentsBuilder::new(
+                    "item",
+                    View::List(user_id.clone(), ids.clone()),
+                    CosmosQuery::new("SELECT id, normalized_rating FROM item".into_query()?),
+                ))
+                .await?
+                .into_iter()
+                .map(|item| {
+                    (
+                        item["id"].as_str().unwrap_or_default().to_owned(),
+                        item["normalized_rating"].as_f64(),
+                    )
+                })
+                .collect();
+            // Ratings are compared across scales and unrated items go last
+            ids.sort_by(|a, b| {
+                let rating = |id| ratings.get(id).copied().flatten().unwrap_or(f64::MIN);
+                rating(b).total_cmp(&rating(a))
+            });
+        }
+    }
+    Ok(ids)
+}
+
 pub async fn query_list(
     client: &SqlSessionClient,
     user_id: &UserId,
@@ -136,7 +193,7 @@ pub async fn query_list(
             } else {
                 list.query.into_query()?
             }),
-            View::PublicList(list.items.into_iter().map(|i| i.id).collect()),
+            View::PublicListTiers(list.id),
         )
     } else {
         (
@@ -145,10 +202,7 @@ pub async fn query_list(
             } else {
                 list.query.into_query()?
             }),
-            View::List(
-                user_id.clone(),
-                list.items.into_iter().map(|i| i.id).collect(),
-            ),
+            View::ListTiers(user_id.clone(), list.id),
         )
     };
     Ok(client
@@ -170,10 +224,19 @@ pub async fn query_list(
 }
 
 pub fn rewrite_query(query: impl IntoQuery) -> Result<(Query, Vec<String>), Error> {
-    rewrite_query_impl(query.into_query()?)
+    rewrite_query_impl(query.into_query()?, JsonOperator::Arrow)
+}
+
+/// Rewrite a smart list filter, where metadata fields are extracted as SQL values with `->>` so
+/// that metadata numbers compare as numbers instead of JSON text
+pub fn rewrite_filter(query: Query) -> Result<Query, Error> {
+    Ok(rewrite_query_impl(query, JsonOperator::LongArrow)?.0)
 }
 
-fn rewrite_query_impl(mut query: Query) -> Result<(Query, Vec<String>), Error> {
+fn rewrite_query_impl(
+    mut query: Query,
+    operator: JsonOperator,
+) -> Result<(Query, Vec<String>), Error> {
     let SetExpr::Select(select) = &mut *query.body else {
         return Err(Error::client_error("Only SELECT queries are supported"));
     };
@@ -182,10 +245,16 @@ fn rewrite_query_impl(mut query: Query) -> Result<(Query, Vec<String>), Error> {
     if select.from.is_empty() {
         return Err(Error::client_error("FROM clause is omitted"));
     }
+    let fields: &[&str] = match &select.from[0].relation {
+        TableFactor::Table { name, .. } if name.to_string() == "item_history" => {
+            &ITEM_HISTORY_FIELDS
+        }
+        _ => &ITEM_FIELDS,
+    };
     let column_names = select.projection.iter().map(ToString::to_string).collect();
     for expr in &mut select.projection {
         match expr {
-            SelectItem::UnnamedExpr(expr) => rewrite_expr(expr),
+            SelectItem::UnnamedExpr(expr) => rewrite_expr(expr, fields, operator)?,
             // TODO: support alias
             SelectItem::ExprWithAlias { .. } => {
                 return Err(Error::client_error("alias is not supported"));
@@ -197,14 +266,14 @@ fn rewrite_query_impl(mut query: Query) -> Result<(Query, Vec<String>), Error> {
     }
     let mut sanitized_select = select.selection.take();
     if let Some(selection) = &mut sanitized_select {
-        rewrite_expr(selection);
+        rewrite_expr(selection, fields, operator)?;
     }
     select.selection = sanitized_select;
     for expr in &mut select.group_by {
-        rewrite_expr(expr);
+        rewrite_expr(expr, fields, operator)?;
     }
     for expr in &mut query.order_by {
-        rewrite_expr(&mut expr.expr);
+        rewrite_expr(&mut expr.expr, fields, operator)?;
     }
     Ok((query, column_names))
 }
@@ -238,17 +307,17 @@ impl IntoQuery for Query {
     }
 }
 
-fn rewrite_expr(expr: &mut Expr) {
+fn rewrite_expr(expr: &mut Expr, fields: &[&str], operator: JsonOperator) -> Result<(), Error> {
     let mut queue = VecDeque::new();
     queue.push_back(expr);
     while let Some(expr) = queue.pop_front() {
         match expr {
             Expr::Identifier(id) => {
-                *expr = rewrite_identifier(id.clone());
+                *expr = rewrite_identifier(id.clone(), fields, operator)?;
             }
             Expr::InList { expr, .. } => {
                 if let Expr::Identifier(id) = &**expr {
-                    *expr = Box::new(rewrite_identifier(id.clone()));
+                    **expr = rewrite_identifier(id.clone(), fields, operator)?;
                 }
             }
             Expr::BinaryOp { left, op: _, right } => {
@@ -257,27 +326,33 @@ fn rewrite_expr(expr: &mut Expr) {
             }
             Expr::Function(f) => {
                 for arg in &mut f.args {
-                    if let FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) = arg {
-                        if let Expr::Identifier(id) = expr.clone() {
-                            *expr = rewrite_identifier(id);
-                        }
+                    if let FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) = arg
+                        && let Expr::Identifier(id) = expr.clone()
+                    {
+                        *expr = rewrite_identifier(id, fields, operator)?;
                     }
                 }
             }
             _ => {}
         }
     }
+    Ok(())
 }
 
-fn rewrite_identifier(id: Ident) -> Expr {
-    if ITEM_FIELDS.contains(&id.value.as_ref()) {
-        Expr::Identifier(id)
+fn rewrite_identifier(id: Ident, fields: &[&str], operator: JsonOperator) -> Result<Expr, Error> {
+    if fields.contains(&id.value.as_ref()) {
+        Ok(Expr::Identifier(id))
+    } else if id.value == "tier" {
+        // Tiers are kept per list, so only list queries have a tier column
+        Err(Error::client_error(
+            "tier can only be used in the query of a list",
+        ))
     } else {
-        Expr::JsonAccess {
+        Ok(Expr::JsonAccess {
             left: Box::new(Expr::Identifier(Ident::new("metadata"))),
-            operator: JsonOperator::Arrow,
+            operator,
             right: Box::new(Expr::Identifier(Ident::new(format!("'{}'", id.value)))),
-        }
+        })
     }
 }
 
@@ -285,15 +360,47 @@ fn rewrite_identifier(id: Ident) -> Expr {
 pub mod test {
     use async_trait::async_trait;
     use mybops::{
-        Error, ItemMetadata, Items, List, ListMode, UserId,
+        Error, ItemMetadata, Items, List, ListMode, SortMode, UserId,
         storage::{
             CreateDocumentBuilder, DeleteDocumentBuilder, DocumentWriter, GetDocumentBuilder,
-            QueryDocumentsBuilder, ReplaceDocumentBuilder, SessionClient,
+            QueryDocumentsBuilder, ReplaceDocumentBuilder, SessionClient, SqlSessionClient,
         },
     };
+    use rusqlite::Connection;
     use serde::{Serialize, de::DeserializeOwned};
+    use serde_json::Value;
     use std::sync::{Arc, Mutex};
 
+    const SCHEMA: &str = "CREATE TABLE _item (id TEXT, user_id TEXT, type TEXT, name TEXT, iframe TEXT, rating INTEGER, rating_scale TEXT, normalized_rating REAL, user_score INTEGER, user_wins INTEGER, user_losses INTEGER, metadata TEXT, hidden BOOLEAN, PRIMARY KEY (id, user_id));
+        CREATE TABLE _list (id TEXT, user_id TEXT, mode TEXT, name TEXT, sources TEXT, iframe TEXT, items TEXT, favorite BOOLEAN, query TEXT, public BOOLEAN, sync TEXT, sort TEXT, tiers TEXT, rating_scale TEXT, PRIMARY KEY (id, user_id));
+        CREATE TABLE _play (id TEXT, user_id TEXT, item_id TEXT, ts TEXT, ms_played INTEGER, PRIMARY KEY (id, user_id));
+        CREATE TABLE _cache (id TEXT, user_id TEXT, version TEXT, payload TEXT, updated_at INTEGER, PRIMARY KEY (id, user_id));
+        CREATE TABLE _item_history (user_id TEXT, item_id TEXT, list_id TEXT, ts TEXT, score INTEGER, rank INTEGER);";
+
+    /// Create an empty SQLite database in the temp directory
+    pub fn sql_client(name: &str) -> SqlSessionClient {
+        let path =
+            std::env::temp_dir().join(format!("mybops-web-{name}-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(SCHEMA)
+            .unwrap();
+        SqlSessionClient {
+            path: Box::leak(path.to_str().unwrap().to_owned().into_boxed_str()),
+        }
+    }
+
+    pub fn insert_item(client: &SqlSessionClient, user_id: &str, id: &str, metadata: Value) {
+        Connection::open(client.path)
+            .unwrap()
+            .execute(
+                "INSERT INTO _item (id, user_id, type, name, iframe, rating, user_score, user_wins, user_losses, metadata, hidden) VALUES (?1, ?2, 'track', ?1, NULL, NULL, 1500, 0, 0, ?3, false)",
+                (id, user_id, metadata.to_string()),
+            )
+            .unwrap();
+    }
+
     pub struct Mock<T, U> {
         pub call_args: Arc<Mutex<Vec<T>>>,
         side_effect: Arc<Mutex<Vec<Option<U>>>>,
@@ -393,6 +500,10 @@ pub mod test {
             favorite: false,
             query: String::from("SELECT name, user_score FROM item"),
             public: false,
+            sync: None,
+            sort: SortMode::default(),
+            tiers: mybops::default_tiers(),
+            rating_scale: None,
         };
         assert_eq!(
             super::get_list_items(
@@ -410,6 +521,47 @@ pub mod test {
         );
     }
 
+    #[tokio::test]
+    async fn test_query_list_tier() {
+        let client = sql_client("tier");
+        insert_item(&client, "user", "a", Value::Null);
+        insert_item(&client, "user", "b", Value::Null);
+        insert_item(&client, "user", "c", Value::Null);
+        let item = |id: &str, tier: Option<&str>| ItemMetadata {
+            tier: tier.map(ToOwned::to_owned),
+            ..ItemMetadata::new(id.to_owned(), id.to_owned(), None)
+        };
+        let user_id = UserId("user".to_owned());
+        let list = List::new(
+            "list".to_owned(),
+            &user_id,
+            ListMode::User(None),
+            String::new(),
+            Vec::new(),
+            None,
+            vec![item("a", Some("S")), item("b", Some("A")), item("c", None)],
+        );
+        Connection::open(client.path)
+            .unwrap()
+            .execute(
+                "INSERT INTO _list (id, user_id, items) VALUES ('list', 'user', ?1)",
+                [serde_json::to_string(&list.items).unwrap()],
+            )
+            .unwrap();
+        let rows = super::query_list(
+            &client,
+            &user_id,
+            list,
+            Some(&"SELECT id, tier FROM item WHERE tier = 'S'".to_owned()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            Value::from(rows.into_iter().map(Value::Object).collect::<Vec<_>>()),
+            serde_json::json!([{"id": "a", "tier": "S"}])
+        );
+    }
+
     #[tokio::test]
     async fn test_get_list_empty_query() {
         let list = List {
@@ -427,10 +579,15 @@ pub mod test {
                 wins: 0,
                 losses: 0,
                 rank: None,
+                tier: None,
             }],
             favorite: false,
             query: String::from("SELECT name, user_score FROM item"),
             public: false,
+            sync: None,
+            sort: SortMode::default(),
+            tiers: mybops::default_tiers(),
+            rating_scale: None,
         };
         let client = TestSessionClient {
             get_mock: Mock::empty(),
@@ -449,7 +606,8 @@ pub mod test {
                     score: 0,
                     wins: 0,
                     losses: 0,
-                    rank: None
+                    rank: None,
+                    tier: None,
                 })]
             }
         );
@@ -479,10 +637,15 @@ pub mod test {
                 wins: 0,
                 losses: 0,
                 rank: None,
+                tier: None,
             }],
             favorite: false,
             query: String::from("SELECT name, user_score FROM item"),
             public: false,
+            sync: None,
+            sort: SortMode::default(),
+            tiers: mybops::default_tiers(),
+            rating_scale: None,
         };
         let client = TestSessionClient {
             get_mock: Mock::empty(),
@@ -504,6 +667,58 @@ pub mod test {
         );
     }
 
+    #[tokio::test]
+    async fn test_sort_items() {
+        let item = |id: &str, score, rank| ItemMetadata {
+            id: id.to_owned(),
+            name: String::new(),
+            iframe: None,
+            score,
+            wins: 0,
+            losses: 0,
+            rank,
+            tier: None,
+        };
+        let mut list = List::new(
+            String::new(),
+            &UserId(String::new()),
+            ListMode::User(None),
+            String::new(),
+            Vec::new(),
+            None,
+            vec![
+                item("a", 1400, None),
+                item("b", 1600, Some(2)),
+                item("c", 1500, Some(1)),
+            ],
+        );
+        let ids = || vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        let client = TestSessionClient {
+            get_mock: Mock::empty(),
+            query_mock: Mock::new(vec![
+                r#"[{"id":"a","normalized_rating":70.0},{"id":"b","normalized_rating":null},{"id":"c","normalized_rating":90.0}]"#,
+            ]),
+            write_mock: Mock::empty(),
+        };
+        let user_id = UserId(String::new());
+        for (sort, expected) in [
+            (SortMode::Query, ["a", "b", "c"]),
+            (SortMode::Manual, ["a", "b", "c"]),
+            (SortMode::Score, ["b", "c", "a"]),
+            (SortMode::Rank, ["c", "b", "a"]),
+            (SortMode::Rating, ["c", "a", "b"]),
+        ] {
+            list.sort = sort;
+            assert_eq!(
+                super::sort_items(&client, &user_id, &list, ids())
+                    .await
+                    .unwrap(),
+                expected,
+                "{sort:?}"
+            );
+        }
+    }
+
     #[test]
     fn test_select() {
         let (query, column_names) =
@@ -594,6 +809,24 @@ pub mod test {
         assert_eq!(column_names, vec!["name", "user_score"]);
     }
 
+    #[test]
+    fn test_item_history() {
+        for (input, expected) in [
+            (
+                "SELECT ts, score, name FROM item_history WHERE album = 'foo' AND rank <= 10",
+                "SELECT ts, score, name FROM item_history WHERE metadata -> 'album' = 'foo' AND rank <= 10",
+            ),
+            (
+                "SELECT ts, score, name FROM item_history WHERE item_id IN (SELECT id FROM item ORDER BY user_score DESC LIMIT 5)",
+                "SELECT ts, score, name FROM item_history WHERE item_id IN (SELECT id FROM item ORDER BY user_score DESC LIMIT 5)",
+            ),
+        ] {
+            let (query, column_names) = super::rewrite_query(input).unwrap();
+            assert_eq!(query.to_string(), expected);
+            assert_eq!(column_names, vec!["ts", "score", "name"]);
+        }
+    }
+
     #[test]
     fn test_errors() {
         for (input, expected) in [
@@ -614,6 +847,10 @@ pub mod test {
                 "SELECT name, user_score FROM item WHERE user_score IN (1500",
                 "Expected ), found: EOF",
             ),
+            (
+                "SELECT name FROM item WHERE tier = 'S'",
+                "tier can only be used in the query of a list",
+            ),
         ] {
             let err = super::rewrite_query(input).unwrap_err();
             if let Error::ClientError(error) = err {
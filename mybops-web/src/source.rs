@@ -1,35 +1,105 @@
-use crate::RawItem;
+use crate::{RawItem, query::IntoQuery};
 use futures::{StreamExt, TryStreamExt, stream::FuturesUnordered};
 use mybops::{
-    Error, InternalError, ItemMetadata, List, RawList, Source, SourceType, Spotify, UserId,
+    Error, InternalError, ItemMetadata, List, RawList, SortMode, Source, SourceType, Spotify,
+    UserId,
     storage::{
-        CreateDocumentBuilder, DocumentWriter, GetDocumentBuilder, ReplaceDocumentBuilder,
-        SessionClient, View,
+        CosmosQuery, CreateDocumentBuilder, DocumentWriter, GetDocumentBuilder,
+        QueryDocumentsBuilder, ReplaceDocumentBuilder, SessionClient, View,
     },
 };
 use serde_json::{Map, Value};
+use std::collections::HashMap;
 
+pub mod bgg;
+pub mod cache;
+pub mod goodreads;
+pub mod history;
+pub mod igdb;
+pub mod import;
+pub mod letterboxd;
+pub mod local;
+pub mod scrobble;
 pub mod setlist;
+pub mod smart;
 pub mod spotify;
+pub mod sync;
 
+/// Update the items of a list and the lists that inherit from it
 pub async fn update_list_items(
     client: &impl SessionClient,
     user_id: &UserId,
-    mut list: List,
+    list: List,
 ) -> Result<(), Error> {
+    update_list_items_if(client, user_id, list, |_| true).await
+}
+
+/// Update the items of a list and the lists that inherit from it that `include` accepts
+pub async fn update_list_items_if(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    list: List,
+    include: impl Fn(&List) -> bool,
+) -> Result<(), Error> {
+    let id = list.id.clone();
+    if !update_single_list(client, user_id, list).await? {
+        return Ok(());
+    }
+    // Lists are only visited once in case a cycle was saved before cycles were rejected
+    let mut visited = vec![id.clone()];
+    let mut changed = vec![id];
+    while let Some(id) = changed.pop() {
+        for parent in get_parent_lists(client, user_id, &id).await? {
+            if visited.contains(&parent.id) || !include(&parent) {
+                continue;
+            }
+            visited.push(parent.id.clone());
+            let parent_id = parent.id.clone();
+            if update_single_list(client, user_id, parent).await? {
+                changed.push(parent_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return whether the items in the list changed
+async fn update_single_list(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    mut list: List,
+) -> Result<bool, Error> {
     let current_list = get_list(client, user_id, &list.id).await?;
+    // Sync state is only updated by syncing and is reset if the list is pointed at a new playlist
+    list.sync = if list.mode == current_list.mode {
+        current_list.sync.clone()
+    } else {
+        None
+    };
     // Avoid updating list items if sources haven't changed as reading from source can be expensive
-    // TODO: we should also check the snapshot ID
     let source_update = current_list
         .sources
         .iter()
         .map(|s| &s.source_type)
         .ne(list.sources.iter().map(|s| &s.source_type));
     // Always update items if ListItems is used as the underlying list could change at any time
-    let list_source = current_list
-        .sources
-        .iter()
-        .any(|s| matches!(s.source_type, SourceType::ListItems(_)));
+    // Spotify playlists can also change but the cache avoids refetching unchanged snapshots
+    // Imports are read back from the item table so they pick up items from the latest upload
+    // Local libraries only read files that were added or changed since the last scan
+    // Smart lists are re-evaluated as the items matching the filter can change
+    let list_source = leaf_source_types(&current_list.sources).any(|source_type| {
+        matches!(
+            source_type,
+            SourceType::ListItems(_)
+                | SourceType::Spotify(Spotify::Playlist(_))
+                | SourceType::Import(_)
+                | SourceType::LocalLibrary(_)
+                | SourceType::Query(_)
+        )
+    });
+    if source_update {
+        validate_sources(client, user_id, &list).await?;
+    }
     if source_update || list_source {
         list.items.clear();
         let sources = list.sources;
@@ -46,12 +116,214 @@ pub async fn update_list_items(
             list.sources.push(source);
             list.items.extend(items);
         }
+        if !list.items.is_empty() {
+            list.items = crate::identity::resolve_aliases(client, user_id, list.items).await?;
+        }
+        // Keep the scores of items that stay in the list so refreshing doesn't reset matches
+        let current: HashMap<_, _> = current_list.items.iter().map(|i| (&i.id, i)).collect();
+        for item in &mut list.items {
+            if let Some(current) = current.get(&item.id) {
+                item.score = current.score;
+                item.wins = current.wins;
+                item.losses = current.losses;
+                item.rank = current.rank;
+                item.tier.clone_from(&current.tier);
+            }
+        }
+        // Manually ordered lists keep their order and new items are added to the end
+        if list.sort == SortMode::Manual {
+            let positions: HashMap<_, _> = current_list
+                .items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| (item.id.clone(), i))
+                .collect();
+            list.items
+                .sort_by_key(|item| positions.get(&item.id).copied().unwrap_or(usize::MAX));
+        }
+    } else {
+        // Keep source names and match reports as they aren't sent back when editing a list
+        list.sources = current_list.sources;
+    }
+    let changed = list
+        .items
+        .iter()
+        .map(|i| &i.id)
+        .ne(current_list.items.iter().map(|i| &i.id));
+    // Items fall out of tiers that were removed in the list settings
+    for item in &mut list.items {
+        if item
+            .tier
+            .as_ref()
+            .is_some_and(|tier| !list.tiers.contains(tier))
+        {
+            item.tier = None;
+        }
     }
     list.update_iframe();
     update_list(client, user_id, list).await?;
+    Ok(changed)
+}
+
+/// Check that a list doesn't include itself through the lists it uses as data sources
+///
+/// Only list sources are read so that sources are checked before anything is fetched, and cycles
+/// are returned as client errors.
+pub async fn validate_sources(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    list: &List,
+) -> Result<(), Error> {
+    let mut list = list.clone();
+    list.sources = leaf_sources(list.sources);
+    // Each entry is a list on the current path and the index of the next source to visit
+    let mut path = vec![(list, 0)];
+    while let Some((current, i)) = path.last_mut() {
+        let Some(source) = current.sources.get(*i).cloned() else {
+            path.pop();
+            continue;
+        };
+        *i += 1;
+        let SourceType::ListItems(id) = &source.source_type else {
+            continue;
+        };
+        if let Some(start) = path.iter().position(|(list, _)| &list.id == id) {
+            let names: Vec<_> = path[start..]
+                .iter()
+                .chain(&path[start..=start])
+                .map(|(list, _)| list.name.as_str())
+                .collect();
+            return Err(Error::client_error(format!(
+                "Lists can't include themselves through their data sources: {}",
+                names.join(" → ")
+            )));
+        }
+        let mut child = get_source_list(client, user_id, id).await?;
+        child.sources = leaf_sources(child.sources);
+        path.push((child, 0));
+    }
     Ok(())
 }
 
+/// Whether all of the list's sources are read from the database, so that updating its items doesn't
+/// call other services or read the local library
+pub fn is_database_list(list: &List) -> bool {
+    leaf_source_types(&list.sources).all(|source_type| {
+        matches!(
+            source_type,
+            SourceType::Query(_)
+                | SourceType::ListItems(_)
+                | SourceType::Import(_)
+                | SourceType::Custom(_)
+        )
+    })
+}
+
+/// Source types of the sources with composites replaced by the sources they combine
+pub fn leaf_source_types(sources: &[Source]) -> impl Iterator<Item = &SourceType> {
+    sources.iter().flat_map(|source| match &source.source_type {
+        SourceType::Composite(composite) => composite.leaves(),
+        source_type => vec![source_type],
+    })
+}
+
+fn leaf_sources(sources: Vec<Source>) -> Vec<Source> {
+    let mut leaves = Vec::with_capacity(sources.len());
+    for source in sources {
+        let SourceType::Composite(composite) = &source.source_type else {
+            leaves.push(source);
+            continue;
+        };
+        leaves.extend(composite.leaves().into_iter().map(|source_type| Source {
+            source_type: source_type.clone(),
+            name: String::new(),
+            unmatched: Vec::new(),
+        }));
+    }
+    leaves
+}
+
+async fn get_source_list(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    id: &str,
+) -> Result<List, Error> {
+    get_list(client, user_id, id).await.map_err(|e| match e {
+        Error::NotFound => {
+            Error::client_error(format!("List {id} used as a data source doesn't exist"))
+        }
+        e => e,
+    })
+}
+
+/// Lists of the user that use the list as a data source
+async fn get_parent_lists(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    id: &str,
+) -> Result<Vec<List>, Error> {
+    let lists: Vec<RawList> = client
+        .query_documents(QueryDocumentsBuilder::new(
+            "list",
+            View::User(user_id.clone()),
+            CosmosQuery::new("SELECT * FROM list".into_query()?),
+        ))
+        .await?;
+    let mut parents = Vec::new();
+    for list in lists {
+        let list: List = list.try_into()?;
+        if leaf_source_types(&list.sources)
+            .any(|source_type| matches!(source_type, SourceType::ListItems(source) if source == id))
+        {
+            parents.push(list);
+        }
+    }
+    Ok(parents)
+}
+
+/// Move items to the given order and switch the list to manual sorting
+///
+/// Items that aren't in the order keep their relative order after the ordered items.
+pub async fn reorder_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    id: &str,
+    order: &[String],
+) -> Result<(), Error> {
+    let mut list = get_list(client, user_id, id).await?;
+    if list.user_id != user_id.0 {
+        return Err(Error::client_error("Only your own lists can be reordered"));
+    }
+    let positions: HashMap<_, _> = order.iter().enumerate().map(|(i, id)| (id, i)).collect();
+    list.items
+        .sort_by_key(|item| positions.get(&item.id).copied().unwrap_or(usize::MAX));
+    list.sort = SortMode::Manual;
+    update_list(client, user_id, list).await
+}
+
+/// Place items into tiers with items that aren't given a tier left untiered
+pub async fn set_tiers(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    id: &str,
+    tiers: &HashMap<String, String>,
+) -> Result<(), Error> {
+    let mut list = get_list(client, user_id, id).await?;
+    if list.user_id != user_id.0 {
+        return Err(Error::client_error("Only your own lists can be tiered"));
+    }
+    if let Some(tier) = tiers.values().find(|tier| !list.tiers.contains(tier)) {
+        return Err(Error::client_error(format!(
+            "{tier} is not a tier of {}",
+            list.name
+        )));
+    }
+    for item in &mut list.items {
+        item.tier = tiers.get(&item.id).cloned();
+    }
+    update_list(client, user_id, list).await
+}
+
 pub async fn update_list(
     client: &impl SessionClient,
     user_id: &UserId,
@@ -68,6 +340,55 @@ pub async fn update_list(
 }
 
 async fn get_source_and_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    source: Source,
+) -> Result<(Source, Vec<ItemMetadata>), Error> {
+    let SourceType::Composite(composite) = source.source_type else {
+        return get_leaf_source_and_items(client, user_id, source).await;
+    };
+    let leaves = composite.leaves();
+    let sources: Vec<_> = leaves
+        .iter()
+        .map(|&source_type| Source {
+            source_type: source_type.clone(),
+            name: String::new(),
+            unmatched: Vec::new(),
+        })
+        .collect();
+    let results = futures::stream::iter(
+        sources
+            .into_iter()
+            .map(|source| get_leaf_source_and_items(client, user_id, source)),
+    )
+    .buffered(5)
+    .try_collect::<Vec<_>>()
+    .await?;
+    let get_result = |source_type: &SourceType| {
+        let i = leaves
+            .iter()
+            .position(|&leaf| leaf == source_type)
+            .expect("leaf should be fetched");
+        &results[i]
+    };
+    let items = composite.evaluate(&|source_type| get_result(source_type).1.clone());
+    let name = composite.format(&|source_type| get_result(source_type).0.name.clone());
+    let unmatched = results
+        .iter()
+        .flat_map(|(source, _)| source.unmatched.iter().cloned())
+        .collect();
+    Ok((
+        Source {
+            source_type: SourceType::Composite(composite),
+            name,
+            unmatched,
+        },
+        items,
+    ))
+}
+
+/// Sources that aren't composites are read directly so that reading sources isn't recursive
+async fn get_leaf_source_and_items(
     client: &impl SessionClient,
     user_id: &UserId,
     mut source: Source,
@@ -78,22 +399,91 @@ async fn get_source_and_items(
             source.name = "Custom".to_owned();
             (source, items)
         }
-        SourceType::Spotify(Spotify::Playlist(id)) => spotify::get_playlist(user_id, id).await?,
-        SourceType::Spotify(Spotify::Album(id)) => spotify::get_album(user_id, id).await?,
-        SourceType::Spotify(Spotify::Track(id)) => spotify::get_track(user_id, id).await?,
+        SourceType::Spotify(spotify) => return get_spotify_source(client, user_id, spotify).await,
         SourceType::Setlist(id) => setlist::get_setlist(user_id, id).await?,
-        // TODO: inherit data sources
+        SourceType::Scrobbles(scrobbles) => scrobble::get_scrobbles(user_id, scrobbles).await?,
+        SourceType::Setlists(setlists) => setlist::get_setlists(user_id, setlists).await?,
+        SourceType::Import(format) => return import::get_import(client, user_id, format).await,
+        SourceType::LocalLibrary(path) => return local::get_library(client, user_id, path).await,
+        SourceType::Query(filter) => return smart::get_smart_items(client, user_id, filter).await,
+        // Lists are updated when a list they inherit from changes so their items are current
         SourceType::ListItems(ref id) => {
-            let list = get_list(client, user_id, id).await?;
+            let list = get_source_list(client, user_id, id).await?;
             source.name = list.name;
             return Ok((source, list.items));
         }
+        SourceType::Composite(_) => {
+            return Err(Error::client_error(
+                "Combined sources can be nested with parentheses",
+            ));
+        }
     };
     let list_items = crate::convert_items(&items);
     create_items(client, items, false).await?;
     Ok((source, list_items))
 }
 
+/// Read a Spotify source through the cache, skipping the item upserts on a cache hit
+async fn get_spotify_source(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    spotify: Spotify,
+) -> Result<(Source, Vec<ItemMetadata>), Error> {
+    let key = cache::spotify_key(&spotify);
+    let entry = cache::get(client, user_id, &key).await?;
+    let mut version = None;
+    if let Some(entry) = entry {
+        let hit = if entry.is_fresh(cache::spotify_ttl(&spotify), cache::now()) {
+            Some(entry)
+        } else if let Spotify::Playlist(id) = &spotify {
+            let snapshot_id = spotify::get_playlist_snapshot_id(&id.id).await?;
+            if entry.version.as_ref() == Some(&snapshot_id) {
+                // The playlist hasn't changed so the entry is good for another TTL
+                cache::touch(client, user_id, &entry).await?;
+                Some(entry)
+            } else {
+                version = Some(snapshot_id);
+                None
+            }
+        } else {
+            None
+        };
+        if let Some(entry) = hit {
+            let cached = entry.source()?;
+            return Ok((
+                Source {
+                    source_type: SourceType::Spotify(spotify),
+                    name: cached.name,
+                    unmatched: Vec::new(),
+                },
+                cached.items,
+            ));
+        }
+    } else if let Spotify::Playlist(id) = &spotify {
+        version = Some(spotify::get_playlist_snapshot_id(&id.id).await?);
+    }
+    let (source, items) = match spotify {
+        Spotify::Playlist(id) => spotify::get_playlist(user_id, id).await?,
+        Spotify::Album(id) => spotify::get_album(user_id, id).await?,
+        Spotify::Track(id) => spotify::get_track(user_id, id).await?,
+    };
+    let list_items = crate::convert_items(&items);
+    merge_item_metadata(client, items).await?;
+    let cached = cache::CachedSource {
+        name: source.name,
+        items: list_items,
+    };
+    cache::put(client, user_id, key, version, &cached).await?;
+    Ok((
+        Source {
+            source_type: source.source_type,
+            name: cached.name,
+            unmatched: Vec::new(),
+        },
+        cached.items,
+    ))
+}
+
 // TODO: support arbitrary input
 fn get_custom_items(user_id: &UserId, value: &Value) -> Result<Vec<super::Item>, Error> {
     let Value::Array(a) = value else {
@@ -130,6 +520,7 @@ fn new_custom_item(
         name,
         iframe: None,
         rating: None,
+        rating_scale: None,
         user_score: 1500,
         user_wins: 0,
         user_losses: 0,
@@ -197,14 +588,80 @@ pub async fn create_items(
         .await
 }
 
+/// Create items or merge their metadata into items that exist, keeping ratings and stats
+pub async fn merge_item_metadata(
+    client: &impl SessionClient,
+    items: Vec<super::Item>,
+) -> Result<(), Error> {
+    items
+        .into_iter()
+        .map(|item| {
+            client.write_document(DocumentWriter::Create(CreateDocumentBuilder {
+                collection_name: "item_metadata",
+                document: RawItem::from(item),
+                is_upsert: true,
+            }))
+        })
+        .collect::<FuturesUnordered<_>>()
+        .try_collect()
+        .await
+}
+
 #[cfg(test)]
 mod test {
     use crate::query::test::{Mock, TestSessionClient};
     use mybops::{
-        List, ListMode, Source, SourceType, UserId,
-        storage::{DocumentWriter, ReplaceDocumentBuilder},
+        Error, Id, ItemMetadata, List, ListMode, SortMode, Source, SourceType, Spotify, UserId,
+        storage::{DocumentWriter, GetDocumentBuilder, ReplaceDocumentBuilder, View},
     };
 
+    #[tokio::test]
+    async fn test_get_spotify_source_cache_hit() {
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![
+                r#"{"id":"spotify:album:id","user_id":"user","version":null,"payload":"{\"name\":\"album\",\"items\":[{\"id\":\"spotify:track:id\",\"name\":\"track\",\"iframe\":null,\"score\":1500,\"wins\":0,\"losses\":0,\"rank\":null}]}","updated_at":9999999999}"#,
+            ]),
+            query_mock: Mock::empty(),
+            write_mock: Mock::empty(),
+        };
+        let id = Id {
+            id: "id".to_owned(),
+            raw_id: "https://open.spotify.com/album/id".to_owned(),
+        };
+        let (source, items) = super::get_spotify_source(
+            &client,
+            &UserId("user".to_owned()),
+            Spotify::Album(id.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            source,
+            Source {
+                source_type: SourceType::Spotify(Spotify::Album(id)),
+                name: "album".to_owned(),
+                unmatched: Vec::new(),
+            }
+        );
+        assert_eq!(
+            items,
+            [ItemMetadata::new(
+                "spotify:track:id".to_owned(),
+                "track".to_owned(),
+                None
+            )]
+        );
+        assert_eq!(
+            *client.get_mock.call_args.lock().unwrap(),
+            [GetDocumentBuilder::new(
+                "cache",
+                "spotify:album:id".to_owned(),
+                View::User(UserId("user".to_owned()))
+            )]
+        );
+        assert!(client.write_mock.call_args.lock().unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_update_empty_list_items() {
         let client = TestSessionClient {
@@ -228,6 +685,10 @@ mod test {
                 favorite: false,
                 query: String::from("SELECT name, user_score FROM c"),
                 public: false,
+                sync: None,
+                sort: SortMode::default(),
+                tiers: mybops::default_tiers(),
+                rating_scale: None,
             },
         )
         .await
@@ -238,7 +699,7 @@ mod test {
                 collection_name: "list",
                 document_name: "".to_owned(),
                 partition_key: UserId("".to_owned()),
-                document: r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"New List","sources":"[]","iframe":null,"items":"[]","favorite":false,"query":"SELECT name, user_score FROM c","public":false}"#.to_owned(),
+                document: r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"New List","sources":"[]","iframe":null,"items":"[]","favorite":false,"query":"SELECT name, user_score FROM c","public":false,"sync":null,"sort":"\"Query\"","tiers":"[\"S\",\"A\",\"B\",\"C\",\"D\"]","rating_scale":null}"#.to_owned(),
             })]
         );
     }
@@ -248,7 +709,8 @@ mod test {
         let client = TestSessionClient {
             get_mock: Mock::new(vec![
                 r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
-                r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"source","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"source","user_id":"","mode":"{\"User\":null}","name":"source","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"source","user_id":"","mode":"{\"User\":null}","name":"source","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
             ]),
             query_mock: Mock::empty(),
             write_mock: Mock::new(vec![()]),
@@ -262,14 +724,19 @@ mod test {
                 mode: ListMode::User(None),
                 name: String::from("New List"),
                 sources: vec![Source {
-                    source_type: SourceType::ListItems("".to_owned()),
+                    source_type: SourceType::ListItems("source".to_owned()),
                     name: String::new(),
+                    unmatched: Vec::new(),
                 }],
                 iframe: None,
                 items: Vec::new(),
                 favorite: false,
                 query: String::from("SELECT name, user_score FROM c"),
                 public: false,
+                sync: None,
+                sort: SortMode::default(),
+                tiers: mybops::default_tiers(),
+                rating_scale: None,
             },
         )
         .await
@@ -280,7 +747,7 @@ mod test {
                 collection_name: "list",
                 document_name: "".to_owned(),
                 partition_key: UserId("".to_owned()),
-                document: r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"New List","sources":"[{\"source_type\":{\"ListItems\":\"\"},\"name\":\"source\"}]","iframe":null,"items":"[]","favorite":false,"query":"SELECT name, user_score FROM c","public":false}"#.to_owned(),
+                document: r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"New List","sources":"[{\"source_type\":{\"ListItems\":\"source\"},\"name\":\"source\"}]","iframe":null,"items":"[]","favorite":false,"query":"SELECT name, user_score FROM c","public":false,"sync":null,"sort":"\"Query\"","tiers":"[\"S\",\"A\",\"B\",\"C\",\"D\"]","rating_scale":null}"#.to_owned(),
             })]
         );
     }
@@ -290,9 +757,10 @@ mod test {
         let client = TestSessionClient {
             get_mock: Mock::new(vec![
                 r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
-                r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"source","sources":"[]","items":"[{\"id\":\"\",\"name\":\"item\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"source","user_id":"","mode":"{\"User\":null}","name":"source","sources":"[]","items":"[{\"id\":\"\",\"name\":\"item\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"source","user_id":"","mode":"{\"User\":null}","name":"source","sources":"[]","items":"[{\"id\":\"\",\"name\":\"item\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
             ]),
-            query_mock: Mock::empty(),
+            query_mock: Mock::new(vec!["[]", "[]"]),
             write_mock: Mock::new(vec![()]),
         };
         super::update_list_items(
@@ -304,14 +772,19 @@ mod test {
                 mode: ListMode::User(None),
                 name: String::from("New List"),
                 sources: vec![Source {
-                    source_type: SourceType::ListItems("".to_owned()),
+                    source_type: SourceType::ListItems("source".to_owned()),
                     name: String::new(),
+                    unmatched: Vec::new(),
                 }],
                 iframe: None,
                 items: Vec::new(),
                 favorite: false,
                 query: String::from("SELECT name, user_score FROM c"),
                 public: false,
+                sync: None,
+                sort: SortMode::default(),
+                tiers: mybops::default_tiers(),
+                rating_scale: None,
             },
         )
         .await
@@ -322,8 +795,288 @@ mod test {
                 collection_name: "list",
                 document_name: "".to_owned(),
                 partition_key: UserId("".to_owned()),
-                document: r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"New List","sources":"[{\"source_type\":{\"ListItems\":\"\"},\"name\":\"source\"}]","iframe":null,"items":"[{\"id\":\"\",\"name\":\"item\",\"iframe\":null,\"score\":0,\"wins\":0,\"losses\":0,\"rank\":null}]","favorite":false,"query":"SELECT name, user_score FROM c","public":false}"#.to_owned(),
+                document: r#"{"id":"","user_id":"","mode":"{\"User\":null}","name":"New List","sources":"[{\"source_type\":{\"ListItems\":\"source\"},\"name\":\"source\"}]","iframe":null,"items":"[{\"id\":\"\",\"name\":\"item\",\"iframe\":null,\"score\":0,\"wins\":0,\"losses\":0,\"rank\":null}]","favorite":false,"query":"SELECT name, user_score FROM c","public":false,"sync":null,"sort":"\"Query\"","tiers":"[\"S\",\"A\",\"B\",\"C\",\"D\"]","rating_scale":null}"#.to_owned(),
             })]
         );
     }
+
+    fn list(id: &str, sources: Vec<Source>) -> List {
+        List {
+            id: id.to_owned(),
+            user_id: String::new(),
+            mode: ListMode::User(None),
+            name: id.to_uppercase(),
+            sources,
+            iframe: None,
+            items: Vec::new(),
+            favorite: false,
+            query: String::from("SELECT name, user_score FROM c"),
+            public: false,
+            sync: None,
+            sort: SortMode::default(),
+            tiers: mybops::default_tiers(),
+            rating_scale: None,
+        }
+    }
+
+    fn list_source(id: &str) -> Source {
+        Source {
+            source_type: SourceType::ListItems(id.to_owned()),
+            name: String::new(),
+            unmatched: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_list_items_with_cycle() {
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![
+                r#"{"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"b","user_id":"","mode":"{\"User\":null}","name":"B","sources":"[{\"source_type\":{\"ListItems\":\"c\"},\"name\":\"C\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"c","user_id":"","mode":"{\"User\":null}","name":"C","sources":"[{\"source_type\":{\"ListItems\":\"a\"},\"name\":\"A\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+            ]),
+            query_mock: Mock::empty(),
+            write_mock: Mock::empty(),
+        };
+        let Err(Error::ClientError(e)) = super::update_list_items(
+            &client,
+            &UserId(String::new()),
+            list("a", vec![list_source("b")]),
+        )
+        .await
+        else {
+            panic!("expected a cycle error");
+        };
+        assert_eq!(
+            e,
+            "Lists can't include themselves through their data sources: A → B → C → A"
+        );
+        assert!(client.write_mock.call_args.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_validate_sources() {
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![
+                r#"{"id":"b","user_id":"","mode":"{\"User\":null}","name":"B","sources":"[{\"source_type\":{\"ListItems\":\"c\"},\"name\":\"C\"},{\"source_type\":{\"Custom\":[\"x\"]},\"name\":\"Custom\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"c","user_id":"","mode":"{\"User\":null}","name":"C","sources":"[{\"source_type\":{\"Custom\":[\"y\"]},\"name\":\"Custom\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"c","user_id":"","mode":"{\"User\":null}","name":"C","sources":"[{\"source_type\":{\"Custom\":[\"y\"]},\"name\":\"Custom\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+            ]),
+            query_mock: Mock::empty(),
+            write_mock: Mock::empty(),
+        };
+        // Lists can be included more than once without forming a cycle
+        super::validate_sources(
+            &client,
+            &UserId(String::new()),
+            &list("a", vec![list_source("b"), list_source("c")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.get_mock.call_args.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_update_list_items_with_composite() {
+        let gym = r#"{"id":"gym","user_id":"","mode":"{\"User\":null}","name":"Gym","sources":"[]","items":"[{\"id\":\"x\",\"name\":\"x\",\"score\":0,\"wins\":0,\"losses\":0},{\"id\":\"y\",\"name\":\"y\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#;
+        let running = r#"{"id":"running","user_id":"","mode":"{\"User\":null}","name":"Running","sources":"[]","items":"[{\"id\":\"y\",\"name\":\"y\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#;
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![
+                r#"{"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                gym,
+                running,
+                gym,
+                running,
+            ]),
+            query_mock: Mock::new(vec!["[]", "[]"]),
+            write_mock: Mock::new(vec![()]),
+        };
+        super::update_list_items(
+            &client,
+            &UserId(String::new()),
+            list(
+                "a",
+                vec![Source {
+                    source_type: SourceType::Composite("list:gym - list:running".parse().unwrap()),
+                    name: String::new(),
+                    unmatched: Vec::new(),
+                }],
+            ),
+        )
+        .await
+        .unwrap();
+        let writes = client.write_mock.call_args.lock().unwrap();
+        let [DocumentWriter::Replace(a)] = &writes[..] else {
+            panic!("unexpected writes {writes:?}");
+        };
+        let a: List = serde_json::from_str::<mybops::RawList>(&a.document)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(a.sources[0].name, "Gym - Running");
+        assert_eq!(
+            a.items.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(),
+            ["x"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_list_items_keeps_scores() {
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![
+                r#"{"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[{\"source_type\":{\"Query\":\"rating >= 8\"},\"name\":\"rating >= 8\"}]","items":"[{\"id\":\"x\",\"name\":\"x\",\"score\":1600,\"wins\":3,\"losses\":1,\"tier\":\"A\"},{\"id\":\"z\",\"name\":\"z\",\"score\":1400,\"wins\":0,\"losses\":2}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+            ]),
+            query_mock: Mock::new(vec![
+                r#"[{"id":"x","name":"x","iframe":null},{"id":"y","name":"y","iframe":null}]"#,
+                "[]",
+                "[]",
+            ]),
+            write_mock: Mock::new(vec![()]),
+        };
+        super::update_list_items(
+            &client,
+            &UserId(String::new()),
+            list(
+                "a",
+                vec![Source {
+                    source_type: SourceType::Query("rating >= 8".to_owned()),
+                    name: String::new(),
+                    unmatched: Vec::new(),
+                }],
+            ),
+        )
+        .await
+        .unwrap();
+        let writes = client.write_mock.call_args.lock().unwrap();
+        let [DocumentWriter::Replace(a)] = &writes[..] else {
+            panic!("unexpected writes {writes:?}");
+        };
+        let a: List = serde_json::from_str::<mybops::RawList>(&a.document)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let scores: Vec<_> = a
+            .items
+            .iter()
+            .map(|i| (i.id.as_str(), i.score, i.wins, i.losses, i.tier.as_deref()))
+            .collect();
+        assert_eq!(
+            scores,
+            [("x", 1600, 3, 1, Some("A")), ("y", 1500, 0, 0, None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reorder_items() {
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![
+                r#"{"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[]","items":"[{\"id\":\"x\",\"name\":\"x\",\"score\":0,\"wins\":0,\"losses\":0},{\"id\":\"y\",\"name\":\"y\",\"score\":0,\"wins\":0,\"losses\":0},{\"id\":\"z\",\"name\":\"z\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+            ]),
+            query_mock: Mock::empty(),
+            write_mock: Mock::new(vec![()]),
+        };
+        super::reorder_items(
+            &client,
+            &UserId(String::new()),
+            "a",
+            &["z".to_owned(), "x".to_owned()],
+        )
+        .await
+        .unwrap();
+        let writes = client.write_mock.call_args.lock().unwrap();
+        let [DocumentWriter::Replace(a)] = &writes[..] else {
+            panic!("unexpected writes {writes:?}");
+        };
+        let a: List = serde_json::from_str::<mybops::RawList>(&a.document)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(a.sort, SortMode::Manual);
+        assert_eq!(
+            a.items.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(),
+            ["z", "x", "y"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_tiers() {
+        let list = r#"{"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[]","items":"[{\"id\":\"x\",\"name\":\"x\",\"score\":0,\"wins\":0,\"losses\":0,\"tier\":\"B\"},{\"id\":\"y\",\"name\":\"y\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c","tiers":"[\"S\",\"A\",\"B\"]"}"#;
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![list, list]),
+            query_mock: Mock::empty(),
+            write_mock: Mock::new(vec![()]),
+        };
+        super::set_tiers(
+            &client,
+            &UserId(String::new()),
+            "a",
+            &[("y".to_owned(), "S".to_owned())].into_iter().collect(),
+        )
+        .await
+        .unwrap();
+        let Err(Error::ClientError(_)) = super::set_tiers(
+            &client,
+            &UserId(String::new()),
+            "a",
+            &[("y".to_owned(), "D".to_owned())].into_iter().collect(),
+        )
+        .await
+        else {
+            panic!("expected an unknown tier error");
+        };
+        let writes = client.write_mock.call_args.lock().unwrap();
+        let [DocumentWriter::Replace(a)] = &writes[..] else {
+            panic!("unexpected writes {writes:?}");
+        };
+        let a: List = serde_json::from_str::<mybops::RawList>(&a.document)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(a.tiers, ["S", "A", "B"]);
+        assert_eq!(
+            a.items
+                .iter()
+                .map(|i| i.tier.as_deref())
+                .collect::<Vec<_>>(),
+            [None, Some("S")]
+        );
+        assert_eq!(a.tier_index(&a.items[0]), 3);
+        assert_eq!(a.tier_index(&a.items[1]), 0);
+    }
+
+    #[tokio::test]
+    async fn test_update_list_items_updates_parents() {
+        let client = TestSessionClient {
+            get_mock: Mock::new(vec![
+                r#"{"id":"b","user_id":"","mode":"{\"User\":null}","name":"B","sources":"[{\"source_type\":{\"ListItems\":\"c\"},\"name\":\"C\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"c","user_id":"","mode":"{\"User\":null}","name":"C","sources":"[]","items":"[{\"id\":\"x\",\"name\":\"item\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[{\"source_type\":{\"ListItems\":\"b\"},\"name\":\"B\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+                r#"{"id":"b","user_id":"","mode":"{\"User\":null}","name":"B","sources":"[{\"source_type\":{\"ListItems\":\"c\"},\"name\":\"C\"}]","items":"[{\"id\":\"x\",\"name\":\"item\",\"score\":0,\"wins\":0,\"losses\":0}]","favorite":false,"query":"SELECT name, user_score FROM c"}"#,
+            ]),
+            query_mock: Mock::new(vec![
+                "[]",
+                r#"[
+                    {"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[{\"source_type\":{\"ListItems\":\"b\"},\"name\":\"B\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"},
+                    {"id":"d","user_id":"","mode":"{\"User\":null}","name":"D","sources":"[]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM c"}
+                ]"#,
+                "[]",
+                "[]",
+            ]),
+            write_mock: Mock::new(vec![(), ()]),
+        };
+        super::update_list_items(
+            &client,
+            &UserId(String::new()),
+            list("b", vec![list_source("c")]),
+        )
+        .await
+        .unwrap();
+        let writes = client.write_mock.call_args.lock().unwrap();
+        let [DocumentWriter::Replace(b), DocumentWriter::Replace(a)] = &writes[..] else {
+            panic!("unexpected writes {writes:?}");
+        };
+        assert_eq!(b.document_name, "b");
+        assert_eq!(a.document_name, "a");
+        assert!(a.document.contains(r#"{\"id\":\"x\""#));
+    }
 }
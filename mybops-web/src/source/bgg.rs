@@ -0,0 +1,214 @@
+use mybops::{Error, UserId};
+use roxmltree::{Document, Node};
+use serde_json::{Map, Value};
+
+/// Parse a BoardGameGeek collection or thing XML.
+///
+/// Collections from `/xmlapi2/collection?stats=1` include the user's rating, play count and player
+/// counts but not designers or weight, which are read from `/xmlapi2/thing?stats=1` items instead.
+pub fn parse_export(user_id: &UserId, body: &[u8]) -> Result<Vec<crate::Item>, Error> {
+    let invalid = || Error::client_error("Expected a BoardGameGeek collection or thing XML");
+    let body = std::str::from_utf8(body).map_err(|_| invalid())?;
+    let document = Document::parse(body).map_err(|_| invalid())?;
+    let root = document.root_element();
+    if !root.has_tag_name("items") {
+        return Err(invalid());
+    }
+
+    let mut items = Vec::new();
+    for node in root.children().filter(|n| n.has_tag_name("item")) {
+        let Some(id) = node.attribute("objectid").or_else(|| node.attribute("id")) else {
+            continue;
+        };
+        let Some(name) = get_name(node) else {
+            continue;
+        };
+        let stats = child(node, "stats");
+        // Collections use attributes on the stats element and things use child elements
+        let number = |name: &str| {
+            stats
+                .and_then(|stats| stats.attribute(name))
+                .or_else(|| child(node, name).and_then(value))
+                .and_then(|value| value.parse::<i64>().ok())
+                .filter(|&value| value > 0)
+        };
+        // Unrated games have a rating of N/A
+        let rating = stats
+            .and_then(|stats| child(stats, "rating"))
+            .and_then(|rating| rating.attribute("value"))
+            .and_then(|rating| rating.parse::<f64>().ok())
+            .map(|rating| rating.round().clamp(1.0, 10.0) as i32);
+
+        let mut metadata = Map::new();
+        let designers: Vec<_> = links(node, "boardgamedesigner").collect();
+        if let Some(&designer) = designers.first() {
+            metadata.insert(String::from("designer"), Value::String(designer.to_owned()));
+            metadata.insert(
+                String::from("designers"),
+                Value::Array(
+                    designers
+                        .iter()
+                        .map(|&d| Value::String(d.to_owned()))
+                        .collect(),
+                ),
+            );
+        }
+        if let Some(year) = number("yearpublished") {
+            metadata.insert(String::from("year"), Value::Number(year.into()));
+        }
+        if let Some(weight) = node
+            .descendants()
+            .find(|n| n.has_tag_name("averageweight"))
+            .and_then(value)
+            .and_then(|weight| weight.parse::<f64>().ok())
+            .filter(|&weight| weight > 0.0)
+            .and_then(serde_json::Number::from_f64)
+        {
+            metadata.insert(String::from("weight"), Value::Number(weight));
+        }
+        if let Some(players) = number("minplayers") {
+            metadata.insert(String::from("min_players"), Value::Number(players.into()));
+        }
+        if let Some(players) = number("maxplayers") {
+            metadata.insert(String::from("max_players"), Value::Number(players.into()));
+        }
+        if let Some(time) = number("playingtime") {
+            metadata.insert(String::from("playing_time"), Value::Number(time.into()));
+        }
+        let categories: Vec<_> = links(node, "boardgamecategory")
+            .map(|c| Value::String(c.to_owned()))
+            .collect();
+        if !categories.is_empty() {
+            metadata.insert(String::from("categories"), Value::Array(categories));
+        }
+        if let Some(plays) = child(node, "numplays")
+            .and_then(|n| n.text())
+            .and_then(|plays| plays.trim().parse::<i64>().ok())
+        {
+            metadata.insert(String::from("play_count"), Value::Number(plays.into()));
+        }
+        if let Some(status) = child(node, "status") {
+            metadata.insert(
+                String::from("owned"),
+                Value::Bool(status.attribute("own") == Some("1")),
+            );
+        }
+        metadata.insert(
+            String::from("bgg_url"),
+            Value::String(format!("https://boardgamegeek.com/boardgame/{id}")),
+        );
+        items.push(crate::Item {
+            id: format!("bgg:boardgame:{id}"),
+            user_id: user_id.0.clone(),
+            r#type: String::from("boardgame"),
+            name: name.to_owned(),
+            iframe: None,
+            rating,
+            rating_scale: None,
+            user_score: 1500,
+            user_wins: 0,
+            user_losses: 0,
+            metadata,
+            hidden: false,
+        });
+    }
+    Ok(items)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+/// Things store values in a value attribute and collections store them as text
+fn value<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    node.attribute("value")
+        .or_else(|| node.text())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Things have primary and alternate names
+fn get_name<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    let mut names = node.children().filter(|n| n.has_tag_name("name"));
+    names
+        .clone()
+        .find(|n| n.attribute("type") == Some("primary"))
+        .or_else(|| names.next())
+        .and_then(value)
+}
+
+fn links<'a>(node: Node<'a, '_>, link_type: &'a str) -> impl Iterator<Item = &'a str> {
+    node.children()
+        .filter(move |n| n.has_tag_name("link") && n.attribute("type") == Some(link_type))
+        .filter_map(|n| n.attribute("value"))
+}
+
+#[cfg(test)]
+mod test {
+    use mybops::UserId;
+
+    #[test]
+    fn test_parse_collection() {
+        let items = super::parse_export(
+            &UserId("user".to_owned()),
+            br#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<items totalitems="2" termsofuse="https://boardgamegeek.com/xmlapi/termsofuse">
+  <item objecttype="thing" objectid="174430" subtype="boardgame" collid="1">
+    <name sortindex="1">Gloomhaven</name>
+    <yearpublished>2017</yearpublished>
+    <stats minplayers="1" maxplayers="4" minplaytime="60" maxplaytime="120" playingtime="120" numowned="100">
+      <rating value="8.6"><average value="8.5"/></rating>
+    </stats>
+    <status own="1" prevowned="0" fortrade="0" want="0" wanttoplay="0" wanttobuy="0" wishlist="0" preordered="0" lastmodified="2023-01-01 00:00:00"/>
+    <numplays>5</numplays>
+  </item>
+  <item objecttype="thing" objectid="13" subtype="boardgame" collid="2">
+    <name sortindex="1">CATAN</name>
+    <stats minplayers="3" maxplayers="4" playingtime="120">
+      <rating value="N/A"/>
+    </stats>
+    <status own="0" wishlist="1"/>
+    <numplays>0</numplays>
+  </item>
+</items>"#,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].id, "bgg:boardgame:174430");
+        assert_eq!(items[0].r#type, "boardgame");
+        assert_eq!(items[0].rating, Some(9));
+        assert_eq!(
+            serde_json::to_string(&items[0].metadata).unwrap(),
+            r#"{"year":2017,"min_players":1,"max_players":4,"playing_time":120,"play_count":5,"owned":true,"bgg_url":"https://boardgamegeek.com/boardgame/174430"}"#
+        );
+        assert_eq!(items[1].rating, None);
+        assert_eq!(items[1].metadata["owned"], false);
+        assert!(super::parse_export(&UserId("user".to_owned()), b"<error/>").is_err());
+    }
+
+    #[test]
+    fn test_parse_things() {
+        let items = super::parse_export(
+            &UserId("user".to_owned()),
+            br#"<items termsofuse="https://boardgamegeek.com/xmlapi/termsofuse">
+  <item type="boardgame" id="13">
+    <name type="alternate" sortindex="1" value="Die Siedler von Catan"/>
+    <name type="primary" sortindex="1" value="CATAN"/>
+    <yearpublished value="1995"/>
+    <minplayers value="3"/>
+    <maxplayers value="4"/>
+    <playingtime value="120"/>
+    <link type="boardgamecategory" id="1026" value="Negotiation"/>
+    <link type="boardgamedesigner" id="11" value="Klaus Teuber"/>
+    <statistics page="1"><ratings><averageweight value="2.29"/></ratings></statistics>
+  </item>
+</items>"#,
+        )
+        .unwrap();
+        assert_eq!(items[0].name, "CATAN");
+        assert_eq!(
+            serde_json::to_string(&items[0].metadata).unwrap(),
+            r#"{"designer":"Klaus Teuber","designers":["Klaus Teuber"],"year":1995,"weight":2.29,"min_players":3,"max_players":4,"playing_time":120,"categories":["Negotiation"],"bgg_url":"https://boardgamegeek.com/boardgame/13"}"#
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use mybops::{
+    Error, ItemMetadata, Spotify, UserId,
+    storage::{
+        CreateDocumentBuilder, DocumentWriter, GetDocumentBuilder, ReplaceDocumentBuilder,
+        SessionClient, View,
+    },
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// Playlists are checked for a new snapshot after this many seconds
+const PLAYLIST_TTL: i64 = 10 * 60;
+/// Albums and tracks rarely change so they are refetched less often
+const ALBUM_TTL: i64 = 7 * 24 * 60 * 60;
+const TRACK_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// Cached source payloads are stored per user so that a cache hit means the user's items have
+/// already been created
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct CacheEntry {
+    pub id: String,
+    pub user_id: String,
+    /// Spotify playlist snapshot ID
+    pub version: Option<String>,
+    pub payload: String,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct CachedSource {
+    pub name: String,
+    pub items: Vec<ItemMetadata>,
+}
+
+impl CacheEntry {
+    pub fn is_fresh(&self, ttl: i64, now: i64) -> bool {
+        now - self.updated_at < ttl
+    }
+
+    pub fn source(&self) -> Result<CachedSource, Error> {
+        Ok(serde_json::from_str(&self.payload)?)
+    }
+}
+
+pub fn spotify_key(spotify: &Spotify) -> String {
+    match spotify {
+        Spotify::Playlist(id) => format!("spotify:playlist:{}", id.id),
+        Spotify::Album(id) => format!("spotify:album:{}", id.id),
+        Spotify::Track(id) => format!("spotify:track:{}", id.id),
+    }
+}
+
+pub fn spotify_ttl(spotify: &Spotify) -> i64 {
+    match spotify {
+        Spotify::Playlist(_) => PLAYLIST_TTL,
+        Spotify::Album(_) => ALBUM_TTL,
+        Spotify::Track(_) => TRACK_TTL,
+    }
+}
+
+pub fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+pub async fn get(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    key: &str,
+) -> Result<Option<CacheEntry>, Error> {
+    client
+        .get_document(GetDocumentBuilder::new(
+            "cache",
+            key.to_owned(),
+            View::User(user_id.clone()),
+        ))
+        .await
+}
+
+pub async fn put(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    key: String,
+    version: Option<String>,
+    payload: &impl Serialize,
+) -> Result<(), Error> {
+    client
+        .write_document(DocumentWriter::Create(CreateDocumentBuilder {
+            collection_name: "cache",
+            document: CacheEntry {
+                id: key,
+                user_id: user_id.0.clone(),
+                version,
+                payload: serde_json::to_string(payload)?,
+                updated_at: now(),
+            },
+            is_upsert: true,
+        }))
+        .await
+}
+
+/// Mark an entry as fresh after confirming that it hasn't changed
+pub async fn touch(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    entry: &CacheEntry,
+) -> Result<(), Error> {
+    client
+        .write_document(DocumentWriter::Replace(ReplaceDocumentBuilder {
+            collection_name: "cache",
+            document_name: entry.id.clone(),
+            partition_key: user_id.clone(),
+            document: CacheEntry {
+                id: entry.id.clone(),
+                user_id: user_id.0.clone(),
+                version: entry.version.clone(),
+                payload: entry.payload.clone(),
+                updated_at: now(),
+            },
+        }))
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::CacheEntry;
+    use mybops::{Id, Spotify};
+
+    #[test]
+    fn test_is_fresh() {
+        let entry = CacheEntry {
+            id: super::spotify_key(&Spotify::Playlist(Id {
+                id: "id".to_owned(),
+                raw_id: String::new(),
+            })),
+            user_id: String::new(),
+            version: Some("snapshot".to_owned()),
+            payload: String::new(),
+            updated_at: 1000,
+        };
+        assert_eq!(entry.id, "spotify:playlist:id");
+        assert!(entry.is_fresh(super::PLAYLIST_TTL, 1000 + super::PLAYLIST_TTL - 1));
+        assert!(!entry.is_fresh(super::PLAYLIST_TTL, 1000 + super::PLAYLIST_TTL));
+    }
+}
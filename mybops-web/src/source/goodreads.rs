@@ -0,0 +1,138 @@
+use mybops::{Error, UserId};
+use serde_json::{Map, Value};
+
+/// Parse a Goodreads library export CSV
+pub fn parse_export(user_id: &UserId, body: &[u8]) -> Result<Vec<crate::Item>, Error> {
+    let invalid = || Error::client_error("Expected a Goodreads library export CSV");
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body);
+    let headers = reader.headers().map_err(|_| invalid())?.clone();
+    let find = |name: &str| headers.iter().position(|h| h.trim() == name);
+    let id_column = find("Book Id").ok_or_else(invalid)?;
+    let title_column = find("Title").ok_or_else(invalid)?;
+    let author_column = find("Author");
+    let additional_column = find("Additional Authors");
+    let rating_column = find("My Rating");
+    let original_year_column = find("Original Publication Year");
+    let year_column = find("Year Published");
+    let pages_column = find("Number of Pages");
+    let isbn_column = find("ISBN13");
+    let read_column = find("Date Read");
+    let added_column = find("Date Added");
+    let shelf_column = find("Exclusive Shelf");
+
+    let mut items = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|_| invalid())?;
+        let get = |column: Option<usize>| {
+            column
+                .and_then(|i| record.get(i))
+                .map(str::trim)
+                // ISBNs are exported as ="9780000000000" so spreadsheets don't treat them as numbers
+                .map(|value| value.trim_start_matches('=').trim_matches('"'))
+                .filter(|value| !value.is_empty())
+        };
+        let (Some(id), Some(title)) = (get(Some(id_column)), get(Some(title_column))) else {
+            continue;
+        };
+        // Unrated books have a rating of 0
+        let rating = get(rating_column)
+            .and_then(|rating| rating.parse::<i32>().ok())
+            .filter(|&stars| stars > 0)
+            .map(|stars| stars * 2);
+
+        let mut metadata = Map::new();
+        if let Some(author) = get(author_column) {
+            metadata.insert(String::from("author"), Value::String(author.to_owned()));
+            let authors = std::iter::once(author)
+                .chain(
+                    get(additional_column)
+                        .into_iter()
+                        .flat_map(|a| a.split(',')),
+                )
+                .map(str::trim)
+                .filter(|a| !a.is_empty())
+                .map(|a| Value::String(a.to_owned()))
+                .collect();
+            metadata.insert(String::from("authors"), Value::Array(authors));
+        }
+        if let Some(year) = get(original_year_column)
+            .or_else(|| get(year_column))
+            .and_then(|year| year.parse::<i64>().ok())
+        {
+            metadata.insert(String::from("year"), Value::Number(year.into()));
+        }
+        if let Some(pages) = get(pages_column).and_then(|pages| pages.parse::<i64>().ok()) {
+            metadata.insert(String::from("pages"), Value::Number(pages.into()));
+        }
+        if let Some(isbn) = get(isbn_column) {
+            metadata.insert(String::from("isbn13"), Value::String(isbn.to_owned()));
+        }
+        // Dates are exported as YYYY/MM/DD
+        if let Some(date) = get(read_column) {
+            metadata.insert(
+                String::from("read_date"),
+                Value::String(date.replace('/', "-")),
+            );
+        }
+        if let Some(date) = get(added_column) {
+            metadata.insert(
+                String::from("added_date"),
+                Value::String(date.replace('/', "-")),
+            );
+        }
+        if let Some(shelf) = get(shelf_column) {
+            metadata.insert(String::from("shelf"), Value::String(shelf.to_owned()));
+        }
+        metadata.insert(
+            String::from("goodreads_url"),
+            Value::String(format!("https://www.goodreads.com/book/show/{id}")),
+        );
+        items.push(crate::Item {
+            id: format!("goodreads:book:{id}"),
+            user_id: user_id.0.clone(),
+            r#type: String::from("book"),
+            name: title.to_owned(),
+            iframe: None,
+            rating,
+            rating_scale: None,
+            user_score: 1500,
+            user_wins: 0,
+            user_losses: 0,
+            metadata,
+            hidden: false,
+        });
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod test {
+    use mybops::UserId;
+
+    #[test]
+    fn test_parse_export() {
+        let items = super::parse_export(
+            &UserId("user".to_owned()),
+            br#"Book Id,Title,Author,Author l-f,Additional Authors,ISBN,ISBN13,My Rating,Average Rating,Publisher,Binding,Number of Pages,Year Published,Original Publication Year,Date Read,Date Added,Bookshelves,Bookshelves with positions,Exclusive Shelf
+2767052,The Hunger Games,Suzanne Collins,"Collins, Suzanne",,"=""0439023483""","=""9780439023481""",4,4.33,Scholastic Press,Hardcover,374,2008,2008,2023/05/01,2023/04/01,,,read
+11,Good Omens,Terry Pratchett,"Pratchett, Terry",Neil Gaiman,"=""""","=""""",0,4.25,,,,2006,1990,,2024/01/01,to-read,to-read (#1),to-read
+"#,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].id, "goodreads:book:2767052");
+        assert_eq!(items[0].r#type, "book");
+        assert_eq!(items[0].rating, Some(8));
+        assert_eq!(
+            serde_json::to_string(&items[0].metadata).unwrap(),
+            r#"{"author":"Suzanne Collins","authors":["Suzanne Collins"],"year":2008,"pages":374,"isbn13":"9780439023481","read_date":"2023-05-01","added_date":"2023-04-01","shelf":"read","goodreads_url":"https://www.goodreads.com/book/show/2767052"}"#
+        );
+        assert_eq!(items[1].rating, None);
+        assert_eq!(items[1].metadata["year"], 1990);
+        assert_eq!(
+            items[1].metadata["authors"],
+            serde_json::json!(["Terry Pratchett", "Neil Gaiman"])
+        );
+        assert!(!items[1].metadata.contains_key("isbn13"));
+    }
+}
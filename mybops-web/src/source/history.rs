@@ -0,0 +1,279 @@
+use crate::query::IntoQuery;
+use mybops::{
+    Error, UserId,
+    spotify::HistorySummary,
+    storage::{
+        CosmosQuery, CreateDocumentBuilder, DocumentWriter, QueryDocumentsBuilder, SessionClient,
+        View,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+/// An entry from a Spotify Extended Streaming History export (Streaming_History_Audio_*.json)
+#[derive(Debug, Deserialize)]
+pub struct StreamingHistoryEntry {
+    pub ts: String,
+    pub ms_played: i64,
+    pub master_metadata_track_name: Option<String>,
+    pub master_metadata_album_artist_name: Option<String>,
+    pub master_metadata_album_album_name: Option<String>,
+    /// Podcast episodes and some local files don't have a track URI
+    pub spotify_track_uri: Option<String>,
+}
+
+/// Plays are stored individually so that uploading overlapping exports doesn't double count
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Play {
+    pub id: String,
+    pub item_id: String,
+    pub ts: String,
+    pub ms_played: i64,
+}
+
+/// Plays are written in one statement because exports can contain tens of thousands of entries
+#[derive(Debug, Serialize)]
+pub struct Plays {
+    pub user_id: String,
+    pub plays: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct PlayStats {
+    pub id: String,
+    pub play_count: i64,
+    pub ms_played: i64,
+    pub first_played: String,
+    pub last_played: String,
+    pub existing: bool,
+}
+
+pub fn parse_history(body: &[u8]) -> Result<Vec<StreamingHistoryEntry>, Error> {
+    serde_json::from_slice(body)
+        .map_err(|_| Error::client_error("Expected a Spotify extended streaming history file"))
+}
+
+/// Spotify track plays keyed by end time and track so that duplicate entries collapse
+pub fn get_plays(entries: &[StreamingHistoryEntry]) -> Vec<Play> {
+    let mut seen = HashSet::new();
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let item_id = entry.spotify_track_uri.as_ref()?;
+            let id = format!("{}|{}", entry.ts, item_id);
+            seen.insert(id.clone()).then(|| Play {
+                id,
+                item_id: item_id.clone(),
+                ts: entry.ts.clone(),
+                ms_played: entry.ms_played,
+            })
+        })
+        .collect()
+}
+
+/// Import plays and recalculate listening stats for the items that were played
+pub async fn import_history(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    entries: Vec<StreamingHistoryEntry>,
+) -> Result<HistorySummary, Error> {
+    let plays = get_plays(&entries);
+    if plays.is_empty() {
+        return Ok(HistorySummary { plays: 0, items: 0 });
+    }
+    client
+        .write_document(DocumentWriter::Create(CreateDocumentBuilder {
+            collection_name: "play",
+            document: Plays {
+                user_id: user_id.0.clone(),
+                plays: serde_json::to_string(&plays)?,
+            },
+            is_upsert: false,
+        }))
+        .await?;
+
+    let played: HashSet<_> = plays.iter().map(|p| p.item_id.as_str()).collect();
+    // Stats are recalculated from every stored play so uploads are idempotent
+    let stats: Vec<PlayStats> = client
+        .query_documents(QueryDocumentsBuilder::new(
+            "play",
+            View::User(user_id.clone()),
+            CosmosQuery::new(
+                "SELECT play.item_id AS id, COUNT(*) AS play_count, SUM(play.ms_played) AS ms_played, MIN(play.ts) AS first_played, MAX(play.ts) AS last_played, item.id IS NOT NULL AS existing FROM play LEFT JOIN item ON item.id = play.item_id GROUP BY play.item_id"
+                    .into_query()?,
+            ),
+        ))
+        .await?;
+    let entries: HashMap<_, _> = entries
+        .iter()
+        .filter_map(|e| Some((e.spotify_track_uri.as_deref()?, e)))
+        .collect();
+    let items: Vec<_> = stats
+        .into_iter()
+        .filter(|s| played.contains(s.id.as_str()))
+        .filter_map(|s| {
+            let entry = entries[s.id.as_str()];
+            new_item(user_id, entry, s)
+        })
+        .collect();
+    let summary = HistorySummary {
+        plays: plays.len(),
+        items: items.len(),
+    };
+    super::create_items(client, items, false).await?;
+    Ok(summary)
+}
+
+/// Existing items only have their stats merged into metadata as the export has less track
+/// information than the Spotify API
+fn new_item(
+    user_id: &UserId,
+    entry: &StreamingHistoryEntry,
+    stats: PlayStats,
+) -> Option<crate::Item> {
+    let id = stats.id.strip_prefix("spotify:track:")?.to_owned();
+    let mut metadata: Map<_, _> = [
+        (
+            String::from("play_count"),
+            Value::Number(stats.play_count.into()),
+        ),
+        (
+            String::from("ms_played"),
+            Value::Number(stats.ms_played.into()),
+        ),
+        (
+            String::from("first_played"),
+            Value::String(stats.first_played),
+        ),
+        (
+            String::from("last_played"),
+            Value::String(stats.last_played),
+        ),
+    ]
+    .into_iter()
+    .collect();
+    if !stats.existing {
+        if let Some(album) = &entry.master_metadata_album_album_name {
+            metadata.insert(String::from("album"), Value::String(album.clone()));
+        }
+        if let Some(artist) = &entry.master_metadata_album_artist_name {
+            metadata.insert(
+                String::from("artists"),
+                Value::Array(vec![Value::String(artist.clone())]),
+            );
+        }
+    }
+    Some(crate::Item {
+        iframe: Some(format!(
+            "https://open.spotify.com/embed/track/{id}?utm_source=generator"
+        )),
+        id: stats.id,
+        user_id: user_id.0.clone(),
+        r#type: String::from("track"),
+        name: entry.master_metadata_track_name.clone().unwrap_or_default(),
+        rating: None,
+        rating_scale: None,
+        user_score: 1500,
+        user_wins: 0,
+        user_losses: 0,
+        metadata,
+        hidden: false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Play, PlayStats};
+    use crate::query::test::{Mock, TestSessionClient};
+    use mybops::{UserId, spotify::HistorySummary, storage::DocumentWriter};
+
+    const HISTORY: &str = r#"[
+        {"ts": "2023-01-01T10:00:00Z", "platform": "android", "ms_played": 200000, "master_metadata_track_name": "Track", "master_metadata_album_artist_name": "Artist", "master_metadata_album_album_name": "Album", "spotify_track_uri": "spotify:track:1", "episode_name": null, "skipped": false},
+        {"ts": "2023-01-02T10:00:00Z", "platform": "android", "ms_played": 1000, "master_metadata_track_name": "Track", "master_metadata_album_artist_name": "Artist", "master_metadata_album_album_name": "Album", "spotify_track_uri": "spotify:track:1", "episode_name": null, "skipped": true},
+        {"ts": "2023-01-02T10:00:00Z", "platform": "android", "ms_played": 1000, "master_metadata_track_name": "Track", "master_metadata_album_artist_name": "Artist", "master_metadata_album_album_name": "Album", "spotify_track_uri": "spotify:track:1", "episode_name": null, "skipped": true},
+        {"ts": "2023-01-03T10:00:00Z", "platform": "android", "ms_played": 3000000, "master_metadata_track_name": null, "master_metadata_album_artist_name": null, "master_metadata_album_album_name": null, "spotify_track_uri": null, "episode_name": "Episode", "skipped": null}
+    ]"#;
+
+    #[test]
+    fn test_get_plays() {
+        let entries = super::parse_history(HISTORY.as_bytes()).unwrap();
+        assert_eq!(
+            super::get_plays(&entries),
+            [
+                Play {
+                    id: "2023-01-01T10:00:00Z|spotify:track:1".to_owned(),
+                    item_id: "spotify:track:1".to_owned(),
+                    ts: "2023-01-01T10:00:00Z".to_owned(),
+                    ms_played: 200000,
+                },
+                Play {
+                    id: "2023-01-02T10:00:00Z|spotify:track:1".to_owned(),
+                    item_id: "spotify:track:1".to_owned(),
+                    ts: "2023-01-02T10:00:00Z".to_owned(),
+                    ms_played: 1000,
+                },
+            ]
+        );
+        assert!(super::parse_history(br#"{"tracks": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_new_item() {
+        let entries = super::parse_history(HISTORY.as_bytes()).unwrap();
+        let stats = |existing| PlayStats {
+            id: "spotify:track:1".to_owned(),
+            play_count: 2,
+            ms_played: 201000,
+            first_played: "2023-01-01T10:00:00Z".to_owned(),
+            last_played: "2023-01-02T10:00:00Z".to_owned(),
+            existing,
+        };
+        let item = super::new_item(&UserId("user".to_owned()), &entries[0], stats(false)).unwrap();
+        assert_eq!(item.name, "Track");
+        assert_eq!(
+            serde_json::to_string(&item.metadata).unwrap(),
+            r#"{"play_count":2,"ms_played":201000,"first_played":"2023-01-01T10:00:00Z","last_played":"2023-01-02T10:00:00Z","album":"Album","artists":["Artist"]}"#
+        );
+        let item = super::new_item(&UserId("user".to_owned()), &entries[0], stats(true)).unwrap();
+        assert_eq!(
+            serde_json::to_string(&item.metadata).unwrap(),
+            r#"{"play_count":2,"ms_played":201000,"first_played":"2023-01-01T10:00:00Z","last_played":"2023-01-02T10:00:00Z"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_history() {
+        let entries = super::parse_history(HISTORY.as_bytes()).unwrap();
+        let client = TestSessionClient {
+            get_mock: Mock::empty(),
+            query_mock: Mock::new(vec![
+                r#"[{"id": "spotify:track:1", "play_count": 2, "ms_played": 201000, "first_played": "2023-01-01T10:00:00Z", "last_played": "2023-01-02T10:00:00Z", "existing": true}, {"id": "spotify:track:2", "play_count": 1, "ms_played": 1000, "first_played": "2022-01-01T10:00:00Z", "last_played": "2022-01-01T10:00:00Z", "existing": true}]"#,
+            ]),
+            write_mock: Mock::new(vec![(), ()]),
+        };
+        assert_eq!(
+            super::import_history(&client, &UserId("user".to_owned()), entries)
+                .await
+                .unwrap(),
+            HistorySummary { plays: 2, items: 1 }
+        );
+        let writes: Vec<_> = client
+            .write_mock
+            .call_args
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|w| match w {
+                DocumentWriter::Create(builder) => {
+                    (builder.collection_name, builder.document.clone())
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(writes[0].0, "play");
+        assert!(writes[0].1.contains("2023-01-02T10:00:00Z|spotify:track:1"));
+        assert_eq!(writes[1].0, "item");
+        assert!(writes[1].1.contains(r#"\"play_count\":2"#));
+    }
+}
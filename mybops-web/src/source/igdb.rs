@@ -0,0 +1,164 @@
+use mybops::{Error, UserId};
+use serde::{Deserialize, de::IgnoredAny};
+use serde_json::{Map, Value};
+use time::OffsetDateTime;
+
+#[derive(Debug, Deserialize)]
+struct Game {
+    id: u64,
+    name: String,
+    /// Unix timestamp
+    first_release_date: Option<i64>,
+    #[serde(default)]
+    platforms: Vec<Reference>,
+    #[serde(default)]
+    genres: Vec<Reference>,
+    #[serde(default)]
+    game_modes: Vec<Reference>,
+    #[serde(default)]
+    involved_companies: Vec<InvolvedCompany>,
+    #[serde(default)]
+    multiplayer_modes: Vec<MultiplayerMode>,
+    /// IGDB user rating out of 100
+    rating: Option<f64>,
+    /// Personal rating out of 100, added to the dump by the user
+    user_rating: Option<f64>,
+    url: Option<String>,
+}
+
+/// Fields are only names if they were expanded in the API request
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Reference {
+    Expanded { name: String },
+    Id(IgnoredAny),
+}
+
+#[derive(Debug, Deserialize)]
+struct InvolvedCompany {
+    company: Reference,
+    #[serde(default)]
+    developer: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+struct MultiplayerMode {
+    offlinemax: Option<i64>,
+    onlinemax: Option<i64>,
+}
+
+/// Parse a JSON array of games from the IGDB games endpoint.
+///
+/// Platforms, genres, game modes and companies need to be expanded, e.g. with
+/// `fields name,first_release_date,platforms.name,genres.name,involved_companies.*,involved_companies.company.name;`
+pub fn parse_export(user_id: &UserId, body: &[u8]) -> Result<Vec<crate::Item>, Error> {
+    let games: Vec<Game> = serde_json::from_slice(body)
+        .map_err(|e| Error::client_error(format!("Expected a JSON array of IGDB games: {e}")))?;
+    Ok(games
+        .into_iter()
+        .map(|game| {
+            let rating = game
+                .user_rating
+                .filter(|&rating| rating > 0.0)
+                .map(|rating| (rating / 10.0).round().clamp(1.0, 10.0) as i32);
+
+            let mut metadata = Map::new();
+            let developers = names(
+                game.involved_companies
+                    .iter()
+                    .filter(|c| c.developer)
+                    .map(|c| &c.company),
+            );
+            if let Some(Value::String(developer)) = developers.first() {
+                metadata.insert(String::from("developer"), Value::String(developer.clone()));
+                metadata.insert(String::from("developers"), Value::Array(developers));
+            }
+            if let Some(date) = game
+                .first_release_date
+                .and_then(|date| OffsetDateTime::from_unix_timestamp(date).ok())
+            {
+                metadata.insert(String::from("year"), Value::Number(date.year().into()));
+            }
+            for (key, references) in [
+                ("platforms", &game.platforms),
+                ("genres", &game.genres),
+                ("game_modes", &game.game_modes),
+            ] {
+                let values = names(references.iter());
+                if !values.is_empty() {
+                    metadata.insert(String::from(key), Value::Array(values));
+                }
+            }
+            if let Some(players) = game
+                .multiplayer_modes
+                .iter()
+                .flat_map(|mode| [mode.offlinemax, mode.onlinemax])
+                .flatten()
+                .max()
+            {
+                metadata.insert(String::from("max_players"), Value::Number(players.into()));
+            }
+            if let Some(rating) = game.rating.and_then(serde_json::Number::from_f64) {
+                metadata.insert(String::from("igdb_rating"), Value::Number(rating));
+            }
+            if let Some(url) = game.url {
+                metadata.insert(String::from("igdb_url"), Value::String(url));
+            }
+            crate::Item {
+                id: format!("igdb:videogame:{}", game.id),
+                user_id: user_id.0.clone(),
+                r#type: String::from("videogame"),
+                name: game.name,
+                iframe: None,
+                rating,
+                rating_scale: None,
+                user_score: 1500,
+                user_wins: 0,
+                user_losses: 0,
+                metadata,
+                hidden: false,
+            }
+        })
+        .collect())
+}
+
+fn names<'a>(references: impl Iterator<Item = &'a Reference>) -> Vec<Value> {
+    references
+        .filter_map(|reference| match reference {
+            Reference::Expanded { name } => Some(Value::String(name.clone())),
+            Reference::Id(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use mybops::UserId;
+
+    #[test]
+    fn test_parse_export() {
+        let items = super::parse_export(
+            &UserId("user".to_owned()),
+            br#"[
+                {"id": 1942, "name": "The Witcher 3: Wild Hunt", "first_release_date": 1431993600, "platforms": [{"id": 6, "name": "PC (Microsoft Windows)"}, {"id": 48, "name": "PlayStation 4"}], "genres": [{"id": 12, "name": "Role-playing (RPG)"}], "game_modes": [{"id": 1, "name": "Single player"}], "involved_companies": [{"company": {"id": 908, "name": "CD Projekt RED"}, "developer": true, "publisher": false}, {"company": {"id": 1, "name": "Bandai Namco"}, "developer": false, "publisher": true}], "rating": 93.5, "user_rating": 96, "url": "https://www.igdb.com/games/the-witcher-3-wild-hunt"},
+                {"id": 7346, "name": "The Legend of Zelda: Breath of the Wild", "platforms": [130, 41], "multiplayer_modes": [{"offlinemax": 0, "onlinemax": 2}]}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].id, "igdb:videogame:1942");
+        assert_eq!(items[0].r#type, "videogame");
+        assert_eq!(items[0].rating, Some(10));
+        assert_eq!(
+            serde_json::to_string(&items[0].metadata).unwrap(),
+            r#"{"developer":"CD Projekt RED","developers":["CD Projekt RED"],"year":2015,"platforms":["PC (Microsoft Windows)","PlayStation 4"],"genres":["Role-playing (RPG)"],"game_modes":["Single player"],"igdb_rating":93.5,"igdb_url":"https://www.igdb.com/games/the-witcher-3-wild-hunt"}"#
+        );
+        assert_eq!(items[1].rating, None);
+        assert_eq!(
+            serde_json::to_string(&items[1].metadata).unwrap(),
+            r#"{"max_players":2}"#
+        );
+        assert!(super::parse_export(&UserId("user".to_owned()), b"{}").is_err());
+    }
+}
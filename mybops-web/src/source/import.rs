@@ -0,0 +1,218 @@
+use crate::{Item, RawItem, query::IntoQuery};
+use futures::{StreamExt, TryStreamExt};
+use mybops::{
+    Error, ItemMetadata, List, RawList, Source, SourceType, UserId,
+    import::ImportFormat,
+    storage::{
+        CosmosQuery, DocumentWriter, QueryDocumentsBuilder, ReplaceDocumentBuilder, SessionClient,
+        View,
+    },
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+struct ImportedItem {
+    id: String,
+    name: String,
+    iframe: Option<String>,
+}
+
+pub fn parse_export(
+    format: ImportFormat,
+    user_id: &UserId,
+    body: &[u8],
+) -> Result<Vec<Item>, Error> {
+    let items = match format {
+        ImportFormat::Letterboxd => super::letterboxd::parse_export(user_id, body)?,
+        ImportFormat::Goodreads => super::goodreads::parse_export(user_id, body)?,
+        ImportFormat::BoardGameGeek => super::bgg::parse_export(user_id, body)?,
+        ImportFormat::Igdb => super::igdb::parse_export(user_id, body)?,
+    };
+    if items.is_empty() {
+        return Err(Error::client_error(format!(
+            "No items found in the {} export",
+            format.name()
+        )));
+    }
+    Ok(items)
+}
+
+/// Imported items are read back from the item table as the export isn't stored
+pub async fn get_import(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    format: ImportFormat,
+) -> Result<(Source, Vec<ItemMetadata>), Error> {
+    let items: Vec<ImportedItem> = client
+        .query_documents(QueryDocumentsBuilder::new(
+            "item",
+            View::User(user_id.clone()),
+            CosmosQuery::new(
+                format!(
+                    "SELECT id, name, iframe FROM item WHERE id LIKE '{}:%' ORDER BY name",
+                    format.id()
+                )
+                .as_str()
+                .into_query()?,
+            ),
+        ))
+        .await?;
+    Ok((
+        Source {
+            source_type: SourceType::Import(format),
+            name: format.list_name().to_owned(),
+            unmatched: Vec::new(),
+        },
+        items
+            .into_iter()
+            .map(|item| ItemMetadata::new(item.id, item.name, item.iframe))
+            .collect(),
+    ))
+}
+
+/// Create new items and update the ratings and metadata of items from a previous import
+///
+/// Scores and match records are kept so that exports can be uploaded again.
+pub async fn save_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    format: ImportFormat,
+    items: Vec<Item>,
+) -> Result<(), Error> {
+    let mut existing: HashMap<_, _> = client
+        .query_documents::<RawItem>(QueryDocumentsBuilder::new(
+            "item",
+            View::User(user_id.clone()),
+            CosmosQuery::new(
+                format!("SELECT * FROM item WHERE id LIKE '{}:%'", format.id())
+                    .as_str()
+                    .into_query()?,
+            ),
+        ))
+        .await?
+        .into_iter()
+        .map(|item| Ok((item.id.clone(), Item::try_from(item)?)))
+        .collect::<Result<_, Error>>()?;
+    let mut new_items = Vec::new();
+    let mut updates = Vec::new();
+    let mut metadata = Vec::new();
+    for item in items {
+        match existing.remove(&item.id) {
+            Some(mut current) => {
+                // Imported ratings are on the scale of the item type
+                if item.rating.is_some() {
+                    current.rating = item.rating;
+                    current.rating_scale = item.rating_scale;
+                }
+                updates.push(current);
+                metadata.push(item);
+            }
+            None => new_items.push(item),
+        }
+    }
+    super::create_items(client, new_items, false).await?;
+    // Imported metadata is merged into the stored metadata instead of replacing it
+    super::merge_item_metadata(client, metadata).await?;
+    futures::stream::iter(updates.into_iter().map(|item| {
+        client.write_document(DocumentWriter::Replace(ReplaceDocumentBuilder {
+            collection_name: "item",
+            document_name: item.id.clone(),
+            partition_key: user_id.clone(),
+            document: RawItem::from(item),
+        }))
+    }))
+    .buffered(5)
+    .try_collect()
+    .await
+}
+
+/// Update lists that use the import and return the first one
+pub async fn refresh_lists(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    format: ImportFormat,
+) -> Result<Option<List>, Error> {
+    let lists: Vec<RawList> = client
+        .query_documents(QueryDocumentsBuilder::new(
+            "list",
+            View::User(user_id.clone()),
+            CosmosQuery::new("SELECT * FROM list".into_query()?),
+        ))
+        .await?;
+    let mut first = None;
+    for list in lists {
+        let list: List = list.try_into()?;
+        if !super::leaf_source_types(&list.sources)
+            .any(|source_type| *source_type == SourceType::Import(format))
+        {
+            continue;
+        }
+        let id = list.id.clone();
+        super::update_list_items(client, user_id, list).await?;
+        if first.is_none() {
+            first = Some(id);
+        }
+    }
+    match first {
+        Some(id) => Ok(Some(super::get_list(client, user_id, &id).await?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::query::test::{Mock, TestSessionClient};
+    use mybops::{UserId, import::ImportFormat, storage::DocumentWriter};
+
+    #[tokio::test]
+    async fn test_save_items() {
+        let user_id = UserId("user".to_owned());
+        let items = super::parse_export(
+            ImportFormat::Letterboxd,
+            &user_id,
+            b"Date,Name,Year,Letterboxd URI,Rating
+2023-01-02,Parasite,2019,https://boxd.it/1,4.5
+2023-01-03,Heat,1995,https://boxd.it/2,
+",
+        )
+        .unwrap();
+        let client = TestSessionClient {
+            get_mock: Mock::empty(),
+            query_mock: Mock::new(vec![
+                r#"[{"id": "letterboxd:film:parasite-2019", "user_id": "user", "type": "film", "name": "Parasite", "iframe": null, "rating": 6, "user_score": 1600, "user_wins": 2, "user_losses": 0, "metadata": "{\"year\":2019,\"watched_date\":\"2022-01-01\"}", "hidden": false}]"#,
+            ]),
+            write_mock: Mock::new(vec![(), (), ()]),
+        };
+        super::save_items(&client, &user_id, ImportFormat::Letterboxd, items)
+            .await
+            .unwrap();
+        let writes = client.write_mock.call_args.lock().unwrap();
+        let [
+            DocumentWriter::Create(create),
+            DocumentWriter::Create(merge),
+            DocumentWriter::Replace(replace),
+        ] = &writes[..]
+        else {
+            panic!("unexpected writes {writes:?}");
+        };
+        assert!(
+            create
+                .document
+                .contains(r#""id":"letterboxd:film:heat-1995""#)
+        );
+        assert_eq!(merge.collection_name, "item_metadata");
+        assert!(
+            merge
+                .document
+                .contains(r#"\"letterboxd_uri\":\"https://boxd.it/1\""#)
+        );
+        assert_eq!(
+            replace.document,
+            r#"{"id":"letterboxd:film:parasite-2019","user_id":"user","type":"film","name":"Parasite","iframe":null,"rating":9,"rating_scale":null,"normalized_rating":90.0,"user_score":1600,"user_wins":2,"user_losses":0,"metadata":"{\"year\":2019,\"watched_date\":\"2022-01-01\"}","hidden":false}"#
+        );
+        assert!(
+            super::parse_export(ImportFormat::Goodreads, &user_id, b"Book Id,Title\n").is_err()
+        );
+    }
+}
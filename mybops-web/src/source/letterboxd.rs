@@ -0,0 +1,161 @@
+use mybops::{Error, UserId};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Parse a Letterboxd diary, ratings or watched CSV.
+///
+/// Letterboxd exports don't include directors so a `Director` column is only read if it has
+/// been added to the file.
+pub fn parse_export(user_id: &UserId, body: &[u8]) -> Result<Vec<crate::Item>, Error> {
+    let invalid = || Error::client_error("Expected a Letterboxd diary, ratings or watched CSV");
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body);
+    let headers = reader.headers().map_err(|_| invalid())?.clone();
+    let find = |name: &str| headers.iter().position(|h| h.trim() == name);
+    let name_column = find("Name").ok_or_else(invalid)?;
+    let year_column = find("Year");
+    let uri_column = find("Letterboxd URI");
+    let rating_column = find("Rating");
+    let date_column = find("Date");
+    let watched_column = find("Watched Date");
+    let director_column = find("Director").or_else(|| find("Directors"));
+    // The date column is when the film was logged, rated or marked as watched depending on the file
+    let date_key = match (watched_column, rating_column) {
+        (Some(_), _) => None,
+        (None, Some(_)) => Some("rated_date"),
+        (None, None) => Some("watched_date"),
+    };
+
+    let mut items: Vec<crate::Item> = Vec::new();
+    let mut positions = HashMap::new();
+    for record in reader.records() {
+        let record = record.map_err(|_| invalid())?;
+        let get = |column: Option<usize>| {
+            column
+                .and_then(|i| record.get(i))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let Some(name) = get(Some(name_column)) else {
+            continue;
+        };
+        let year = get(year_column).and_then(|year| year.parse::<i64>().ok());
+        let id = match year {
+            Some(year) => format!("letterboxd:film:{}-{year}", slug(name)),
+            None => format!("letterboxd:film:{}", slug(name)),
+        };
+        let rating = get(rating_column)
+            .and_then(|rating| rating.parse::<f64>().ok())
+            .map(|stars| (stars * 2.0).round() as i32);
+
+        let mut metadata = Map::new();
+        if let Some(year) = year {
+            metadata.insert(String::from("year"), Value::Number(year.into()));
+        }
+        if let Some(director) = get(director_column) {
+            metadata.insert(String::from("director"), Value::String(director.to_owned()));
+        }
+        if let Some(date) = get(watched_column) {
+            metadata.insert(String::from("watched_date"), Value::String(date.to_owned()));
+        }
+        if let (Some(key), Some(date)) = (date_key, get(date_column)) {
+            metadata.insert(String::from(key), Value::String(date.to_owned()));
+        }
+        // Diary URIs link to the diary entry instead of the film
+        if watched_column.is_some() {
+            metadata.insert(String::from("watch_count"), Value::Number(1.into()));
+        } else if let Some(uri) = get(uri_column) {
+            metadata.insert(
+                String::from("letterboxd_uri"),
+                Value::String(uri.to_owned()),
+            );
+        }
+
+        if let Some(&i) = positions.get(&id) {
+            // Diary entries are in chronological order so later entries take precedence
+            let item: &mut crate::Item = &mut items[i];
+            let count = item.metadata.get("watch_count").and_then(Value::as_i64);
+            item.rating = rating.or(item.rating);
+            item.metadata.extend(metadata);
+            if let Some(count) = count {
+                item.metadata.insert(
+                    String::from("watch_count"),
+                    Value::Number((count + 1).into()),
+                );
+            }
+            continue;
+        }
+        positions.insert(id.clone(), items.len());
+        items.push(crate::Item {
+            id,
+            user_id: user_id.0.clone(),
+            r#type: String::from("film"),
+            name: name.to_owned(),
+            iframe: None,
+            rating,
+            rating_scale: None,
+            user_score: 1500,
+            user_wins: 0,
+            user_losses: 0,
+            metadata,
+            hidden: false,
+        });
+    }
+    Ok(items)
+}
+
+/// Lowercase words joined by dashes, similar to Letterboxd film URLs
+fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod test {
+    use mybops::UserId;
+
+    #[test]
+    fn test_parse_diary() {
+        let items = super::parse_export(
+            &UserId("user".to_owned()),
+            b"Date,Name,Year,Letterboxd URI,Rating,Rewatch,Tags,Watched Date
+2023-01-02,Parasite,2019,https://boxd.it/1,4,,,2023-01-01
+2023-02-02,Heat,1995,https://boxd.it/2,,,,2023-02-01
+2024-01-02,Parasite,2019,https://boxd.it/3,4.5,Yes,,2024-01-01
+",
+        )
+        .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].id, "letterboxd:film:parasite-2019");
+        assert_eq!(items[0].r#type, "film");
+        assert_eq!(items[0].rating, Some(9));
+        assert_eq!(
+            serde_json::to_string(&items[0].metadata).unwrap(),
+            r#"{"year":2019,"watched_date":"2024-01-01","watch_count":2}"#
+        );
+        assert_eq!(items[1].rating, None);
+    }
+
+    #[test]
+    fn test_parse_ratings() {
+        let items = super::parse_export(
+            &UserId("user".to_owned()),
+            b"Date,Name,Year,Letterboxd URI,Rating,Director
+2023-01-02,\"Crouching Tiger, Hidden Dragon\",2000,https://boxd.it/a,5,Ang Lee
+",
+        )
+        .unwrap();
+        assert_eq!(
+            items[0].id,
+            "letterboxd:film:crouching-tiger-hidden-dragon-2000"
+        );
+        assert_eq!(items[0].rating, Some(10));
+        assert_eq!(
+            serde_json::to_string(&items[0].metadata).unwrap(),
+            r#"{"year":2000,"director":"Ang Lee","rated_date":"2023-01-02","letterboxd_uri":"https://boxd.it/a"}"#
+        );
+        assert!(super::parse_export(&UserId("user".to_owned()), b"Title,Author\n").is_err());
+    }
+}
@@ -0,0 +1,520 @@
+use mybops::{Error, ItemMetadata, Source, SourceType, UserId, storage::SessionClient};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use symphonia::core::{
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, StandardTagKey, Tag},
+    probe::Hint,
+};
+
+const EXTENSIONS: [&str; 7] = ["flac", "m4a", "mp3", "mp4", "ogg", "opus", "wav"];
+
+/// Files from the previous scan are reused if their size and modification time haven't changed
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct LibraryScan {
+    files: Vec<ScannedFile>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct ScannedFile {
+    path: String,
+    size: u64,
+    modified: i64,
+    item: ItemMetadata,
+}
+
+/// Scan a directory under `LIBRARY_ROOT` for tagged audio files.
+///
+/// Items are identified by a hash of their audio packets so that retagging or moving a file keeps
+/// its scores.
+pub async fn get_library(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    path: String,
+) -> Result<(Source, Vec<ItemMetadata>), Error> {
+    let (root, dir) = resolve_path(&path)?;
+    let items = scan_library(client, user_id, format!("local:{path}"), root, dir).await?;
+    let name = if path.is_empty() {
+        String::from("Local library")
+    } else {
+        format!("Local library {path}")
+    };
+    Ok((
+        Source {
+            source_type: SourceType::LocalLibrary(path),
+            name,
+            unmatched: Vec::new(),
+        },
+        items,
+    ))
+}
+
+/// Scan a library directory and save its items
+///
+/// Items of files that were retagged keep their IDs, so their names and metadata are updated.
+async fn scan_library(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    key: String,
+    root: PathBuf,
+    dir: PathBuf,
+) -> Result<Vec<ItemMetadata>, Error> {
+    let previous = match super::cache::get(client, user_id, &key).await? {
+        Some(entry) => serde_json::from_str(&entry.payload)?,
+        None => LibraryScan::default(),
+    };
+    let scan_user_id = user_id.clone();
+    let (scan, items) =
+        tokio::task::spawn_blocking(move || scan(&root, &dir, previous, &scan_user_id))
+            .await
+            .map_err(|e| Error::internal_error(format!("Library scan failed: {e}")))??;
+    super::merge_item_metadata(client, items).await?;
+    super::cache::put(client, user_id, key, None, &scan).await?;
+
+    let mut seen = Vec::new();
+    Ok(scan
+        .files
+        .into_iter()
+        // The same recording can be in a library more than once
+        .filter(|file| {
+            let new = !seen.contains(&file.item.id);
+            seen.push(file.item.id.clone());
+            new
+        })
+        .map(|file| file.item)
+        .collect())
+}
+
+/// Libraries have to be under `LIBRARY_ROOT` so that users can't read arbitrary server directories
+fn resolve_path(path: &str) -> Result<(PathBuf, PathBuf), Error> {
+    let root = std::env::var("LIBRARY_ROOT")
+        .map_err(|_| Error::internal_error("LIBRARY_ROOT is missing"))?;
+    let root = Path::new(&root)
+        .canonicalize()
+        .map_err(|e| Error::internal_error(format!("LIBRARY_ROOT is invalid: {e}")))?;
+    let dir = root
+        .join(path.trim_start_matches('/'))
+        .canonicalize()
+        .map_err(|_| Error::client_error(format!("{path} doesn't exist in the library")))?;
+    if !dir.starts_with(&root) || !dir.is_dir() {
+        return Err(Error::client_error(format!(
+            "{path} isn't a library directory"
+        )));
+    }
+    Ok((root, dir))
+}
+
+/// Return the new scan and items for files that were added or changed since the previous scan.
+///
+/// Scanned files are relative to the library directory and item paths are relative to the root.
+fn scan(
+    root: &Path,
+    dir: &Path,
+    previous: LibraryScan,
+    user_id: &UserId,
+) -> Result<(LibraryScan, Vec<crate::Item>), Error> {
+    let mut previous: HashMap<_, _> = previous
+        .files
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect();
+    let mut paths = Vec::new();
+    list_files(dir, &mut paths)?;
+    paths.sort();
+
+    let mut files = Vec::new();
+    let mut items = Vec::new();
+    for path in paths {
+        let Ok(stat) = path.metadata() else {
+            continue;
+        };
+        let relative = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        let size = stat.len();
+        let modified = stat
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_millis() as i64);
+        if let Some(file) = previous.remove(&relative)
+            && file.size == size
+            && file.modified == modified
+        {
+            files.push(file);
+            continue;
+        }
+        let item_path = path.strip_prefix(root).unwrap_or(&path).to_string_lossy();
+        let item = match read_file(&path, &item_path, user_id) {
+            Ok(item) => item,
+            // Unreadable files are skipped so that one bad file doesn't break the library
+            Err(e) => {
+                tracing::warn!("Skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        files.push(ScannedFile {
+            path: relative,
+            size,
+            modified,
+            item: ItemMetadata::new(item.id.clone(), item.name.clone(), None),
+        });
+        items.push(item);
+    }
+    Ok((LibraryScan { files }, items))
+}
+
+fn list_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::internal_error(format!("Failed to read {}: {e}", dir.display())))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            list_files(&path, paths)?;
+        } else if file_type.is_file()
+            && path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_file(
+    path: &Path,
+    item_path: &str,
+    user_id: &UserId,
+) -> Result<crate::Item, SymphoniaError> {
+    let file = File::open(path)?;
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        MediaSourceStream::new(Box::new(file), Default::default()),
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    // ID3 tags are read while probing and container tags are read by the format reader
+    let mut tags = Vec::new();
+    if let Some(metadata) = probed.metadata.get()
+        && let Some(revision) = metadata.current()
+    {
+        tags.extend_from_slice(revision.tags());
+    }
+    let mut format = probed.format;
+    if let Some(revision) = format.metadata().current() {
+        tags.extend_from_slice(revision.tags());
+    }
+
+    let track = format
+        .default_track()
+        .ok_or(SymphoniaError::Unsupported("no audio track"))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let mut hasher = Sha256::new();
+    let mut end = 0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        if packet.track_id() == track_id {
+            hasher.update(packet.buf());
+            end = end.max(packet.ts() + packet.dur());
+        }
+    }
+    let hash: String = hasher.finalize()[..16]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let duration_ms = match (params.n_frames, params.sample_rate) {
+        (Some(frames), Some(rate)) if rate > 0 => Some(frames * 1000 / u64::from(rate)),
+        _ => params.time_base.map(|time_base| {
+            let time = time_base.calc_time(end);
+            time.seconds * 1000 + (time.frac * 1000.0) as u64
+        }),
+    };
+
+    let mut metadata = tags_to_metadata(&tags);
+    if let Some(duration_ms) = duration_ms {
+        metadata.insert(
+            String::from("duration_ms"),
+            Value::Number(duration_ms.into()),
+        );
+    }
+    metadata.insert(String::from("path"), Value::String(item_path.to_owned()));
+    let name = match metadata.remove("title") {
+        Some(Value::String(title)) => title,
+        _ => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    Ok(crate::Item {
+        id: format!("local:track:{hash}"),
+        user_id: user_id.0.clone(),
+        r#type: String::from("track"),
+        name,
+        iframe: None,
+        rating: None,
+        rating_scale: None,
+        user_score: 1500,
+        user_wins: 0,
+        user_losses: 0,
+        metadata,
+        hidden: false,
+    })
+}
+
+/// Map tags to the same metadata keys as Spotify tracks.
+///
+/// The title is returned under `title` so that the caller can use it as the item name.
+fn tags_to_metadata(tags: &[Tag]) -> Map<String, Value> {
+    let mut metadata = Map::new();
+    let mut artists = Vec::new();
+    let mut genres = Vec::new();
+    for tag in tags {
+        let Some(key) = tag.std_key else {
+            continue;
+        };
+        // RIFF INFO values keep their null terminators
+        let value = tag.value.to_string();
+        let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        if value.is_empty() {
+            continue;
+        }
+        match key {
+            StandardTagKey::TrackTitle => {
+                metadata.insert(String::from("title"), Value::String(value.to_owned()));
+            }
+            // Multiple values are either separate tags or joined in a single tag
+            StandardTagKey::Artist => artists.extend(split_values(value)),
+            StandardTagKey::Genre => genres.extend(split_values(value)),
+            StandardTagKey::Album => {
+                metadata.insert(String::from("album"), Value::String(value.to_owned()));
+            }
+            StandardTagKey::AlbumArtist => {
+                metadata.insert(
+                    String::from("album_artist"),
+                    Value::String(value.to_owned()),
+                );
+            }
+            StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate => {
+                if metadata.contains_key("release_date") {
+                    continue;
+                }
+                metadata.insert(
+                    String::from("release_date"),
+                    Value::String(value.to_owned()),
+                );
+                if let Some(year) = value.get(..4).and_then(|year| year.parse::<i64>().ok()) {
+                    metadata.insert(String::from("year"), Value::Number(year.into()));
+                }
+            }
+            // Track numbers can include the number of tracks, e.g. 3/12
+            StandardTagKey::TrackNumber => {
+                if let Some(number) = value
+                    .split('/')
+                    .next()
+                    .and_then(|number| number.trim().parse::<i64>().ok())
+                {
+                    metadata.insert(String::from("track_number"), Value::Number(number.into()));
+                }
+            }
+            StandardTagKey::IdentIsrc => {
+                metadata.insert(String::from("isrc"), Value::String(value.to_uppercase()));
+            }
+            _ => {}
+        }
+    }
+    if !artists.is_empty() {
+        artists.dedup();
+        metadata.insert(
+            String::from("artists"),
+            Value::Array(artists.into_iter().map(Value::String).collect()),
+        );
+    }
+    if !genres.is_empty() {
+        genres.dedup();
+        metadata.insert(
+            String::from("genres"),
+            Value::Array(genres.into_iter().map(Value::String).collect()),
+        );
+    }
+    metadata
+}
+
+fn split_values(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split([';', '\0'])
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::LibraryScan;
+    use crate::query::test::sql_client;
+    use mybops::UserId;
+    use rusqlite::Connection;
+    use std::path::Path;
+    use symphonia::core::meta::{StandardTagKey, Tag, Value};
+
+    #[test]
+    fn test_tags_to_metadata() {
+        let tag = |key, value: &str| Tag::new(Some(key), "", Value::from(value));
+        let metadata = super::tags_to_metadata(&[
+            tag(StandardTagKey::TrackTitle, "Airbag"),
+            tag(StandardTagKey::Artist, "Radiohead"),
+            tag(StandardTagKey::Album, "OK Computer"),
+            tag(StandardTagKey::Date, "1997-05-21"),
+            tag(StandardTagKey::OriginalDate, "1997"),
+            tag(StandardTagKey::TrackNumber, "1/12"),
+            tag(StandardTagKey::IdentIsrc, "gbaye9700134"),
+            tag(StandardTagKey::Genre, "Alternative; Rock"),
+            Tag::new(None, "COMMENT", Value::from("ripped")),
+        ]);
+        assert_eq!(
+            serde_json::to_string(&metadata).unwrap(),
+            r#"{"title":"Airbag","album":"OK Computer","release_date":"1997-05-21","year":1997,"track_number":1,"isrc":"GBAYE9700134","artists":["Radiohead"],"genres":["Alternative","Rock"]}"#
+        );
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join(format!("mybops-library-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("album")).unwrap();
+        write_wav(&dir.join("album/01.wav"), "Airbag", "Radiohead", &[1; 800]);
+        write_wav(
+            &dir.join("album/02.WAV"),
+            "Paranoid Android",
+            "Radiohead",
+            &[2; 400],
+        );
+        std::fs::write(dir.join("album/cover.jpg"), b"").unwrap();
+        let user_id = UserId("user".to_owned());
+
+        let (scan, items) =
+            super::scan(&dir, &dir.join("album"), LibraryScan::default(), &user_id).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].name, "Airbag");
+        assert!(items[0].id.starts_with("local:track:"));
+        assert_eq!(items[0].r#type, "track");
+        assert_eq!(
+            items[0].metadata["artists"],
+            serde_json::json!(["Radiohead"])
+        );
+        assert_eq!(items[0].metadata["duration_ms"], 100);
+        assert_eq!(items[0].metadata["path"], "album/01.wav");
+        assert_eq!(scan.files[0].path, "01.wav");
+
+        // Retagging a file keeps its ID
+        write_wav(
+            &dir.join("album/02.WAV"),
+            "Paranoid",
+            "Radiohead",
+            &[2; 400],
+        );
+        let first = scan.files[0].clone();
+        let (scan, items) = super::scan(&dir, &dir.join("album"), scan, &user_id).unwrap();
+        assert_eq!(scan.files[0], first);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name, "Paranoid");
+        assert_eq!(items[0].id, scan.files[1].item.id);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rescan_retagged_file() {
+        let client = sql_client("library");
+        let dir = std::env::temp_dir().join(format!("mybops-rescan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_wav(&dir.join("01.wav"), "Airbag", "Radiohead", &[1; 800]);
+        let user_id = UserId("user".to_owned());
+        let scan = || {
+            super::scan_library(
+                &client,
+                &user_id,
+                "local:".to_owned(),
+                dir.clone(),
+                dir.clone(),
+            )
+        };
+        let items = scan().await.unwrap();
+        write_wav(
+            &dir.join("01.wav"),
+            "Airbag (Remastered)",
+            "Thom Yorke",
+            &[1; 800],
+        );
+        assert_eq!(scan().await.unwrap()[0].id, items[0].id);
+        let (name, artists): (String, String) = Connection::open(client.path)
+            .unwrap()
+            .query_row("SELECT name, metadata->'artists' FROM _item", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(name, "Airbag (Remastered)");
+        assert_eq!(artists, r#"["Thom Yorke"]"#);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Write a mono 8 kHz 8-bit WAV with an INFO chunk
+    fn write_wav(path: &Path, title: &str, artist: &str, samples: &[u8]) {
+        let mut info = b"INFO".to_vec();
+        for (id, value) in [(b"INAM", title), (b"IART", artist)] {
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            if value.len() % 2 == 1 {
+                value.push(0);
+            }
+            info.extend_from_slice(id);
+            info.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            info.extend_from_slice(&value);
+        }
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&8000u32.to_le_bytes());
+        fmt.extend_from_slice(&8000u32.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&8u16.to_le_bytes());
+
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in [
+            (b"fmt ", &fmt),
+            (b"LIST", &info),
+            (b"data", &samples.to_vec()),
+        ] {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(chunk);
+        }
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&body);
+        std::fs::write(path, wav).unwrap();
+    }
+}
@@ -0,0 +1,505 @@
+use futures::StreamExt;
+use mybops::{
+    Error, Source, SourceType, UserId,
+    scrobble::{ScrobbleKind, ScrobblePeriod, ScrobbleService, Scrobbles},
+};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::Level;
+
+/// Number of top entries that are matched against Spotify
+const LIMIT: usize = 50;
+
+/// A ranked track, album or artist with the number of times it was played
+#[derive(Debug, PartialEq)]
+pub struct TopEntry {
+    pub name: String,
+    pub artist: Option<String>,
+    pub count: i64,
+}
+
+/// A single play from an export file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listen {
+    pub track: String,
+    pub artist: String,
+    pub album: Option<String>,
+}
+
+/// Responses are keyed by method, e.g. `{"toptracks": {"track": [...]}}`
+#[derive(Debug, Deserialize)]
+enum LastFmResponse {
+    #[serde(rename = "toptracks")]
+    Tracks { track: Vec<LastFmEntry> },
+    #[serde(rename = "topalbums")]
+    Albums { album: Vec<LastFmEntry> },
+    #[serde(rename = "topartists")]
+    Artists { artist: Vec<LastFmEntry> },
+}
+
+#[derive(Debug, Deserialize)]
+struct LastFmEntry {
+    name: String,
+    /// Last.fm returns counts as strings
+    playcount: String,
+    artist: Option<LastFmArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LastFmArtist {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenBrainzResponse {
+    payload: ListenBrainzPayload,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ListenBrainzPayload {
+    #[serde(default)]
+    recordings: Vec<ListenBrainzEntry>,
+    #[serde(default)]
+    releases: Vec<ListenBrainzEntry>,
+    #[serde(default)]
+    artists: Vec<ListenBrainzEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenBrainzEntry {
+    track_name: Option<String>,
+    release_name: Option<String>,
+    artist_name: String,
+    listen_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenBrainzListen {
+    track_metadata: ListenBrainzTrack,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenBrainzTrack {
+    track_name: String,
+    artist_name: String,
+    release_name: Option<String>,
+}
+
+pub async fn get_scrobbles(
+    user_id: &UserId,
+    scrobbles: Scrobbles,
+) -> Result<(Source, Vec<crate::Item>), Error> {
+    let entries = match scrobbles.service {
+        ScrobbleService::LastFm => get_lastfm_top(&scrobbles).await?,
+        ScrobbleService::ListenBrainz => get_listenbrainz_top(&scrobbles).await?,
+    };
+    let items = match_entries(user_id, scrobbles.service, scrobbles.kind, entries).await?;
+    let name = format!(
+        "{} top {} for {} ({})",
+        scrobbles.service.name(),
+        scrobbles.kind.id(),
+        scrobbles.user,
+        scrobbles.period.name()
+    );
+    Ok((
+        Source {
+            source_type: SourceType::Scrobbles(scrobbles),
+            name,
+            unmatched: Vec::new(),
+        },
+        items,
+    ))
+}
+
+/// Match top entries from an export file against Spotify
+pub async fn import_export(
+    user_id: &UserId,
+    service: ScrobbleService,
+    kind: ScrobbleKind,
+    body: &[u8],
+) -> Result<Vec<crate::Item>, Error> {
+    let listens = parse_export(body)?;
+    let entries = get_top_entries(listens, kind, LIMIT);
+    match_entries(user_id, service, kind, entries).await
+}
+
+/// Look up entries in Spotify and record play counts. Entries that can't be matched are skipped.
+async fn match_entries(
+    user_id: &UserId,
+    service: ScrobbleService,
+    kind: ScrobbleKind,
+    entries: Vec<TopEntry>,
+) -> Result<Vec<crate::Item>, Error> {
+    let token = super::spotify::get_token().await?;
+    let token = &token;
+    let total = entries.len();
+    let items: Vec<_> = futures::stream::iter(entries.into_iter().map(|entry| async move {
+        let mut item = match kind {
+            ScrobbleKind::Tracks => {
+                super::spotify::search_song(token, entry.name, entry.artist, user_id).await?
+            }
+            ScrobbleKind::Albums => {
+                super::spotify::search_album(token, entry.name, entry.artist, user_id).await?
+            }
+            ScrobbleKind::Artists => {
+                super::spotify::search_artist(token, entry.name, user_id).await?
+            }
+        };
+        item.metadata.insert(
+            format!("{}_play_count", service.id()),
+            Value::Number(entry.count.into()),
+        );
+        Ok::<_, Error>(item)
+    }))
+    .buffered(5)
+    .collect::<Vec<_>>()
+    .await
+    .into_iter()
+    .filter_map(Result::ok)
+    .collect();
+    if items.len() < total {
+        tracing::event!(
+            Level::WARN,
+            "Matched {} of {} {} entries",
+            items.len(),
+            total,
+            service.name()
+        );
+    }
+    Ok(items)
+}
+
+async fn get_lastfm_top(scrobbles: &Scrobbles) -> Result<Vec<TopEntry>, Error> {
+    let key =
+        std::env::var("LASTFM_KEY").map_err(|_| Error::internal_error("LASTFM_KEY is missing"))?;
+    let method = match scrobbles.kind {
+        ScrobbleKind::Tracks => "user.gettoptracks",
+        ScrobbleKind::Albums => "user.gettopalbums",
+        ScrobbleKind::Artists => "user.gettopartists",
+    };
+    let period = match scrobbles.period {
+        ScrobblePeriod::Week => "7day",
+        ScrobblePeriod::Month => "1month",
+        ScrobblePeriod::Quarter => "3month",
+        ScrobblePeriod::HalfYear => "6month",
+        ScrobblePeriod::Year => "12month",
+        ScrobblePeriod::AllTime => "overall",
+    };
+    let value: Value = Client::new()
+        .get("https://ws.audioscrobbler.com/2.0/")
+        .query(&[
+            ("method", method),
+            ("user", &scrobbles.user),
+            ("period", period),
+            ("api_key", &key),
+            ("format", "json"),
+            ("limit", &LIMIT.to_string()),
+        ])
+        .send()
+        .await?
+        .json()
+        .await?;
+    parse_lastfm(value)
+}
+
+fn parse_lastfm(value: Value) -> Result<Vec<TopEntry>, Error> {
+    if let Some(message) = value.get("message").and_then(Value::as_str) {
+        return Err(Error::client_error(format!("Last.fm: {message}")));
+    }
+    let (LastFmResponse::Tracks { track: entries }
+    | LastFmResponse::Albums { album: entries }
+    | LastFmResponse::Artists { artist: entries }) = serde_json::from_value(value)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| TopEntry {
+            name: entry.name,
+            artist: entry.artist.map(|a| a.name),
+            count: entry.playcount.parse().unwrap_or_default(),
+        })
+        .collect())
+}
+
+async fn get_listenbrainz_top(scrobbles: &Scrobbles) -> Result<Vec<TopEntry>, Error> {
+    let stat = match scrobbles.kind {
+        ScrobbleKind::Tracks => "recordings",
+        ScrobbleKind::Albums => "releases",
+        ScrobbleKind::Artists => "artists",
+    };
+    let range = match scrobbles.period {
+        ScrobblePeriod::Week => "week",
+        ScrobblePeriod::Month => "month",
+        ScrobblePeriod::Quarter => "quarter",
+        ScrobblePeriod::HalfYear => "half_yearly",
+        ScrobblePeriod::Year => "year",
+        ScrobblePeriod::AllTime => "all_time",
+    };
+    let mut request = Client::new()
+        .get(format!(
+            "https://api.listenbrainz.org/1/stats/user/{}/{stat}",
+            scrobbles.user
+        ))
+        .query(&[("range", range), ("count", &LIMIT.to_string())]);
+    if let Ok(token) = std::env::var("LISTENBRAINZ_TOKEN") {
+        request = request.header("Authorization", format!("Token {token}"));
+    }
+    let resp = request.send().await?;
+    match resp.status() {
+        // Statistics haven't been calculated for the user yet
+        StatusCode::NO_CONTENT => return Ok(Vec::new()),
+        StatusCode::NOT_FOUND => {
+            return Err(Error::client_error(format!(
+                "ListenBrainz user {} doesn't exist",
+                scrobbles.user
+            )));
+        }
+        _ => {}
+    }
+    parse_listenbrainz(scrobbles.kind, resp.json().await?)
+}
+
+fn parse_listenbrainz(
+    kind: ScrobbleKind,
+    response: ListenBrainzResponse,
+) -> Result<Vec<TopEntry>, Error> {
+    let payload = response.payload;
+    let entries = match kind {
+        ScrobbleKind::Tracks => payload.recordings,
+        ScrobbleKind::Albums => payload.releases,
+        ScrobbleKind::Artists => payload.artists,
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            let (name, artist) = match kind {
+                ScrobbleKind::Tracks => (entry.track_name, Some(entry.artist_name)),
+                ScrobbleKind::Albums => (entry.release_name, Some(entry.artist_name)),
+                ScrobbleKind::Artists => (Some(entry.artist_name), None),
+            };
+            Ok(TopEntry {
+                name: name.ok_or(Error::internal_error("Unexpected ListenBrainz response"))?,
+                artist,
+                count: entry.listen_count,
+            })
+        })
+        .collect()
+}
+
+/// Parse a ListenBrainz JSON or JSON lines export or a Last.fm CSV export
+///
+/// CSV files can either have `artist`, `album` and `track` headers or no headers with artist,
+/// album, track and date columns.
+pub fn parse_export(body: &[u8]) -> Result<Vec<Listen>, Error> {
+    let invalid = || Error::client_error("Expected a Last.fm CSV or ListenBrainz JSON export");
+    let text = std::str::from_utf8(body).map_err(|_| invalid())?.trim();
+    let listens: Vec<ListenBrainzListen> = if text.starts_with('[') {
+        serde_json::from_str(text).map_err(|_| invalid())?
+    } else if text.starts_with('{') {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?
+    } else {
+        return parse_csv(text).map_err(|_| invalid());
+    };
+    Ok(listens
+        .into_iter()
+        .map(|listen| Listen {
+            track: listen.track_metadata.track_name,
+            artist: listen.track_metadata.artist_name,
+            album: listen.track_metadata.release_name,
+        })
+        .collect())
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Listen>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut records = reader.records().peekable();
+    let mut columns = (0, Some(1), 2);
+    if let Some(Ok(header)) = records.peek() {
+        let find = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+        if let (Some(artist), Some(track)) = (find("artist"), find("track")) {
+            columns = (artist, find("album"), track);
+            records.next();
+        }
+    }
+    let (artist, album, track) = columns;
+    let mut listens = Vec::new();
+    for record in records {
+        let record = record?;
+        let (Some(artist), Some(track)) = (record.get(artist), record.get(track)) else {
+            continue;
+        };
+        listens.push(Listen {
+            track: track.to_owned(),
+            artist: artist.to_owned(),
+            album: album
+                .and_then(|album| record.get(album))
+                .filter(|album| !album.is_empty())
+                .map(str::to_owned),
+        });
+    }
+    Ok(listens)
+}
+
+/// Count plays per track, album or artist and keep the most played entries
+pub fn get_top_entries(listens: Vec<Listen>, kind: ScrobbleKind, limit: usize) -> Vec<TopEntry> {
+    let mut counts: HashMap<(String, String), TopEntry> = HashMap::new();
+    for listen in listens {
+        let (name, artist) = match kind {
+            ScrobbleKind::Tracks => (listen.track, Some(listen.artist)),
+            ScrobbleKind::Albums => match listen.album {
+                Some(album) => (album, Some(listen.artist)),
+                None => continue,
+            },
+            ScrobbleKind::Artists => (listen.artist, None),
+        };
+        let key = (
+            name.to_lowercase(),
+            artist.as_deref().unwrap_or_default().to_lowercase(),
+        );
+        counts
+            .entry(key)
+            .or_insert(TopEntry {
+                name,
+                artist,
+                count: 0,
+            })
+            .count += 1;
+    }
+    let mut entries: Vec<_> = counts.into_values().collect();
+    entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    entries.truncate(limit);
+    entries
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Listen, TopEntry};
+    use mybops::scrobble::ScrobbleKind;
+
+    #[test]
+    fn test_parse_lastfm() {
+        let value = serde_json::json!({"toptracks": {"track": [
+            {"name": "Karma Police", "playcount": "12", "artist": {"name": "Radiohead", "mbid": ""}},
+        ], "@attr": {"user": "rj"}}});
+        assert_eq!(
+            super::parse_lastfm(value).unwrap(),
+            [TopEntry {
+                name: "Karma Police".to_owned(),
+                artist: Some("Radiohead".to_owned()),
+                count: 12,
+            }]
+        );
+        let value = serde_json::json!({"topartists": {"artist": [{"name": "Radiohead", "playcount": "30"}]}});
+        assert_eq!(
+            super::parse_lastfm(value).unwrap(),
+            [TopEntry {
+                name: "Radiohead".to_owned(),
+                artist: None,
+                count: 30,
+            }]
+        );
+        let value = serde_json::json!({"error": 6, "message": "User not found"});
+        assert!(super::parse_lastfm(value).is_err());
+    }
+
+    #[test]
+    fn test_parse_listenbrainz() {
+        let response = serde_json::from_value(serde_json::json!({"payload": {"releases": [
+            {"release_name": "OK Computer", "artist_name": "Radiohead", "listen_count": 7},
+        ], "count": 1}}))
+        .unwrap();
+        assert_eq!(
+            super::parse_listenbrainz(ScrobbleKind::Albums, response).unwrap(),
+            [TopEntry {
+                name: "OK Computer".to_owned(),
+                artist: Some("Radiohead".to_owned()),
+                count: 7,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_export() {
+        let karma_police = Listen {
+            track: "Karma Police".to_owned(),
+            artist: "Radiohead".to_owned(),
+            album: Some("OK Computer".to_owned()),
+        };
+        assert_eq!(
+            super::parse_export(b"Radiohead,OK Computer,Karma Police,08 Oct 2023 12:34\n").unwrap(),
+            std::slice::from_ref(&karma_police)
+        );
+        assert_eq!(
+            super::parse_export(
+                b"uts,artist,album,track\n1696768440,Radiohead,OK Computer,Karma Police\n"
+            )
+            .unwrap(),
+            std::slice::from_ref(&karma_police)
+        );
+        let listen = r#"{"listened_at": 1696768440, "track_metadata": {"track_name": "Karma Police", "artist_name": "Radiohead", "release_name": "OK Computer"}}"#;
+        assert_eq!(
+            super::parse_export(format!("[{listen}]").as_bytes()).unwrap(),
+            std::slice::from_ref(&karma_police)
+        );
+        assert_eq!(
+            super::parse_export(format!("{listen}\n{listen}\n").as_bytes()).unwrap(),
+            [karma_police.clone(), karma_police]
+        );
+        assert!(super::parse_export(b"{\"tracks\": 1}").is_err());
+    }
+
+    #[test]
+    fn test_top_entries() {
+        let listen = |track: &str, artist: &str, album: Option<&str>| Listen {
+            track: track.to_owned(),
+            artist: artist.to_owned(),
+            album: album.map(str::to_owned),
+        };
+        let listens = || {
+            vec![
+                listen("Karma Police", "Radiohead", Some("OK Computer")),
+                listen("karma police", "radiohead", Some("OK Computer")),
+                listen("Airbag", "Radiohead", Some("OK Computer")),
+                listen("Idioteque", "Radiohead", None),
+            ]
+        };
+        assert_eq!(
+            super::get_top_entries(listens(), ScrobbleKind::Tracks, 2),
+            [
+                TopEntry {
+                    name: "Karma Police".to_owned(),
+                    artist: Some("Radiohead".to_owned()),
+                    count: 2,
+                },
+                TopEntry {
+                    name: "Airbag".to_owned(),
+                    artist: Some("Radiohead".to_owned()),
+                    count: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            super::get_top_entries(listens(), ScrobbleKind::Albums, 10),
+            [TopEntry {
+                name: "OK Computer".to_owned(),
+                artist: Some("Radiohead".to_owned()),
+                count: 3,
+            }]
+        );
+        assert_eq!(
+            super::get_top_entries(listens(), ScrobbleKind::Artists, 10),
+            [TopEntry {
+                name: "Radiohead".to_owned(),
+                artist: None,
+                count: 4,
+            }]
+        );
+    }
+}
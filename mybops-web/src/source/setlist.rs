@@ -1,9 +1,18 @@
 use futures::StreamExt;
-use mybops::{Error, Id, Source, SourceType, UserId};
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use mybops::{
+    Error, Id, Source, SourceType, UserId,
+    setlist::{SetlistFilter, Setlists},
+};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::collections::HashMap;
 use time::Date;
 
+const SETLIST_URL: &str = "https://api.setlist.fm/rest/1.0";
+/// Tours can have hundreds of shows so only the first pages are aggregated
+const MAX_PAGES: usize = 5;
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct Setlist {
@@ -16,6 +25,8 @@ struct Setlist {
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Artist {
+    #[serde(default)]
+    pub mbid: String,
     pub name: String,
 }
 
@@ -29,7 +40,8 @@ struct Venue {
 #[serde(rename_all = "camelCase")]
 struct City {
     pub name: String,
-    pub state_code: String,
+    /// Only set for some countries
+    pub state_code: Option<String>,
     pub country: Country,
 }
 
@@ -55,64 +67,317 @@ struct Song {
     pub cover: Option<Artist>,
 }
 
-pub async fn get_setlist(user_id: &UserId, id: Id) -> Result<(Source, Vec<crate::Item>), Error> {
-    let token = crate::source::spotify::get_token().await?;
-    let token = &token;
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetlistPage {
+    #[serde(default)]
+    pub setlist: Vec<Setlist>,
+    pub total: usize,
+    pub page: usize,
+    pub items_per_page: usize,
+}
 
-    let setlist: Setlist = Client::new()
-        .get(format!("https://api.setlist.fm/rest/1.0/setlist/{}", id.id))
-        .header("Accept", "application/json")
-        .header(
-            "x-api-key",
-            std::env::var("SETLIST_KEY").expect("SETLIST_KEY is missing"),
-        )
-        .send()
-        .await?
-        .json()
-        .await?;
+#[derive(Debug, Deserialize)]
+struct ArtistPage {
+    #[serde(default)]
+    pub artist: Vec<Artist>,
+}
+
+/// A song and how many of the setlists it was played in
+#[derive(Debug, PartialEq)]
+struct SongCount {
+    name: String,
+    artist: String,
+    count: usize,
+}
 
-    let songs: Vec<_> = setlist
-        .sets
-        .set
-        .into_iter()
-        .flat_map(|s| {
-            s.song.into_iter().filter_map(|s| match s.tape {
-                None => Some((
-                    s.name.clone(),
-                    Some(s.cover.as_ref().unwrap_or(&setlist.artist).name.clone()),
-                )),
-                Some(_) => None,
-            })
-        })
-        .collect();
-    let items =
-        futures::stream::iter(songs.into_iter().map(|(song, artist)| {
-            crate::source::spotify::search_song(token, song, artist, user_id)
-        }))
-        .buffered(5)
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .filter_map(Result::ok)
-        .collect();
-    let read_format = time::format_description::parse("[day]-[month]-[year]").unwrap();
-    let write_format = time::format_description::parse("[month repr:short] [day], [year]").unwrap();
+pub async fn get_setlist(user_id: &UserId, id: Id) -> Result<(Source, Vec<crate::Item>), Error> {
+    let setlist: Setlist = get(&format!("/setlist/{}", id.id), &[])
+        .await?
+        .ok_or(Error::client_error("Setlist doesn't exist"))?;
+    let (items, unmatched) =
+        match_songs(user_id, count_songs(std::slice::from_ref(&setlist)), false).await?;
+    let read_format =
+        time::format_description::parse_borrowed::<2>("[day]-[month]-[year]").unwrap();
+    let write_format =
+        time::format_description::parse_borrowed::<2>("[month repr:short] [day], [year]").unwrap();
     let date = Date::parse(&setlist.event_date, &read_format)
         .map_err(|e| Error::internal_error(format!("Unexpected date from setlist.fm: {e}")))?;
+    let city = &setlist.venue.city;
+    let location = if let Some(state_code) = &city.state_code {
+        format!("{}, {}, {}", city.name, state_code, city.country.code)
+    } else {
+        format!("{}, {}", city.name, city.country.code)
+    };
     let name = format!(
-        "{} {} at {}, {}, {}, {}",
+        "{} {} at {}, {}",
         date.format(&write_format).unwrap(),
         setlist.artist.name,
         setlist.venue.name,
-        setlist.venue.city.name,
-        setlist.venue.city.state_code,
-        setlist.venue.city.country.code
+        location
     );
     Ok((
         Source {
             source_type: SourceType::Setlist(id),
             name,
+            unmatched,
         },
         items,
     ))
 }
+
+/// Songs played on a tour or in an artist's recent setlists, ordered by how often they were played
+pub async fn get_setlists(
+    user_id: &UserId,
+    setlists: Setlists,
+) -> Result<(Source, Vec<crate::Item>), Error> {
+    let artist = get_artist(&setlists.artist).await?;
+    let (shows, name) = match &setlists.filter {
+        SetlistFilter::Tour(tour) => {
+            let query = [("artistMbid", artist.mbid.as_str()), ("tourName", tour)];
+            let (shows, total) = get_pages(
+                |page| get_page("/search/setlists", &query, page),
+                usize::MAX,
+            )
+            .await?;
+            if shows.is_empty() {
+                return Err(Error::client_error(format!(
+                    "Couldn't find any {} setlists for {tour}",
+                    artist.name
+                )));
+            }
+            let name = tour_name(&artist.name, tour, shows.len(), total);
+            (shows, name)
+        }
+        SetlistFilter::Recent(count) => {
+            let path = format!("/artist/{}/setlists", artist.mbid);
+            let (shows, _) = get_pages(|page| get_page(&path, &[], page), *count).await?;
+            let name = format!("{} last {} setlists", artist.name, shows.len());
+            (shows, name)
+        }
+    };
+    let (items, unmatched) = match_songs(user_id, count_songs(&shows), true).await?;
+    Ok((
+        Source {
+            source_type: SourceType::Setlists(setlists),
+            name,
+            unmatched,
+        },
+        items,
+    ))
+}
+
+/// Artists can be referenced by MusicBrainz ID or searched by name
+async fn get_artist(artist: &str) -> Result<Artist, Error> {
+    let is_mbid = artist.len() == 36 && artist.chars().all(|c| c == '-' || c.is_ascii_hexdigit());
+    let artist = if is_mbid {
+        get(&format!("/artist/{artist}"), &[]).await?
+    } else {
+        get::<ArtistPage>(
+            "/search/artists",
+            &[("artistName", artist), ("sort", "relevance")],
+        )
+        .await?
+        .and_then(|page| page.artist.into_iter().next())
+    };
+    artist.ok_or(Error::client_error("Couldn't find artist on setlist.fm"))
+}
+
+/// Read pages until `limit` setlists or `MAX_PAGES` pages are read and return the setlists along
+/// with the total number of setlists
+async fn get_pages<F, Fut>(mut get_page: F, limit: usize) -> Result<(Vec<Setlist>, usize), Error>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Option<SetlistPage>, Error>>,
+{
+    let mut setlists = Vec::new();
+    let mut total = 0;
+    for page in 1..=MAX_PAGES {
+        let Some(page) = get_page(page).await? else {
+            break;
+        };
+        let last_page = page.page * page.items_per_page >= page.total;
+        total = page.total;
+        setlists.extend(page.setlist);
+        if last_page || setlists.len() >= limit {
+            break;
+        }
+    }
+    setlists.truncate(limit);
+    Ok((setlists, total))
+}
+
+async fn get_page(
+    path: &str,
+    query: &[(&str, &str)],
+    page: usize,
+) -> Result<Option<SetlistPage>, Error> {
+    let page = page.to_string();
+    let mut params = query.to_vec();
+    params.push(("p", &page));
+    get(path, &params).await
+}
+
+/// Tours that are cut off at `MAX_PAGES` say how many of their shows were counted
+fn tour_name(artist: &str, tour: &str, shows: usize, total: usize) -> String {
+    if shows < total {
+        format!("{artist} {tour} (first {shows} of {total} shows)")
+    } else {
+        format!("{artist} {tour} ({shows} shows)")
+    }
+}
+
+/// setlist.fm returns a 404 for searches without results
+async fn get<T: DeserializeOwned>(path: &str, query: &[(&str, &str)]) -> Result<Option<T>, Error> {
+    let key = std::env::var("SETLIST_KEY")
+        .map_err(|_| Error::internal_error("SETLIST_KEY is missing"))?;
+    let resp = Client::new()
+        .get(format!("{SETLIST_URL}{path}"))
+        .query(query)
+        .header("Accept", "application/json")
+        .header("x-api-key", key)
+        .send()
+        .await?;
+    match resp.status() {
+        StatusCode::NOT_FOUND => Ok(None),
+        StatusCode::TOO_MANY_REQUESTS => Err(Error::client_error(
+            "setlist.fm is rate limiting requests. Try again later.",
+        )),
+        _ => Ok(Some(resp.error_for_status()?.json().await?)),
+    }
+}
+
+/// Count how many setlists each song was played in, skipping tapes and songs without names
+fn count_songs(setlists: &[Setlist]) -> Vec<SongCount> {
+    let mut songs: Vec<SongCount> = Vec::new();
+    let mut positions: HashMap<(String, String), usize> = HashMap::new();
+    for setlist in setlists {
+        let mut seen = Vec::new();
+        for song in setlist.sets.set.iter().flat_map(|s| &s.song) {
+            if song.tape.is_some() || song.name.trim().is_empty() {
+                continue;
+            }
+            let artist = &song.cover.as_ref().unwrap_or(&setlist.artist).name;
+            let key = (song.name.to_lowercase(), artist.to_lowercase());
+            if seen.contains(&key) {
+                continue;
+            }
+            match positions.get(&key) {
+                Some(&i) => songs[i].count += 1,
+                None => {
+                    positions.insert(key.clone(), songs.len());
+                    songs.push(SongCount {
+                        name: song.name.clone(),
+                        artist: artist.clone(),
+                        count: 1,
+                    });
+                }
+            }
+            seen.push(key);
+        }
+    }
+    // Stable sort keeps songs that were played the same number of times in setlist order
+    songs.sort_by_key(|song| std::cmp::Reverse(song.count));
+    songs
+}
+
+/// Match songs to Spotify tracks and return the songs that couldn't be found
+async fn match_songs(
+    user_id: &UserId,
+    songs: Vec<SongCount>,
+    with_counts: bool,
+) -> Result<(Vec<crate::Item>, Vec<String>), Error> {
+    let token = crate::source::spotify::get_token().await?;
+    let token = &token;
+    let results = futures::stream::iter(songs.into_iter().map(|song| async move {
+        let result = crate::source::spotify::search_song(
+            token,
+            song.name.clone(),
+            Some(song.artist.clone()),
+            user_id,
+        )
+        .await;
+        (song, result)
+    }))
+    .buffered(5)
+    .collect::<Vec<_>>()
+    .await;
+    let mut items = Vec::new();
+    let mut unmatched = Vec::new();
+    for (song, result) in results {
+        match result {
+            Ok(mut item) => {
+                if with_counts {
+                    item.metadata.insert(
+                        String::from("setlist_plays"),
+                        Value::Number(song.count.into()),
+                    );
+                }
+                items.push(item);
+            }
+            // Searches without results are reported so that they can be added manually
+            Err(Error::ClientError(_)) => {
+                unmatched.push(format!("{} by {}", song.name, song.artist))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((items, unmatched))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MAX_PAGES, Setlist, SetlistPage, SongCount};
+
+    #[test]
+    fn test_count_songs() {
+        let setlists: Vec<Setlist> = serde_json::from_str(
+            r#"[
+                {"eventDate": "01-06-2024", "artist": {"mbid": "a74b1b7f-71a5-4011-9441-d0b5e4122711", "name": "Radiohead"}, "venue": {"name": "Venue", "city": {"name": "London", "country": {"code": "GB"}}}, "sets": {"set": [{"song": [{"name": "Airbag"}, {"name": "Intro", "tape": true}, {"name": "Karma Police"}]}, {"song": [{"name": "Airbag"}, {"name": ""}]}]}, "url": ""},
+                {"eventDate": "02-06-2024", "artist": {"mbid": "a74b1b7f-71a5-4011-9441-d0b5e4122711", "name": "Radiohead"}, "venue": {"name": "Venue", "city": {"name": "Paris", "country": {"code": "FR"}}}, "sets": {"set": [{"song": [{"name": "Nude"}, {"name": "karma police"}, {"name": "Ceremony", "cover": {"name": "Joy Division"}}]}]}, "url": ""}
+            ]"#,
+        )
+        .unwrap();
+        let song = |name: &str, artist: &str, count| SongCount {
+            name: name.to_owned(),
+            artist: artist.to_owned(),
+            count,
+        };
+        assert_eq!(
+            super::count_songs(&setlists),
+            [
+                song("Karma Police", "Radiohead", 2),
+                song("Airbag", "Radiohead", 1),
+                song("Nude", "Radiohead", 1),
+                song("Ceremony", "Joy Division", 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_pages() {
+        let setlist = r#"{"eventDate": "01-06-2024", "artist": {"name": "Radiohead"}, "venue": {"name": "Venue", "city": {"name": "London", "country": {"code": "GB"}}}, "sets": {"set": []}, "url": ""}"#;
+        let page = |page| async move {
+            Ok(Some(SetlistPage {
+                setlist: (0..20)
+                    .map(|_| serde_json::from_str(setlist).unwrap())
+                    .collect(),
+                total: 250,
+                page,
+                items_per_page: 20,
+            }))
+        };
+        let (shows, total) = super::get_pages(page, usize::MAX).await.unwrap();
+        assert_eq!((shows.len(), total), (MAX_PAGES * 20, 250));
+        assert_eq!(
+            super::tour_name("Radiohead", "OK Computer", shows.len(), total),
+            "Radiohead OK Computer (first 100 of 250 shows)"
+        );
+        let (shows, _) = super::get_pages(page, 30).await.unwrap();
+        assert_eq!(shows.len(), 30);
+        assert_eq!(
+            super::tour_name("Radiohead", "OK Computer", 12, 12),
+            "Radiohead OK Computer (12 shows)"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use crate::query::IntoQuery;
+use mybops::{
+    Error, InternalError, ItemMetadata, List, RawList, Source, SourceType, UserId,
+    storage::{CosmosQuery, QueryDocumentsBuilder, SessionClient, View},
+};
+use serde_json::{Map, Value};
+use sqlparser::ast::{Query, SetExpr};
+
+/// Build the query for a smart list filter such as `rating >= 8 AND year > 2015`
+///
+/// Filters can start with WHERE and can be followed by ORDER BY to order the items.
+pub fn filter_query(filter: &str) -> Result<Query, Error> {
+    let filter = filter.trim();
+    let filter = match filter.split_once(char::is_whitespace) {
+        Some((prefix, filter)) if prefix.eq_ignore_ascii_case("where") => filter.trim(),
+        _ if filter.eq_ignore_ascii_case("where") => "",
+        _ => filter,
+    };
+    if filter.is_empty() {
+        return Err(Error::client_error("Smart list filters can't be empty"));
+    }
+    let query = format!("SELECT id, name, iframe FROM item WHERE {filter}")
+        .as_str()
+        .into_query()?;
+    let SetExpr::Select(select) = &*query.body else {
+        return Err(Error::client_error(format!(
+            "{filter} is not a valid filter"
+        )));
+    };
+    if !select.group_by.is_empty() {
+        return Err(Error::client_error("Smart list filters can't group items"));
+    }
+    Ok(query)
+}
+
+/// Items are read from the item table so the list picks up items that were added or changed
+pub async fn get_smart_items(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    filter: String,
+) -> Result<(Source, Vec<ItemMetadata>), Error> {
+    let items = client
+        .query_documents::<Map<String, Value>>(QueryDocumentsBuilder::new(
+            "item",
+            View::User(user_id.clone()),
+            CosmosQuery::new(crate::query::rewrite_filter(filter_query(&filter)?)?),
+        ))
+        .await
+        .map_err(|e| match e {
+            Error::InternalError(InternalError::SqlError(e)) => {
+                Error::client_error(format!("{filter} is not a valid filter: {e}"))
+            }
+            e => e,
+        })?
+        .into_iter()
+        .map(|item| {
+            ItemMetadata::new(
+                item["id"].as_str().unwrap().to_owned(),
+                item["name"].as_str().unwrap().to_owned(),
+                item["iframe"].as_str().map(ToOwned::to_owned),
+            )
+        })
+        .collect();
+    Ok((
+        Source {
+            source_type: SourceType::Query(filter.clone()),
+            name: filter,
+            unmatched: Vec::new(),
+        },
+        items,
+    ))
+}
+
+/// Re-evaluate the lists that use a smart filter after items are changed
+///
+/// Lists that also read other services are left for saves and refreshes so that item updates
+/// don't call those services.
+pub async fn refresh_lists(client: &impl SessionClient, user_id: &UserId) -> Result<(), Error> {
+    let lists: Vec<RawList> = client
+        .query_documents(QueryDocumentsBuilder::new(
+            "list",
+            View::User(user_id.clone()),
+            CosmosQuery::new("SELECT * FROM list".into_query()?),
+        ))
+        .await?;
+    for list in lists {
+        let list: List = list.try_into()?;
+        if super::leaf_source_types(&list.sources)
+            .any(|source_type| matches!(source_type, SourceType::Query(_)))
+            && super::is_database_list(&list)
+        {
+            super::update_list_items_if(client, user_id, list, super::is_database_list).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::query::test::{Mock, TestSessionClient, insert_item, sql_client};
+    use mybops::{Error, UserId};
+    use serde_json::json;
+
+    #[test]
+    fn test_filter_query() {
+        assert_eq!(
+            super::filter_query("WHERE rating >= 8 AND year > 2015")
+                .unwrap()
+                .to_string(),
+            "SELECT id, name, iframe FROM item WHERE rating >= 8 AND year > 2015"
+        );
+        assert_eq!(
+            super::filter_query("hidden = false ORDER BY user_score DESC")
+                .unwrap()
+                .to_string(),
+            "SELECT id, name, iframe FROM item WHERE hidden = false ORDER BY user_score DESC"
+        );
+        assert!(super::filter_query(" where ").is_err());
+        assert!(super::filter_query("rating > 8 GROUP BY year").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_smart_items() {
+        let client = TestSessionClient {
+            get_mock: Mock::empty(),
+            query_mock: Mock::new(vec![
+                r#"[{"id":"a","name":"A","iframe":null},{"id":"b","name":"B","iframe":"https://b"}]"#,
+            ]),
+            write_mock: Mock::empty(),
+        };
+        let (source, items) =
+            super::get_smart_items(&client, &UserId(String::new()), "year > 2015".to_owned())
+                .await
+                .unwrap();
+        assert_eq!(source.name, "year > 2015");
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].iframe.as_deref(), Some("https://b"));
+        assert_eq!(items[0].score, 1500);
+        assert_eq!(
+            client.query_mock.call_args.lock().unwrap()[0]
+                .query
+                .query
+                .to_string(),
+            "SELECT id, name, iframe FROM item WHERE metadata ->> 'year' > 2015"
+        );
+        let Err(Error::ClientError(_)) =
+            super::get_smart_items(&client, &UserId(String::new()), String::new()).await
+        else {
+            panic!("expected an empty filter error");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_smart_items_metadata() {
+        let client = sql_client("smart");
+        insert_item(&client, "user", "old", json!({"year": 2010, "album": "A"}));
+        insert_item(&client, "user", "new", json!({"year": 2020, "album": "B"}));
+        insert_item(&client, "user", "unknown", json!({}));
+        insert_item(&client, "other", "other", json!({"year": 2020}));
+        for (filter, expected) in [
+            ("year > 2015", vec!["new"]),
+            ("year <= 2015", vec!["old"]),
+            ("album = 'A'", vec!["old"]),
+            ("year > 2000 ORDER BY year DESC", vec!["new", "old"]),
+        ] {
+            let (_, items) =
+                super::get_smart_items(&client, &UserId("user".to_owned()), filter.to_owned())
+                    .await
+                    .unwrap();
+            assert_eq!(
+                items
+                    .iter()
+                    .map(|item| item.id.as_str())
+                    .collect::<Vec<_>>(),
+                expected,
+                "{filter}"
+            );
+        }
+        // Tiers belong to lists, so they can't be used to pick items for a list
+        let err =
+            super::get_smart_items(&client, &UserId("user".to_owned()), "tier = 'S'".to_owned())
+                .await
+                .unwrap_err();
+        assert!(matches!(err, Error::ClientError(e) if e.contains("tier")));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_lists_skips_other_services() {
+        let client = TestSessionClient {
+            get_mock: Mock::empty(),
+            query_mock: Mock::new(vec![
+                r#"[{"id":"a","user_id":"","mode":"{\"User\":null}","name":"A","sources":"[{\"source_type\":{\"Query\":\"year > 2015\"},\"name\":\"year > 2015\"},{\"source_type\":{\"LocalLibrary\":\"music\"},\"name\":\"Local library music\"}]","items":"[]","favorite":false,"query":"SELECT name, user_score FROM item"}]"#,
+            ]),
+            write_mock: Mock::empty(),
+        };
+        super::refresh_lists(&client, &UserId(String::new()))
+            .await
+            .unwrap();
+        assert_eq!(client.query_mock.call_args.lock().unwrap().len(), 1);
+        assert!(client.get_mock.call_args.lock().unwrap().is_empty());
+        assert!(client.write_mock.call_args.lock().unwrap().is_empty());
+    }
+}
@@ -1,156 +1,45 @@
 use crate::query::IntoQuery;
 use mybops::{
     Error, Id, List, ListMode, Source, SourceType, Spotify, UserId,
-    spotify::{Playlist, Playlists, RecentTrack},
+    spotify::{Playlist, Playlists, RecentTrack, SearchPage, SearchResult, SearchResults},
     storage::{
         CosmosParam, CosmosQuery, QueryDocumentsBuilder, SessionClient, SqlSessionClient, View,
     },
 };
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use spotify::api::{AudioFeatures, Page, SearchResponse, SimplifiedArtist, SpotifyApi, Track};
 use std::collections::HashMap;
 use tracing::Level;
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct CreatePlaylist {
-    pub name: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct PlaylistItems {
-    pub items: Vec<Item>,
-    pub next: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct UpdatePlaylist {
-    pub name: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Item {
-    pub track: Track,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct AlbumItems {
-    pub items: Vec<AlbumTrack>,
-}
-
-// TODO: include album and artist metadata
-#[derive(Debug, Deserialize, Serialize)]
-struct Track {
-    pub id: String,
-    pub name: String,
-    pub album: Album,
-    pub artists: Vec<Artist>,
-    pub duration_ms: i32,
-    pub external_urls: HashMap<String, String>,
-    pub popularity: i32,
-    pub track_number: i32,
-    pub uri: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct AlbumTrack {
-    pub href: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Album {
-    pub href: String,
-    pub name: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Artist {
-    pub name: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Search {
-    pub tracks: SearchTracks,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct SearchTracks {
-    pub items: Vec<Track>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct User {
-    pub id: String,
-    pub external_urls: HashMap<String, String>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct RecentTracks {
-    items: Vec<PlayHistory>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct PlayHistory {
-    track: Track,
-}
+const SEARCH_TYPES: [&str; 4] = ["track", "album", "artist", "playlist"];
 
 pub async fn get_playlist(
     user_id: &UserId,
     playlist_id: Id,
 ) -> Result<(Source, Vec<crate::Item>), Error> {
     let token = get_token().await?;
-    let client = Client::new();
-
-    let playlist: Playlist = client
-        .get(format!(
-            "https://api.spotify.com/v1/playlists/{}?limit=50",
-            playlist_id.id
-        ))
-        .header("Authorization", format!("Bearer {}", token.access_token))
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    let mut playlist_items: PlaylistItems = client
-        .get(format!(
-            "https://api.spotify.com/v1/playlists/{}/tracks",
-            playlist_id.id
-        ))
-        .header("Authorization", format!("Bearer {}", token.access_token))
-        .send()
-        .await?
-        .json()
-        .await?;
-    let mut items: Vec<_> = playlist_items
-        .items
-        .into_iter()
-        .map(|i| new_spotify_item(i.track, user_id))
-        .collect();
-    while let Some(uri) = playlist_items.next {
-        playlist_items = client
-            .get(uri)
-            .header("Authorization", format!("Bearer {}", token.access_token))
-            .send()
-            .await?
-            .json()
-            .await?;
-        items.extend(
-            playlist_items
-                .items
-                .into_iter()
-                .map(|i| new_spotify_item(i.track, user_id)),
-        );
-    }
+    let api = SpotifyApi::new(token.access_token);
+    let playlist = api.playlist(&playlist_id.id).await?;
+    let tracks = api.playlist_tracks(&playlist_id.id).await?;
+    let items = new_spotify_items(&api, tracks, user_id).await;
     Ok((
         Source {
             source_type: SourceType::Spotify(Spotify::Playlist(playlist_id)),
             name: playlist.name,
+            unmatched: Vec::new(),
         },
         items,
     ))
 }
 
+pub async fn get_playlist_snapshot_id(playlist_id: &str) -> Result<String, Error> {
+    let token = get_token().await?;
+    SpotifyApi::new(token.access_token)
+        .playlist_snapshot_id(playlist_id)
+        .await
+}
+
 pub async fn import_playlist(
     user_id: &UserId,
     playlist_id: String,
@@ -177,41 +66,15 @@ pub async fn import_playlist(
 
 pub async fn get_album(user_id: &UserId, id: Id) -> Result<(Source, Vec<crate::Item>), Error> {
     let token = get_token().await?;
-    let client = Client::new();
-    let client = &client;
-
-    let album: Album = client
-        .get(format!("https://api.spotify.com/v1/albums/{}", id.id))
-        .header("Authorization", format!("Bearer {}", token.access_token))
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    let album_items: AlbumItems = client
-        .get(format!(
-            "https://api.spotify.com/v1/albums/{}/tracks?limit=50",
-            id.id
-        ))
-        .header("Authorization", format!("Bearer {}", token.access_token))
-        .send()
-        .await?
-        .json()
-        .await?;
-    let mut items = Vec::with_capacity(album_items.items.len());
-    for item in &album_items.items {
-        let response = client
-            .get(&item.href)
-            .header("Authorization", format!("Bearer {}", token.access_token))
-            .send()
-            .await?;
-        tracing::event!(Level::DEBUG, "{} {}", item.href, response.status());
-        items.push(new_spotify_item(response.json().await?, user_id));
-    }
+    let api = SpotifyApi::new(token.access_token);
+    let album = api.album(&id.id).await?;
+    let tracks = api.album_tracks(&id.id).await?;
+    let items = new_spotify_items(&api, tracks, user_id).await;
     Ok((
         Source {
             source_type: SourceType::Spotify(Spotify::Album(id)),
             name: album.name,
+            unmatched: Vec::new(),
         },
         items,
     ))
@@ -240,20 +103,15 @@ pub async fn import_album(user_id: &UserId, id: String) -> Result<(List, Vec<cra
 
 pub async fn get_track(user_id: &UserId, id: Id) -> Result<(Source, Vec<crate::Item>), Error> {
     let token = get_token().await?;
-
-    let track: Track = Client::new()
-        .get(format!("https://api.spotify.com/v1/tracks/{}", id.id))
-        .header("Authorization", format!("Bearer {}", token.access_token))
-        .send()
-        .await?
-        .json()
-        .await?;
+    let api = SpotifyApi::new(token.access_token);
+    let track = api.track(&id.id).await?;
     Ok((
         Source {
             source_type: SourceType::Spotify(Spotify::Track(id)),
             name: track.name.clone(),
+            unmatched: Vec::new(),
         },
-        vec![new_spotify_item(track, user_id)],
+        new_spotify_items(&api, vec![track], user_id).await,
     ))
 }
 
@@ -262,21 +120,9 @@ pub async fn create_playlist(
     user_id: &UserId,
     name: &str,
 ) -> Result<Playlist, Error> {
-    // TODO: error handling
-    let playlist = CreatePlaylist {
-        name: name.to_owned(),
-    };
-    Ok(Client::new()
-        .post(format!(
-            "https://api.spotify.com/v1/users/{}/playlists",
-            user_id.0
-        ))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .json(&playlist)
-        .send()
-        .await?
-        .json()
-        .await?)
+    SpotifyApi::new(access_token)
+        .create_playlist(&user_id.0, name)
+        .await
 }
 
 pub async fn update_playlist(
@@ -284,19 +130,9 @@ pub async fn update_playlist(
     playlist_id: &str,
     name: &str,
 ) -> Result<(), Error> {
-    // TODO: error handling
-    let playlist = UpdatePlaylist {
-        name: name.to_owned(),
-    };
-    Client::new()
-        .put(format!(
-            "https://api.spotify.com/v1/playlists/{playlist_id}"
-        ))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .json(&playlist)
-        .send()
-        .await?;
-    Ok(())
+    SpotifyApi::new(access_token)
+        .update_playlist(playlist_id, name)
+        .await
 }
 
 pub async fn update_list(
@@ -304,48 +140,9 @@ pub async fn update_list(
     playlist_id: &str,
     ids: &[String],
 ) -> Result<(), Error> {
-    let client = Client::new();
-    let mut chunks = ids.chunks(100);
-    let uri = format!(
-        "https://api.spotify.com/v1/playlists/{}/tracks?uris={}",
-        playlist_id,
-        // Clear playlist if empty
-        chunks.next().unwrap_or(&[]).join(",")
-    );
-    // TODO: error handling
-    let resp = client
-        .put(uri)
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Length", "0")
-        .send()
+    SpotifyApi::new(access_token)
+        .replace_playlist_items(playlist_id, ids)
         .await?;
-    if resp.status().is_client_error() || resp.status().is_server_error() {
-        let error = format!(
-            "Spotify update playlist items error: {}",
-            resp.text().await?
-        );
-        return Err(Error::internal_error(error));
-    }
-    for ids in chunks {
-        let uri = format!(
-            "https://api.spotify.com/v1/playlists/{}/tracks?uris={}",
-            playlist_id,
-            ids.join(",")
-        );
-        let resp = client
-            .post(uri)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .header("Content-Length", "0")
-            .send()
-            .await?;
-        if resp.status().is_client_error() || resp.status().is_server_error() {
-            let error = format!(
-                "Spotify update playlist items error: {}",
-                resp.text().await?
-            );
-            return Err(Error::internal_error(error));
-        }
-    }
     Ok(())
 }
 
@@ -407,8 +204,102 @@ async fn get_user_token(refresh_token: &str) -> Result<crate::Token, Error> {
         .await?)
 }
 
+/// Artist genres and audio features need extra requests so they are only fetched when
+/// `SPOTIFY_ENRICH` is set
+async fn new_spotify_items(
+    api: &SpotifyApi,
+    tracks: Vec<Track>,
+    user_id: &UserId,
+) -> Vec<crate::Item> {
+    let enrichment = if std::env::var_os("SPOTIFY_ENRICH").is_some() {
+        get_enrichment(api, &tracks).await
+    } else {
+        Enrichment::default()
+    };
+    tracks
+        .into_iter()
+        .map(|track| {
+            let metadata = enrichment.metadata(&track);
+            let mut item = new_spotify_item(track, user_id);
+            item.metadata.extend(metadata);
+            item
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Enrichment {
+    genres: HashMap<String, Vec<String>>,
+    audio_features: HashMap<String, AudioFeatures>,
+}
+
+impl Enrichment {
+    fn metadata(&self, track: &Track) -> Map<String, Value> {
+        let mut metadata = Map::new();
+        if !self.genres.is_empty() {
+            let mut genres = Vec::new();
+            for genre in track
+                .artists
+                .iter()
+                .filter_map(|a| a.id.as_ref())
+                .filter_map(|id| self.genres.get(id))
+                .flatten()
+            {
+                if !genres.contains(genre) {
+                    genres.push(genre.clone());
+                }
+            }
+            metadata.insert(
+                String::from("genres"),
+                Value::Array(genres.into_iter().map(Value::String).collect()),
+            );
+        }
+        if let Some(features) = self.audio_features.get(&track.id)
+            && let Ok(Value::Object(features)) = serde_json::to_value(features)
+        {
+            metadata.extend(features.into_iter().filter(|(k, _)| k != "id"));
+        }
+        metadata
+    }
+}
+
+/// Enrichment is best effort as audio features aren't available to every Spotify app
+async fn get_enrichment(api: &SpotifyApi, tracks: &[Track]) -> Enrichment {
+    let mut artist_ids: Vec<_> = tracks
+        .iter()
+        .flat_map(|t| t.artists.iter().filter_map(|a| a.id.clone()))
+        .collect();
+    artist_ids.sort();
+    artist_ids.dedup();
+    let genres = match api.artists(&artist_ids).await {
+        Ok(artists) => artists.into_iter().map(|a| (a.id, a.genres)).collect(),
+        Err(e) => {
+            tracing::event!(Level::WARN, "failed to get artist genres: {e}");
+            HashMap::new()
+        }
+    };
+    let track_ids: Vec<_> = tracks.iter().map(|t| t.id.clone()).collect();
+    let audio_features = match api.audio_features(&track_ids).await {
+        Ok(features) => features.into_iter().map(|f| (f.id.clone(), f)).collect(),
+        Err(e) => {
+            tracing::event!(Level::WARN, "failed to get audio features: {e}");
+            HashMap::new()
+        }
+    };
+    Enrichment {
+        genres,
+        audio_features,
+    }
+}
+
 fn new_spotify_item(track: Track, user_id: &UserId) -> crate::Item {
-    let metadata: Map<_, _> = [
+    let release_date = track.album.release_date;
+    let year = release_date
+        .as_ref()
+        .and_then(|date| date.get(..4))
+        .and_then(|year| year.parse::<i64>().ok());
+    let isrc = track.external_ids.get("isrc").cloned();
+    let mut metadata: Map<_, _> = [
         (String::from("album"), Value::String(track.album.name)),
         (
             String::from("artists"),
@@ -432,9 +323,19 @@ fn new_spotify_item(track: Track, user_id: &UserId) -> crate::Item {
             String::from("track_number"),
             Value::Number(track.track_number.into()),
         ),
+        (String::from("explicit"), Value::Bool(track.explicit)),
     ]
     .into_iter()
     .collect();
+    if let Some(release_date) = release_date {
+        metadata.insert(String::from("release_date"), Value::String(release_date));
+    }
+    if let Some(year) = year {
+        metadata.insert(String::from("year"), Value::Number(year.into()));
+    }
+    if let Some(isrc) = isrc {
+        metadata.insert(String::from("isrc"), Value::String(isrc));
+    }
     crate::Item {
         iframe: Some(format!(
             "https://open.spotify.com/embed/track/{}?utm_source=generator",
@@ -445,6 +346,7 @@ fn new_spotify_item(track: Track, user_id: &UserId) -> crate::Item {
         r#type: String::from("track"),
         name: track.name,
         rating: None,
+        rating_scale: None,
         user_score: 1500,
         user_wins: 0,
         user_losses: 0,
@@ -459,76 +361,118 @@ pub async fn search_song(
     artist: Option<String>,
     user_id: &UserId,
 ) -> Result<crate::Item, Error> {
-    let uri = if let Some(artist) = artist {
-        format!(
-            "https://api.spotify.com/v1/search?q=track:{}%20artist:{}&type=track",
-            urlencoding::encode(&name),
-            urlencoding::encode(&artist)
-        )
+    let query = if let Some(artist) = artist {
+        format!("track:{name} artist:{artist}")
     } else {
-        format!(
-            "https://api.spotify.com/v1/search?q=track:{}&type=track",
-            urlencoding::encode(&name),
-        )
+        format!("track:{name}")
     };
-    let result: Search = Client::new()
-        .get(&uri)
-        .header("Authorization", format!("Bearer {}", token.access_token))
-        .send()
-        .await?
-        .json()
+    let result = SpotifyApi::new(&token.access_token)
+        .search(&query, "track", 0, 1)
         .await?;
     Ok(new_spotify_item(
         result
             .tracks
-            .items
-            .into_iter()
-            .next()
+            .and_then(|tracks| tracks.items.into_iter().next())
             .ok_or(Error::client_error("Couldn't find song for query"))?,
         user_id,
     ))
 }
 
+pub async fn search_album(
+    token: &crate::Token,
+    name: String,
+    artist: Option<String>,
+    user_id: &UserId,
+) -> Result<crate::Item, Error> {
+    let query = if let Some(artist) = artist {
+        format!("album:{name} artist:{artist}")
+    } else {
+        format!("album:{name}")
+    };
+    let album = SpotifyApi::new(&token.access_token)
+        .search(&query, "album", 0, 1)
+        .await?
+        .albums
+        .and_then(|albums| albums.items.into_iter().next())
+        .ok_or(Error::client_error("Couldn't find album for query"))?;
+    Ok(crate::Item {
+        iframe: Some(format!(
+            "https://open.spotify.com/embed/album/{}?utm_source=generator",
+            album.id
+        )),
+        id: album.uri,
+        user_id: user_id.0.clone(),
+        r#type: String::from("album"),
+        name: album.name,
+        rating: None,
+        rating_scale: None,
+        user_score: 1500,
+        user_wins: 0,
+        user_losses: 0,
+        metadata: [(
+            String::from("artists"),
+            Value::Array(
+                album
+                    .artists
+                    .into_iter()
+                    .map(|a| Value::String(a.name))
+                    .collect(),
+            ),
+        )]
+        .into_iter()
+        .collect(),
+        hidden: false,
+    })
+}
+
+pub async fn search_artist(
+    token: &crate::Token,
+    name: String,
+    user_id: &UserId,
+) -> Result<crate::Item, Error> {
+    let artist = SpotifyApi::new(&token.access_token)
+        .search(&format!("artist:{name}"), "artist", 0, 1)
+        .await?
+        .artists
+        .and_then(|artists| artists.items.into_iter().next())
+        .ok_or(Error::client_error("Couldn't find artist for query"))?;
+    Ok(crate::Item {
+        iframe: Some(format!(
+            "https://open.spotify.com/embed/artist/{}?utm_source=generator",
+            artist.id
+        )),
+        id: artist.uri,
+        user_id: user_id.0.clone(),
+        r#type: String::from("artist"),
+        name: artist.name,
+        rating: None,
+        rating_scale: None,
+        user_score: 1500,
+        user_wins: 0,
+        user_losses: 0,
+        metadata: [(
+            String::from("genres"),
+            Value::Array(artist.genres.into_iter().map(Value::String).collect()),
+        )]
+        .into_iter()
+        .collect(),
+        hidden: false,
+    })
+}
+
 pub async fn get_recent_tracks(
     cosmos_client: &SqlSessionClient,
     user_id: &UserId,
     access_token: &str,
 ) -> Result<mybops::spotify::RecentTracks, Error> {
-    let recent_tracks: RecentTracks = Client::new()
-        .get("https://api.spotify.com/v1/me/player/recently-played?limit=50")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send()
-        .await?
-        .json()
-        .await?;
+    let recent_tracks = SpotifyApi::new(access_token).recently_played().await?;
     let ids: Vec<_> = recent_tracks
-        .items
         .iter()
         .map(|i| i.track.uri.to_owned())
         .collect();
-    let query = format!(
-        "SELECT id, rating, user_score FROM item WHERE id IN ({})",
-        &"?,".repeat(ids.len())[..ids.len() * 2 - 1]
-    );
-    let items = cosmos_client
-        .query_documents(QueryDocumentsBuilder::new(
-            "item",
-            View::User(user_id.clone()),
-            CosmosQuery::with_params(
-                query.into_query()?,
-                ids.into_iter()
-                    .map(|i| CosmosParam::new(String::from("@ids"), i))
-                    .collect::<Vec<_>>(),
-            ),
-        ))
-        .await?;
-    let map: HashMap<_, _> = items
-        .into_iter()
-        .map(|r: Map<String, Value>| (r["id"].as_str().expect("string id").to_owned(), r))
-        .collect();
+    let map = get_existing(cosmos_client, user_id, "item", ids).await?;
     Ok(mybops::spotify::RecentTracks {
         tracks: recent_tracks
-            .items
             .into_iter()
             .map(|mut i| {
                 let id = i.track.uri;
@@ -559,12 +503,322 @@ pub async fn get_recent_tracks(
     })
 }
 
+/// Look up which IDs already exist in the user's collection
+async fn get_existing(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    collection_name: &'static str,
+    ids: Vec<String>,
+) -> Result<HashMap<String, Map<String, Value>>, Error> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let fields = if collection_name == "item" {
+        "id, rating, user_score"
+    } else {
+        "id"
+    };
+    let query = format!(
+        "SELECT {fields} FROM {collection_name} WHERE id IN ({})",
+        &"?,".repeat(ids.len())[..ids.len() * 2 - 1]
+    );
+    let documents = client
+        .query_documents(QueryDocumentsBuilder::new(
+            collection_name,
+            View::User(user_id.clone()),
+            CosmosQuery::with_params(
+                query.into_query()?,
+                ids.into_iter()
+                    .map(|i| CosmosParam::new(String::from("@ids"), i))
+                    .collect::<Vec<_>>(),
+            ),
+        ))
+        .await?;
+    Ok(documents
+        .into_iter()
+        .map(|r: Map<String, Value>| (r["id"].as_str().expect("string id").to_owned(), r))
+        .collect())
+}
+
+pub async fn search(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    token: &crate::Token,
+    query: &str,
+    types: &str,
+    offset: usize,
+    limit: usize,
+) -> Result<SearchResults, Error> {
+    validate_search(query, types, offset, limit)?;
+    let response = SpotifyApi::new(&token.access_token)
+        .search(query.trim(), types, offset, limit)
+        .await?;
+    let track_ids = response
+        .tracks
+        .iter()
+        .flat_map(|page| page.items.iter().map(|t| t.uri.clone()))
+        .collect();
+    // Imported albums and playlists use the Spotify ID as the list ID
+    let list_ids = response
+        .albums
+        .iter()
+        .flat_map(|page| page.items.iter().map(|a| a.id.clone()))
+        .chain(
+            response
+                .playlists
+                .iter()
+                .flat_map(|page| page.items.iter().flatten().map(|p| p.id.clone())),
+        )
+        .collect();
+    let items = get_existing(client, user_id, "item", track_ids).await?;
+    let lists = get_existing(client, user_id, "list", list_ids).await?;
+    Ok(get_search_results(response, &items, &lists))
+}
+
+fn validate_search(query: &str, types: &str, offset: usize, limit: usize) -> Result<(), Error> {
+    if query.trim().is_empty() {
+        return Err(Error::client_error("Search query is required"));
+    }
+    if let Some(t) = types.split(',').find(|t| !SEARCH_TYPES.contains(t)) {
+        return Err(Error::client_error(format!("Invalid search type: {t}")));
+    }
+    if !(1..=50).contains(&limit) {
+        return Err(Error::client_error("limit must be between 1 and 50"));
+    }
+    // Spotify rejects offsets past 1000
+    if offset + limit > 1000 {
+        return Err(Error::client_error("offset is too large"));
+    }
+    Ok(())
+}
+
+fn get_search_results(
+    response: SearchResponse,
+    items: &HashMap<String, Map<String, Value>>,
+    lists: &HashMap<String, Map<String, Value>>,
+) -> SearchResults {
+    fn page<T>(page: Page<T>, f: impl FnMut(T) -> Option<SearchResult>) -> SearchPage {
+        SearchPage {
+            items: page.items.into_iter().filter_map(f).collect(),
+            offset: page.offset,
+            limit: page.limit,
+            total: page.total,
+        }
+    }
+    fn artists(artists: Vec<SimplifiedArtist>) -> String {
+        artists
+            .into_iter()
+            .map(|a| a.name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    SearchResults {
+        tracks: response.tracks.map(|tracks| {
+            page(tracks, |mut t| {
+                let item = items.get(&t.uri);
+                Some(SearchResult {
+                    id: t.id,
+                    name: t.name,
+                    description: artists(t.artists),
+                    url: t.external_urls.remove("spotify")?,
+                    added: item.is_some(),
+                    rating: item
+                        .and_then(|m| m.get("rating"))
+                        .and_then(Value::as_i64)
+                        .map(|i| i as i32),
+                    user_score: item
+                        .and_then(|m| m.get("user_score"))
+                        .and_then(Value::as_i64)
+                        .map(|i| i as i32),
+                    uri: t.uri,
+                })
+            })
+        }),
+        albums: response.albums.map(|albums| {
+            page(albums, |mut a| {
+                Some(SearchResult {
+                    added: lists.contains_key(&a.id),
+                    id: a.id,
+                    uri: a.uri,
+                    name: a.name,
+                    description: artists(a.artists),
+                    url: a.external_urls.remove("spotify")?,
+                    rating: None,
+                    user_score: None,
+                })
+            })
+        }),
+        artists: response.artists.map(|artists| {
+            page(artists, |mut a| {
+                Some(SearchResult {
+                    id: a.id,
+                    uri: a.uri,
+                    name: a.name,
+                    description: String::new(),
+                    url: a.external_urls.remove("spotify")?,
+                    added: false,
+                    rating: None,
+                    user_score: None,
+                })
+            })
+        }),
+        playlists: response.playlists.map(|playlists| {
+            page(playlists, |p| {
+                let mut p = p?;
+                Some(SearchResult {
+                    added: lists.contains_key(&p.id),
+                    id: p.id,
+                    uri: p.uri,
+                    name: p.name,
+                    description: p.owner.display_name.unwrap_or_default(),
+                    url: p.external_urls.remove("spotify")?,
+                    rating: None,
+                    user_score: None,
+                })
+            })
+        }),
+    }
+}
+
 pub async fn get_playlists(access_token: &str) -> Result<Playlists, Error> {
-    Ok(Client::new()
-        .get("https://api.spotify.com/v1/me/playlists")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send()
-        .await?
-        .json()
-        .await?)
+    Ok(Playlists {
+        items: SpotifyApi::new(access_token)
+            .current_user_playlists()
+            .await?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::Enrichment;
+    use mybops::UserId;
+    use serde_json::{Map, Value, json};
+    use spotify::api::{SearchResponse, Track};
+    use std::collections::HashMap;
+
+    fn track() -> Track {
+        serde_json::from_value(json!({
+            "id": "1",
+            "name": "Track",
+            "album": {"name": "Album", "release_date": "1999-12"},
+            "artists": [{"id": "a", "name": "A"}, {"id": "b", "name": "B"}],
+            "duration_ms": 1000,
+            "explicit": true,
+            "external_ids": {"isrc": "USABC9900001"},
+            "external_urls": {"spotify": "https://open.spotify.com/track/1"},
+            "popularity": 50,
+            "track_number": 1,
+            "uri": "spotify:track:1",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_new_spotify_item() {
+        let item = super::new_spotify_item(track(), &UserId(String::new()));
+        assert_eq!(item.metadata["release_date"], "1999-12");
+        assert_eq!(item.metadata["year"], 1999);
+        assert_eq!(item.metadata["explicit"], true);
+        assert_eq!(item.metadata["isrc"], "USABC9900001");
+        assert!(!item.metadata.contains_key("genres"));
+    }
+
+    #[test]
+    fn test_enrichment() {
+        let enrichment = Enrichment {
+            genres: HashMap::from([
+                (
+                    "a".to_owned(),
+                    vec!["rock".to_owned(), "pop rock".to_owned()],
+                ),
+                ("b".to_owned(), vec!["rock".to_owned(), "pop".to_owned()]),
+            ]),
+            audio_features: HashMap::from([(
+                "1".to_owned(),
+                serde_json::from_value(json!({
+                    "id": "1",
+                    "acousticness": 0.1,
+                    "danceability": 0.2,
+                    "energy": 0.3,
+                    "instrumentalness": 0.4,
+                    "key": 5,
+                    "liveness": 0.6,
+                    "loudness": -7.0,
+                    "mode": 1,
+                    "speechiness": 0.8,
+                    "tempo": 120.0,
+                    "time_signature": 4,
+                    "valence": 0.9,
+                }))
+                .unwrap(),
+            )]),
+        };
+        let metadata = enrichment.metadata(&track());
+        assert_eq!(metadata["genres"], json!(["rock", "pop rock", "pop"]));
+        assert_eq!(metadata["tempo"], 120.0);
+        assert_eq!(metadata["energy"], 0.3);
+        assert!(!metadata.contains_key("id"));
+        assert!(Enrichment::default().metadata(&track()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_search() {
+        assert!(super::validate_search(" the beatles ", "track,album", 0, 20).is_ok());
+        assert!(super::validate_search(" ", "track", 0, 20).is_err());
+        assert!(super::validate_search("q", "show", 0, 20).is_err());
+        assert!(super::validate_search("q", "track", 0, 0).is_err());
+        assert!(super::validate_search("q", "track", 990, 20).is_err());
+    }
+
+    #[test]
+    fn test_get_search_results() {
+        let response: SearchResponse = serde_json::from_str(
+            r#"{
+                "tracks": {"items": [
+                    {"id": "1", "name": "Track", "album": {"href": "", "name": "Album"}, "artists": [{"name": "A"}, {"name": "B"}], "duration_ms": 0, "external_urls": {"spotify": "https://open.spotify.com/track/1"}, "popularity": 0, "track_number": 1, "uri": "spotify:track:1"},
+                    {"id": "2", "name": "Other", "album": {"href": "", "name": "Album"}, "artists": [], "duration_ms": 0, "external_urls": {"spotify": "https://open.spotify.com/track/2"}, "popularity": 0, "track_number": 2, "uri": "spotify:track:2"}
+                ], "offset": 0, "limit": 2, "total": 10},
+                "playlists": {"items": [
+                    null,
+                    {"id": "3", "name": "Playlist", "owner": {"display_name": "Owner"}, "external_urls": {"spotify": "https://open.spotify.com/playlist/3"}, "uri": "spotify:playlist:3"}
+                ], "offset": 0, "limit": 2, "total": 2}
+            }"#,
+        )
+        .unwrap();
+        let items = HashMap::from([(
+            String::from("spotify:track:1"),
+            serde_json::from_str::<Map<String, Value>>(
+                r#"{"id": "spotify:track:1", "rating": 5, "user_score": 1600}"#,
+            )
+            .unwrap(),
+        )]);
+        let lists = HashMap::from([(String::from("3"), Map::new())]);
+        let results = super::get_search_results(response, &items, &lists);
+
+        let tracks = results.tracks.unwrap();
+        assert_eq!(tracks.total, 10);
+        assert_eq!(
+            tracks
+                .items
+                .iter()
+                .map(|t| (
+                    t.name.as_str(),
+                    t.description.as_str(),
+                    t.added,
+                    t.rating,
+                    t.user_score
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("Track", "A, B", true, Some(5), Some(1600)),
+                ("Other", "", false, None, None)
+            ]
+        );
+        let playlists = results.playlists.unwrap();
+        assert_eq!(playlists.items.len(), 1);
+        assert_eq!(playlists.items[0].description, "Owner");
+        assert!(playlists.items[0].added);
+        assert!(results.albums.is_none());
+        assert!(results.artists.is_none());
+    }
 }
@@ -0,0 +1,309 @@
+use mybops::{
+    Error, Id, List, ListMode, Source, SourceType, Spotify, SyncConflict, SyncState, UserId,
+    storage::SessionClient,
+};
+use spotify::api::SpotifyApi;
+use std::collections::HashSet;
+
+const TRACK_PREFIX: &str = "spotify:track:";
+
+/// Two-way sync between a user list and the Spotify playlist it was pushed to.
+///
+/// The playlist contents from the previous sync are used as the base of a three-way merge.
+/// Tracks added in Spotify are pulled in as track sources and tracks removed in Spotify drop
+/// their track source. Tracks removed in Spotify that are still provided by another source are
+/// reported as conflicts and left out of the playlist until they are resolved.
+pub async fn sync_list(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    access_token: &str,
+    mut list: List,
+) -> Result<SyncState, Error> {
+    let ListMode::User(Some(external_id)) = list.mode.clone() else {
+        return Err(Error::client_error(
+            "Only user lists that have been pushed to Spotify can be synced",
+        ));
+    };
+    let api = SpotifyApi::new(access_token);
+    api.update_playlist(&external_id.id, &list.name).await?;
+    let snapshot_id = api.playlist_snapshot_id(&external_id.id).await?;
+    let previous = list.sync.take();
+    let remote = match &previous {
+        Some(sync) if sync.snapshot_id == snapshot_id => sync.items.clone(),
+        _ => api
+            .playlist_tracks(&external_id.id)
+            .await?
+            .into_iter()
+            .map(|track| format!("{TRACK_PREFIX}{}", track.id))
+            .collect(),
+    };
+    let (base, previous_conflicts) = match previous {
+        Some(sync) => (sync.items, sync.conflicts),
+        // Without a base, tracks on either side are treated as additions so nothing is lost
+        None => {
+            let local: HashSet<_> = list.items.iter().map(|i| i.id.as_str()).collect();
+            let base = remote
+                .iter()
+                .filter(|id| local.contains(id.as_str()))
+                .cloned()
+                .collect();
+            (base, Vec::new())
+        }
+    };
+    let (added, removed) = get_remote_changes(&base, &remote);
+
+    let current: HashSet<_> = list.items.iter().map(|i| i.id.clone()).collect();
+    if apply_remote_changes(&mut list.sources, &added, &removed, &current) {
+        super::update_list_items(client, user_id, list.clone()).await?;
+        list = super::get_list(client, user_id, &list.id).await?;
+    }
+
+    let local = get_local_tracks(client, user_id, &list).await?;
+    let conflicts = get_conflicts(
+        &local,
+        &remote,
+        removed
+            .iter()
+            .map(String::as_str)
+            .chain(previous_conflicts.iter().map(|c| c.id.as_str())),
+    );
+    let items = get_push(&local, &added, &conflicts);
+    let snapshot_id = if items != remote {
+        api.replace_playlist_items(&external_id.id, &items).await?
+    } else {
+        snapshot_id
+    };
+    let sync = SyncState {
+        snapshot_id,
+        items,
+        conflicts: list
+            .items
+            .iter()
+            .filter(|i| conflicts.contains(&i.id))
+            .map(|i| SyncConflict {
+                id: i.id.clone(),
+                name: i.name.clone(),
+            })
+            .collect(),
+    };
+    list.sync = Some(sync.clone());
+    super::update_list(client, user_id, list).await?;
+    Ok(sync)
+}
+
+/// Keep a track that was removed in Spotify so that it is added back on the next sync
+pub async fn resolve_conflict(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    id: &str,
+    item: &str,
+) -> Result<(), Error> {
+    let mut list = super::get_list(client, user_id, id).await?;
+    let Some(sync) = &mut list.sync else {
+        return Err(Error::client_error("List hasn't been synced"));
+    };
+    let len = sync.conflicts.len();
+    sync.conflicts.retain(|c| c.id != item);
+    if sync.conflicts.len() == len {
+        return Err(Error::NotFound);
+    }
+    super::update_list(client, user_id, list).await
+}
+
+/// Spotify tracks in the list after applying the list query, in the list's sort order
+pub async fn get_local_tracks(
+    client: &impl SessionClient,
+    user_id: &UserId,
+    list: &List,
+) -> Result<Vec<String>, Error> {
+    crate::query::get_list_items(client, user_id, list.clone())
+        .await?
+        .items
+        .into_iter()
+        .map(|i| i.map(|i| i.id).ok_or(Error::NotFound))
+        .filter(|id| id.as_ref().map_or(true, |id| id.starts_with(TRACK_PREFIX)))
+        .collect()
+}
+
+/// Ranked items go first and unranked items keep their existing order
+pub fn order_by_rank(list: &List, mut ids: Vec<String>) -> Vec<String> {
+    let ranks: std::collections::HashMap<_, _> = list
+        .items
+        .iter()
+        .filter_map(|i| i.rank.map(|rank| (i.id.as_str(), rank)))
+        .collect();
+    ids.sort_by_key(|id| ranks.get(id.as_str()).copied().unwrap_or(i32::MAX));
+    ids
+}
+
+/// Tracks added and removed in Spotify since the last sync
+fn get_remote_changes(base: &[String], remote: &[String]) -> (Vec<String>, HashSet<String>) {
+    let base_set: HashSet<_> = base.iter().collect();
+    let remote_set: HashSet<_> = remote.iter().collect();
+    let added = remote
+        .iter()
+        .filter(|id| !base_set.contains(id))
+        .cloned()
+        .collect();
+    let removed = base
+        .iter()
+        .filter(|id| !remote_set.contains(id))
+        .cloned()
+        .collect();
+    (added, removed)
+}
+
+/// Add track sources for tracks added in Spotify and drop track sources for removed tracks
+fn apply_remote_changes(
+    sources: &mut Vec<Source>,
+    added: &[String],
+    removed: &HashSet<String>,
+    current: &HashSet<String>,
+) -> bool {
+    let len = sources.len();
+    sources.retain(|source| match &source.source_type {
+        SourceType::Spotify(Spotify::Track(id)) => {
+            !removed.contains(&format!("{TRACK_PREFIX}{}", id.id))
+        }
+        _ => true,
+    });
+    let mut changed = sources.len() != len;
+    for uri in added {
+        if current.contains(uri) {
+            continue;
+        }
+        let Some(id) = uri.strip_prefix(TRACK_PREFIX) else {
+            continue;
+        };
+        sources.push(Source {
+            source_type: SourceType::Spotify(Spotify::Track(Id {
+                id: id.to_owned(),
+                raw_id: format!("https://open.spotify.com/track/{id}"),
+            })),
+            name: String::new(),
+            unmatched: Vec::new(),
+        });
+        changed = true;
+    }
+    changed
+}
+
+/// Tracks that are still in the list but were removed in Spotify
+fn get_conflicts<'a>(
+    local: &[String],
+    remote: &[String],
+    candidates: impl Iterator<Item = &'a str>,
+) -> HashSet<String> {
+    let local: HashSet<_> = local.iter().map(String::as_str).collect();
+    let remote: HashSet<_> = remote.iter().map(String::as_str).collect();
+    candidates
+        .filter(|id| local.contains(id) && !remote.contains(id))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Local tracks minus conflicts followed by any Spotify additions that weren't pulled in
+fn get_push(local: &[String], added: &[String], conflicts: &HashSet<String>) -> Vec<String> {
+    let mut items: Vec<_> = local
+        .iter()
+        .filter(|id| !conflicts.contains(*id))
+        .cloned()
+        .collect();
+    let seen: HashSet<_> = items.iter().cloned().collect();
+    items.extend(added.iter().filter(|id| !seen.contains(*id)).cloned());
+    items
+}
+
+#[cfg(test)]
+mod test {
+    use mybops::{Id, ItemMetadata, List, ListMode, Source, SourceType, Spotify, UserId};
+    use std::collections::HashSet;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| format!("spotify:track:{id}")).collect()
+    }
+
+    fn track(id: &str) -> Source {
+        Source {
+            source_type: SourceType::Spotify(Spotify::Track(Id {
+                id: id.to_owned(),
+                raw_id: format!("https://open.spotify.com/track/{id}"),
+            })),
+            name: String::new(),
+            unmatched: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_remote_changes() {
+        let (added, removed) = super::get_remote_changes(&ids(&["a", "b", "c"]), &ids(&["b", "d"]));
+        assert_eq!(added, ids(&["d"]));
+        assert_eq!(removed, ids(&["a", "c"]).into_iter().collect());
+    }
+
+    #[test]
+    fn test_apply_remote_changes() {
+        let mut sources = vec![track("a"), track("b")];
+        let removed = ids(&["a"]).into_iter().collect();
+        let current = ids(&["a", "b", "c"]).into_iter().collect();
+        assert!(super::apply_remote_changes(
+            &mut sources,
+            &ids(&["c", "d"]),
+            &removed,
+            &current,
+        ));
+        assert_eq!(sources, vec![track("b"), track("d")]);
+        assert!(!super::apply_remote_changes(
+            &mut sources,
+            &[],
+            &HashSet::new(),
+            &current,
+        ));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let local = ids(&["a", "b", "c"]);
+        let remote = ids(&["b", "e"]);
+        let candidates = ids(&["a", "b", "d"]);
+        let conflicts =
+            super::get_conflicts(&local, &remote, candidates.iter().map(String::as_str));
+        assert_eq!(conflicts, ids(&["a"]).into_iter().collect());
+        assert_eq!(
+            super::get_push(&local, &ids(&["e", "b"]), &conflicts),
+            ids(&["b", "c", "e"])
+        );
+    }
+
+    #[test]
+    fn test_order_by_rank() {
+        let item = |id: &str, rank| ItemMetadata {
+            id: id.to_owned(),
+            name: String::new(),
+            iframe: None,
+            score: 0,
+            wins: 0,
+            losses: 0,
+            rank,
+            tier: None,
+        };
+        let list = List::new(
+            String::new(),
+            &UserId(String::new()),
+            ListMode::User(None),
+            String::new(),
+            Vec::new(),
+            None,
+            vec![
+                item("a", None),
+                item("b", Some(2)),
+                item("c", None),
+                item("d", Some(1)),
+            ],
+        );
+        assert_eq!(
+            super::order_by_rank(&list, vec!["a".into(), "b".into(), "c".into(), "d".into()]),
+            vec!["d", "b", "a", "c"]
+        );
+    }
+}
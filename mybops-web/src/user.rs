@@ -3,21 +3,26 @@ use async_trait::async_trait;
 use axum_login::{
     AuthUser, AuthnBackend,
     tower_sessions::{
-        SessionStore,
+        ExpiredDeletion, SessionStore,
         session::{Id, Record},
         session_store,
     },
 };
 #[cfg(feature = "azure")]
 use azure_data_cosmos::CosmosEntity;
-use base64::prelude::{BASE64_STANDARD, Engine};
-use mybops::Error;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD, Engine};
+use mybops::{Credentials, Error, PasswordChange};
+use oidc::OidcUser;
 use rand::Rng;
 use reqwest::Client;
 use rusqlite::{Connection, OptionalExtension, Params, Row};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
 use uuid::Uuid;
 
+pub mod oidc;
+
 #[async_trait]
 pub trait Auth {
     fn current_user(&self) -> &Option<User>;
@@ -32,6 +37,8 @@ pub struct User {
     pub secret: String,
     pub spotify_credentials: Option<SpotifyCredentials>,
     pub google_email: Option<String>,
+    pub password: Option<String>,
+    pub oidc_subject: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -41,6 +48,8 @@ pub struct RawUser {
     pub secret: String,
     pub spotify_credentials: Option<String>,
     pub google_email: Option<String>,
+    pub password: Option<String>,
+    pub oidc_subject: Option<String>,
 }
 
 impl From<User> for RawUser {
@@ -53,6 +62,8 @@ impl From<User> for RawUser {
                 .spotify_credentials
                 .map(|s| serde_json::to_string(&s).expect("spotify credentials should serialize")),
             google_email: value.google_email,
+            password: value.password,
+            oidc_subject: value.oidc_subject,
         }
     }
 }
@@ -69,6 +80,8 @@ impl TryFrom<RawUser> for User {
                 .map(|s| serde_json::from_str(&s))
                 .transpose()?,
             google_email: value.google_email,
+            password: value.password,
+            oidc_subject: value.oidc_subject,
         })
     }
 }
@@ -92,6 +105,11 @@ impl CosmosEntity for RawUser {
     }
 }
 
+/// User whose lists are shown to visitors that aren't logged in
+pub const DEMO_USER: &str = "demo";
+
+const INSERT_USER: &str = "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email, password, oidc_subject) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email, :password, :oidc_subject)";
+
 pub async fn spotify_login(
     conn: impl SqlConnection,
     spotify: impl AuthClient<Credentials = SpotifyCredentials>,
@@ -134,11 +152,10 @@ pub async fn spotify_login(
             secret: generate_secret(),
             google_email: None,
             spotify_credentials: Some(spotify_credentials),
+            password: None,
+            oidc_subject: None,
         };
-        conn.execute(
-                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email)",
-                Param::Named(RawUser::from(user.clone())),
-            )?;
+        conn.execute(INSERT_USER, Param::Named(RawUser::from(user.clone())))?;
         user
     };
     auth.login(&user).await.unwrap();
@@ -188,17 +205,319 @@ pub async fn google_login(
             secret: generate_secret(),
             google_email: Some(google_user.email),
             spotify_credentials: None,
+            password: None,
+            oidc_subject: None,
         };
-        conn.execute(
-                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email)",
-                Param::Named(RawUser::from(user.clone()))
-            )?;
+        conn.execute(INSERT_USER, Param::Named(RawUser::from(user.clone())))?;
         user
     };
     auth.login(&user).await.unwrap();
     Ok(())
 }
 
+pub async fn oidc_login(
+    conn: impl SqlConnection,
+    auth_client: impl AuthClient<Credentials = OidcUser>,
+    auth: &mut impl Auth,
+    code: &str,
+    origin: &str,
+) -> Result<(), Error> {
+    let oidc_user = auth_client.get_credentials(code, origin).await?;
+
+    // Add OpenID Connect identity to user if a session already exists
+    if let Some(user) = &auth.current_user() {
+        conn.execute(
+            "UPDATE user SET oidc_subject = ?1 WHERE id = ?2",
+            Param::Positional::<()>(&[&oidc_user.subject, &user.id]),
+        )?;
+        return Ok(());
+    }
+
+    let user = if let Some(user) = conn
+        .query_row(
+            "SELECT * FROM user WHERE oidc_subject = ?1",
+            [&oidc_user.subject],
+            |row| Ok(serde_rusqlite::from_row::<RawUser>(row)),
+        )
+        .optional()?
+        .transpose()?
+    {
+        User::try_from(user)?
+    } else {
+        let user_id = oidc_user
+            .preferred_username
+            .clone()
+            .or_else(|| {
+                oidc_user
+                    .email
+                    .as_ref()
+                    .and_then(|email| email.split_once('@'))
+                    .map(|(user_id, _)| user_id.to_owned())
+            })
+            .ok_or(Error::client_error(
+                "Identity provider didn't return a username or email",
+            ))?;
+        let user_id = unique_user_id(&conn, oidc_user_id(&user_id))?;
+        let user = User {
+            id: Uuid::new_v4().to_hyphenated().to_string(),
+            user_id,
+            secret: generate_secret(),
+            google_email: None,
+            spotify_credentials: None,
+            password: None,
+            oidc_subject: Some(oidc_user.subject),
+        };
+        conn.execute(INSERT_USER, Param::Named(RawUser::from(user.clone())))?;
+        user
+    };
+    auth.login(&user).await?;
+    Ok(())
+}
+
+/// Usernames from identity providers are limited to the characters that signups allow, leaving room
+/// for a number to be appended, and are replaced with a generated name if they're still invalid
+fn oidc_user_id(username: &str) -> String {
+    let user_id: String = username
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || ['-', '_', '.'].contains(c))
+        .take(28)
+        .collect();
+    if validate_username(&user_id).is_ok() {
+        user_id
+    } else {
+        format!("user-{}", &Uuid::new_v4().to_simple().to_string()[..8])
+    }
+}
+
+/// Usernames from identity providers get a number appended if another account already uses them
+fn unique_user_id(conn: &impl SqlConnection, user_id: String) -> Result<String, Error> {
+    let mut candidate = user_id.clone();
+    for i in 2.. {
+        if conn
+            .query_row(
+                "SELECT * FROM user WHERE user_id = ?1",
+                [&candidate],
+                |row| Ok(serde_rusqlite::from_row::<RawUser>(row)),
+            )
+            .optional()?
+            .is_none()
+        {
+            break;
+        }
+        candidate = format!("{user_id}{i}");
+    }
+    Ok(candidate)
+}
+
+pub async fn signup(
+    conn: impl SqlConnection,
+    auth: &mut impl Auth,
+    credentials: Credentials,
+) -> Result<(), Error> {
+    if auth.current_user().is_some() {
+        return Err(Error::client_error(
+            "Set a password from settings to add a login to an existing account",
+        ));
+    }
+    validate_username(&credentials.username)?;
+    validate_password(&credentials.password)?;
+    if conn
+        .query_row(
+            "SELECT * FROM user WHERE user_id = ?1",
+            [&credentials.username],
+            |row| Ok(serde_rusqlite::from_row::<RawUser>(row)),
+        )
+        .optional()?
+        .is_some()
+    {
+        return Err(Error::client_error("Username is already taken"));
+    }
+
+    let user = User {
+        id: Uuid::new_v4().to_hyphenated().to_string(),
+        user_id: credentials.username,
+        secret: generate_secret(),
+        spotify_credentials: None,
+        google_email: None,
+        password: Some(password_auth::generate_hash(credentials.password)),
+        oidc_subject: None,
+    };
+    conn.execute(INSERT_USER, Param::Named(RawUser::from(user.clone())))?;
+    auth.login(&user).await?;
+    Ok(())
+}
+
+pub async fn password_login(
+    conn: impl SqlConnection,
+    auth: &mut impl Auth,
+    credentials: Credentials,
+) -> Result<(), Error> {
+    let Some(user) = verify_credentials(&conn, credentials)? else {
+        return Err(Error::client_error("Invalid username or password"));
+    };
+    auth.login(&user).await?;
+    Ok(())
+}
+
+/// Local credentials are stored on the same user row as Spotify and Google identities
+pub async fn change_password(
+    conn: impl SqlConnection,
+    auth: &mut impl Auth,
+    change: PasswordChange,
+) -> Result<(), Error> {
+    let Some(mut user) = auth.current_user().clone() else {
+        return Err(Error::client_error("Log in to change your password"));
+    };
+    if let Some(hash) = &user.password {
+        let Some(current_password) = change.current_password else {
+            return Err(Error::client_error("Current password is required"));
+        };
+        if password_auth::verify_password(current_password, hash).is_err() {
+            return Err(Error::client_error("Current password is incorrect"));
+        }
+    }
+    validate_password(&change.new_password)?;
+
+    // Rotate the secret so that other sessions are logged out
+    user.password = Some(password_auth::generate_hash(change.new_password));
+    user.secret = generate_secret();
+    conn.execute(
+        "UPDATE user SET password = ?1, secret = ?2 WHERE id = ?3",
+        Param::Positional::<()>(&[user.password.as_ref().unwrap(), &user.secret, &user.id]),
+    )?;
+    auth.login(&user).await?;
+    Ok(())
+}
+
+/// Removes a linked identity unless it's the only way left to log in
+pub fn unlink_login(conn: impl SqlConnection, user: &User, provider: &str) -> Result<(), Error> {
+    let (column, linked) = match provider {
+        "spotify" => ("spotify_credentials", user.spotify_credentials.is_some()),
+        "google" => ("google_email", user.google_email.is_some()),
+        "oidc" => ("oidc_subject", user.oidc_subject.is_some()),
+        _ => {
+            return Err(Error::client_error(format!(
+                "Unknown login provider: {provider}"
+            )));
+        }
+    };
+    if !linked {
+        return Err(Error::client_error(format!("{provider} isn't linked")));
+    }
+    if count_logins(user) == 1 {
+        return Err(Error::client_error(
+            "Add another way to log in before removing the last one",
+        ));
+    }
+    conn.execute(
+        &format!("UPDATE user SET {column} = NULL WHERE id = ?1"),
+        Param::Positional::<()>(&[&user.id]),
+    )?;
+    Ok(())
+}
+
+fn count_logins(user: &User) -> usize {
+    [
+        user.password.is_some(),
+        user.spotify_credentials.is_some(),
+        user.google_email.is_some(),
+        user.oidc_subject.is_some(),
+    ]
+    .into_iter()
+    .filter(|linked| *linked)
+    .count()
+}
+
+/// Deletes lists, items and match results before removing the user and their sessions
+pub async fn delete_user(
+    users: impl SqlConnection,
+    data: impl SqlConnection,
+    auth: &mut impl Auth,
+    confirmation: &str,
+) -> Result<(), Error> {
+    let Some(user) = auth.current_user().clone() else {
+        return Err(Error::client_error("Log in to delete your account"));
+    };
+    if confirmation != user.user_id {
+        return Err(Error::client_error(
+            "Enter your username to confirm account deletion",
+        ));
+    }
+    for stmt in [
+        "DELETE FROM _item WHERE user_id = ?1",
+        "DELETE FROM _list WHERE user_id = ?1",
+        "DELETE FROM _play WHERE user_id = ?1",
+        "DELETE FROM _item_history WHERE user_id = ?1",
+        "DELETE FROM _cache WHERE user_id = ?1",
+    ] {
+        data.execute(stmt, Param::Positional::<()>(&[&user.user_id]))?;
+    }
+    users.execute(
+        "DELETE FROM session WHERE user_id = ?1",
+        Param::Positional::<()>(&[&user.id]),
+    )?;
+    users.execute(
+        "DELETE FROM user WHERE id = ?1",
+        Param::Positional::<()>(&[&user.id]),
+    )?;
+    auth.logout().await;
+    Ok(())
+}
+
+fn verify_credentials(
+    conn: &impl SqlConnection,
+    credentials: Credentials,
+) -> Result<Option<User>, Error> {
+    let Some(user) = conn
+        .query_row(
+            "SELECT * FROM user WHERE user_id = ?1",
+            [&credentials.username],
+            |row| Ok(serde_rusqlite::from_row::<RawUser>(row)),
+        )
+        .optional()?
+        .transpose()?
+    else {
+        return Ok(None);
+    };
+    let user = User::try_from(user)?;
+    // Users that only log in with Spotify or Google don't have a password
+    let Some(hash) = &user.password else {
+        return Ok(None);
+    };
+    Ok(password_auth::verify_password(credentials.password, hash)
+        .ok()
+        .map(|_| user))
+}
+
+fn validate_username(username: &str) -> Result<(), Error> {
+    if !(3..=32).contains(&username.len()) {
+        return Err(Error::client_error(
+            "Username must be between 3 and 32 characters",
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || ['-', '_', '.'].contains(&c))
+    {
+        return Err(Error::client_error(
+            "Username can only contain letters, numbers, '-', '_' and '.'",
+        ));
+    }
+    if username.eq_ignore_ascii_case(DEMO_USER) {
+        return Err(Error::client_error("Username is reserved"));
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<(), Error> {
+    if password.chars().count() < 8 {
+        return Err(Error::client_error(
+            "Password must be at least 8 characters",
+        ));
+    }
+    Ok(())
+}
+
 pub trait SqlConnection {
     fn execute<T: Serialize>(&self, sql: &str, params: Param<'_, T>) -> Result<usize, Error>;
     fn query_row<T, P, F>(
@@ -213,10 +532,32 @@ pub trait SqlConnection {
         F: FnOnce(&Row<'_>) -> rusqlite::Result<Result<T, serde_rusqlite::Error>>;
 }
 
+impl<C: SqlConnection> SqlConnection for &C {
+    fn execute<T: Serialize>(&self, sql: &str, params: Param<'_, T>) -> Result<usize, Error> {
+        (*self).execute(sql, params)
+    }
+
+    fn query_row<T, P, F>(
+        &self,
+        sql: &str,
+        params: P,
+        f: F,
+    ) -> rusqlite::Result<Result<T, serde_rusqlite::Error>>
+    where
+        T: DeserializeOwned + Send + Sync,
+        P: Params + std::fmt::Debug,
+        F: FnOnce(&Row<'_>) -> rusqlite::Result<Result<T, serde_rusqlite::Error>>,
+    {
+        (*self).query_row(sql, params, f)
+    }
+}
+
 impl SqlConnection for Connection {
     fn execute<T: Serialize>(&self, sql: &str, params: Param<'_, T>) -> Result<usize, Error> {
         match params {
-            Param::Positional(params) => self.execute(sql, params).map_err(Error::from),
+            Param::Positional(params) => self
+                .execute(sql, rusqlite::params_from_iter(params))
+                .map_err(Error::from),
             Param::Named(params) => self
                 .execute(
                     sql,
@@ -244,12 +585,29 @@ impl SqlConnection for Connection {
 }
 
 pub enum Param<'a, T> {
-    Positional(&'a [&'a str; 2]),
+    Positional(&'a [&'a str]),
     Named(T),
 }
 
+pub const GOOGLE_CLIENT_ID: &str =
+    "1038220726403-n55jha2cvprd8kdb4akdfvo0uiok4p5u.apps.googleusercontent.com";
+
 pub struct GoogleClient;
 
+impl GoogleClient {
+    pub fn client_id() -> String {
+        std::env::var("GOOGLE_CLIENT_ID").unwrap_or_else(|_| GOOGLE_CLIENT_ID.to_owned())
+    }
+
+    pub fn authorize_url(redirect_uri: &str) -> String {
+        format!(
+            "https://accounts.google.com/o/oauth2/v2/auth?client_id={}&redirect_uri={}&response_type=code&scope=email",
+            Self::client_id(),
+            urlencoding::encode(redirect_uri)
+        )
+    }
+}
+
 #[async_trait]
 impl AuthClient for GoogleClient {
     type Credentials = GoogleUser;
@@ -260,10 +618,7 @@ impl AuthClient for GoogleClient {
             .post("https://oauth2.googleapis.com/token")
             .form(&[
                 ("code", code),
-                (
-                    "client_id",
-                    "1038220726403-n55jha2cvprd8kdb4akdfvo0uiok4p5u.apps.googleusercontent.com",
-                ),
+                ("client_id", &Self::client_id()),
                 (
                     "client_secret",
                     &std::env::var("GOOGLE_SECRET").expect("GOOGLE_SECRET is missing"),
@@ -310,14 +665,16 @@ impl SessionStore for SqlStore {
             .map_err(|e| session_store::Error::Backend(e.to_string()))?;
         let mut stmt = conn
             .prepare(
-                "INSERT INTO session (id, data) VALUES (?1, ?2) ON CONFLICT(id) DO UPDATE SET data=excluded.data",
+                "INSERT INTO session (id, data, expiry_date, user_id) VALUES (?1, ?2, ?3, ?4) ON CONFLICT(id) DO UPDATE SET data=excluded.data, expiry_date=excluded.expiry_date, user_id=excluded.user_id",
             )
             .map_err(|e| session_store::Error::Backend(e.to_string()))?;
-        match stmt.execute([
+        match stmt.execute((
             session.id.to_string(),
             serde_json::to_string(&session)
                 .map_err(|e| session_store::Error::Encode(e.to_string()))?,
-        ]) {
+            session.expiry_date.unix_timestamp(),
+            get_session_user(session),
+        )) {
             Ok(_) => Ok(()),
             Err(e) => Err(session_store::Error::Backend(e.to_string())),
         }
@@ -325,19 +682,25 @@ impl SessionStore for SqlStore {
 
     async fn load(&self, cookie_value: &Id) -> session_store::Result<Option<Record>> {
         let id = cookie_value.to_string();
-        let conn = Connection::open(self.path)
-            .map_err(|e| session_store::Error::Backend(e.to_string()))?;
-        let mut stmt = conn
-            .prepare("SELECT data FROM session WHERE id = ?1")
-            .map_err(|e| session_store::Error::Backend(e.to_string()))?;
-        match stmt.query_row([&id], |row| row.get::<_, String>(0)) {
-            Ok(s) => match serde_json::from_str(&s) {
-                Ok(record) => Ok(Some(record)),
-                Err(e) => Err(session_store::Error::Decode(e.to_string())),
-            },
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(session_store::Error::Backend(e.to_string())),
+        let data = {
+            let conn = Connection::open(self.path)
+                .map_err(|e| session_store::Error::Backend(e.to_string()))?;
+            let mut stmt = conn
+                .prepare("SELECT data FROM session WHERE id = ?1")
+                .map_err(|e| session_store::Error::Backend(e.to_string()))?;
+            match stmt.query_row([&id], |row| row.get::<_, String>(0)) {
+                Ok(data) => data,
+                Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+                Err(e) => return Err(session_store::Error::Backend(e.to_string())),
+            }
+        };
+        let record: Record =
+            serde_json::from_str(&data).map_err(|e| session_store::Error::Decode(e.to_string()))?;
+        if record.expiry_date <= OffsetDateTime::now_utc() {
+            self.delete(cookie_value).await?;
+            return Ok(None);
         }
+        Ok(Some(record))
     }
 
     async fn delete(&self, session: &Id) -> session_store::Result<()> {
@@ -354,25 +717,107 @@ impl SessionStore for SqlStore {
     }
 }
 
+#[async_trait]
+impl ExpiredDeletion for SqlStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let conn = Connection::open(self.path)
+            .map_err(|e| session_store::Error::Backend(e.to_string()))?;
+        // Sessions saved before expiry_date was tracked are removed when they're loaded
+        match conn.execute(
+            "DELETE FROM session WHERE expiry_date <= ?1",
+            [OffsetDateTime::now_utc().unix_timestamp()],
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(session_store::Error::Backend(e.to_string())),
+        }
+    }
+}
+
+/// Session key for the device that a session was created on
+pub const DEVICE_KEY: &str = "device";
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub created: i64,
+}
+
+impl SqlStore {
+    /// Active sessions of a user, including the current one
+    pub fn get_sessions(&self, user: &User) -> Result<Vec<Record>, Error> {
+        let conn = Connection::open(self.path)?;
+        let mut stmt =
+            conn.prepare("SELECT data FROM session WHERE user_id = ?1 AND expiry_date > ?2")?;
+        let records = stmt
+            .query_map(
+                (&user.id, OffsetDateTime::now_utc().unix_timestamp()),
+                |row| row.get::<_, String>(0),
+            )?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect::<Result<Vec<Record>, Error>>()?;
+        Ok(records
+            .into_iter()
+            // Sessions from before a secret rotation are already logged out
+            .filter(|record| {
+                get_session_auth_hash(record).as_deref() == Some(user.secret.as_bytes())
+            })
+            .collect())
+    }
+
+    /// Revokes the sessions of a user that match `filter`
+    pub fn delete_sessions(
+        &self,
+        user: &User,
+        filter: impl Fn(&Record) -> bool,
+    ) -> Result<usize, Error> {
+        let conn = Connection::open(self.path)?;
+        let mut count = 0;
+        for record in self.get_sessions(user)?.into_iter().filter(filter) {
+            count += conn.execute(
+                "DELETE FROM session WHERE id = ?1 AND user_id = ?2",
+                [&record.id.to_string(), &user.id],
+            )?;
+        }
+        Ok(count)
+    }
+}
+
+/// Opaque identifier for a session that can be shared without leaking the cookie value
+pub fn get_public_session_id(id: &Id) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(&Sha256::digest(id.to_string().as_bytes())[..16])
+}
+
+fn get_session_user(record: &Record) -> Option<String> {
+    record
+        .data
+        .get("axum-login.data")?
+        .get("user_id")?
+        .as_str()
+        .map(String::from)
+}
+
+fn get_session_auth_hash(record: &Record) -> Option<Vec<u8>> {
+    serde_json::from_value(
+        record
+            .data
+            .get("axum-login.data")?
+            .get("auth_hash")?
+            .clone(),
+    )
+    .ok()
+}
+
 #[async_trait]
 impl AuthnBackend for SqlStore {
     type User = User;
-    type Credentials = User;
+    type Credentials = Credentials;
     type Error = Error;
 
     async fn authenticate(
         &self,
         creds: Self::Credentials,
     ) -> Result<Option<Self::User>, Self::Error> {
-        let user: Option<Self::User> = self.get_user(&creds.user_id).await?;
-
-        Ok(user.filter(|user| {
-            password_auth::verify_password(creds.secret, &user.secret)
-                .ok()
-                .is_some() // We're using password-based authentication--this
-            // works by comparing our form input with an argon2
-            // password hash.
-        }))
+        verify_credentials(&Connection::open(self.path)?, creds)
     }
 
     async fn get_user(&self, user_id: &String) -> Result<Option<Self::User>, Error> {
@@ -394,10 +839,14 @@ pub fn generate_secret() -> String {
 
 #[cfg(test)]
 mod test {
-    use super::{Auth, GoogleUser, Param, RawUser, SqlConnection, User};
+    use super::{Auth, GoogleUser, Param, RawUser, SqlConnection, SqlStore, User, oidc::OidcUser};
     use crate::query::test::Mock;
     use async_trait::async_trait;
-    use mybops::Error;
+    use axum_login::tower_sessions::{
+        ExpiredDeletion, SessionStore,
+        session::{Id, Record},
+    };
+    use mybops::{Credentials, Error, PasswordChange};
     use rusqlite::{Params, Row};
     use serde::{Serialize, de::DeserializeOwned};
     use spotify::{AuthClient, SpotifyCredentials};
@@ -439,6 +888,8 @@ mod test {
                 secret: String::new(),
                 spotify_credentials: None,
                 google_email: None,
+                password: None,
+                oidc_subject: None,
             }
         }
     }
@@ -526,9 +977,9 @@ mod test {
         assert_eq!(
             write_mock,
             [(
-                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email)".to_owned(),
+                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email, password, oidc_subject) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email, :password, :oidc_subject)".to_owned(),
                 format!(
-                    r#"{{"id":"{id}","user_id":"user","secret":"{secret}","spotify_credentials":"{{\"user_id\":\"user\",\"url\":\"\",\"access_token\":\"test\",\"refresh_token\":\"\"}}","google_email":null}}"#
+                    r#"{{"id":"{id}","user_id":"user","secret":"{secret}","spotify_credentials":"{{\"user_id\":\"user\",\"url\":\"\",\"access_token\":\"test\",\"refresh_token\":\"\"}}","google_email":null,"password":null,"oidc_subject":null}}"#
                 ),
             )]
         );
@@ -596,6 +1047,8 @@ mod test {
                     refresh_token: String::new(),
                 }),
                 google_email: None,
+                password: None,
+                oidc_subject: None,
             }),
         );
     }
@@ -676,9 +1129,9 @@ mod test {
         assert_eq!(
             write_mock,
             [(
-                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email)".to_owned(),
+                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email, password, oidc_subject) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email, :password, :oidc_subject)".to_owned(),
                 format!(
-                    r#"{{"id":"{id}","user_id":"user","secret":"{secret}","spotify_credentials":null,"google_email":"user@gmail.com"}}"#
+                    r#"{{"id":"{id}","user_id":"user","secret":"{secret}","spotify_credentials":null,"google_email":"user@gmail.com","password":null,"oidc_subject":null}}"#
                 ),
             )]
         );
@@ -740,6 +1193,8 @@ mod test {
                 secret: String::new(),
                 spotify_credentials: None,
                 google_email: None,
+                password: None,
+                oidc_subject: None,
             }),
             expected_user: None,
         };
@@ -764,4 +1219,538 @@ mod test {
         );
         assert!(auth.expected_user.is_none());
     }
+
+    #[tokio::test]
+    async fn test_signup_new_user() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1]),
+            query_row_mock: Mock::new(vec![Err(rusqlite::Error::QueryReturnedNoRows)]),
+        }));
+        let mut auth = TestAuth::new(None);
+        super::signup(
+            Arc::clone(&conn),
+            &mut auth,
+            Credentials {
+                username: "user".to_owned(),
+                password: "password".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+        let conn = Mutex::into_inner(Arc::into_inner(conn).unwrap()).unwrap();
+        assert_eq!(
+            *conn.query_row_mock.call_args.lock().unwrap(),
+            [(
+                "SELECT * FROM user WHERE user_id = ?1".to_owned(),
+                "[\"user\"]".to_owned()
+            )]
+        );
+        let write_mock =
+            Mutex::into_inner(Arc::into_inner(conn.execute_mock.call_args).unwrap()).unwrap();
+        let RawUser {
+            id,
+            secret,
+            password,
+            ..
+        } = serde_json::de::from_str(&write_mock[0].1).unwrap();
+        let password = password.unwrap();
+        password_auth::verify_password("password", &password).unwrap();
+        assert_eq!(
+            write_mock,
+            [(
+                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email, password, oidc_subject) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email, :password, :oidc_subject)".to_owned(),
+                format!(
+                    r#"{{"id":"{id}","user_id":"user","secret":"{secret}","spotify_credentials":null,"google_email":null,"password":"{password}","oidc_subject":null}}"#
+                ),
+            )]
+        );
+        assert_eq!(
+            auth.expected_user,
+            Some(User {
+                id,
+                user_id: "user".to_owned(),
+                secret,
+                spotify_credentials: None,
+                google_email: None,
+                password: Some(password),
+                oidc_subject: None,
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signup_existing_username() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::empty(),
+            query_row_mock: Mock::new(vec![Ok(
+                r#"{"id":"","user_id":"user","secret":"","spotify_credentials":null,"google_email":null}"#,
+            )]),
+        }));
+        let mut auth = TestAuth::new(None);
+        let result = super::signup(
+            Arc::clone(&conn),
+            &mut auth,
+            Credentials {
+                username: "user".to_owned(),
+                password: "password".to_owned(),
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(Error::ClientError(_))));
+        assert!(auth.expected_user.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_signup_reserved_username() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::empty(),
+            query_row_mock: Mock::empty(),
+        }));
+        let mut auth = TestAuth::new(None);
+        let result = super::signup(
+            Arc::clone(&conn),
+            &mut auth,
+            Credentials {
+                username: "Demo".to_owned(),
+                password: "password".to_owned(),
+            },
+        )
+        .await;
+        let Err(Error::ClientError(error)) = result else {
+            panic!("demo shouldn't be available");
+        };
+        assert_eq!(error, "Username is reserved");
+        assert!(auth.expected_user.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_password_login() {
+        let hash = password_auth::generate_hash("password");
+        let row = format!(
+            r#"{{"id":"","user_id":"user","secret":"","spotify_credentials":null,"google_email":null,"password":"{hash}"}}"#
+        );
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::empty(),
+            query_row_mock: Mock::new(vec![Ok(row.clone().leak()), Ok(row.leak())]),
+        }));
+        let mut auth = TestAuth::new(None);
+        let result = super::password_login(
+            Arc::clone(&conn),
+            &mut auth,
+            Credentials {
+                username: "user".to_owned(),
+                password: "wrong password".to_owned(),
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(Error::ClientError(_))));
+        assert!(auth.expected_user.is_none());
+
+        super::password_login(
+            Arc::clone(&conn),
+            &mut auth,
+            Credentials {
+                username: "user".to_owned(),
+                password: "password".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            auth.expected_user,
+            Some(User {
+                user_id: "user".to_owned(),
+                password: Some(hash),
+                ..User::default()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1]),
+            query_row_mock: Mock::empty(),
+        }));
+        let mut auth = TestAuth::new(Some(User {
+            password: Some(password_auth::generate_hash("password")),
+            ..User::default()
+        }));
+        let result = super::change_password(
+            Arc::clone(&conn),
+            &mut auth,
+            PasswordChange {
+                current_password: Some("wrong password".to_owned()),
+                new_password: "new password".to_owned(),
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(Error::ClientError(_))));
+
+        super::change_password(
+            Arc::clone(&conn),
+            &mut auth,
+            PasswordChange {
+                current_password: Some("password".to_owned()),
+                new_password: "new password".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+        let user = auth.expected_user.unwrap();
+        password_auth::verify_password("new password", user.password.as_ref().unwrap()).unwrap();
+        let conn = Mutex::into_inner(Arc::into_inner(conn).unwrap()).unwrap();
+        assert_eq!(
+            *conn.execute_mock.call_args.lock().unwrap(),
+            [(
+                "UPDATE user SET password = ?1, secret = ?2 WHERE id = ?3".to_owned(),
+                format!(r#"["{}", "{}", ""]"#, user.password.unwrap(), user.secret)
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_password_for_oauth_user() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1]),
+            query_row_mock: Mock::empty(),
+        }));
+        let mut auth = TestAuth::new(Some(User {
+            google_email: Some("user@gmail.com".to_owned()),
+            ..User::default()
+        }));
+        super::change_password(
+            Arc::clone(&conn),
+            &mut auth,
+            PasswordChange {
+                current_password: None,
+                new_password: "password".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+        let user = auth.expected_user.unwrap();
+        assert_eq!(user.google_email, Some("user@gmail.com".to_owned()));
+        password_auth::verify_password("password", &user.password.unwrap()).unwrap();
+    }
+
+    struct TestOidc;
+
+    #[async_trait]
+    impl AuthClient for TestOidc {
+        type Credentials = OidcUser;
+
+        async fn get_credentials(&self, _: &str, _: &str) -> Result<OidcUser, Error> {
+            Ok(OidcUser {
+                subject: "http://localhost:8080|1234".to_owned(),
+                email: Some("user@example.com".to_owned()),
+                preferred_username: Some("user".to_owned()),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_new_user() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1]),
+            query_row_mock: Mock::new(vec![
+                Err(rusqlite::Error::QueryReturnedNoRows),
+                Err(rusqlite::Error::QueryReturnedNoRows),
+            ]),
+        }));
+        let mut auth = TestAuth::new(None);
+        super::oidc_login(
+            Arc::clone(&conn),
+            TestOidc,
+            &mut auth,
+            "test",
+            "http://localhost:3000/api/login/oidc",
+        )
+        .await
+        .unwrap();
+        let conn = Mutex::into_inner(Arc::into_inner(conn).unwrap()).unwrap();
+        assert_eq!(
+            *conn.query_row_mock.call_args.lock().unwrap(),
+            [
+                (
+                    "SELECT * FROM user WHERE oidc_subject = ?1".to_owned(),
+                    "[\"http://localhost:8080|1234\"]".to_owned()
+                ),
+                (
+                    "SELECT * FROM user WHERE user_id = ?1".to_owned(),
+                    "[\"user\"]".to_owned()
+                ),
+            ]
+        );
+        let write_mock =
+            Mutex::into_inner(Arc::into_inner(conn.execute_mock.call_args).unwrap()).unwrap();
+        let RawUser { id, secret, .. } = serde_json::de::from_str(&write_mock[0].1).unwrap();
+        assert_eq!(
+            write_mock,
+            [(
+                "INSERT INTO user (id, user_id, secret, spotify_credentials, google_email, password, oidc_subject) VALUES (:id, :user_id, :secret, :spotify_credentials, :google_email, :password, :oidc_subject)".to_owned(),
+                format!(
+                    r#"{{"id":"{id}","user_id":"user","secret":"{secret}","spotify_credentials":null,"google_email":null,"password":null,"oidc_subject":"http://localhost:8080|1234"}}"#
+                ),
+            )]
+        );
+        assert_eq!(
+            auth.expected_user,
+            Some(User {
+                id,
+                user_id: "user".to_owned(),
+                secret,
+                oidc_subject: Some("http://localhost:8080|1234".to_owned()),
+                ..User::default()
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_taken_username() {
+        let existing = r#"{"id":"1","user_id":"user","secret":"","spotify_credentials":null,"google_email":null}"#;
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1]),
+            query_row_mock: Mock::new(vec![
+                Err(rusqlite::Error::QueryReturnedNoRows),
+                Ok(existing),
+                Ok(existing),
+                Err(rusqlite::Error::QueryReturnedNoRows),
+            ]),
+        }));
+        let mut auth = TestAuth::new(None);
+        super::oidc_login(
+            Arc::clone(&conn),
+            TestOidc,
+            &mut auth,
+            "test",
+            "http://localhost:3000/api/login/oidc",
+        )
+        .await
+        .unwrap();
+        let conn = Mutex::into_inner(Arc::into_inner(conn).unwrap()).unwrap();
+        assert_eq!(
+            conn.query_row_mock.call_args.lock().unwrap()[1..]
+                .iter()
+                .map(|(_, params)| params.as_str())
+                .collect::<Vec<_>>(),
+            [r#"["user"]"#, r#"["user2"]"#, r#"["user3"]"#]
+        );
+        let write_mock =
+            Mutex::into_inner(Arc::into_inner(conn.execute_mock.call_args).unwrap()).unwrap();
+        let RawUser { user_id, .. } = serde_json::de::from_str(&write_mock[0].1).unwrap();
+        assert_eq!(user_id, "user3");
+        assert_eq!(auth.expected_user.unwrap().user_id, "user3");
+    }
+
+    #[test]
+    fn test_oidc_user_id() {
+        assert_eq!(super::oidc_user_id("jane.doe"), "jane.doe");
+        assert_eq!(super::oidc_user_id("x' OR '1'='1"), "xOR11");
+        assert_eq!(super::oidc_user_id(&"a".repeat(40)), "a".repeat(28));
+        for username in ["demo", "DEMO", "ab", "'; --", ""] {
+            let user_id = super::oidc_user_id(username);
+            assert!(user_id.starts_with("user-"), "{username} became {user_id}");
+            assert_eq!(user_id.len(), 13);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_add_oidc_credentials() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1]),
+            query_row_mock: Mock::empty(),
+        }));
+        let mut auth = TestAuth::new(Some(User::default()));
+        super::oidc_login(
+            Arc::clone(&conn),
+            TestOidc,
+            &mut auth,
+            "test",
+            "http://localhost:3000/api/login/oidc",
+        )
+        .await
+        .unwrap();
+        let conn = Mutex::into_inner(Arc::into_inner(conn).unwrap()).unwrap();
+        assert_eq!(
+            *conn.execute_mock.call_args.lock().unwrap(),
+            [(
+                "UPDATE user SET oidc_subject = ?1 WHERE id = ?2".to_owned(),
+                r#"["http://localhost:8080|1234", ""]"#.to_owned()
+            )]
+        );
+        assert!(auth.expected_user.is_none());
+    }
+
+    #[test]
+    fn test_unlink_login() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1]),
+            query_row_mock: Mock::empty(),
+        }));
+        let user = User {
+            id: "1".to_owned(),
+            google_email: Some("user@gmail.com".to_owned()),
+            oidc_subject: Some("http://localhost:8080|1234".to_owned()),
+            ..User::default()
+        };
+        super::unlink_login(Arc::clone(&conn), &user, "google").unwrap();
+        assert!(matches!(
+            super::unlink_login(Arc::clone(&conn), &user, "spotify"),
+            Err(Error::ClientError(_))
+        ));
+        let conn = Mutex::into_inner(Arc::into_inner(conn).unwrap()).unwrap();
+        assert_eq!(
+            *conn.execute_mock.call_args.lock().unwrap(),
+            [(
+                "UPDATE user SET google_email = NULL WHERE id = ?1".to_owned(),
+                r#"["1"]"#.to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unlink_last_login() {
+        let conn = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::empty(),
+            query_row_mock: Mock::empty(),
+        }));
+        let user = User {
+            google_email: Some("user@gmail.com".to_owned()),
+            ..User::default()
+        };
+        assert!(matches!(
+            super::unlink_login(Arc::clone(&conn), &user, "google"),
+            Err(Error::ClientError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let users = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1, 1]),
+            query_row_mock: Mock::empty(),
+        }));
+        let data = Arc::new(Mutex::new(TestConnection {
+            execute_mock: Mock::new(vec![1, 1, 1, 1, 1]),
+            query_row_mock: Mock::empty(),
+        }));
+        let mut auth = TestAuth::new(Some(User {
+            id: "1".to_owned(),
+            user_id: "user".to_owned(),
+            ..User::default()
+        }));
+        assert!(matches!(
+            super::delete_user(Arc::clone(&users), Arc::clone(&data), &mut auth, "other").await,
+            Err(Error::ClientError(_))
+        ));
+        super::delete_user(Arc::clone(&users), Arc::clone(&data), &mut auth, "user")
+            .await
+            .unwrap();
+        let data = Mutex::into_inner(Arc::into_inner(data).unwrap()).unwrap();
+        assert_eq!(
+            *data.execute_mock.call_args.lock().unwrap(),
+            [
+                (
+                    "DELETE FROM _item WHERE user_id = ?1".to_owned(),
+                    r#"["user"]"#.to_owned()
+                ),
+                (
+                    "DELETE FROM _list WHERE user_id = ?1".to_owned(),
+                    r#"["user"]"#.to_owned()
+                ),
+                (
+                    "DELETE FROM _play WHERE user_id = ?1".to_owned(),
+                    r#"["user"]"#.to_owned()
+                ),
+                (
+                    "DELETE FROM _item_history WHERE user_id = ?1".to_owned(),
+                    r#"["user"]"#.to_owned()
+                ),
+                (
+                    "DELETE FROM _cache WHERE user_id = ?1".to_owned(),
+                    r#"["user"]"#.to_owned()
+                ),
+            ]
+        );
+        let users = Mutex::into_inner(Arc::into_inner(users).unwrap()).unwrap();
+        assert_eq!(
+            *users.execute_mock.call_args.lock().unwrap(),
+            [
+                (
+                    "DELETE FROM session WHERE user_id = ?1".to_owned(),
+                    r#"["1"]"#.to_owned()
+                ),
+                (
+                    "DELETE FROM user WHERE id = ?1".to_owned(),
+                    r#"["1"]"#.to_owned()
+                ),
+            ]
+        );
+    }
+
+    fn record(user: &User, expiry_date: time::OffsetDateTime) -> Record {
+        Record {
+            id: Id::default(),
+            data: [(
+                "axum-login.data".to_owned(),
+                serde_json::json!({"user_id": user.id, "auth_hash": user.secret.as_bytes()}),
+            )]
+            .into_iter()
+            .collect(),
+            expiry_date,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_expiry() {
+        let path = std::env::temp_dir().join(format!("mybops-{}", uuid::Uuid::new_v4()));
+        let store = SqlStore {
+            path: path.to_str().unwrap().to_owned().leak(),
+        };
+        rusqlite::Connection::open(store.path)
+            .unwrap()
+            .execute(
+                "CREATE TABLE session (id TEXT PRIMARY KEY, data TEXT, expiry_date INTEGER, user_id TEXT)",
+                (),
+            )
+            .unwrap();
+        let user = User {
+            id: "1".to_owned(),
+            secret: "secret".to_owned(),
+            ..User::default()
+        };
+        let now = time::OffsetDateTime::now_utc();
+        let active = record(&user, now + time::Duration::days(1));
+        let expired = record(&user, now - time::Duration::days(1));
+        let other = record(&User::default(), now + time::Duration::days(1));
+        for record in [&active, &expired, &other] {
+            store.save(record).await.unwrap();
+        }
+
+        assert_eq!(store.load(&active.id).await.unwrap(), Some(active.clone()));
+        assert_eq!(
+            store.get_sessions(&user).unwrap(),
+            std::slice::from_ref(&active)
+        );
+        assert_eq!(store.load(&expired.id).await.unwrap(), None);
+
+        store.save(&expired).await.unwrap();
+        store.delete_expired().await.unwrap();
+        let count: usize = rusqlite::Connection::open(store.path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM session", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+
+        assert_eq!(
+            store
+                .delete_sessions(&user, |record| record.id == active.id)
+                .unwrap(),
+            1
+        );
+        assert_eq!(store.load(&active.id).await.unwrap(), None);
+        assert_eq!(store.load(&other.id).await.unwrap(), Some(other));
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,376 @@
+use ::spotify::AuthClient;
+use async_trait::async_trait;
+use base64::prelude::{BASE64_URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation,
+    jwk::{AlgorithmParameters, JwkSet},
+};
+use mybops::Error;
+use rand::Rng;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Settings for a generic OpenID Connect provider such as Keycloak or Authentik
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OidcConfig {
+    /// Name shown on the login button
+    #[serde(default = "default_name")]
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_name() -> String {
+    String::from("OpenID Connect")
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        String::from("openid"),
+        String::from("email"),
+        String::from("profile"),
+    ]
+}
+
+impl OidcConfig {
+    /// Reads the provider from the JSON file at `OIDC_CONFIG` or from `OIDC_*` variables.
+    /// Returns `None` if no provider is configured.
+    pub fn from_env() -> Result<Option<OidcConfig>, Error> {
+        if let Ok(path) = std::env::var("OIDC_CONFIG") {
+            let config = std::fs::read_to_string(path)?;
+            return Ok(Some(serde_json::from_str(&config)?));
+        }
+        let (Ok(issuer), Ok(client_id)) = (
+            std::env::var("OIDC_ISSUER"),
+            std::env::var("OIDC_CLIENT_ID"),
+        ) else {
+            return Ok(None);
+        };
+        Ok(Some(OidcConfig {
+            name: std::env::var("OIDC_NAME").unwrap_or_else(|_| default_name()),
+            issuer,
+            client_id,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+            scopes: std::env::var("OIDC_SCOPES")
+                .map(|scopes| scopes.split_whitespace().map(String::from).collect())
+                .unwrap_or_else(|_| default_scopes()),
+        }))
+    }
+}
+
+/// Subset of the discovery document that is needed for the authorization code flow
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+pub async fn discover(client: &Client, issuer: &str) -> Result<ProviderMetadata, Error> {
+    let metadata: ProviderMetadata = client
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    // https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderConfigurationValidation
+    if metadata.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+        return Err(Error::internal_error(format!(
+            "Discovery document issuer {} doesn't match {issuer}",
+            metadata.issuer
+        )));
+    }
+    Ok(metadata)
+}
+
+/// Values that need to be kept in the session between the redirect and the callback
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuthorizationRequest {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl AuthorizationRequest {
+    pub fn new() -> AuthorizationRequest {
+        AuthorizationRequest {
+            state: random_token(),
+            nonce: random_token(),
+            code_verifier: random_token(),
+        }
+    }
+
+    pub fn url(
+        &self,
+        config: &OidcConfig,
+        metadata: &ProviderMetadata,
+        redirect_uri: &str,
+    ) -> String {
+        format!(
+            "{}?response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+            metadata.authorization_endpoint,
+            urlencoding::encode(&config.client_id),
+            urlencoding::encode(redirect_uri),
+            urlencoding::encode(&config.scopes.join(" ")),
+            self.state,
+            self.nonce,
+            code_challenge(&self.code_verifier),
+        )
+    }
+}
+
+impl Default for AuthorizationRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn random_token() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand::thread_rng().r#gen::<[u8; 32]>())
+}
+
+/// https://datatracker.ietf.org/doc/html/rfc7636#section-4.2
+pub fn code_challenge(code_verifier: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub preferred_username: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OidcUser {
+    /// Issuer and subject together identify the user across providers
+    pub subject: String,
+    pub email: Option<String>,
+    pub preferred_username: Option<String>,
+}
+
+pub struct OidcClient {
+    pub config: OidcConfig,
+    pub metadata: ProviderMetadata,
+    pub request: AuthorizationRequest,
+}
+
+#[async_trait]
+impl AuthClient for OidcClient {
+    type Credentials = OidcUser;
+
+    async fn get_credentials(&self, code: &str, origin: &str) -> Result<Self::Credentials, Error> {
+        let client = Client::new();
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", origin),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &self.request.code_verifier),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let token: TokenResponse = client
+            .post(&self.metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let jwks: JwkSet = client
+            .get(&self.metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let claims = validate_id_token(
+            &token.id_token,
+            &jwks,
+            &self.config,
+            &self.metadata,
+            &self.request.nonce,
+        )?;
+        Ok(OidcUser {
+            subject: format!("{}|{}", claims.iss, claims.sub),
+            email: claims
+                .email
+                .filter(|_| claims.email_verified != Some(false)),
+            preferred_username: claims.preferred_username,
+        })
+    }
+}
+
+/// Verifies the signature, issuer, audience, expiry and nonce of an ID token
+pub fn validate_id_token(
+    id_token: &str,
+    jwks: &JwkSet,
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    nonce: &str,
+) -> Result<IdTokenClaims, Error> {
+    let header = jsonwebtoken::decode_header(id_token).map_err(invalid_token)?;
+    let key = match header.alg {
+        // Symmetric signatures use the client secret as the key
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let Some(client_secret) = &config.client_secret else {
+                return Err(Error::client_error(
+                    "ID token is signed with a client secret that isn't configured",
+                ));
+            };
+            DecodingKey::from_secret(client_secret.as_bytes())
+        }
+        _ => {
+            let jwk = match &header.kid {
+                Some(kid) => jwks.find(kid),
+                None => jwks
+                    .keys
+                    .iter()
+                    .find(|jwk| !matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_))),
+            }
+            .ok_or(Error::client_error(
+                "ID token is signed with an unknown key",
+            ))?;
+            DecodingKey::from_jwk(jwk).map_err(invalid_token)?
+        }
+    };
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(invalid_token)?
+        .claims;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(Error::client_error("ID token nonce doesn't match"));
+    }
+    Ok(claims)
+}
+
+fn invalid_token(e: jsonwebtoken::errors::Error) -> Error {
+    Error::client_error(format!("Invalid ID token: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AuthorizationRequest, OidcConfig, ProviderMetadata};
+    use jsonwebtoken::{EncodingKey, Header, jwk::JwkSet};
+    use mybops::Error;
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn config() -> OidcConfig {
+        OidcConfig {
+            name: String::from("Test"),
+            issuer: String::from("http://localhost:8080"),
+            client_id: String::from("mybops"),
+            client_secret: Some(String::from("secret")),
+            scopes: super::default_scopes(),
+        }
+    }
+
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata {
+            issuer: String::from("http://localhost:8080"),
+            authorization_endpoint: String::from("http://localhost:8080/authorize"),
+            token_endpoint: String::from("http://localhost:8080/token"),
+            jwks_uri: String::from("http://localhost:8080/jwks"),
+        }
+    }
+
+    fn token(claims: serde_json::Value) -> String {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        // https://datatracker.ietf.org/doc/html/rfc7636#appendix-B
+        assert_eq!(
+            super::code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_authorization_url() {
+        let request = AuthorizationRequest {
+            state: String::from("state"),
+            nonce: String::from("nonce"),
+            code_verifier: String::from("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+        };
+        assert_eq!(
+            request.url(
+                &config(),
+                &metadata(),
+                "http://localhost:3000/api/login/oidc"
+            ),
+            "http://localhost:8080/authorize?response_type=code&client_id=mybops&redirect_uri=http%3A%2F%2Flocalhost%3A3000%2Fapi%2Flogin%2Foidc&scope=openid%20email%20profile&state=state&nonce=nonce&code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM&code_challenge_method=S256"
+        );
+    }
+
+    #[test]
+    fn test_validate_id_token() {
+        let jwks = JwkSet { keys: Vec::new() };
+        let claims = super::validate_id_token(
+            &token(json!({
+                "iss": "http://localhost:8080",
+                "sub": "user",
+                "aud": "mybops",
+                "exp": now() + 60,
+                "nonce": "nonce",
+                "email": "user@example.com",
+            })),
+            &jwks,
+            &config(),
+            &metadata(),
+            "nonce",
+        )
+        .unwrap();
+        assert_eq!(claims.sub, "user");
+        assert_eq!(claims.email.as_deref(), Some("user@example.com"));
+
+        for claims in [
+            json!({"iss": "http://localhost:8081", "sub": "user", "aud": "mybops", "exp": now() + 60, "nonce": "nonce"}),
+            json!({"iss": "http://localhost:8080", "sub": "user", "aud": "other", "exp": now() + 60, "nonce": "nonce"}),
+            json!({"iss": "http://localhost:8080", "sub": "user", "aud": "mybops", "exp": now() - 600, "nonce": "nonce"}),
+            json!({"iss": "http://localhost:8080", "sub": "user", "aud": "mybops", "exp": now() + 60, "nonce": "other"}),
+            json!({"iss": "http://localhost:8080", "sub": "user", "aud": "mybops", "exp": now() + 60}),
+        ] {
+            assert!(matches!(
+                super::validate_id_token(&token(claims), &jwks, &config(), &metadata(), "nonce"),
+                Err(Error::ClientError(_))
+            ));
+        }
+    }
+}
@@ -66,7 +66,8 @@ pub fn assert_bop_to_the_top(list: &List) {
                 id: "5MztFbRbMpyxbVYuOSfQV9".to_owned(),
                 raw_id: "https://open.spotify.com/embed/playlist/5MztFbRbMpyxbVYuOSfQV9?utm_source=generator".to_owned()
             })),
-            name: "Bop to the Top".to_owned()
+            name: "Bop to the Top".to_owned(),
+            unmatched: Vec::new(),
         }]
     );
     assert_eq!(
@@ -0,0 +1,409 @@
+use crate::{Id, ItemMetadata, SourceType, Spotify};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt, str::FromStr};
+
+/// Sources combined with set operations
+///
+/// Sources are written as expressions of operands joined by `|` (union), `&` (intersection) or
+/// `-` (difference), e.g. `list:gym - list:running` or `(list:mine & list:friend) | list:other`.
+/// Intersections are evaluated before unions and differences, and operators need to be separated
+/// by spaces since list IDs can contain dashes. Operands are `list:id`, Spotify URLs or URIs,
+/// `import:format` or `scrobbles:service:user:kind:period`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Composite {
+    pub operation: SetOperation,
+    pub operands: Vec<SourceType>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SetOperation {
+    /// Items in any operand
+    Union,
+    /// Items in every operand
+    Intersection,
+    /// Items in the first operand that aren't in the others
+    Difference,
+}
+
+impl SetOperation {
+    pub fn name(self) -> &'static str {
+        match self {
+            SetOperation::Union => "Union",
+            SetOperation::Intersection => "Intersection",
+            SetOperation::Difference => "Difference",
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            SetOperation::Union => "|",
+            SetOperation::Intersection => "&",
+            SetOperation::Difference => "-",
+        }
+    }
+}
+
+impl Composite {
+    /// Sources that aren't composites, without duplicates
+    pub fn leaves(&self) -> Vec<&SourceType> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(&mut leaves);
+        leaves
+    }
+
+    fn collect_leaves<'a>(&'a self, leaves: &mut Vec<&'a SourceType>) {
+        for operand in &self.operands {
+            match operand {
+                SourceType::Composite(composite) => composite.collect_leaves(leaves),
+                source => {
+                    if !leaves.contains(&source) {
+                        leaves.push(source);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Combine the items of each leaf, keeping the order of the first operand that has an item
+    pub fn evaluate(&self, items: &dyn Fn(&SourceType) -> Vec<ItemMetadata>) -> Vec<ItemMetadata> {
+        let mut operands = self.operands.iter().map(|operand| match operand {
+            SourceType::Composite(composite) => composite.evaluate(items),
+            source => items(source),
+        });
+        let mut result = operands.next().unwrap_or_default();
+        match self.operation {
+            SetOperation::Union => {
+                result.extend(operands.flatten());
+                let mut seen = HashSet::new();
+                result.retain(|item| seen.insert(item.id.clone()));
+            }
+            SetOperation::Intersection => {
+                for operand in operands {
+                    let ids: HashSet<_> = operand.into_iter().map(|item| item.id).collect();
+                    result.retain(|item| ids.contains(&item.id));
+                }
+            }
+            SetOperation::Difference => {
+                let ids: HashSet<_> = operands.flatten().map(|item| item.id).collect();
+                result.retain(|item| !ids.contains(&item.id));
+            }
+        }
+        result
+    }
+
+    /// Write the expression with a custom format for leaves, e.g. to use source names
+    pub fn format(&self, leaf: &dyn Fn(&SourceType) -> String) -> String {
+        self.operands
+            .iter()
+            .map(|operand| match operand {
+                SourceType::Composite(composite) => format!("({})", composite.format(leaf)),
+                source => leaf(source),
+            })
+            .collect::<Vec<_>>()
+            .join(&format!(" {} ", self.operation.symbol()))
+    }
+}
+
+/// Write a source as an operand that can be parsed again
+pub fn format_operand(source: &SourceType) -> String {
+    match source {
+        SourceType::ListItems(id) => format!("list:{id}"),
+        SourceType::Spotify(
+            Spotify::Playlist(Id { raw_id, .. })
+            | Spotify::Album(Id { raw_id, .. })
+            | Spotify::Track(Id { raw_id, .. }),
+        ) => raw_id.clone(),
+        SourceType::Import(format) => format!("import:{}", format.id()),
+        SourceType::Scrobbles(scrobbles) => format!("scrobbles:{scrobbles}"),
+        SourceType::Composite(composite) => format!("({composite})"),
+        source => format!("{source:?}"),
+    }
+}
+
+fn parse_operand(s: &str) -> Result<SourceType, String> {
+    if let Some(id) = s.strip_prefix("list:") {
+        return Ok(SourceType::ListItems(id.to_owned()));
+    }
+    if let Some(format) = s.strip_prefix("import:") {
+        return Ok(SourceType::Import(format.parse()?));
+    }
+    if let Some(scrobbles) = s.strip_prefix("scrobbles:") {
+        return Ok(SourceType::Scrobbles(scrobbles.parse()?));
+    }
+    let path = s
+        .strip_prefix("spotify:")
+        .map(|uri| uri.split(':').collect::<Vec<_>>())
+        .or_else(|| {
+            s.split_once("open.spotify.com/").map(|(_, path)| {
+                path.split(['?', '#'])
+                    .next()
+                    .unwrap_or_default()
+                    .split('/')
+                    .collect()
+            })
+        });
+    let Some([kind, id]) = path.as_deref() else {
+        return Err(format!(
+            "{s} must be list:id, a Spotify link, import:format or scrobbles:service:user"
+        ));
+    };
+    let id = Id {
+        id: (*id).to_owned(),
+        raw_id: s.to_owned(),
+    };
+    match *kind {
+        "playlist" => Ok(SourceType::Spotify(Spotify::Playlist(id))),
+        "album" => Ok(SourceType::Spotify(Spotify::Album(id))),
+        "track" => Ok(SourceType::Spotify(Spotify::Track(id))),
+        _ => Err(format!("{s} must be a Spotify playlist, album or track")),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Operation(SetOperation),
+    Operand(&'a str),
+}
+
+fn tokenize(s: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    for word in s.split_whitespace() {
+        let start = word.len() - word.trim_start_matches('(').len();
+        let end = word.trim_end_matches(')').len().max(start);
+        tokens.extend((0..start).map(|_| Token::Open));
+        tokens.extend(match &word[start..end] {
+            "" => None,
+            "|" => Some(Token::Operation(SetOperation::Union)),
+            "&" => Some(Token::Operation(SetOperation::Intersection)),
+            "-" => Some(Token::Operation(SetOperation::Difference)),
+            operand => Some(Token::Operand(operand)),
+        });
+        tokens.extend((end..word.len()).map(|_| Token::Close));
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token<'_>> {
+        self.tokens.get(self.position)
+    }
+
+    /// Unions and differences of intersections
+    fn expression(&mut self) -> Result<SourceType, String> {
+        let mut left = self.intersection()?;
+        while let Some(&Token::Operation(
+            operation @ (SetOperation::Union | SetOperation::Difference),
+        )) = self.peek()
+        {
+            self.position += 1;
+            let right = self.intersection()?;
+            left = combine(operation, left, right);
+        }
+        Ok(left)
+    }
+
+    fn intersection(&mut self) -> Result<SourceType, String> {
+        let mut left = self.operand()?;
+        while let Some(Token::Operation(SetOperation::Intersection)) = self.peek() {
+            self.position += 1;
+            let right = self.operand()?;
+            left = combine(SetOperation::Intersection, left, right);
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<SourceType, String> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        match token {
+            Some(Token::Operand(operand)) => parse_operand(operand),
+            Some(Token::Open) => {
+                let expression = self.expression()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        // Keep parenthesized composites separate so they aren't merged
+                        Ok(match unwrap_group(expression) {
+                            SourceType::Composite(composite) => SourceType::Composite(Composite {
+                                operation: composite.operation,
+                                operands: vec![SourceType::Composite(composite)],
+                            }),
+                            source => source,
+                        })
+                    }
+                    _ => Err(String::from("expected )")),
+                }
+            }
+            Some(Token::Close) => Err(String::from("unexpected )")),
+            Some(Token::Operation(operation)) => {
+                Err(format!("expected a source before {}", operation.symbol()))
+            }
+            None => Err(String::from("expected a source")),
+        }
+    }
+}
+
+/// Chains of the same operation are combined into one composite, e.g. `a - b - c`
+fn combine(operation: SetOperation, left: SourceType, right: SourceType) -> SourceType {
+    let right = unwrap_group(right);
+    match left {
+        SourceType::Composite(mut composite) if composite.operation == operation => {
+            if composite.operands.len() == 1 {
+                return SourceType::Composite(Composite {
+                    operation,
+                    operands: vec![unwrap_group(SourceType::Composite(composite)), right],
+                });
+            }
+            composite.operands.push(right);
+            SourceType::Composite(composite)
+        }
+        left => SourceType::Composite(Composite {
+            operation,
+            operands: vec![unwrap_group(left), right],
+        }),
+    }
+}
+
+/// Parenthesized composites are wrapped while parsing so that they aren't extended
+fn unwrap_group(source: SourceType) -> SourceType {
+    match source {
+        SourceType::Composite(mut composite) if composite.operands.len() == 1 => {
+            composite.operands.pop().unwrap()
+        }
+        source => source,
+    }
+}
+
+impl FromStr for Composite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s),
+            position: 0,
+        };
+        let expression = unwrap_group(parser.expression()?);
+        if parser.position < parser.tokens.len() {
+            return Err(String::from("expected |, & or - between sources"));
+        }
+        match expression {
+            SourceType::Composite(composite) => Ok(composite),
+            _ => Err(String::from(
+                "combine at least two sources with |, & or -, e.g. list:a - list:b",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Composite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(&format_operand))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Composite, SetOperation};
+    use crate::{Id, ItemMetadata, SourceType, Spotify};
+
+    fn list(id: &str) -> SourceType {
+        SourceType::ListItems(id.to_owned())
+    }
+
+    #[test]
+    fn test_parse() {
+        let composite: Composite =
+            "(list:a & list:b-1) | list:c | https://open.spotify.com/playlist/p?si=x - list:d"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            composite,
+            Composite {
+                operation: SetOperation::Difference,
+                operands: vec![
+                    SourceType::Composite(Composite {
+                        operation: SetOperation::Union,
+                        operands: vec![
+                            SourceType::Composite(Composite {
+                                operation: SetOperation::Intersection,
+                                operands: vec![list("a"), list("b-1")],
+                            }),
+                            list("c"),
+                            SourceType::Spotify(Spotify::Playlist(Id {
+                                id: "p".to_owned(),
+                                raw_id: "https://open.spotify.com/playlist/p?si=x".to_owned(),
+                            })),
+                        ],
+                    }),
+                    list("d"),
+                ],
+            }
+        );
+        assert_eq!(
+            composite.to_string(),
+            "((list:a & list:b-1) | list:c | https://open.spotify.com/playlist/p?si=x) - list:d"
+        );
+        assert_eq!(
+            composite.to_string().parse::<Composite>().unwrap(),
+            composite
+        );
+        // Parenthesized differences aren't merged into the outer difference
+        let composite: Composite = "list:a - ( list:b - list:c )".parse().unwrap();
+        assert_eq!(
+            composite.operands[1],
+            SourceType::Composite(Composite {
+                operation: SetOperation::Difference,
+                operands: vec![list("b"), list("c")],
+            })
+        );
+        assert!("list:a".parse::<Composite>().is_err());
+        assert!("list:a -".parse::<Composite>().is_err());
+        assert!("(list:a | list:b".parse::<Composite>().is_err());
+        assert!("list:a list:b".parse::<Composite>().is_err());
+        assert!("list:a | spotify:artist:x".parse::<Composite>().is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let items = |source: &SourceType| {
+            let ids: &[&str] = match source {
+                SourceType::ListItems(id) if id == "gym" => &["1", "2", "3"],
+                SourceType::ListItems(id) if id == "running" => &["2", "4"],
+                SourceType::ListItems(id) if id == "friend" => &["3", "4", "5"],
+                _ => &[],
+            };
+            ids.iter()
+                .map(|id| ItemMetadata::new(id.to_string(), id.to_string(), None))
+                .collect()
+        };
+        let evaluate = |s: &str| -> Vec<String> {
+            s.parse::<Composite>()
+                .unwrap()
+                .evaluate(&items)
+                .into_iter()
+                .map(|item| item.id)
+                .collect()
+        };
+        assert_eq!(evaluate("list:gym - list:running"), ["1", "3"]);
+        assert_eq!(evaluate("list:gym & list:friend"), ["3"]);
+        assert_eq!(evaluate("list:running | list:gym"), ["2", "4", "1", "3"]);
+        assert_eq!(
+            evaluate("list:gym | list:friend - list:running & list:friend"),
+            ["1", "2", "3", "5"]
+        );
+        assert_eq!(
+            "list:gym - list:running | list:gym"
+                .parse::<Composite>()
+                .unwrap()
+                .leaves(),
+            [&list("gym"), &list("running")]
+        );
+    }
+}
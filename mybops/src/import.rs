@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// File exports that can be uploaded again to update their items
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ImportFormat {
+    /// Letterboxd diary, ratings or watched CSV
+    Letterboxd,
+    /// Goodreads library export CSV
+    Goodreads,
+    /// BoardGameGeek collection or thing XML
+    BoardGameGeek,
+    /// JSON array of games from the IGDB API
+    Igdb,
+}
+
+impl ImportFormat {
+    pub fn id(self) -> &'static str {
+        match self {
+            ImportFormat::Letterboxd => "letterboxd",
+            ImportFormat::Goodreads => "goodreads",
+            ImportFormat::BoardGameGeek => "bgg",
+            ImportFormat::Igdb => "igdb",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImportFormat::Letterboxd => "Letterboxd",
+            ImportFormat::Goodreads => "Goodreads",
+            ImportFormat::BoardGameGeek => "BoardGameGeek",
+            ImportFormat::Igdb => "IGDB",
+        }
+    }
+
+    /// Item type of imported items
+    pub fn item_type(self) -> &'static str {
+        match self {
+            ImportFormat::Letterboxd => "film",
+            ImportFormat::Goodreads => "book",
+            ImportFormat::BoardGameGeek => "boardgame",
+            ImportFormat::Igdb => "videogame",
+        }
+    }
+
+    pub fn list_name(self) -> &'static str {
+        match self {
+            ImportFormat::Letterboxd => "Letterboxd films",
+            ImportFormat::Goodreads => "Goodreads books",
+            ImportFormat::BoardGameGeek => "BoardGameGeek board games",
+            ImportFormat::Igdb => "IGDB video games",
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "letterboxd" => Ok(ImportFormat::Letterboxd),
+            "goodreads" => Ok(ImportFormat::Goodreads),
+            "bgg" => Ok(ImportFormat::BoardGameGeek),
+            "igdb" => Ok(ImportFormat::Igdb),
+            _ => Err(format!("{s} is not a supported import format")),
+        }
+    }
+}
@@ -11,6 +11,10 @@ use azure_data_cosmos::prelude::CosmosEntity;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+pub mod composite;
+pub mod import;
+pub mod scrobble;
+pub mod setlist;
 pub mod spotify;
 #[cfg(feature = "full")]
 pub mod storage;
@@ -40,6 +44,20 @@ pub struct List {
     // For external lists, query is only used to select fields (not filter)
     pub query: String,
     pub public: bool,
+    #[serde(default)]
+    pub sync: Option<SyncState>,
+    #[serde(default)]
+    pub sort: SortMode,
+    /// Tier names from best to worst
+    #[serde(default = "default_tiers")]
+    pub tiers: Vec<String>,
+    /// Ratings are shown on the scale of each item's type if the list doesn't set one
+    #[serde(default)]
+    pub rating_scale: Option<RatingScale>,
+}
+
+pub fn default_tiers() -> Vec<String> {
+    ["S", "A", "B", "C", "D"].map(String::from).to_vec()
 }
 
 impl List {
@@ -63,8 +81,20 @@ impl List {
             favorite: false,
             query: String::from("SELECT name, user_score FROM item"),
             public: false,
+            sync: None,
+            sort: SortMode::default(),
+            tiers: default_tiers(),
+            rating_scale: None,
         }
     }
+
+    /// Position of the item's tier with untiered items after the last tier
+    pub fn tier_index(&self, item: &ItemMetadata) -> usize {
+        item.tier
+            .as_ref()
+            .and_then(|tier| self.tiers.iter().position(|t| t == tier))
+            .unwrap_or(self.tiers.len())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -83,6 +113,14 @@ pub struct RawList {
     // For external lists, query is only used to select fields (not filter)
     pub query: String,
     pub public: Option<bool>,
+    #[serde(default)]
+    pub sync: Option<String>,
+    #[serde(default)]
+    pub sort: Option<String>,
+    #[serde(default)]
+    pub tiers: Option<String>,
+    #[serde(default)]
+    pub rating_scale: Option<String>,
 }
 
 impl From<List> for RawList {
@@ -98,6 +136,12 @@ impl From<List> for RawList {
             favorite: l.favorite,
             query: l.query,
             public: Some(l.public),
+            sync: l
+                .sync
+                .map(|sync| serde_json::to_string(&sync).expect("sync should serialize")),
+            sort: Some(serde_json::to_string(&l.sort).expect("sort should serialize")),
+            tiers: Some(serde_json::to_string(&l.tiers).expect("tiers should serialize")),
+            rating_scale: l.rating_scale.map(|scale| scale.id().to_owned()),
         }
     }
 }
@@ -116,10 +160,189 @@ impl TryFrom<RawList> for List {
             favorite: l.favorite,
             query: l.query,
             public: l.public.unwrap_or_default(),
+            sync: l.sync.map(|sync| serde_json::from_str(&sync)).transpose()?,
+            sort: l
+                .sort
+                .map(|sort| serde_json::from_str(&sort))
+                .transpose()?
+                .unwrap_or_default(),
+            tiers: l
+                .tiers
+                .map(|tiers| serde_json::from_str(&tiers))
+                .transpose()?
+                .unwrap_or_else(default_tiers),
+            rating_scale: l
+                .rating_scale
+                .map(|scale| scale.parse().map_err(Error::internal_error))
+                .transpose()?,
         })
     }
 }
 
+/// How the items of a list are ordered when they are shown, pushed or exported
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SortMode {
+    /// The list query's ORDER BY or the order items were added in
+    #[default]
+    Query,
+    /// Highest Elo score in the list first
+    Score,
+    /// Tournament rank with unranked items after ranked items
+    Rank,
+    /// Highest item rating first
+    Rating,
+    /// Positions set by dragging items in the list
+    Manual,
+}
+
+impl SortMode {
+    pub fn id(self) -> &'static str {
+        match self {
+            SortMode::Query => "query",
+            SortMode::Score => "score",
+            SortMode::Rank => "rank",
+            SortMode::Rating => "rating",
+            SortMode::Manual => "manual",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortMode::Query => "Query",
+            SortMode::Score => "Elo score",
+            SortMode::Rank => "Tournament rank",
+            SortMode::Rating => "Rating",
+            SortMode::Manual => "Manual",
+        }
+    }
+}
+
+impl std::str::FromStr for SortMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "query" => Ok(SortMode::Query),
+            "score" => Ok(SortMode::Score),
+            "rank" => Ok(SortMode::Rank),
+            "rating" => Ok(SortMode::Rating),
+            "manual" => Ok(SortMode::Manual),
+            _ => Err(format!("{s} is not a supported sort mode")),
+        }
+    }
+}
+
+/// How ratings are entered and shown for a list or item type
+///
+/// Ratings are stored as whole numbers of the scale's smallest step, so 3.5 stars is stored as 7.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RatingScale {
+    #[default]
+    Ten,
+    /// 1 to 5 stars in half star steps
+    HalfStars,
+    Hundred,
+    LikeDislike,
+}
+
+impl RatingScale {
+    pub fn id(self) -> &'static str {
+        match self {
+            RatingScale::Ten => "ten",
+            RatingScale::HalfStars => "stars",
+            RatingScale::Hundred => "hundred",
+            RatingScale::LikeDislike => "like",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RatingScale::Ten => "0 to 10",
+            RatingScale::HalfStars => "1 to 5 stars with halves",
+            RatingScale::Hundred => "0 to 100",
+            RatingScale::LikeDislike => "Like or dislike",
+        }
+    }
+
+    /// Stored values that can be entered with the scale
+    pub fn values(self) -> std::ops::RangeInclusive<i32> {
+        match self {
+            RatingScale::Ten => 0..=10,
+            RatingScale::HalfStars => 1..=10,
+            RatingScale::Hundred => 0..=100,
+            RatingScale::LikeDislike => 0..=1,
+        }
+    }
+
+    pub fn label(self, rating: i32) -> String {
+        match self {
+            RatingScale::Ten | RatingScale::Hundred => rating.to_string(),
+            RatingScale::HalfStars => format!("{} ★", f64::from(rating) / 2.),
+            RatingScale::LikeDislike if rating > 0 => String::from("Like"),
+            RatingScale::LikeDislike => String::from("Dislike"),
+        }
+    }
+
+    /// Rating out of 100 so ratings on different scales can be compared
+    pub fn normalize(self, rating: i32) -> f64 {
+        f64::from(rating) * 100. / f64::from(*self.values().end())
+    }
+
+    /// Closest rating on this scale to a normalized rating
+    pub fn denormalize(self, normalized: f64) -> i32 {
+        let values = self.values();
+        ((normalized * f64::from(*values.end()) / 100.).round() as i32)
+            .clamp(*values.start(), *values.end())
+    }
+
+    pub fn convert(self, rating: i32, to: RatingScale) -> i32 {
+        if self == to {
+            rating
+        } else {
+            to.denormalize(self.normalize(rating))
+        }
+    }
+
+    /// Films and books are rated with stars by Letterboxd and Goodreads
+    pub fn for_type(r#type: &str) -> RatingScale {
+        match r#type {
+            "film" | "book" => RatingScale::HalfStars,
+            _ => RatingScale::Ten,
+        }
+    }
+}
+
+impl std::str::FromStr for RatingScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ten" => Ok(RatingScale::Ten),
+            "stars" => Ok(RatingScale::HalfStars),
+            "hundred" => Ok(RatingScale::Hundred),
+            "like" => Ok(RatingScale::LikeDislike),
+            _ => Err(format!("{s} is not a supported rating scale")),
+        }
+    }
+}
+
+/// Spotify playlist state after the last two-way sync
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SyncState {
+    pub snapshot_id: String,
+    /// Track URIs in the playlist after the last sync
+    pub items: Vec<String>,
+    /// Tracks that were removed in Spotify but are still provided by a list source
+    #[serde(default)]
+    pub conflicts: Vec<SyncConflict>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SyncConflict {
+    pub id: String,
+    pub name: String,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct ItemMetadata {
     pub id: String,
@@ -129,6 +352,9 @@ pub struct ItemMetadata {
     pub wins: i32,
     pub losses: i32,
     pub rank: Option<i32>,
+    /// Tier the item was dragged into on the list's tier page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
 }
 
 impl ItemMetadata {
@@ -141,6 +367,7 @@ impl ItemMetadata {
             wins: 0,
             losses: 0,
             rank: None,
+            tier: None,
         }
     }
 }
@@ -160,6 +387,9 @@ pub enum ListMode {
 pub struct Source {
     pub source_type: SourceType,
     pub name: String,
+    /// Entries from the source that couldn't be matched to an item
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -168,6 +398,16 @@ pub enum SourceType {
     Spotify(Spotify),
     Setlist(Id),
     ListItems(String),
+    Scrobbles(scrobble::Scrobbles),
+    Setlists(setlist::Setlists),
+    /// Items from a file export that are updated by uploading the export again
+    Import(import::ImportFormat),
+    /// Tagged audio files in a directory under the server's library root
+    LocalLibrary(String),
+    /// Other sources combined with union, intersection or difference
+    Composite(composite::Composite),
+    /// Items of the user that match a filter, re-evaluated when items change
+    Query(String),
 }
 
 impl List {
@@ -227,8 +467,13 @@ impl List {
 fn get_source_id(source: &Source) -> Option<&str> {
     match source.source_type {
         SourceType::Spotify(_) => Some("spotify"),
-        SourceType::Setlist(_) => Some("spotify"),
+        SourceType::Setlist(_) | SourceType::Setlists(_) => Some("spotify"),
         SourceType::ListItems(_) => Some("list"),
+        // Albums and artists can't be added to playlists
+        SourceType::Scrobbles(scrobble::Scrobbles {
+            kind: scrobble::ScrobbleKind::Tracks,
+            ..
+        }) => Some("spotify"),
         _ => None,
     }
 }
@@ -260,12 +505,104 @@ pub struct Items {
     pub items: Vec<Option<ItemMetadata>>,
 }
 
+/// Items that look like the same recording, with the suggested merge target first
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DuplicateGroup {
+    /// How the items were matched, e.g. ISRC or title and artist
+    pub reasons: Vec<String>,
+    pub items: Vec<DuplicateItem>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DuplicateItem {
+    pub id: String,
+    pub name: String,
+    pub album: Option<String>,
+    pub artists: Vec<String>,
+    pub rating: Option<i32>,
+    pub score: i32,
+    pub wins: i32,
+    pub losses: i32,
+}
+
+/// Merge items into a target item
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MergeItems {
+    pub target: String,
+    pub ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IdentifySummary {
+    /// Number of items looked up in MusicBrainz
+    pub items: usize,
+    /// Number of items that matched a MusicBrainz recording
+    pub matched: usize,
+    /// Number of items that still need to be looked up
+    pub remaining: usize,
+}
+
+/// Rankings of the current user and another user over the items they have both ranked
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TasteComparison {
+    pub user_id: String,
+    /// Common items in the current user's ranking order
+    pub items: Vec<ComparedItem>,
+    /// Kendall tau-b, which is missing if either ranking has no order
+    pub kendall_tau: Option<f64>,
+    pub spearman: Option<f64>,
+    /// Common items with the largest rank differences first
+    pub disagreements: Vec<ComparedItem>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ComparedItem {
+    pub id: String,
+    pub name: String,
+    pub score: i32,
+    pub other_score: i32,
+    pub rank: usize,
+    pub other_rank: usize,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct User {
     pub user_id: String,
     pub spotify_user: Option<String>,
     pub spotify_url: Option<String>,
     pub google_email: Option<String>,
+    #[serde(default)]
+    pub has_password: bool,
+    #[serde(default)]
+    pub oidc_linked: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub created: Option<i64>,
+    pub expires: i64,
+    pub current: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoginProvider {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PasswordChange {
+    pub current_password: Option<String>,
+    pub new_password: String,
 }
 
 #[derive(Debug)]
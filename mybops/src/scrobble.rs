@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Top tracks, albums or artists for a Last.fm or ListenBrainz user
+///
+/// Sources are written as `service:user[:kind[:period]]`, e.g. `lastfm:rj:tracks:month`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Scrobbles {
+    pub service: ScrobbleService,
+    pub user: String,
+    pub kind: ScrobbleKind,
+    pub period: ScrobblePeriod,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ScrobbleService {
+    LastFm,
+    ListenBrainz,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ScrobbleKind {
+    Tracks,
+    Albums,
+    Artists,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ScrobblePeriod {
+    Week,
+    Month,
+    Quarter,
+    HalfYear,
+    Year,
+    AllTime,
+}
+
+impl ScrobbleService {
+    pub fn id(self) -> &'static str {
+        match self {
+            ScrobbleService::LastFm => "lastfm",
+            ScrobbleService::ListenBrainz => "listenbrainz",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScrobbleService::LastFm => "Last.fm",
+            ScrobbleService::ListenBrainz => "ListenBrainz",
+        }
+    }
+}
+
+impl FromStr for ScrobbleService {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lastfm" => Ok(ScrobbleService::LastFm),
+            "listenbrainz" => Ok(ScrobbleService::ListenBrainz),
+            _ => Err(format!("{s} is not a supported scrobble service")),
+        }
+    }
+}
+
+impl ScrobbleKind {
+    pub fn id(self) -> &'static str {
+        match self {
+            ScrobbleKind::Tracks => "tracks",
+            ScrobbleKind::Albums => "albums",
+            ScrobbleKind::Artists => "artists",
+        }
+    }
+}
+
+impl FromStr for ScrobbleKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tracks" => Ok(ScrobbleKind::Tracks),
+            "albums" => Ok(ScrobbleKind::Albums),
+            "artists" => Ok(ScrobbleKind::Artists),
+            _ => Err(format!("{s} must be tracks, albums or artists")),
+        }
+    }
+}
+
+impl ScrobblePeriod {
+    pub fn id(self) -> &'static str {
+        match self {
+            ScrobblePeriod::Week => "week",
+            ScrobblePeriod::Month => "month",
+            ScrobblePeriod::Quarter => "quarter",
+            ScrobblePeriod::HalfYear => "half_year",
+            ScrobblePeriod::Year => "year",
+            ScrobblePeriod::AllTime => "all_time",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScrobblePeriod::Week => "last week",
+            ScrobblePeriod::Month => "last month",
+            ScrobblePeriod::Quarter => "last 3 months",
+            ScrobblePeriod::HalfYear => "last 6 months",
+            ScrobblePeriod::Year => "last year",
+            ScrobblePeriod::AllTime => "all time",
+        }
+    }
+}
+
+impl FromStr for ScrobblePeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "week" => Ok(ScrobblePeriod::Week),
+            "month" => Ok(ScrobblePeriod::Month),
+            "quarter" => Ok(ScrobblePeriod::Quarter),
+            "half_year" => Ok(ScrobblePeriod::HalfYear),
+            "year" => Ok(ScrobblePeriod::Year),
+            "all_time" => Ok(ScrobblePeriod::AllTime),
+            _ => Err(format!(
+                "{s} must be week, month, quarter, half_year, year or all_time"
+            )),
+        }
+    }
+}
+
+impl FromStr for Scrobbles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+        let service = parts.next().unwrap_or_default().parse()?;
+        let user = match parts.next() {
+            Some(user) if !user.is_empty() => user.to_owned(),
+            _ => return Err(String::from("user is required")),
+        };
+        let kind = parts.next().map_or(Ok(ScrobbleKind::Tracks), str::parse)?;
+        let period = parts
+            .next()
+            .map_or(Ok(ScrobblePeriod::AllTime), str::parse)?;
+        if parts.next().is_some() {
+            return Err(String::from("expected service:user:kind:period"));
+        }
+        Ok(Scrobbles {
+            service,
+            user,
+            kind,
+            period,
+        })
+    }
+}
+
+impl fmt::Display for Scrobbles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.service.id(),
+            self.user,
+            self.kind.id(),
+            self.period.id()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ScrobbleKind, ScrobblePeriod, ScrobbleService, Scrobbles};
+
+    #[test]
+    fn test_parse() {
+        let scrobbles: Scrobbles = "lastfm:rj:albums:month".parse().unwrap();
+        assert_eq!(
+            scrobbles,
+            Scrobbles {
+                service: ScrobbleService::LastFm,
+                user: "rj".to_owned(),
+                kind: ScrobbleKind::Albums,
+                period: ScrobblePeriod::Month,
+            }
+        );
+        assert_eq!(scrobbles.to_string(), "lastfm:rj:albums:month");
+        assert_eq!(
+            "listenbrainz:rob".parse::<Scrobbles>().unwrap().to_string(),
+            "listenbrainz:rob:tracks:all_time"
+        );
+        assert!("lastfm".parse::<Scrobbles>().is_err());
+        assert!("spotify:rj".parse::<Scrobbles>().is_err());
+        assert!("lastfm:rj:songs".parse::<Scrobbles>().is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Songs played across an artist's setlists on setlist.fm
+///
+/// Sources are written as `tour:artist:tour name` or `recent:artist[:count]`, e.g.
+/// `tour:Radiohead:OK Computer` or `recent:Radiohead:10`. Artists can be a name or a
+/// MusicBrainz ID.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Setlists {
+    pub artist: String,
+    pub filter: SetlistFilter,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SetlistFilter {
+    /// All setlists for a tour
+    Tour(String),
+    /// The artist's most recent setlists
+    Recent(usize),
+}
+
+impl Setlists {
+    pub const DEFAULT_RECENT: usize = 10;
+    /// setlist.fm returns 20 setlists per page and is rate limited
+    pub const MAX_RECENT: usize = 60;
+}
+
+impl FromStr for Setlists {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, ':');
+        let filter = parts.next().unwrap_or_default();
+        let artist = match parts.next() {
+            Some(artist) if !artist.trim().is_empty() => artist.trim().to_owned(),
+            _ => return Err(String::from("artist is required")),
+        };
+        let filter = match (filter, parts.next()) {
+            ("tour", Some(tour)) if !tour.trim().is_empty() => {
+                SetlistFilter::Tour(tour.trim().to_owned())
+            }
+            ("tour", _) => return Err(String::from("tour name is required")),
+            ("recent", None) => SetlistFilter::Recent(Setlists::DEFAULT_RECENT),
+            ("recent", Some(count)) => match count.trim().parse() {
+                Ok(count @ 1..=Setlists::MAX_RECENT) => SetlistFilter::Recent(count),
+                _ => {
+                    return Err(format!(
+                        "count must be a number from 1 to {}",
+                        Setlists::MAX_RECENT
+                    ));
+                }
+            },
+            _ => return Err(format!("{filter} must be tour or recent")),
+        };
+        Ok(Setlists { artist, filter })
+    }
+}
+
+impl fmt::Display for Setlists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.filter {
+            SetlistFilter::Tour(tour) => write!(f, "tour:{}:{tour}", self.artist),
+            SetlistFilter::Recent(count) => write!(f, "recent:{}:{count}", self.artist),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SetlistFilter, Setlists};
+
+    #[test]
+    fn test_parse() {
+        let setlists: Setlists = "tour:Radiohead:A Moon Shaped Pool: Part 2".parse().unwrap();
+        assert_eq!(
+            setlists,
+            Setlists {
+                artist: "Radiohead".to_owned(),
+                filter: SetlistFilter::Tour("A Moon Shaped Pool: Part 2".to_owned()),
+            }
+        );
+        assert_eq!(
+            setlists.to_string(),
+            "tour:Radiohead:A Moon Shaped Pool: Part 2"
+        );
+        assert_eq!(
+            "recent:Radiohead".parse::<Setlists>().unwrap().to_string(),
+            "recent:Radiohead:10"
+        );
+        assert!("tour:Radiohead".parse::<Setlists>().is_err());
+        assert!("recent:Radiohead:0".parse::<Setlists>().is_err());
+        assert!("recent:Radiohead:100".parse::<Setlists>().is_err());
+        assert!("latest:Radiohead".parse::<Setlists>().is_err());
+        assert!("recent:".parse::<Setlists>().is_err());
+    }
+}
@@ -16,6 +16,37 @@ pub struct RecentTrack {
     pub user_score: Option<i32>,
 }
 
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SearchResults {
+    pub tracks: Option<SearchPage>,
+    pub albums: Option<SearchPage>,
+    pub artists: Option<SearchPage>,
+    pub playlists: Option<SearchPage>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SearchPage {
+    pub items: Vec<SearchResult>,
+    pub offset: usize,
+    pub limit: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchResult {
+    pub id: String,
+    pub uri: String,
+    pub name: String,
+    /// Artists for tracks and albums or the owner for playlists
+    pub description: String,
+    pub url: String,
+    /// Tracks are added if they are in the user's items and albums and playlists are added if
+    /// they were imported as a list
+    pub added: bool,
+    pub rating: Option<i32>,
+    pub user_score: Option<i32>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Playlists {
     pub items: Vec<Playlist>,
@@ -27,3 +58,12 @@ pub struct Playlist {
     pub name: String,
     pub external_urls: HashMap<String, String>,
 }
+
+/// Result of uploading a Spotify extended streaming history file
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct HistorySummary {
+    /// Track plays in the file
+    pub plays: usize,
+    /// Items that had their listening stats updated
+    pub items: usize,
+}
@@ -132,6 +132,11 @@ pub enum View {
     List(UserId, Vec<String>),
     Public,
     PublicList(Vec<String>),
+    /// Items of a list by list ID with the list's tiers as a tier column
+    ListTiers(UserId, String),
+    PublicListTiers(String),
+    /// Items of another user that are in their public lists
+    Shared(UserId),
 }
 
 #[async_trait]
@@ -166,7 +171,8 @@ impl SessionClient for SqlSessionClient {
             View::User(user_id) => {
                 conn.execute_batch(&format!(
                     "CREATE TEMP VIEW list AS SELECT * FROM _list WHERE user_id = '{user_id}';
-                        CREATE TEMP VIEW item AS SELECT * FROM _item WHERE user_id = '{user_id}';",
+                        CREATE TEMP VIEW item AS SELECT * FROM _item WHERE user_id = '{user_id}';
+                        CREATE TEMP VIEW cache AS SELECT * FROM _cache WHERE user_id = '{user_id}';",
                     user_id = user_id.0
                 ))?;
             }
@@ -207,6 +213,15 @@ impl SessionClient for SqlSessionClient {
         if query.contains("_item") {
             return Err(Error::client_error("no such table: _item"));
         }
+        // Item fields like ms_played also contain _play
+        for table in ["_play", "_cache"] {
+            if query
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .any(|token| token == table)
+            {
+                return Err(Error::client_error(format!("no such table: {table}")));
+            }
+        }
         let params: Vec<_> = builder
             .query
             .parameters
@@ -237,7 +252,9 @@ impl SessionClient for SqlSessionClient {
             View::User(user_id) => {
                 conn.execute_batch(&format!(
                     "CREATE TEMP VIEW list AS SELECT * FROM _list WHERE user_id = '{user_id}';
-                        CREATE TEMP VIEW item AS SELECT * FROM _item WHERE user_id = '{user_id}';",
+                        CREATE TEMP VIEW item AS SELECT * FROM _item WHERE user_id = '{user_id}';
+                        CREATE TEMP VIEW play AS SELECT * FROM _play WHERE user_id = '{user_id}';
+                        CREATE TEMP VIEW item_history AS SELECT history.item_id, history.list_id, history.ts, history.score, history.rank, _item.name, _item.type, _item.metadata FROM _item_history AS history JOIN _item ON _item.id = history.item_id AND _item.user_id = history.user_id WHERE history.user_id = '{user_id}';",
                     user_id = user_id.0
                 ))?;
             }
@@ -272,6 +289,28 @@ impl SessionClient for SqlSessionClient {
                     ),
                 )?;
             }
+            View::ListTiers(user_id, id) => {
+                conn.execute_batch(&format!(
+                    "CREATE TEMP VIEW list AS SELECT * FROM _list WHERE user_id = '{user_id}';
+                        CREATE TEMP VIEW item AS SELECT _item.*, json_each.value->>'tier' AS tier FROM _list, json_each(_list.items) JOIN _item ON _item.id=json_each.value->>'id' AND _item.user_id=_list.user_id WHERE _list.id = '{id}' AND _list.user_id = '{user_id}';",
+                    id = id.replace('\'', "''"),
+                    user_id = user_id.0
+                ))?;
+            }
+            View::PublicListTiers(id) => {
+                conn.execute_batch(&format!(
+                    "CREATE TEMP VIEW list AS SELECT * FROM _list WHERE public = true;
+                    CREATE TEMP VIEW item AS SELECT _item.*, json_each.value->>'tier' AS tier FROM _list, json_each(_list.items) JOIN _item ON _item.id=json_each.value->>'id' AND _item.user_id=_list.user_id WHERE _list.id = '{id}' AND public = true;",
+                    id = id.replace('\'', "''")
+                ))?;
+            }
+            View::Shared(user_id) => {
+                conn.execute_batch(&format!(
+                    "CREATE TEMP VIEW list AS SELECT * FROM _list WHERE public = true AND user_id = '{user_id}';
+                    CREATE TEMP VIEW item AS SELECT DISTINCT _item.* FROM _list, json_each(_list.items) JOIN _item ON _item.id=json_each.value->>'id' AND _item.user_id=_list.user_id WHERE public = true AND _list.user_id = '{user_id}';",
+                    user_id = user_id.0.replace('\'', "''")
+                ))?;
+            }
         }
         let mut stmt = conn.prepare(&query)?;
         let query = stmt.query(rusqlite::params_from_iter(params))?;
@@ -318,17 +357,32 @@ impl SessionClient for SqlSessionClient {
 fn get_insert_stmt(collection_name: &str, is_upsert: bool) -> &str {
     match (collection_name, is_upsert) {
         ("item", false) => {
-            "INSERT INTO _item (id, user_id, type, name, iframe, rating, user_score, user_wins, user_losses, metadata, hidden) VALUES (:id, :user_id, :type, :name, :iframe, :rating, :user_score, :user_wins, :user_losses, :metadata, :hidden)"
+            "INSERT INTO _item (id, user_id, type, name, iframe, rating, rating_scale, normalized_rating, user_score, user_wins, user_losses, metadata, hidden) VALUES (:id, :user_id, :type, :name, :iframe, :rating, :rating_scale, :normalized_rating, :user_score, :user_wins, :user_losses, :metadata, :hidden)"
         }
         ("list", false) => {
-            "INSERT INTO _list (id, user_id, mode, name, sources, iframe, items, favorite, query, public) VALUES (:id, :user_id, :mode, :name, :sources, :iframe, :items, :favorite, :query, :public)"
+            "INSERT INTO _list (id, user_id, mode, name, sources, iframe, items, favorite, query, public, sync, sort, tiers, rating_scale) VALUES (:id, :user_id, :mode, :name, :sources, :iframe, :items, :favorite, :query, :public, :sync, :sort, :tiers, :rating_scale)"
         }
         // is_upsert is currently only used to reset demo lists and items
         ("item", true) => {
-            "INSERT INTO _item (id, user_id, type, name, iframe, rating, user_score, user_wins, user_losses, metadata, hidden) VALUES (:id, :user_id, :type, :name, :iframe, :rating, :user_score, :user_wins, :user_losses, :metadata, :hidden) ON CONFLICT(id, user_id) DO UPDATE SET rating=excluded.rating, user_score=excluded.user_score, user_wins=excluded.user_wins, user_losses=excluded.user_losses"
+            "INSERT INTO _item (id, user_id, type, name, iframe, rating, rating_scale, normalized_rating, user_score, user_wins, user_losses, metadata, hidden) VALUES (:id, :user_id, :type, :name, :iframe, :rating, :rating_scale, :normalized_rating, :user_score, :user_wins, :user_losses, :metadata, :hidden) ON CONFLICT(id, user_id) DO UPDATE SET rating=excluded.rating, rating_scale=excluded.rating_scale, normalized_rating=excluded.normalized_rating, user_score=excluded.user_score, user_wins=excluded.user_wins, user_losses=excluded.user_losses"
         }
         ("list", true) => {
-            "INSERT INTO _list (id, user_id, mode, name, sources, iframe, items, favorite, query, public) VALUES (:id, :user_id, :mode, :name, :sources, :iframe, :items, :favorite, :query, :public) ON CONFLICT(id, user_id) DO UPDATE SET items=excluded.items, query=excluded.query, public=excluded.public"
+            "INSERT INTO _list (id, user_id, mode, name, sources, iframe, items, favorite, query, public, sync, sort, tiers, rating_scale) VALUES (:id, :user_id, :mode, :name, :sources, :iframe, :items, :favorite, :query, :public, :sync, :sort, :tiers, :rating_scale) ON CONFLICT(id, user_id) DO UPDATE SET items=excluded.items, query=excluded.query, public=excluded.public"
+        }
+        // Plays are batched as a JSON array and plays that were already imported are skipped
+        ("play", false) => {
+            "INSERT INTO _play (id, user_id, item_id, ts, ms_played) SELECT value->>'id', :user_id, value->>'item_id', value->>'ts', value->>'ms_played' FROM json_each(:plays) WHERE true ON CONFLICT(id, user_id) DO NOTHING"
+        }
+        // Scores and ranks within the list are read from the items so snapshots are taken after the match is saved
+        ("item_history", false) => {
+            "INSERT INTO _item_history (user_id, item_id, list_id, ts, score, rank) SELECT :user_id, _item.id, :list_id, :ts, _item.user_score, (SELECT COUNT(DISTINCT other.id) + 1 FROM _list, json_each(_list.items) AS list_item JOIN _item AS other ON other.id = list_item.value->>'id' AND other.user_id = _list.user_id WHERE _list.id = :list_id AND _list.user_id = :user_id AND other.hidden = false AND other.user_score > _item.user_score) FROM _item, json_each(:item_ids) WHERE _item.user_id = :user_id AND _item.id = json_each.value"
+        }
+        // Updates names and merges new metadata into existing items without resetting stats when sources are refreshed
+        ("item_metadata", true) => {
+            "INSERT INTO _item (id, user_id, type, name, iframe, rating, rating_scale, normalized_rating, user_score, user_wins, user_losses, metadata, hidden) VALUES (:id, :user_id, :type, :name, :iframe, :rating, :rating_scale, :normalized_rating, :user_score, :user_wins, :user_losses, :metadata, :hidden) ON CONFLICT(id, user_id) DO UPDATE SET name=excluded.name, metadata=json_patch(_item.metadata, excluded.metadata)"
+        }
+        ("cache", true) => {
+            "INSERT INTO _cache (id, user_id, version, payload, updated_at) VALUES (:id, :user_id, :version, :payload, :updated_at) ON CONFLICT(id, user_id) DO UPDATE SET version=excluded.version, payload=excluded.payload, updated_at=excluded.updated_at"
         }
         _ => unreachable!(),
     }
@@ -337,23 +391,46 @@ fn get_insert_stmt(collection_name: &str, is_upsert: bool) -> &str {
 fn get_update_stmt(collection_name: &str) -> (&str, &[&str]) {
     match collection_name {
         "item" => (
-            "UPDATE _item SET rating = :rating, user_score = :user_score, user_wins = :user_wins, user_losses = :user_losses WHERE id = :id AND user_id = :user_id",
+            "UPDATE _item SET rating = :rating, rating_scale = :rating_scale, normalized_rating = :normalized_rating, user_score = :user_score, user_wins = :user_wins, user_losses = :user_losses WHERE id = :id AND user_id = :user_id",
             &[
                 "id",
                 "user_id",
                 "rating",
+                "rating_scale",
+                "normalized_rating",
                 "user_score",
                 "user_wins",
                 "user_losses",
             ],
         ),
+        // Merging duplicates hides them and records the item they were merged into
+        "item_identity" => (
+            "UPDATE _item SET metadata = :metadata, hidden = :hidden WHERE id = :id AND user_id = :user_id",
+            &["id", "user_id", "metadata", "hidden"],
+        ),
         "list" => (
-            "UPDATE _list SET mode = :mode, name = :name, sources = :sources, iframe = :iframe, items = :items, favorite = :favorite, query = :query, public = :public WHERE id = :id AND user_id = :user_id",
+            "UPDATE _list SET mode = :mode, name = :name, sources = :sources, iframe = :iframe, items = :items, favorite = :favorite, query = :query, public = :public, sync = :sync, sort = :sort, tiers = :tiers, rating_scale = :rating_scale WHERE id = :id AND user_id = :user_id",
             &[
-                "id", "user_id", "mode", "name", "sources", "iframe", "items", "favorite", "query",
+                "id",
+                "user_id",
+                "mode",
+                "name",
+                "sources",
+                "iframe",
+                "items",
+                "favorite",
+                "query",
                 "public",
+                "sync",
+                "sort",
+                "tiers",
+                "rating_scale",
             ],
         ),
+        "cache" => (
+            "UPDATE _cache SET updated_at = :updated_at WHERE id = :id AND user_id = :user_id",
+            &["id", "user_id", "updated_at"],
+        ),
         _ => unreachable!(),
     }
 }
@@ -386,3 +463,228 @@ pub struct DeleteDocumentBuilder {
     pub document_name: String,
     pub partition_key: UserId,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CosmosQuery, CreateDocumentBuilder, DocumentWriter, QueryDocumentsBuilder,
+        ReplaceDocumentBuilder, SessionClient, SqlSessionClient, View,
+    };
+    use crate::{Error, UserId};
+    use rusqlite::Connection;
+    use serde::Serialize;
+    use serde_json::{Map, Value};
+    use sqlparser::{ast::Statement, dialect::MySqlDialect, parser::Parser};
+
+    const SCHEMA: &str = "CREATE TABLE _item (id TEXT, user_id TEXT, type TEXT, name TEXT, iframe TEXT, rating INTEGER, rating_scale TEXT, normalized_rating REAL, user_score INTEGER, user_wins INTEGER, user_losses INTEGER, metadata TEXT, hidden BOOLEAN, PRIMARY KEY (id, user_id));
+        CREATE TABLE _list (id TEXT, user_id TEXT, mode TEXT, name TEXT, sources TEXT, iframe TEXT, items TEXT, favorite BOOLEAN, query TEXT, public BOOLEAN, sync TEXT, sort TEXT, tiers TEXT, rating_scale TEXT, PRIMARY KEY (id, user_id));
+        CREATE TABLE _play (id TEXT, user_id TEXT, item_id TEXT, ts TEXT, ms_played INTEGER, PRIMARY KEY (id, user_id));
+        CREATE TABLE _cache (id TEXT, user_id TEXT, version TEXT, payload TEXT, updated_at INTEGER, PRIMARY KEY (id, user_id));
+        CREATE TABLE _item_history (user_id TEXT, item_id TEXT, list_id TEXT, ts TEXT, score INTEGER, rank INTEGER);";
+
+    /// Create an empty database in the temp directory
+    fn client(name: &str) -> SqlSessionClient {
+        let path = std::env::temp_dir().join(format!("mybops-{name}-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(SCHEMA)
+            .unwrap();
+        SqlSessionClient {
+            path: Box::leak(path.to_str().unwrap().to_owned().into_boxed_str()),
+        }
+    }
+
+    fn query(sql: &str) -> CosmosQuery {
+        let Some(Statement::Query(query)) = Parser::parse_sql(&MySqlDialect {}, sql).unwrap().pop()
+        else {
+            unreachable!()
+        };
+        CosmosQuery::new(*query)
+    }
+
+    #[tokio::test]
+    async fn test_private_tables() {
+        let client = client("private");
+        Connection::open(client.path)
+            .unwrap()
+            .execute(
+                "INSERT INTO _cache (id, user_id, version, payload, updated_at) VALUES ('playlist', 'other', '1', '[]', 0)",
+                [],
+            )
+            .unwrap();
+        for (sql, expected) in [
+            ("SELECT * FROM _cache", "no such table: _cache"),
+            ("SELECT payload FROM item, _cache", "no such table: _cache"),
+            ("SELECT * FROM _play", "no such table: _play"),
+        ] {
+            let result = client
+                .query_documents::<Map<String, Value>>(QueryDocumentsBuilder::new(
+                    "item",
+                    View::User(UserId("user".to_owned())),
+                    query(sql),
+                ))
+                .await;
+            let Err(Error::ClientError(error)) = result else {
+                panic!("{sql} should fail");
+            };
+            assert_eq!(error, expected);
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Item {
+        id: &'static str,
+        user_id: &'static str,
+        r#type: &'static str,
+        name: &'static str,
+        iframe: Option<String>,
+        rating: Option<i32>,
+        rating_scale: Option<String>,
+        normalized_rating: Option<f64>,
+        user_score: i32,
+        user_wins: i32,
+        user_losses: i32,
+        metadata: &'static str,
+        hidden: bool,
+    }
+
+    fn item(metadata: &'static str) -> Item {
+        Item {
+            id: "track",
+            user_id: "user",
+            r#type: "track",
+            name: "Track",
+            iframe: None,
+            rating: None,
+            rating_scale: None,
+            normalized_rating: None,
+            user_score: 1500,
+            user_wins: 0,
+            user_losses: 0,
+            metadata,
+            hidden: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_item_conflicts() {
+        let client = client("conflicts");
+        let create = |collection_name, metadata| {
+            client.write_document(DocumentWriter::Create(CreateDocumentBuilder {
+                collection_name,
+                document: item(metadata),
+                is_upsert: collection_name == "item_metadata",
+            }))
+        };
+        create("item", r#"{"album":"A"}"#).await.unwrap();
+        Connection::open(client.path)
+            .unwrap()
+            .execute("UPDATE _item SET user_score = 1600, user_wins = 1", [])
+            .unwrap();
+        // Creating an item that exists fails so callers decide whether to ignore it
+        assert!(create("item", r#"{"album":"B"}"#).await.is_err());
+        create("item_metadata", r#"{"year":2020}"#).await.unwrap();
+        let row: (String, i32, i32) = Connection::open(client.path)
+            .unwrap()
+            .query_row(
+                "SELECT metadata, user_score, user_wins FROM _item",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(row, (r#"{"album":"A","year":2020}"#.to_owned(), 1600, 1));
+    }
+
+    #[tokio::test]
+    async fn test_item_updates() {
+        let client = client("updates");
+        Connection::open(client.path)
+            .unwrap()
+            .execute(
+                r#"INSERT INTO _item (id, user_id, type, name, user_score, user_wins, user_losses, metadata, hidden) VALUES ('track', 'user', 'track', 'Track', 1500, 0, 0, '{"album":"A"}', false)"#,
+                [],
+            )
+            .unwrap();
+        let replace = |collection_name, document| {
+            client.write_document(DocumentWriter::Replace(ReplaceDocumentBuilder {
+                collection_name,
+                document_name: "track".to_owned(),
+                partition_key: UserId("user".to_owned()),
+                document,
+            }))
+        };
+        let row = || -> (String, bool, i32) {
+            Connection::open(client.path)
+                .unwrap()
+                .query_row(
+                    "SELECT metadata, hidden, user_score FROM _item",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .unwrap()
+        };
+        let mut stale = item(r#"{"album":"B"}"#);
+        stale.user_score = 1600;
+        stale.hidden = true;
+        // Item updates only write ratings and stats
+        replace("item", stale).await.unwrap();
+        assert_eq!(row(), (r#"{"album":"A"}"#.to_owned(), false, 1600));
+        let mut merged = item(r#"{"merged_into":"other"}"#);
+        merged.hidden = true;
+        replace("item_identity", merged).await.unwrap();
+        assert_eq!(row(), (r#"{"merged_into":"other"}"#.to_owned(), true, 1600));
+    }
+
+    #[tokio::test]
+    async fn test_item_history_rank() {
+        let client = client("history");
+        let conn = Connection::open(client.path).unwrap();
+        for (id, score, hidden) in [
+            ("a", 1600, false),
+            ("b", 1500, false),
+            ("c", 1700, false),
+            ("d", 1800, true),
+        ] {
+            conn.execute(
+                "INSERT INTO _item (id, user_id, type, name, user_score, user_wins, user_losses, metadata, hidden) VALUES (?1, 'user', 'track', ?1, ?2, 0, 0, '{}', ?3)",
+                (id, score, hidden),
+            )
+            .unwrap();
+        }
+        conn.execute(
+            r#"INSERT INTO _list (id, user_id, items) VALUES ('list', 'user', '[{"id":"a"},{"id":"b"},{"id":"d"},{"id":"a"}]')"#,
+            [],
+        )
+        .unwrap();
+        #[derive(Serialize)]
+        struct Snapshots {
+            user_id: &'static str,
+            list_id: &'static str,
+            ts: &'static str,
+            item_ids: &'static str,
+        }
+        client
+            .write_document(DocumentWriter::Create(CreateDocumentBuilder {
+                collection_name: "item_history",
+                document: Snapshots {
+                    user_id: "user",
+                    list_id: "list",
+                    ts: "2024-01-01T00:00:00Z",
+                    item_ids: r#"["a","b"]"#,
+                },
+                is_upsert: false,
+            }))
+            .await
+            .unwrap();
+        // Items outside of the list and hidden items aren't ranked
+        let ranks: Vec<(String, i32)> = conn
+            .prepare("SELECT item_id, rank FROM _item_history ORDER BY item_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(ranks, [("a".to_owned(), 1), ("b".to_owned(), 2)]);
+    }
+}
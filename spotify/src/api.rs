@@ -0,0 +1,612 @@
+use mybops::{Error, spotify::Playlist};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{collections::HashMap, time::Duration};
+
+pub const SPOTIFY_API_URL: &str = "https://api.spotify.com/v1";
+
+/// Spotify rejects track and artist lookups with more than 50 IDs
+const MAX_TRACK_IDS: usize = 50;
+const MAX_ARTIST_IDS: usize = 50;
+const MAX_AUDIO_FEATURES_IDS: usize = 100;
+/// Spotify rejects playlist updates with more than 100 URIs
+const MAX_PLAYLIST_URIS: usize = 100;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+    // Cursor based pages like recently played don't include offset or total
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: usize,
+    #[serde(default)]
+    pub total: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Track {
+    pub id: String,
+    pub name: String,
+    pub album: SimplifiedAlbum,
+    pub artists: Vec<SimplifiedArtist>,
+    pub duration_ms: i32,
+    #[serde(default)]
+    pub explicit: bool,
+    #[serde(default)]
+    pub external_ids: HashMap<String, String>,
+    pub external_urls: HashMap<String, String>,
+    pub popularity: i32,
+    pub track_number: i32,
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimplifiedTrack {
+    pub id: String,
+    pub name: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PlaylistItem {
+    /// Removed tracks and podcast episodes are null
+    pub track: Option<Track>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Album {
+    pub id: String,
+    pub name: String,
+    pub artists: Vec<SimplifiedArtist>,
+    pub external_urls: HashMap<String, String>,
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimplifiedAlbum {
+    pub name: String,
+    /// `YYYY`, `YYYY-MM` or `YYYY-MM-DD` depending on the release date precision
+    pub release_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    pub external_urls: HashMap<String, String>,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimplifiedArtist {
+    pub id: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AudioFeatures {
+    pub id: String,
+    pub acousticness: f64,
+    pub danceability: f64,
+    pub energy: f64,
+    pub instrumentalness: f64,
+    pub key: i32,
+    pub liveness: f64,
+    pub loudness: f64,
+    pub mode: i32,
+    pub speechiness: f64,
+    pub tempo: f64,
+    pub time_signature: i32,
+    pub valence: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchPlaylist {
+    pub id: String,
+    pub name: String,
+    pub owner: PlaylistOwner,
+    pub external_urls: HashMap<String, String>,
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PlaylistOwner {
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchResponse {
+    pub tracks: Option<Page<Track>>,
+    pub albums: Option<Page<Album>>,
+    pub artists: Option<Page<Artist>>,
+    // Spotify can return null for playlists that are no longer available
+    pub playlists: Option<Page<Option<SearchPlaylist>>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PlayHistory {
+    pub track: Track,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct User {
+    pub id: String,
+    pub external_urls: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Tracks {
+    tracks: Vec<Option<Track>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Artists {
+    artists: Vec<Option<Artist>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AudioFeaturesResponse {
+    audio_features: Vec<Option<AudioFeatures>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Snapshot {
+    snapshot_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PlaylistDetails {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Uris<'a> {
+    uris: &'a [String],
+}
+
+/// Client for the Spotify Web API
+///
+/// Requests are retried when Spotify is rate limiting or unavailable. The base URL defaults to
+/// `SPOTIFY_API_URL` so that it can be pointed at a fake server.
+#[derive(Clone)]
+pub struct SpotifyApi {
+    client: Client,
+    base_url: String,
+    access_token: String,
+    max_retries: u32,
+    backoff: Duration,
+    max_retry_after: Duration,
+}
+
+impl SpotifyApi {
+    pub fn new(access_token: impl Into<String>) -> SpotifyApi {
+        SpotifyApi {
+            client: Client::new(),
+            base_url: std::env::var("SPOTIFY_API_URL")
+                .unwrap_or_else(|_| SPOTIFY_API_URL.to_owned()),
+            access_token: access_token.into(),
+            max_retries: 3,
+            backoff: Duration::from_millis(500),
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> SpotifyApi {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Retry failed requests up to `max_retries` times, doubling `backoff` after each server
+    /// error or rate limit without a usable `Retry-After`
+    pub fn with_retries(mut self, max_retries: u32, backoff: Duration) -> SpotifyApi {
+        self.max_retries = max_retries;
+        self.backoff = backoff;
+        self
+    }
+
+    pub async fn me(&self) -> Result<User, Error> {
+        self.get("/me").await
+    }
+
+    pub async fn playlist(&self, id: &str) -> Result<Playlist, Error> {
+        self.get(&format!("/playlists/{id}?fields=id,name,external_urls"))
+            .await
+    }
+
+    /// The snapshot ID changes whenever the playlist is modified
+    pub async fn playlist_snapshot_id(&self, id: &str) -> Result<String, Error> {
+        Ok(self
+            .get::<Snapshot>(&format!("/playlists/{id}?fields=snapshot_id"))
+            .await?
+            .snapshot_id)
+    }
+
+    /// Tracks in a playlist, skipping removed tracks and podcast episodes
+    pub async fn playlist_tracks(&self, id: &str) -> Result<Vec<Track>, Error> {
+        Ok(self
+            .get_all::<PlaylistItem>(&format!("/playlists/{id}/tracks?limit=100"))
+            .await?
+            .into_iter()
+            .filter_map(|item| item.track)
+            .collect())
+    }
+
+    pub async fn album(&self, id: &str) -> Result<Album, Error> {
+        self.get(&format!("/albums/{id}")).await
+    }
+
+    /// Album tracks don't include album metadata or popularity so full tracks are fetched
+    pub async fn album_tracks(&self, id: &str) -> Result<Vec<Track>, Error> {
+        let ids: Vec<_> = self
+            .get_all::<SimplifiedTrack>(&format!("/albums/{id}/tracks?limit=50"))
+            .await?
+            .into_iter()
+            .map(|track| track.id)
+            .collect();
+        self.tracks(&ids).await
+    }
+
+    pub async fn track(&self, id: &str) -> Result<Track, Error> {
+        self.get(&format!("/tracks/{id}")).await
+    }
+
+    pub async fn tracks(&self, ids: &[String]) -> Result<Vec<Track>, Error> {
+        let mut tracks = Vec::with_capacity(ids.len());
+        for ids in ids.chunks(MAX_TRACK_IDS) {
+            let response: Tracks = self.get(&format!("/tracks?ids={}", ids.join(","))).await?;
+            tracks.extend(response.tracks.into_iter().flatten());
+        }
+        Ok(tracks)
+    }
+
+    pub async fn artists(&self, ids: &[String]) -> Result<Vec<Artist>, Error> {
+        let mut artists = Vec::with_capacity(ids.len());
+        for ids in ids.chunks(MAX_ARTIST_IDS) {
+            let response: Artists = self.get(&format!("/artists?ids={}", ids.join(","))).await?;
+            artists.extend(response.artists.into_iter().flatten());
+        }
+        Ok(artists)
+    }
+
+    /// Tracks without audio analysis are skipped
+    pub async fn audio_features(&self, ids: &[String]) -> Result<Vec<AudioFeatures>, Error> {
+        let mut features = Vec::with_capacity(ids.len());
+        for ids in ids.chunks(MAX_AUDIO_FEATURES_IDS) {
+            let response: AudioFeaturesResponse = self
+                .get(&format!("/audio-features?ids={}", ids.join(",")))
+                .await?;
+            features.extend(response.audio_features.into_iter().flatten());
+        }
+        Ok(features)
+    }
+
+    pub async fn search(
+        &self,
+        query: &str,
+        types: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<SearchResponse, Error> {
+        self.get(&format!(
+            "/search?q={}&type={types}&offset={offset}&limit={limit}",
+            urlencoding::encode(query)
+        ))
+        .await
+    }
+
+    /// The 50 most recently played tracks
+    pub async fn recently_played(&self) -> Result<Vec<PlayHistory>, Error> {
+        Ok(self
+            .get::<Page<PlayHistory>>("/me/player/recently-played?limit=50")
+            .await?
+            .items)
+    }
+
+    pub async fn current_user_playlists(&self) -> Result<Vec<Playlist>, Error> {
+        self.get_all("/me/playlists?limit=50").await
+    }
+
+    pub async fn create_playlist(&self, user_id: &str, name: &str) -> Result<Playlist, Error> {
+        self.send(|| {
+            self.request(Method::POST, &format!("/users/{user_id}/playlists"))
+                .json(&PlaylistDetails {
+                    name: name.to_owned(),
+                })
+        })
+        .await?
+        .json()
+        .await
+        .map_err(Error::from)
+    }
+
+    pub async fn update_playlist(&self, id: &str, name: &str) -> Result<(), Error> {
+        self.send(|| {
+            self.request(Method::PUT, &format!("/playlists/{id}"))
+                .json(&PlaylistDetails {
+                    name: name.to_owned(),
+                })
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Replace all playlist items, clearing the playlist if `uris` is empty
+    ///
+    /// Returns the snapshot ID of the updated playlist.
+    pub async fn replace_playlist_items(&self, id: &str, uris: &[String]) -> Result<String, Error> {
+        let mut chunks = uris.chunks(MAX_PLAYLIST_URIS);
+        let first = chunks.next().unwrap_or(&[]);
+        let mut snapshot: Snapshot = self
+            .send(|| {
+                self.request(Method::PUT, &format!("/playlists/{id}/tracks"))
+                    .json(&Uris { uris: first })
+            })
+            .await?
+            .json()
+            .await?;
+        for uris in chunks {
+            snapshot = self
+                .send(|| {
+                    self.request(Method::POST, &format!("/playlists/{id}/tracks"))
+                        .json(&Uris { uris })
+                })
+                .await?
+                .json()
+                .await?;
+        }
+        Ok(snapshot.snapshot_id)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        Ok(self
+            .send(|| self.request(Method::GET, path))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Follow `next` links until every page has been read
+    pub async fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, Error> {
+        let mut page: Page<T> = self.get(path).await?;
+        let mut items = page.items;
+        while let Some(next) = page.next {
+            page = self.get(&next).await?;
+            items.extend(page.items);
+        }
+        Ok(items)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        // Pagination links are absolute URLs
+        let url = if path.starts_with("http") {
+            path.to_owned()
+        } else {
+            format!("{}{path}", self.base_url)
+        };
+        self.client
+            .request(method, url)
+            .bearer_auth(&self.access_token)
+    }
+
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> Result<Response, Error> {
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            let response = request().send().await?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response);
+            }
+            // Spotify sends the number of seconds to wait before retrying a rate limited request
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .filter(|delay| *delay <= self.max_retry_after);
+            let delay = match retry_after {
+                Some(delay) if status == StatusCode::TOO_MANY_REQUESTS => Some(delay),
+                _ if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() => {
+                    let delay = backoff;
+                    backoff *= 2;
+                    Some(delay)
+                }
+                _ => None,
+            };
+            match delay {
+                Some(delay) if retries < self.max_retries => {
+                    retries += 1;
+                    tokio::time::sleep(delay).await;
+                }
+                _ => {
+                    let url = response.url().path().to_owned();
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(if status == StatusCode::NOT_FOUND {
+                        Error::NotFound
+                    } else {
+                        Error::internal_error(format!("Spotify error {status} for {url}: {body}"))
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpotifyApi;
+    use axum::{
+        Json, Router,
+        extract::{Query, State},
+        http::{HeaderMap, StatusCode},
+        response::{IntoResponse, Response},
+        routing::{get, put},
+    };
+    use mybops::Error;
+    use serde_json::{Value, json};
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    #[derive(Default)]
+    struct Fake {
+        base_url: Mutex<String>,
+        requests: AtomicUsize,
+        bodies: Mutex<Vec<Value>>,
+    }
+
+    async fn serve(router: Router<Arc<Fake>>) -> (SpotifyApi, Arc<Fake>) {
+        let fake = Arc::new(Fake::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        *fake.base_url.lock().unwrap() = base_url.clone();
+        let router = router.with_state(fake.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let api = SpotifyApi::new("token")
+            .with_base_url(base_url)
+            .with_retries(2, Duration::ZERO);
+        (api, fake)
+    }
+
+    fn track(id: &str) -> Value {
+        json!({
+            "id": id,
+            "name": format!("Track {id}"),
+            "album": {"name": "Album"},
+            "artists": [{"name": "Artist"}],
+            "duration_ms": 1000,
+            "external_urls": {"spotify": format!("https://open.spotify.com/track/{id}")},
+            "popularity": 50,
+            "track_number": 1,
+            "uri": format!("spotify:track:{id}"),
+        })
+    }
+
+    async fn playlist_tracks(
+        State(fake): State<Arc<Fake>>,
+        headers: HeaderMap,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> Json<Value> {
+        assert_eq!(headers["authorization"], "Bearer token");
+        fake.requests.fetch_add(1, Ordering::SeqCst);
+        let base_url = fake.base_url.lock().unwrap().clone();
+        Json(match params.get("offset").map(String::as_str) {
+            None => json!({
+                "items": [{"track": track("1")}, {"track": null}],
+                "next": format!("{base_url}/playlists/p/tracks?offset=2&limit=2"),
+                "offset": 0,
+                "limit": 2,
+                "total": 3,
+            }),
+            _ => json!({
+                "items": [{"track": track("2")}],
+                "next": null,
+                "offset": 2,
+                "limit": 2,
+                "total": 3,
+            }),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_pagination() {
+        let (api, fake) =
+            serve(Router::new().route("/playlists/p/tracks", get(playlist_tracks))).await;
+        let tracks = api.playlist_tracks("p").await.unwrap();
+        assert_eq!(
+            tracks.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(),
+            ["1", "2"]
+        );
+        assert_eq!(fake.requests.load(Ordering::SeqCst), 2);
+    }
+
+    async fn rate_limited(State(fake): State<Arc<Fake>>) -> Response {
+        if fake.requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "0")]).into_response()
+        } else {
+            Json(track("1")).into_response()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let (api, fake) = serve(Router::new().route("/tracks/1", get(rate_limited))).await;
+        assert_eq!(api.track("1").await.unwrap().id, "1");
+        assert_eq!(fake.requests.load(Ordering::SeqCst), 2);
+    }
+
+    async fn rate_limited_without_retry_after(State(fake): State<Arc<Fake>>) -> Response {
+        match fake.requests.fetch_add(1, Ordering::SeqCst) {
+            0 => StatusCode::TOO_MANY_REQUESTS.into_response(),
+            1 => (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "3600")]).into_response(),
+            _ => Json(track("1")).into_response(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_backoff() {
+        let (api, fake) =
+            serve(Router::new().route("/tracks/1", get(rate_limited_without_retry_after))).await;
+        assert_eq!(api.track("1").await.unwrap().id, "1");
+        assert_eq!(fake.requests.load(Ordering::SeqCst), 3);
+    }
+
+    async fn unavailable(State(fake): State<Arc<Fake>>) -> StatusCode {
+        fake.requests.fetch_add(1, Ordering::SeqCst);
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    #[tokio::test]
+    async fn test_retry_limit() {
+        let (api, fake) = serve(Router::new().route("/tracks/1", get(unavailable))).await;
+        assert!(matches!(api.track("1").await, Err(Error::InternalError(_))));
+        assert_eq!(fake.requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let (api, _) = serve(Router::new()).await;
+        assert!(matches!(api.track("1").await, Err(Error::NotFound)));
+    }
+
+    async fn playlist_items(State(fake): State<Arc<Fake>>, Json(body): Json<Value>) -> Json<Value> {
+        let mut bodies = fake.bodies.lock().unwrap();
+        bodies.push(body);
+        Json(json!({"snapshot_id": format!("snapshot{}", bodies.len())}))
+    }
+
+    #[tokio::test]
+    async fn test_replace_playlist_items() {
+        let (api, fake) = serve(Router::new().route(
+            "/playlists/p/tracks",
+            put(playlist_items).post(playlist_items),
+        ))
+        .await;
+        let uris: Vec<_> = (0..150).map(|i| format!("spotify:track:{i}")).collect();
+        assert_eq!(
+            api.replace_playlist_items("p", &uris).await.unwrap(),
+            "snapshot2"
+        );
+        assert_eq!(
+            api.replace_playlist_items("p", &[]).await.unwrap(),
+            "snapshot3"
+        );
+        let bodies = fake.bodies.lock().unwrap();
+        assert_eq!(
+            bodies
+                .iter()
+                .map(|body| body["uris"].as_array().unwrap().len())
+                .collect::<Vec<_>>(),
+            [100, 50, 0]
+        );
+    }
+}
@@ -1,8 +1,10 @@
+use api::SpotifyApi;
 use async_trait::async_trait;
 use mybops::Error;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+
+pub mod api;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Token {
@@ -10,12 +12,6 @@ pub struct Token {
     pub refresh_token: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct User {
-    pub id: String,
-    pub external_urls: HashMap<String, String>,
-}
-
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SpotifyCredentials {
     pub user_id: String,
@@ -30,8 +26,24 @@ pub trait AuthClient {
     async fn get_credentials(&self, code: &str, origin: &str) -> Result<Self::Credentials, Error>;
 }
 
+pub const SPOTIFY_CLIENT_ID: &str = "ee3d1b4f8d80477ea48743a511ef3018";
+
 pub struct SpotifyClient;
 
+impl SpotifyClient {
+    pub fn client_id() -> String {
+        std::env::var("SPOTIFY_CLIENT_ID").unwrap_or_else(|_| SPOTIFY_CLIENT_ID.to_owned())
+    }
+
+    pub fn authorize_url(redirect_uri: &str) -> String {
+        format!(
+            "https://accounts.spotify.com/authorize?client_id={}&redirect_uri={}&response_type=code&scope=playlist-modify-public%20playlist-modify-private%20user-read-recently-played%20playlist-read-private",
+            Self::client_id(),
+            redirect_uri
+        )
+    }
+}
+
 #[async_trait]
 impl AuthClient for SpotifyClient {
     type Credentials = SpotifyCredentials;
@@ -57,16 +69,13 @@ impl AuthClient for SpotifyClient {
             .json()
             .await?;
 
-        let spotify_user: User = client
-            .get("https://api.spotify.com/v1/me")
-            .header("Authorization", format!("Bearer {}", token.access_token))
-            .send()
-            .await?
-            .json()
-            .await?;
+        let mut spotify_user = SpotifyApi::new(&token.access_token).me().await?;
         Ok(SpotifyCredentials {
-            user_id: spotify_user.id.clone(),
-            url: spotify_user.external_urls["spotify"].clone(),
+            url: spotify_user
+                .external_urls
+                .remove("spotify")
+                .ok_or(Error::internal_error("Spotify did not return user URL"))?,
+            user_id: spotify_user.id,
             access_token: token.access_token,
             refresh_token: token.refresh_token.ok_or(Error::internal_error(
                 "Spotify did not return refresh_token",